@@ -0,0 +1,54 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *  http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing,
+ * software distributed under the License is distributed on an
+ * "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+ * KIND, either express or implied.  See the License for the
+ * specific language governing permissions and limitations
+ * under the License.
+ */
+//! Dispatch Table that allows the Rust application firmware to call OS functions in the Mynewt OS firmware.
+//! Each entry maps the hash of the function name to the function address.
+#ifndef __MYNEWT_RUST_DISPATCH_H__
+#define __MYNEWT_RUST_DISPATCH_H__
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+///  Magic number at the start of the Dispatch Table: "DSPT"
+#define DISPATCH_TABLE_MAGIC 0x54505344
+
+///  Entry in the Dispatch Table
+struct dispatch_entry {
+    uint32_t hash;        //  FNV-1a hash of the function name, computed by `dispatch_hash!()` in Rust
+    const void *address;  //  Address of the function
+};
+
+///  Dispatch Table exported by the OS firmware
+struct dispatch_table {
+    uint32_t magic;                         //  Must be DISPATCH_TABLE_MAGIC
+    uint32_t count;                         //  Number of entries
+    const struct dispatch_entry *entries;   //  Entries, sorted by hash
+};
+
+///  Dispatch Table exported by the OS firmware. Located in section `.dispatch_table`, which is placed at the
+///  fixed address 0x0007b400 by `hw/bsp/nrf52/nrf52xxaa.ld`, so that the application firmware may find the table.
+extern const struct dispatch_table mynewt_dispatch_table;
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* __MYNEWT_RUST_DISPATCH_H__ */
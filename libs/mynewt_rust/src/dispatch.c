@@ -0,0 +1,46 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *  http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing,
+ * software distributed under the License is distributed on an
+ * "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+ * KIND, either express or implied.  See the License for the
+ * specific language governing permissions and limitations
+ * under the License.
+ */
+//  Dispatch Table for OS functions called by the Rust application firmware.
+//  The hash of each function name is the 32-bit FNV-1a hash, same as `dispatch_hash!()` in Rust:
+//    hash = 0x811c9dc5; for each byte: hash = (hash ^ byte) * 0x01000193
+//  Entries must be sorted by hash because the application firmware does a binary search.
+#include <console/console.h>
+#include <mynewt_rust/dispatch.h>
+
+///  Declare an entry in the Dispatch Table
+#define DISPATCH_ENTRY(hash, func) { hash, (const void *) func }
+
+///  Entries in the Dispatch Table, sorted by hash
+static const struct dispatch_entry dispatch_entries[] = {
+    DISPATCH_ENTRY(0x01a6085b, console_printdouble),
+    DISPATCH_ENTRY(0x05410b1c, console_printfloat),
+    DISPATCH_ENTRY(0x37973859, console_dump),
+    DISPATCH_ENTRY(0x5cc2b563, console_flush),
+    DISPATCH_ENTRY(0x67dcee65, console_buffer),
+    DISPATCH_ENTRY(0x6fd3612f, console_printhex),
+    DISPATCH_ENTRY(0xdff62e97, console_printint),
+};
+
+///  Dispatch Table exported by the OS firmware, placed at a fixed address by `hw/bsp/nrf52/nrf52xxaa.ld`
+__attribute__((section(".dispatch_table"), used))
+const struct dispatch_table mynewt_dispatch_table = {
+    DISPATCH_TABLE_MAGIC,
+    sizeof(dispatch_entries) / sizeof(dispatch_entries[0]),
+    dispatch_entries,
+};
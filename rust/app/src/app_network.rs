@@ -27,14 +27,20 @@ use mynewt::{
     hw::sensor::{               //  Import Mynewt Sensor API
         SensorValue, SensorValueType,
     },
-    sys::console,               //  Import Mynewt Console API
+    sys::{
+        console,                //  Import Mynewt Console API
+        crash,                  //  Import Mynewt Crash Record API
+    },
     encoding::coap_context::*,  //  Import Mynewt Encoding API
     libs::{
         sensor_network,         //  Import Mynewt Sensor Network API
     },
     coap, d, Strn,              //  Import Mynewt macros
 };
-use mynewt_macros::strn;        //  Import Mynewt procedural macros
+use mynewt_macros::{ init_strn, strn };  //  Import Mynewt procedural macros
+
+///  Use key (field name) `crash_line` to transmit the line number of the last crash to CoAP Server
+static CRASH_LINE_KEY: Strn = init_strn!("crash_line");
 
 ///  Aggregate the sensor value with other sensor data before transmitting to server.
 ///  If the sensor value is a GPS geolocation, we remember it and attach it to other sensor data for transmission.
@@ -82,6 +88,12 @@ fn send_sensor_data(val: &SensorValue) -> MynewtResult<()>  {  //  Returns an er
     console::print("\n");
     console::flush(); ////
 
+    //  If the last crash has not been reported, report it before the sensor data.
+    //  Retry with the next sensor data if the network is not ready.
+    if let Some(crash) = crash::last_crash() {
+        if !crash.is_reported() { send_crash_report(crash) ? ; }
+    }
+
     //  Get a randomly-generated device ID that changes each time we restart the device.
     let device_id = sensor_network::get_device_id() ? ;
 
@@ -120,6 +132,43 @@ fn send_sensor_data(val: &SensorValue) -> MynewtResult<()>  {  //  Returns an er
     Ok(())
 }
 
+/// Compose a CoAP JSON message with the file, line and message of the last crash and send to the CoAP server.
+/// The crash is marked as reported so that it won't be sent again. Return `SYS_EAGAIN` if network is not ready yet.
+/// ```json
+/// {"values":[
+///   {"key":"crash_line", "value":123},
+///   {"key":"crash_file", "value":"src/chip8.rs"},
+///   {"key":"crash_msg",  "value":"CHIP8 fail"},
+///   {"key":"device",     "value":"0102030405060708090a0b0c0d0e0f10"}
+/// ]}
+/// ```
+fn send_crash_report(crash: &crash::CrashInfo) -> MynewtResult<()>  {  //  Returns an error code upon error.
+    let device_id = sensor_network::get_device_id() ? ;
+    let rc = sensor_network::init_server_post( strn!(()) ) ? ;  //  `strn!(())` means use default CoAP URI in `syscfg.yml`
+    if !rc { return Err(MynewtError::SYS_EAGAIN); }
+
+    //  Compose the CoAP Payload with the crash details
+    let line = SensorValue {
+        key:   &CRASH_LINE_KEY,
+        value: SensorValueType::Uint(crash.line),
+        ..Default::default()
+    };
+    let file    = crash.file_strn();
+    let message = crash.message_strn();
+    let _payload = coap!( @json {
+        line,
+        "crash_file": &file,
+        "crash_msg":  &message,
+        "device":     &device_id,
+    });
+
+    //  Post the CoAP Server message to the CoAP Background Task for transmission
+    sensor_network::do_server_post() ? ;
+    crash::mark_reported();
+    console::print("NET crash reported\n"); console::flush();
+    Ok(())
+}
+
 ///  Current geolocation recorded from GPS
 #[cfg(feature = "use_float")]  //  If floating-point is enabled...
 static mut CURRENT_GEOLOCATION: SensorValueType = SensorValueType::None;
\ No newline at end of file
@@ -21,17 +21,32 @@
 
 use mynewt::{
     result::*,                              //  Import Mynewt API Result and Error types
+    hal::saadc::{ AnalogInput, Gain },      //  Import Mynewt SAADC API for the battery sensor settings
+    hw::battery::{                          //  Import Mynewt Battery Sensor API
+        self,
+        BatteryConfig,
+        LIPO_DISCHARGE_CURVE,
+    },
     hw::sensor_mgr,                         //  Import Mynewt Sensor Manager API
     hw::sensor::{        
         self,                               //  Import Mynewt Sensor API
         sensor_type_t,
+        SensorValue,
+        SensorValueType,
+    },
+    sys::console::{                         //  Import Mynewt Console API
+        self,
+        Level,
     },
-    sys::console,                           //  Import Mynewt Console API
     Strn,                                   //  Import Mynewt macros    
+    info,
 };
 use mynewt_macros::{ init_strn };           //  Import Mynewt procedural macros
 use crate::app_network;                     //  Import `app_network.rs` for sending sensor data
 
+///  Log battery status and progress messages
+const LOG_LEVEL: Level = Level::Info;
+
 ///  Sensor to be polled: `temp_stub_0` is the stub temperature sensor that simulates a temperature sensor
 static SENSOR_DEVICE: Strn      = init_strn!("temp_stub_0");
 ///  Poll sensor every 30,000 milliseconds (30 seconds)  
@@ -41,6 +56,22 @@ const TEMP_SENSOR_KEY: Strn     = init_strn!("t");
 ///  Type of sensor: Raw temperature sensor (integer sensor values 0 to 4095)
 const TEMP_SENSOR_TYPE: sensor_type_t = sensor::SENSOR_TYPE_AMBIENT_TEMPERATURE_RAW;
 
+///  Battery sensor device name
+static BATTERY_DEVICE: Strn     = init_strn!("battery_0");
+///  Poll battery every 60,000 milliseconds (1 minute)
+const BATTERY_POLL_TIME: u32    = 60 * 1000;
+///  Use key (field name) `battery` for the battery status
+static BATTERY_KEY: Strn        = init_strn!("battery");
+///  PineTime battery: Voltage divided by 2 on P0.31/AIN7, charge indication on P0.12, power presence on P0.19
+const BATTERY_CONFIG: BatteryConfig = BatteryConfig {
+    input:        AnalogInput::Ain7,
+    gain:         Gain::Gain1_6,  //  Input range 3.6 V covers the max divided battery voltage of 2.1 V
+    divider:      2,
+    charging_pin: Some(12),       //  P0.12: CHARGE INDICATION (low when charging)
+    power_pin:    Some(19),       //  P0.19: POWER PRESENCE (low when plugged in)
+    curve:        LIPO_DISCHARGE_CURVE,
+};
+
 ///  Ask Mynewt to poll or read the temperature sensor and call `aggregate_sensor_data()`
 ///  Return `Ok()` if successful, else return `Err()` with `MynewtError` error code inside.
 #[allow(dead_code)]
@@ -67,4 +98,39 @@ pub fn start_sensor_listener() -> MynewtResult<()>  {  //  Returns an error code
 
     //  Return `Ok()` to indicate success.  This line should not end with a semicolon (;).
     Ok(())
-}
\ No newline at end of file
+}
+
+///  Create the battery sensor and ask Mynewt to poll the battery every minute, calling `handle_battery_data()`
+pub fn start_battery_listener() -> MynewtResult<()>  {  //  Returns an error code upon error.
+    //  Create the battery sensor and register it with the Sensor Manager
+    battery::init(&BATTERY_DEVICE, BATTERY_CONFIG) ? ;
+
+    //  Fetch the sensor by name.
+    let sensor = sensor_mgr::find_bydevname(&BATTERY_DEVICE)
+        .next()                                 //  Fetch the first sensor that matches
+        .ok_or(MynewtError::SYS_ENODEV) ? ;     //  Fail if no sensor found
+
+    //  Poll the battery every minute
+    sensor::set_poll_rate_ms(&BATTERY_DEVICE, BATTERY_POLL_TIME) ? ;
+
+    //  Create a sensor listener that will call function `handle_battery_data` after polling the battery
+    let listener = sensor::new_sensor_listener(
+        &BATTERY_KEY,                   //  Key for the battery status
+        sensor::SENSOR_TYPE_BATTERY,    //  Type of sensor data: Battery status
+        handle_battery_data             //  Call this function with the polled data
+    ) ? ;
+    sensor::register_listener(sensor, listener) ? ;
+    Ok(())
+}
+
+///  Display the polled battery status
+fn handle_battery_data(sensor_value: &SensorValue) -> MynewtResult<()>  {  //  Returns an error code upon error.
+    if let SensorValueType::Battery { millivolts, percent, charging, plugged } = sensor_value.value {
+        let status =
+            if charging     { " charging" }
+            else if plugged { " plugged" }
+            else            { "" };
+        info!("battery {} mV {}%{}", millivolts, percent, status);
+    }
+    Ok(())
+}
@@ -0,0 +1,421 @@
+//! Touch calibration and rotation mapping. Touch points from the touch controller are mapped to display coordinates
+//! with an affine transform, then rotated according to the display rotation, before they reach the gesture
+//! recognition and the apps:
+//! ```
+//! calibration::start() ? ;                        //  Load the stored calibration, or calibrate on first boot
+//! let (x, y) = calibration::map(raw_x, raw_y);    //  Map a touch point to display coordinates
+//! calibration::start_calibration() ? ;            //  Calibrate again
+//! ```
+//! To calibrate again, the user holds a finger still on the screen for `RECALIBRATE_MS` milliseconds and releases it.
+//! The hold is detected in touch controller coordinates by `watch_point()`, so it works even if the calibration is wrong.
+//! To calibrate, the user taps the centre of 3 crosshairs in turn. The transform is computed from the 3 touch
+//! points with integer arithmetic (see "How To Calibrate Touch Screens" by Carlos E. Vidales) and stored in the
+//! internal flash, so it survives power off. The calibration is done in the unrotated orientation of the panel,
+//! so the same transform works for every rotation. If the user doesn't complete the procedure after `MAX_ATTEMPTS`
+//! attempts, or stops tapping for `TIMEOUT_SECS` seconds, the procedure is cancelled and the touches reach the apps
+//! again, mapped with the current transform.
+//!
+//! The calibration area was the last sector of `FLASH_AREA_NFFS`, which is also the config FCB. Leftover FCB data
+//! is erased by `start()`, so it's never mistaken for a calibration.
+use embedded_graphics::{
+    prelude::*,
+    pixelcolor::Rgb565,
+    primitives::Rectangle,
+};
+use mynewt::{
+    result::*,
+    kernel::{
+        self, os,
+        timer::{ Timer, TimerMode },
+    },
+    hal::flash::{ FlashArea, FLASH_AREA_USER_ID_MIN },
+    sys::console::{
+        self,
+        Level,
+    },
+    info, warn,
+};
+use crate::{
+    crash,
+    st7789::{ Rotation, WIDTH, HEIGHT },
+    touch_sensor::Action,
+};
+
+/// Log level for calibration diagnostics
+const LOG_LEVEL: Level = Level::Info;
+
+/// Load the stored calibration. If there is no stored calibration, start the calibration procedure on PineTime.
+/// The simulated touch controller is aligned with the display, so the simulator doesn't calibrate on first boot.
+pub fn start() -> MynewtResult<()> {
+    TIMEOUT_TIMER.init(os::eventq_dflt_get() ? , TimerMode::OneShot, handle_timeout) ? ;
+    HOLD_TIMER.init(os::eventq_dflt_get() ? , TimerMode::OneShot, handle_hold) ? ;
+    match load() {
+        Some(transform) => unsafe { TRANSFORM = transform },
+        None => {
+            format() ? ;
+            if CALIBRATE_ON_FIRST_BOOT { start_calibration() ? ; }
+        }
+    }
+    Ok(())
+}
+
+/// Start the calibration procedure: Show the first crosshair and wait for the user to tap it.
+/// Touches are not passed to the apps until the procedure is complete or cancelled. `start()` must be called first.
+pub fn start_calibration() -> MynewtResult<()> {
+    start_attempt(1)
+}
+
+/// Start the attempt of the calibration procedure, numbered from 1
+fn start_attempt(attempt: u8) -> MynewtResult<()> {
+    console::print("calibrate touch\n");
+    TIMEOUT_TIMER.start(kernel::ms_to_ticks(TIMEOUT_SECS * 1000)) ? ;
+    unsafe { PROCEDURE = Some(Procedure { step: 0, attempt, touches: [(0, 0); TARGETS.len()], last: None }) };
+    draw_crosshair(TARGETS[0], true);
+    Ok(())
+}
+
+/// Cancel the calibration procedure and keep the current transform, so that the touches reach the apps again.
+/// If there is no stored calibration, store the current transform, so that the watch won't calibrate on every boot.
+/// The user may calibrate later with `start_calibration()`.
+fn cancel() {
+    TIMEOUT_TIMER.stop();
+    if let Some(procedure) = unsafe { PROCEDURE.take() } {
+        draw_crosshair(TARGETS[procedure.step], false);
+    }
+    let transform = unsafe { TRANSFORM };
+    warn!("calibration cancelled, using {:?}", transform);
+    if load().is_none() && save(&transform).is_err() { warn!("calibration not saved"); }
+}
+
+/// Called when the user hasn't tapped the crosshair for `TIMEOUT_SECS` seconds. Cancels the procedure.
+fn handle_timeout() {
+    if is_calibrating() { cancel(); }
+}
+
+/// Return true if the calibration procedure is running
+pub fn is_calibrating() -> bool {
+    unsafe { PROCEDURE.is_some() }
+}
+
+/// Watch the touch points that are not used for calibrating. If the finger is held still for `RECALIBRATE_MS`
+/// milliseconds, start the calibration procedure when the finger is released, after the apps have seen the release.
+/// Called by the touch controller interrupt handler in the Default Event Queue, with touch controller coordinates.
+pub fn watch_point(action: Action, x: u16, y: u16) {
+    if action == Action::Up {
+        HOLD_TIMER.stop();
+        let held = unsafe { HOLD.take() }.map_or(false, |hold| hold.held);
+        if held && start_calibration().is_err() { warn!("calibration not started"); }
+        return;
+    }
+    if (x, y) == (0, 0) { return; }
+    //  Restart the hold if the finger has moved
+    let still = match unsafe { HOLD } {
+        Some(hold) => (hold.x as i32 - x as i32).abs() <= HOLD_SLOP && (hold.y as i32 - y as i32).abs() <= HOLD_SLOP,
+        None => false,
+    };
+    if still { return; }
+    unsafe { HOLD = Some(Hold { x, y, held: false }) };
+    HOLD_TIMER.start(kernel::ms_to_ticks(RECALIBRATE_MS)).ok();
+}
+
+/// Called when the finger has been held still for `RECALIBRATE_MS` milliseconds
+fn handle_hold() {
+    if let Some(hold) = unsafe { HOLD.as_mut() } {
+        hold.held = true;
+        info!("release to calibrate");
+    }
+}
+
+/// Handle a touch point during the calibration procedure. The position is recorded when the finger is released.
+/// Called by the touch controller interrupt handler in the Default Event Queue.
+pub fn handle_point(action: Action, x: u16, y: u16) {
+    let procedure = match unsafe { PROCEDURE.as_mut() } {
+        Some(procedure) => procedure,
+        None => return,
+    };
+    //  Remember where the finger settled. Touch up may be reported at (0, 0).
+    if action != Action::Up {
+        if (x, y) != (0, 0) { procedure.last = Some((x as i32, y as i32)); }
+        return;
+    }
+    let touch = match procedure.last.take() {
+        Some(touch) => touch,
+        None => return,
+    };
+    TIMEOUT_TIMER.reset().ok();  //  Give the user more time for the next target
+    let step = procedure.step;
+    procedure.touches[step] = touch;
+    draw_crosshair(TARGETS[step], false);
+    if step + 1 < TARGETS.len() {
+        procedure.step = step + 1;
+        draw_crosshair(TARGETS[step + 1], true);
+        return;
+    }
+    //  All targets tapped. If the touches are too far from the targets, the user probably missed, so try again,
+    //  up to `MAX_ATTEMPTS` times.
+    let (touches, attempt) = (procedure.touches, procedure.attempt);
+    match Transform::compute(&TARGETS, &touches) {
+        Some(transform) => {
+            TIMEOUT_TIMER.stop();
+            unsafe { TRANSFORM = transform; PROCEDURE = None; }
+            info!("calibrated: {:?}", transform);
+            if save(&transform).is_err() { warn!("calibration not saved"); }
+        }
+        None if attempt < MAX_ATTEMPTS => {
+            warn!("calibration failed, try again");
+            if start_attempt(attempt + 1).is_err() { cancel(); }
+        }
+        None => cancel(),
+    }
+}
+
+/// Map the touch point from the touch controller to display coordinates, according to the calibration and the
+/// display rotation
+pub fn map(x: u16, y: u16) -> (u16, u16) {
+    let (x, y) = unsafe { TRANSFORM.apply(x as i32, y as i32) };
+    //  Rotate the same way as `st7789::Rotation::madctl()`, so that the touch matches the rotated display
+    let (width, height) = (WIDTH as i32, HEIGHT as i32);
+    let (x, y) = match unsafe { ROTATION } {
+        Rotation::Deg0   => (x, y),
+        Rotation::Deg90  => (y, width - 1 - x),
+        Rotation::Deg180 => (width - 1 - x, height - 1 - y),
+        Rotation::Deg270 => (height - 1 - y, x),
+    };
+    //  Clamp to the rotated display
+    let (width, height) = screen_size();
+    (
+        x.max(0).min(width  as i32 - 1) as u16,
+        y.max(0).min(height as i32 - 1) as u16,
+    )
+}
+
+/// Set the display rotation for mapping touch points. Called by the display driver when the display is rotated.
+pub fn set_rotation(rotation: Rotation) {
+    unsafe { ROTATION = rotation };
+}
+
+/// Return the (width, height) of the display for the current rotation, for mapping touch points to the screen
+pub fn screen_size() -> (u16, u16) {
+    match unsafe { ROTATION } {
+        Rotation::Deg0 | Rotation::Deg180  => (WIDTH, HEIGHT),
+        Rotation::Deg90 | Rotation::Deg270 => (HEIGHT, WIDTH),
+    }
+}
+
+/// Affine transform from touch coordinates (x, y) to display coordinates:
+/// `((a * x + b * y + c) / divider, (d * x + e * y + f) / divider)`
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Transform {
+    a: i32, b: i32, c: i32,
+    d: i32, e: i32, f: i32,
+    divider: i32,
+}
+
+impl Transform {
+    /// Transform that passes the touch coordinates straight through
+    const IDENTITY: Transform = Transform { a: 1, b: 0, c: 0, d: 0, e: 1, f: 0, divider: 1 };
+
+    /// Compute the transform that maps the 3 touch points to the 3 display targets. Returns `None` if the
+    /// touch points are too far from the targets or in a line.
+    fn compute(targets: &[(i32, i32); 3], touches: &[(i32, i32); 3]) -> Option<Self> {
+        for (target, touch) in targets.iter().zip(touches.iter()) {
+            if (target.0 - touch.0).abs() > MAX_OFFSET || (target.1 - touch.1).abs() > MAX_OFFSET { return None; }
+        }
+        let [(xd0, yd0), (xd1, yd1), (xd2, yd2)] = widen(targets);
+        let [(xt0, yt0), (xt1, yt1), (xt2, yt2)] = widen(touches);
+        let divider = (xt0 - xt2) * (yt1 - yt2) - (xt1 - xt2) * (yt0 - yt2);
+        if divider == 0 { return None; }
+        let a = (xd0 - xd2) * (yt1 - yt2) - (xd1 - xd2) * (yt0 - yt2);
+        let b = (xt0 - xt2) * (xd1 - xd2) - (xd0 - xd2) * (xt1 - xt2);
+        let c = yt0 * (xt2 * xd1 - xt1 * xd2) + yt1 * (xt0 * xd2 - xt2 * xd0) + yt2 * (xt1 * xd0 - xt0 * xd1);
+        let d = (yd0 - yd2) * (yt1 - yt2) - (yd1 - yd2) * (yt0 - yt2);
+        let e = (xt0 - xt2) * (yd1 - yd2) - (yd0 - yd2) * (xt1 - xt2);
+        let f = yt0 * (xt2 * yd1 - xt1 * yd2) + yt1 * (xt0 * yd2 - xt2 * yd0) + yt2 * (xt1 * yd0 - xt0 * yd1);
+        //  Coefficients must fit in 32 bits for storing
+        let narrow = |v: i64| if v >= i32::min_value() as i64 && v <= i32::max_value() as i64 { Some(v as i32) } else { None };
+        Some(Transform {
+            a: narrow(a) ? , b: narrow(b) ? , c: narrow(c) ? ,
+            d: narrow(d) ? , e: narrow(e) ? , f: narrow(f) ? ,
+            divider: narrow(divider) ? ,
+        })
+    }
+
+    /// Return the display coordinates for the touch coordinates, rounded to the nearest pixel
+    fn apply(&self, x: i32, y: i32) -> (i32, i32) {
+        let (x, y) = (x as i64, y as i64);
+        let (sign, divider) = (self.divider.signum() as i64, self.divider.abs() as i64);
+        let round = |n: i64| { let n = n * sign; ((2 * n + n.signum() * divider) / (2 * divider)) as i32 };
+        (
+            round(self.a as i64 * x + self.b as i64 * y + self.c as i64),
+            round(self.d as i64 * x + self.e as i64 * y + self.f as i64),
+        )
+    }
+
+    /// Return the transform as words for storing in flash
+    fn to_words(&self) -> [u32; 7] {
+        [
+            self.a as u32, self.b as u32, self.c as u32,
+            self.d as u32, self.e as u32, self.f as u32,
+            self.divider as u32,
+        ]
+    }
+
+    /// Return the transform for the words stored in flash. Returns `None` if the divider is 0.
+    fn from_words(words: &[u32]) -> Option<Self> {
+        let w = |i: usize| words[i] as i32;
+        if w(6) == 0 { return None; }
+        Some(Transform { a: w(0), b: w(1), c: w(2), d: w(3), e: w(4), f: w(5), divider: w(6) })
+    }
+}
+
+/// Return the points as 64-bit coordinates, to avoid overflow while computing the transform
+fn widen(points: &[(i32, i32); 3]) -> [(i64, i64); 3] {
+    let w = |i: usize| (points[i].0 as i64, points[i].1 as i64);
+    [w(0), w(1), w(2)]
+}
+
+/// Load the calibration from flash. Returns `None` if there is no valid calibration.
+fn load() -> Option<Transform> {
+    let mut bytes = [0; STORED_WORDS * 4];
+    FlashArea::open(CALIBRATION_AREA).ok() ?
+        .read(0, &mut bytes).ok() ? ;
+    let mut words = [0; STORED_WORDS];
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks(4)) {
+        *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    let (header, body) = words.split_at(2);
+    if header[0] != CALIBRATION_MAGIC || header[1] != checksum(body) { return None; }
+    Transform::from_words(body)
+}
+
+/// Erase the calibration area if it contains anything but erased flash, like the config FCB data left over from
+/// when the area was the last sector of `FLASH_AREA_NFFS`. Called when there is no valid calibration.
+fn format() -> MynewtResult<()> {
+    let area = FlashArea::open(CALIBRATION_AREA) ? ;
+    let mut buf = [0; 64];
+    for offset in (0..area.size()).step_by(buf.len()) {
+        area.read(offset, &mut buf) ? ;
+        if buf.iter().any(|&byte| byte != 0xff) {
+            warn!("erasing calibration area");
+            return area.erase();
+        }
+    }
+    Ok(())
+}
+
+/// Store the calibration in flash
+fn save(transform: &Transform) -> MynewtResult<()> {
+    let body = transform.to_words();
+    let mut words = [0; STORED_WORDS];
+    words[0] = CALIBRATION_MAGIC;
+    words[1] = checksum(&body);
+    words[2..].copy_from_slice(&body);
+    let mut bytes = [0; STORED_WORDS * 4];
+    for (chunk, word) in bytes.chunks_mut(4).zip(words.iter()) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    let area = FlashArea::open(CALIBRATION_AREA) ? ;
+    area.erase() ? ;
+    area.write(0, &bytes)
+}
+
+/// Return the checksum of the stored words. Erased flash (all ones) doesn't match.
+fn checksum(words: &[u32]) -> u32 {
+    !words.iter().fold(CALIBRATION_MAGIC, |sum, word| sum.rotate_left(5) ^ word)
+}
+
+/// Draw (or erase) the crosshair centred at the target
+fn draw_crosshair(target: (i32, i32), visible: bool) {
+    let (x, y) = target;
+    let arm = CROSSHAIR_ARM;
+    let rectangle = |left: i32, top: i32, right: i32, bottom: i32, color: Rgb565|
+        Rectangle::<Rgb565>
+            ::new( Coord::new( left, top ), Coord::new( right, bottom ) )
+            .fill( Some( color ) );
+    crash::while_drawing(|| {
+        //  Erase the crosshair
+        druid::draw_to_display(rectangle(x - arm, y - arm, x + arm, y + arm, BLACK));
+        if !visible { return; }
+        //  Draw the horizontal and vertical lines
+        druid::draw_to_display(rectangle(x - arm, y, x + arm, y, WHITE));
+        druid::draw_to_display(rectangle(x, y - arm, x, y + arm, WHITE));
+    });
+}
+
+/// Calibration procedure in progress
+struct Procedure {
+    /// Index of the target being shown
+    step:    usize,
+    /// Number of the attempt, from 1 to `MAX_ATTEMPTS`
+    attempt: u8,
+    /// Touch points recorded for the targets
+    touches: [(i32, i32); 3],
+    /// Last touch point of the current touch
+    last:    Option<(i32, i32)>,
+}
+
+/// Finger held on the screen, for starting the calibration procedure
+#[derive(Clone, Copy)]
+struct Hold {
+    /// Column where the finger was held, in touch controller coordinates
+    x:    u16,
+    /// Row where the finger was held, in touch controller coordinates
+    y:    u16,
+    /// True if the finger has been held still for `RECALIBRATE_MS`
+    held: bool,
+}
+
+/// Current calibration
+static mut TRANSFORM: Transform = Transform::IDENTITY;
+
+/// Current display rotation
+static mut ROTATION: Rotation = Rotation::Deg0;
+
+/// Calibration procedure, if running
+static mut PROCEDURE: Option<Procedure> = None;
+
+/// Cancels the calibration procedure when the user stops tapping
+static TIMEOUT_TIMER: Timer = Timer::new();
+
+/// Finger held on the screen, if the finger is touching the screen and not calibrating
+static mut HOLD: Option<Hold> = None;
+
+/// Fires when the finger has been held still for `RECALIBRATE_MS`
+static HOLD_TIMER: Timer = Timer::new();
+
+/// Centres of the crosshairs, in unrotated display coordinates. Not in a line, and away from the edges.
+const TARGETS: [(i32, i32); 3] = [(30, 30), (210, 120), (120, 210)];
+
+/// Length of each arm of the crosshair, in pixels
+const CROSSHAIR_ARM: i32 = 10;
+
+/// Max distance in pixels between a target and its touch point. Larger offsets mean the user missed the target.
+const MAX_OFFSET: i32 = 40;
+
+/// Max number of attempts before cancelling the calibration procedure
+const MAX_ATTEMPTS: u8 = 3;
+
+/// Number of seconds to wait for the user to tap the next crosshair, before cancelling the calibration procedure
+const TIMEOUT_SECS: u32 = 30;
+
+/// Number of milliseconds to hold the finger still for calibrating again. Longer than the holds used by the apps.
+const RECALIBRATE_MS: u32 = 10_000;
+
+/// Max movement in touch controller pixels for a finger that is held still
+const HOLD_SLOP: i32 = 10;
+
+/// True to calibrate on first boot if there is no stored calibration
+const CALIBRATE_ON_FIRST_BOOT: bool = cfg!(not(feature = "simulator"));
+
+/// Flash area for storing the calibration: `FLASH_AREA_CALIBRATION` (`user_id: 2`) in `hw/bsp/nrf52/bsp.yml`
+const CALIBRATION_AREA: u8 = FLASH_AREA_USER_ID_MIN + 2;
+
+/// Magic number that marks the stored calibration: "TCAL"
+const CALIBRATION_MAGIC: u32 = 0x4c41_4354;
+
+/// Number of words stored: Magic number, checksum and the transform
+const STORED_WORDS: usize = 9;
+
+/// Colours for the crosshair
+const WHITE: Rgb565 = Rgb565(0xffff);
+const BLACK: Rgb565 = Rgb565(0x0000);
@@ -1,4 +1,6 @@
 //  CHIP8 Emulator App. Need to edit apps/my_sensor_app/syscfg.yml and reduce the main stack size (OS_MAIN_STACK_SIZE) to 2048.
+//  The emulator renders to a `Sink`: `Device` renders to the PineTime display, the tests in `tests/chip8.rs`
+//  render headless to a mock display.
 use embedded_graphics::{
     prelude::*,
     pixelcolor::Rgb565,
@@ -8,32 +10,48 @@ use embedded_graphics::{
 };
 use mynewt::{
     result::*,
-    sys::console,
+    sys::console::{
+        self,
+        Level,
+    },
     kernel::os,
-    NULL, Ptr, Strn, fill_zero,
+    hal::watchdog::{ self, Watch },
+    Ptr,
+    debug, info,
 };
 use mynewt_macros::{
-    init_strn,
+    task,
+};
+use crate::{
+    calibration,
+    compositor::{ Compositor, Rect },
+    crash,
+    gesture::TouchEvent,
 };
 
+/// Log level for CHIP8 diagnostics. Set to `Level::Debug` to trace the pixels and keys. Slows down the emulator.
+const LOG_LEVEL: Level = Level::Info;
+
 /// CHIP8 Physical Screen size, in Physical Pixels
-const PHYSICAL_WIDTH: usize = 240;
-const PHYSICAL_HEIGHT: usize = 200;
+pub const PHYSICAL_WIDTH: usize = 240;
+pub const PHYSICAL_HEIGHT: usize = 200;
 
 /// CHIP8 Virtual Screen size, in Virtual Pixels
-const SCREEN_WIDTH: usize = 64;
-const SCREEN_HEIGHT: usize = 32;
+pub const SCREEN_WIDTH: usize = 64;
+pub const SCREEN_HEIGHT: usize = 32;
 
-/// CHIP8 Virtual Block size. We render the CHIP8 Virtual Screen in blocks of Virtual Pixels, without overflowing the SPI buffer.
-/// PendingDataSize in SPI is 8192. (BLOCK_WIDTH * PIXEL_WIDTH * BLOCK_HEIGHT * PIXEL_HEIGHT) * 2 must be less than PendingDataSize
-const BLOCK_HEIGHT: usize = 5;  //  Letter height
+/// Max number of Virtual Pixels per rendered block. The compositor splits the updated regions of the CHIP8 Virtual
+/// Screen into blocks, without overflowing the SPI buffer. PendingDataSize in SPI is 8192.
+/// (BLOCK_PIXELS * PIXEL_WIDTH * PIXEL_HEIGHT) * 2 must be less than PendingDataSize
 #[cfg(not(feature = "chip8_curve"))]  //  If we are not rendering CHIP8 Emulator as curved surface...
-const BLOCK_WIDTH: usize = 32;        //  Use normal width
+const BLOCK_PIXELS: usize = 160;      //  Use normal size
 #[cfg(feature = "chip8_curve")]       //  If we are rendering CHIP8 Emulator as curved surface...
-const BLOCK_WIDTH: usize = 5;        //  Use shorter width because curved regions have more pixels
+const BLOCK_PIXELS: usize = 25;       //  Use smaller size because curved regions have more pixels
 
 /// CHIP8 Virtual Pixel size, in Physical Pixels
+#[cfg_attr(feature = "chip8_curve", allow(dead_code))]  //  Curved surface maps the pixels with `map_virtual_to_physical()`
 const PIXEL_WIDTH: usize = 3;
+#[cfg_attr(feature = "chip8_curve", allow(dead_code))]
 const PIXEL_HEIGHT: usize = 5;
 
 /// Render some graphics and text to the PineTime display. `start_display()` must have been called earlier.
@@ -46,42 +64,37 @@ pub fn on_start() -> MynewtResult<()> {
         .fill( Some( Rgb565::from(( 0x00, 0x00, 0x00 )) ) );  //  Black
 
     //  Render background to display
-    druid::draw_to_display(background);
-    render_region(0, 0, SCREEN_WIDTH as u8 - 1, SCREEN_HEIGHT as u8 - 1);
+    crash::while_drawing(|| druid::draw_to_display(background));
+    let mut screen = Compositor::new();
+    screen.invalidate_all(SCREEN_WIDTH as u16, SCREEN_HEIGHT as u16);
+    render_regions(&mut Device, &mut screen);
 
     //  Start the emulator in a background task
-    os::task_init(                  //  Create a new task and start it...
-        unsafe { &mut CHIP8_TASK }, //  Task object will be saved here
-        &init_strn!( "chip8" ),     //  Name of task
-        Some( task_func ),    //  Function to execute when task starts
-        NULL,  //  Argument to be passed to above function
-        20,    //  Task priority: highest is 0, lowest is 255 (main task is 127), SPI is 10
-        os::OS_WAIT_FOREVER as u32,       //  Don't do sanity / watchdog checking
-        unsafe { &mut CHIP8_TASK_STACK }, //  Stack space for the task
-        CHIP8_TASK_STACK_SIZE as u16      //  Size of the stack (in 4-byte units)
-    ) ? ;                                 //  `?` means check for error
+    task!(                          //  Create a new task and start it...
+        name:     "chip8",          //  Name of task
+        func:     task_func,        //  Function to execute when task starts
+        priority: 20,               //  Task priority: highest is 0, lowest is 255 (main task is 127), SPI is 10
+        stack:    CHIP8_TASK_STACK_SIZE,  //  Size of the stack (in 4-byte units)
+    ) ? ;                           //  `?` means check for error
+
+    //  Supervise the emulator task, which must check in at every step
+    CHIP8_WATCH.register(CHIP8_TASK_DEADLINE) ? ;
 
     //  Return success to the caller
     Ok(())
 }
 
-/// CHIP8 Background Task
-static mut CHIP8_TASK: os::os_task = fill_zero!(os::os_task);
+/// Supervises the emulator task, which must check in within `CHIP8_TASK_DEADLINE` milliseconds
+static CHIP8_WATCH: Watch = Watch::new("chip8");
 
-/// Stack space for CHIP8 Task, initialised to 0.
-static mut CHIP8_TASK_STACK: [os::os_stack_t; CHIP8_TASK_STACK_SIZE] = 
-    [0; CHIP8_TASK_STACK_SIZE];
+/// Deadline for the emulator task to check in, in milliseconds
+const CHIP8_TASK_DEADLINE: u32 = 10_000;
 
 /// Size of the stack (in 4-byte units). Previously `OS_STACK_ALIGN(256)`  
 const CHIP8_TASK_STACK_SIZE: usize = 4096;  //  Must be 4096 and above because CHIP8 Emulator requires substantial stack space
 
 ///  Run the emulator
 extern "C" fn task_func(_arg: Ptr) {    
-    //  Create the hardware API for rendering the emulator
-    let hardware = Hardware::new();
-
-    //  Create the emulator
-    let chip8 = libchip8::Chip8::new(hardware);
     console::print("CHIP8 started\n"); console::flush();
 
     //  Load the emulator ROM
@@ -89,42 +102,107 @@ extern "C" fn task_func(_arg: Ptr) {
     //  let rom = include_bytes!("../roms/blinky.ch8");
     //  let rom = include_bytes!("../roms/pong.ch8");
 
-    //  Run the emulator ROM. This will block until emulator terminates
-    chip8.run(rom);
+    //  Run the emulator ROM on the PineTime display. This will block until emulator terminates
+    run(&mut Device, rom);
 
     //  Should not come here
     console::print("CHIP8 done\n"); console::flush();
     assert!(false, "CHIP8 should not end");
 }
 
-/// Hardware API for rendering CHIP8 Emulator
-struct Hardware {
-    /// Boundaries of the Virtual Screen region to be refreshed
-    update_left: u8,
-    update_top: u8,
-    update_right: u8,
-    update_bottom: u8,
+/// Run the emulator ROM and render to the sink. Blocks until the sink stops the emulator in `Sink::step()`.
+/// The Virtual Screen is cleared before running.
+pub fn run<S: Sink>(sink: &mut S, rom: &[u8]) {
+    //  Create the hardware API for rendering the emulator
+    let hardware = Hardware::new(sink);
+
+    //  Create the emulator and run the ROM
+    let chip8 = libchip8::Chip8::new(hardware);
+    chip8.run(rom);
+}
+
+/// Destination for the emulator's rendered regions, which also provides the clock and lets other tasks run.
+/// Implemented by `Device` for the PineTime display and by the mock display in `tests/chip8.rs`.
+pub trait Sink {
+    /// Render the Physical Pixels from (left, top) to (right, bottom) inclusive. `pixels` returns the 16-bit colour
+    /// of each Physical Pixel, row by row from the top left.
+    fn set_pixels(&mut self, left: u8, top: u8, right: u8, bottom: u8, pixels: &mut PixelIterator);
+
+    /// Return the current clock value in nanoseconds
+    fn clock(&mut self) -> u64;
+
+    /// Called in every emulator step. Return true to stop the emulator.
+    fn step(&mut self) -> bool;
+
+    /// Called in every emulator step after the emulator has become interactive, to tell the system that the
+    /// emulator is alive
+    fn tickle(&mut self) {}
+
+    /// Called before rendering an updated region, to let other tasks run
+    fn pause(&mut self) {}
+}
+
+/// Sink that renders to the PineTime display. The emulator task is supervised by `CHIP8_WATCH`.
+pub struct Device;
+
+impl Sink for Device {
+    /// Render the Physical Pixels to the display
+    fn set_pixels(&mut self, left: u8, top: u8, right: u8, bottom: u8, pixels: &mut PixelIterator) {
+        crash::while_drawing(|| druid::set_display_pixels(left as u16, top as u16, right as u16, bottom as u16,
+            pixels
+        )).expect("set pixels failed");
+    }
+
+    /// Return the Mynewt clock in nanoseconds
+    fn clock(&mut self) -> u64 {
+        unsafe { os::os_time_get() as u64 * 1000_u64 * 2000_u64 }
+    }
+
+    /// Tell the supervisor that the emulator is alive
+    fn step(&mut self) -> bool {
+        CHIP8_WATCH.checkin().expect("watch fail");
+        false
+    }
+
+    /// Tickle the watchdog so that the Watchdog Timer doesn't expire. Mynewt assumes the process is hung if we don't tickle the watchdog.
+    fn tickle(&mut self) {
+        watchdog::tickle();
+    }
+
+    /// Sleep a while to allow other tasks to run, e.g. SPI background task
+    fn pause(&mut self) {
+        unsafe { os::os_time_delay(1) };
+    }
+}
+
+/// Hardware API for rendering CHIP8 Emulator to the sink
+pub struct Hardware<'a, S: Sink> {
+    /// Destination for the rendered regions
+    sink: &'a mut S,
+    /// Region of the Virtual Screen to be refreshed: the bounding box of the changes, so that the faded Virtual Pixels
+    /// around the changes are refreshed too
+    dirty: Compositor,
     /// True if emulator has started accepting input, i.e. emulator has drawn loading screen
     is_interactive: bool,
     /// True if emulator is checking input, i.e. emulator has updated a sprite
     is_checking_input: bool,
 }
 
-impl Hardware {
-    /// Return a new Hardware API for rendering CHIP8 Emulator
-    pub fn new() -> Hardware {
+impl<'a, S: Sink> Hardware<'a, S> {
+    /// Return a new Hardware API for rendering CHIP8 Emulator to the sink. Clears the Virtual Screen and the key pressed.
+    pub fn new(sink: &'a mut S) -> Self {
+        unsafe { SCREEN_BUFFER = [0; SCREEN_WIDTH * SCREEN_HEIGHT] };
+        unsafe { KEY_PRESSED = None };
         Hardware {
-            update_left: 0,
-            update_top: 0,
-            update_right: 0,
-            update_bottom: 0,
+            sink,
+            dirty: Compositor::with_max_rects(1),
             is_interactive: false,
             is_checking_input: false,
         }
     }
 }
 
-impl libchip8::Hardware for Hardware {
+impl<'a, S: Sink> libchip8::Hardware for Hardware<'a, S> {
     /// Return a random value.
     fn rand(&mut self) -> u8 {
         123  //  TODO
@@ -135,7 +213,7 @@ impl libchip8::Hardware for Hardware {
         //  key is 0-9 for keys "0" to "9", 0xa-0xf to keys "A" to "F"
         if !self.is_interactive {
             self.is_interactive = true;
-            console::print("key\n"); console::flush(); ////
+            info!("key");
         }
         self.is_checking_input = true;
         //  Compare the key with the last touch event
@@ -148,7 +226,7 @@ impl libchip8::Hardware for Hardware {
 
     /// Set the state of a pixel in the screen. true for white, and false for black.
     fn vram_set(&mut self, x: usize, y: usize, d: bool) {
-        //  console::print("set "); console::printint(x as i32); console::print(", "); console::printint(y as i32); console::print("\n"); console::flush(); ////
+        debug!("set {}, {}", x, y);
         assert!(x < SCREEN_WIDTH, "x overflow");
         assert!(y < SCREEN_HEIGHT, "y overflow");
         let i = x + y * SCREEN_WIDTH;
@@ -163,23 +241,13 @@ impl libchip8::Hardware for Hardware {
             }  
         };
 
-        //  Remember the boundaries of the screen region to be updated
-        if self.update_left == 0 && self.update_right == 0 &&
-            self.update_top == 0 && self.update_bottom == 0 {
-            self.update_left = x as u8;
-            self.update_right = x as u8;
-            self.update_top = y as u8;
-            self.update_bottom = y as u8;
-        }
-        if (x as u8) < self.update_left { self.update_left = x as u8; }
-        if (x as u8) > self.update_right { self.update_right = x as u8; }
-        if (y as u8) < self.update_top { self.update_top = y as u8; }
-        if (y as u8) > self.update_bottom { self.update_bottom = y as u8; }
+        //  Remember the screen region to be updated
+        self.dirty.invalidate(Rect::new(x as u16, y as u16, x as u16, y as u16));
     }
 
     /// Get the current state of a pixel in the screen.
     fn vram_get(&mut self, x: usize, y: usize) -> bool {
-        //  console::print("get "); console::printint(x as i32); console::print(", "); console::printint(y as i32); console::print("\n"); console::flush(); ////
+        debug!("get {}, {}", x, y);
         assert!(x < SCREEN_WIDTH, "x overflow");
         assert!(y < SCREEN_HEIGHT, "y overflow");
         let i = x + y * SCREEN_WIDTH;
@@ -190,7 +258,7 @@ impl libchip8::Hardware for Hardware {
     fn vram_setsize(&mut self, size: (usize, usize)) {
         //  Do nothing. We assume SCREEN_WIDTH and SCREEN_HEIGHT.
         assert!(size.0 == SCREEN_WIDTH && size.1 == SCREEN_HEIGHT, "bad size");
-        console::print("setsize "); console::printint(size.0 as i32); console::print(", "); console::printint(size.1 as i32); console::print("\n"); console::flush(); ////
+        info!("setsize {}, {}", size.0, size.1);
     }
 
     /// Get the size of the screen.
@@ -200,7 +268,7 @@ impl libchip8::Hardware for Hardware {
 
     /// Return the current clock value in nanoseconds.
     fn clock(&mut self) -> u64 {
-        unsafe { os::os_time_get() as u64 * 1000_u64 * 2000_u64 }
+        self.sink.clock()
     }
 
     /// Play beep sound.
@@ -210,82 +278,46 @@ impl libchip8::Hardware for Hardware {
 
     /// Called in every step; return true for shutdown.
     fn sched(&mut self) -> bool {
-        //  console::print("sched\n"); console::flush(); ////
+        debug!("sched");
+
+        //  Tell the sink that the emulator is alive. Stop if the sink says so.
+        if self.sink.step() { return true; }
 
         //  If no screen update, return
-        if self.update_left == 0 && self.update_right == 0 &&
-            self.update_top == 0 && self.update_bottom == 0 { return false; }
+        if !self.dirty.is_dirty() { return false; }
 
         //  If emulator is preparing the initial screen, refresh the screen later
         if !self.is_interactive { return false; }
 
-        //  Tickle the watchdog so that the Watchdog Timer doesn't expire. Mynewt assumes the process is hung if we don't tickle the watchdog.
-        unsafe { hal_watchdog_tickle() };
+        //  Tell the system that the emulator is alive, e.g. tickle the watchdog
+        self.sink.tickle();
 
         //  If emulator is not ready to accept input, refresh the screen later
         if !self.is_checking_input { return false; }
         self.is_checking_input = false;
 
-        //  Sleep a while to allow other tasks to run, e.g. SPI background task
-        unsafe { os::os_time_delay(1) };
-
-        //  Render the updated region
-        render_region(
-            self.update_left,
-            self.update_top,
-            self.update_right,
-            self.update_bottom
-        );
+        //  Allow other tasks to run, e.g. SPI background task
+        self.sink.pause();
 
-        //  Reset the screen region to be updated
-        self.update_left = 0;
-        self.update_top = 0;
-        self.update_right = 0;
-        self.update_bottom = 0;
+        //  Render the updated regions
+        render_regions(self.sink, &mut self.dirty);
 
         //  Return false to indicate no shutdown
         false
     }
 }
 
-/// Render the Virtual Screen region
-fn render_region(left: u8, top: u8, right: u8, bottom: u8) {
-    //  Get the physical bounding box width and height
-    let physical_box    = get_bounding_box(left, top, right, bottom);  //  Returns (left,top,right,bottom)
-    let physical_width  = (physical_box.2 - physical_box.0 + 1) as usize;
-    let physical_height = (physical_box.3 - physical_box.1 + 1) as usize;
-    //  If the update region is small, render with a single block
-    if physical_width + physical_height <= (BLOCK_WIDTH * PIXEL_WIDTH) + (BLOCK_HEIGHT * PIXEL_HEIGHT) {  //  Will not overflow SPI buffer
-        render_block(left, top, right, bottom);
-    } else {
-        //  If the update region is too big for a single block, break the region into blocks and render
-        let mut x = left;
-        let mut y = top;
-        loop {
-            let block_right  = (x + BLOCK_WIDTH as u8 - 1).min(right);
-            let block_bottom = (y + BLOCK_HEIGHT as u8 - 1).min(bottom);
-
-            let physical_box     = get_bounding_box(left, top, right, bottom);  //  Returns (left,top,right,bottom)
-            let _physical_width  = (physical_box.2 - physical_box.0 + 1) as usize;
-            let _physical_height = (physical_box.3 - physical_box.1 + 1) as usize;
-            //  assert!(physical_width + physical_height <= (BLOCK_WIDTH * PIXEL_WIDTH) + (BLOCK_HEIGHT * PIXEL_HEIGHT), "region overflow");
-            render_block(x, y,
-                block_right,
-                block_bottom
-            );  //  Will not overflow SPI buffer
-            x += BLOCK_WIDTH as u8;
-            if x > right {
-                x = left;
-                y += BLOCK_HEIGHT as u8;
-                if y > bottom { break; }
-            }
-        }
-    }
+/// Render the updated Virtual Screen regions to the sink, in blocks that fit the SPI buffer
+fn render_regions<S: Sink>(sink: &mut S, dirty: &mut Compositor) {
+    dirty.flush_chunks(SCREEN_WIDTH as u16, SCREEN_HEIGHT as u16, BLOCK_PIXELS, |block| {
+        render_block(sink, block.left as u8, block.top as u8, block.right as u8, block.bottom as u8);
+        Ok(())
+    }).expect("render fail");
 }
 
-/// Render the Virtual Block
-fn render_block(left: u8, top: u8, right: u8, bottom: u8) {
-    //  console::print("render "); console::printint(left as i32); console::print(", "); console::printint(top as i32); console::print(", "); console::printint(right as i32 - left as i32); console::print(", "); console::printint(bottom as i32 - top as i32); console::print("\n"); console::flush(); ////
+/// Render the Virtual Block to the sink
+fn render_block<S: Sink>(sink: &mut S, left: u8, top: u8, right: u8, bottom: u8) {
+    debug!("render {}, {}, {}, {}", left, top, right as i32 - left as i32, bottom as i32 - top as i32);
     //  Create a new block for the region to be updated
     let mut block = PixelIterator::new(
         left, top, 
@@ -293,9 +325,7 @@ fn render_block(left: u8, top: u8, right: u8, bottom: u8) {
     );
     //  Render the block
     let (left_physical, top_physical, right_physical, bottom_physical) = block.get_window();
-    druid::set_display_pixels(left_physical as u16, top_physical as u16, right_physical as u16, bottom_physical as u16,
-        &mut block
-    ).expect("set pixels failed");    
+    sink.set_pixels(left_physical, top_physical, right_physical, bottom_physical, &mut block);
 }
 
 /// CHIP8 Virtual Screen Buffer, 8-bit greyscale (from black=0 to white=255) per Virtual Pixel.
@@ -489,13 +519,18 @@ fn update_color(grey: u8) -> u8 {
     }
 }
 
-/// Handle touch events to emulate buttons
-pub fn handle_touch(x: u16, _y: u16) { 
+/// Handle touch events to emulate buttons. Touching, moving and holding the finger press the key under the finger.
+pub fn handle_touch(event: TouchEvent) {
+    let x = match event {
+        TouchEvent::Down { x, .. } | TouchEvent::Drag { x, .. } | TouchEvent::LongPress { x, .. } => x,
+        _ => return,
+    };
     //  We only handle 3 keys: 4, 5, 6, which correspond to Left, Centre, Right
-    //  console::print("CHIP8 touch\n"); console::flush(); 
+    debug!("CHIP8 touch");
+    let (width, _) = calibration::screen_size();
     let key = 
-        if x < PHYSICAL_WIDTH as u16 / 3 { Some(4) }
-        else if x < 2 * PHYSICAL_WIDTH as u16 / 3 { Some(5) }
+        if x < width / 3 { Some(4) }
+        else if x < 2 * width / 3 { Some(5) }
         else { Some(6) };
     unsafe { KEY_PRESSED = key };
 }
@@ -503,12 +538,6 @@ pub fn handle_touch(x: u16, _y: u16) {
 /// Represents the key pressed: 0-9 for keys "0" to "9", 0xa-0xf to keys "A" to "F", None for nothing pressed
 static mut KEY_PRESSED: Option<u8> = None;
 
-//  TODO: Move this to Mynewt library
-extern "C" { 
-    /// Tickles the watchdog so that the Watchdog Timer doesn't expire. This needs to be done periodically, before the value configured in hal_watchdog_init() expires.
-    fn hal_watchdog_tickle(); 
-}
-
 /// Return Bounding Box of Physical Pixels (left, top, right, bottom) that correspond to the Virtual Pixels
 #[cfg(not(feature = "chip8_curve"))]  //  If we are not rendering CHIP8 Emulator as curved surface...
 fn get_bounding_box(virtual_left: u8, virtual_top: u8, virtual_right: u8, virtual_bottom: u8) -> (u8, u8, u8, u8) {
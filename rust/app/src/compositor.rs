@@ -0,0 +1,281 @@
+//! Dirty-rectangle compositor for display apps. Apps mark the regions of the screen that have changed with
+//! `invalidate()`. Overlapping and adjacent regions are merged. `flush()` asks the app for the pixels of each
+//! region and writes them to the display, in chunks that fit the SPI buffer:
+//! ```
+//! static mut COMPOSITOR: Compositor = Compositor::new();
+//! ...
+//! COMPOSITOR.invalidate(Rect::new(10, 20, 49, 59));  //  Region from (10, 20) to (49, 59) has changed
+//! ...
+//! COMPOSITOR.flush(&mut DISPLAY, |chunk|             //  For each chunk of the changed regions...
+//!     chunk.points().map(|(x, y)| color_at(x, y))    //  Return the 16-bit colour of each pixel
+//! ) ? ;
+//! ```
+//! The pixels are returned by an iterator like `PixelIterator` in `chip8.rs`, row by row from the top left of the chunk.
+//! Apps that scale their screen, like the CHIP8 Emulator, track the dirty regions in their own coordinates and render
+//! the chunks themselves with `flush_chunks()`.
+use mynewt::result::*;
+use crate::st7789::ST7789;
+
+/// Rectangle of pixels from (left, top) to (right, bottom) inclusive
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    /// Left column
+    pub left:   u16,
+    /// Top row
+    pub top:    u16,
+    /// Right column, inclusive
+    pub right:  u16,
+    /// Bottom row, inclusive
+    pub bottom: u16,
+}
+
+impl Rect {
+    /// Create a rectangle from (left, top) to (right, bottom) inclusive. The corners are swapped if necessary.
+    pub fn new(left: u16, top: u16, right: u16, bottom: u16) -> Self {
+        Rect {
+            left:   left.min(right),
+            top:    top.min(bottom),
+            right:  left.max(right),
+            bottom: top.max(bottom),
+        }
+    }
+
+    /// Return the number of columns
+    pub fn width(&self) -> u16 {
+        self.right - self.left + 1
+    }
+
+    /// Return the number of rows
+    pub fn height(&self) -> u16 {
+        self.bottom - self.top + 1
+    }
+
+    /// Return the number of pixels
+    pub fn area(&self) -> u32 {
+        self.width() as u32 * self.height() as u32
+    }
+
+    /// Return true if the rectangles overlap or are next to each other
+    pub fn touches(&self, other: &Rect) -> bool {
+        self.left   as u32 <= other.right  as u32 + 1 &&
+        other.left  as u32 <= self.right   as u32 + 1 &&
+        self.top    as u32 <= other.bottom as u32 + 1 &&
+        other.top   as u32 <= self.bottom  as u32 + 1
+    }
+
+    /// Return the smallest rectangle that contains both rectangles
+    pub fn union(&self, other: &Rect) -> Rect {
+        Rect {
+            left:   self.left.min(other.left),
+            top:    self.top.min(other.top),
+            right:  self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+
+    /// Return the part of the rectangle inside a screen of size (width, height), or `None` if outside
+    pub fn clip(&self, width: u16, height: u16) -> Option<Rect> {
+        if width == 0 || height == 0 || self.left >= width || self.top >= height { return None; }
+        Some(Rect {
+            left:   self.left,
+            top:    self.top,
+            right:  self.right.min(width - 1),
+            bottom: self.bottom.min(height - 1),
+        })
+    }
+
+    /// Return an iterator of the (x, y) coordinates of the pixels, row by row from the top left
+    pub fn points(&self) -> RectPoints {
+        RectPoints { rect: *self, x: self.left as u32, y: self.top as u32 }
+    }
+
+    /// Return an iterator of the chunks of the rectangle, each with at most `max_pixels` pixels.
+    /// Chunks are whole rows if possible, otherwise parts of a row.
+    pub fn chunks(&self, max_pixels: usize) -> RectChunks {
+        RectChunks { rect: *self, max_pixels: max_pixels.max(1) as u32, x: self.left as u32, y: self.top as u32 }
+    }
+}
+
+/// Iterator of the (x, y) coordinates of the pixels in a rectangle, returned by `Rect::points()`
+pub struct RectPoints {
+    /// Rectangle to be iterated
+    rect: Rect,
+    /// Column of the next pixel
+    x:    u32,
+    /// Row of the next pixel
+    y:    u32,
+}
+
+impl Iterator for RectPoints {
+    /// This Iterator returns (x, y) coordinates
+    type Item = (u16, u16);
+
+    /// Return the coordinates of the next pixel
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.y > self.rect.bottom as u32 { return None; }
+        let point = (self.x as u16, self.y as u16);
+        self.x += 1;
+        if self.x > self.rect.right as u32 {
+            self.x = self.rect.left as u32;
+            self.y += 1;
+        }
+        Some(point)
+    }
+}
+
+/// Iterator of the chunks of a rectangle, returned by `Rect::chunks()`
+pub struct RectChunks {
+    /// Rectangle to be split
+    rect:       Rect,
+    /// Max number of pixels per chunk
+    max_pixels: u32,
+    /// Left column of the next chunk
+    x:          u32,
+    /// Top row of the next chunk
+    y:          u32,
+}
+
+impl Iterator for RectChunks {
+    /// This Iterator returns rectangles
+    type Item = Rect;
+
+    /// Return the next chunk
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.y > self.rect.bottom as u32 { return None; }
+        let width = self.rect.width() as u32;
+        let (right, bottom) =
+            if width <= self.max_pixels {
+                //  Chunk contains whole rows
+                let rows = self.max_pixels / width;
+                (self.rect.right as u32, (self.y + rows - 1).min(self.rect.bottom as u32))
+            } else {
+                //  Chunk contains part of a row
+                ((self.x + self.max_pixels - 1).min(self.rect.right as u32), self.y)
+            };
+        let chunk = Rect { left: self.x as u16, top: self.y as u16, right: right as u16, bottom: bottom as u16 };
+        //  Move to the next chunk
+        if right < self.rect.right as u32 { self.x = right + 1; }
+        else {
+            self.x = self.rect.left as u32;
+            self.y = bottom + 1;
+        }
+        Some(chunk)
+    }
+}
+
+/// Collects the dirty rectangles of the screen and writes them to the display
+pub struct Compositor {
+    /// Dirty rectangles. Only the first `count` rectangles are used.
+    dirty: [Rect; MAX_DIRTY_RECTS],
+    /// Number of dirty rectangles
+    count: usize,
+    /// Max number of dirty rectangles before merging, up to `MAX_DIRTY_RECTS`
+    max_rects: usize,
+}
+
+impl Compositor {
+    /// Create a compositor with no dirty rectangles.
+    pub const fn new() -> Self {
+        Compositor {
+            dirty: [Rect { left: 0, top: 0, right: 0, bottom: 0 }; MAX_DIRTY_RECTS],
+            count: 0,
+            max_rects: MAX_DIRTY_RECTS,
+        }
+    }
+
+    /// Create a compositor that keeps at most `max_rects` dirty rectangles (1 to `MAX_DIRTY_RECTS`). With 1, every
+    /// change is merged into the bounding box of the changes.
+    pub fn with_max_rects(max_rects: usize) -> Self {
+        Compositor { max_rects: max_rects.max(1).min(MAX_DIRTY_RECTS), ..Compositor::new() }
+    }
+
+    /// Mark the rectangle as changed, so that it will be written by the next `flush()`. The rectangle is merged
+    /// with the dirty rectangles that overlap or touch it. If there are too many dirty rectangles, the rectangle
+    /// is merged with the dirty rectangle that adds the fewest pixels.
+    pub fn invalidate(&mut self, rect: Rect) {
+        let mut rect = rect;
+        loop {
+            //  Merge with the dirty rectangles that touch the rectangle. Check again after merging,
+            //  since the merged rectangle may touch other dirty rectangles.
+            let mut i = 0;
+            while i < self.count {
+                if self.dirty[i].touches(&rect) {
+                    rect = rect.union(&self.remove(i));
+                    i = 0;
+                } else {
+                    i += 1;
+                }
+            }
+            if self.count < self.max_rects {
+                self.dirty[self.count] = rect;
+                self.count += 1;
+                return;
+            }
+            //  No space left, merge with the dirty rectangle that adds the fewest pixels
+            let mut best = 0;
+            let mut best_cost = u32::max_value();
+            for i in 0..self.count {
+                let cost = self.dirty[i].union(&rect).area() - self.dirty[i].area();
+                if cost < best_cost { best = i; best_cost = cost; }
+            }
+            rect = rect.union(&self.remove(best));
+        }
+    }
+
+    /// Mark the entire screen of size (width, height) as changed
+    pub fn invalidate_all(&mut self, width: u16, height: u16) {
+        if width == 0 || height == 0 { return; }
+        self.count = 0;
+        self.invalidate(Rect::new(0, 0, width - 1, height - 1));
+    }
+
+    /// Return true if there are dirty rectangles to be written
+    pub fn is_dirty(&self) -> bool {
+        self.count > 0
+    }
+
+    /// Return the dirty rectangles
+    pub fn dirty_rects(&self) -> &[Rect] {
+        &self.dirty[..self.count]
+    }
+
+    /// Write the dirty rectangles to the display. Each rectangle is split into chunks that fit the SPI buffer.
+    /// For each chunk, `render` is called to return the 16-bit colours of the pixels in the chunk, row by row from
+    /// the top left. If writing fails, the unwritten rectangles remain dirty.
+    pub fn flush<F, I>(&mut self, display: &mut ST7789, mut render: F) -> MynewtResult<()>
+    where F: FnMut(Rect) -> I, I: IntoIterator<Item = u16> {
+        let (width, height) = display.size();
+        let max_pixels = display.buffer_pixels();
+        self.flush_chunks(width, height, max_pixels, |chunk|
+            display.set_pixels(chunk.left, chunk.top, chunk.right, chunk.bottom, render(chunk))
+        )
+    }
+
+    /// Clip the dirty rectangles to a screen of size (width, height) and split them into chunks of at most
+    /// `max_pixels` pixels. `write` is called for each chunk, then the rectangles are no longer dirty.
+    /// If `write` fails, the unwritten rectangles remain dirty.
+    pub fn flush_chunks<F>(&mut self, width: u16, height: u16, max_pixels: usize, mut write: F) -> MynewtResult<()>
+    where F: FnMut(Rect) -> MynewtResult<()> {
+        while self.count > 0 {
+            let rect = self.dirty[self.count - 1];
+            if let Some(rect) = rect.clip(width, height) {
+                for chunk in rect.chunks(max_pixels) {
+                    write(chunk) ? ;
+                }
+            }
+            self.count -= 1;
+        }
+        Ok(())
+    }
+
+    /// Remove and return the dirty rectangle at the index
+    fn remove(&mut self, index: usize) -> Rect {
+        let rect = self.dirty[index];
+        self.count -= 1;
+        self.dirty[index] = self.dirty[self.count];
+        rect
+    }
+}
+
+/// Max number of dirty rectangles. More rectangles are merged.
+const MAX_DIRTY_RECTS: usize = 8;
@@ -0,0 +1,156 @@
+//!  Panic handling for PineTime. On panic we record the crash in retained RAM, show a crash screen and reboot
+//!  via the Watchdog Timer, so that the watch doesn't freeze when no debugger is connected.
+//!  The crash is reported on the next boot to the console (by `report_last_crash()`) and to the CoAP server
+//!  (by `app_network::send_crash_report()`).
+use core::{
+    fmt::Write,
+    panic::PanicInfo,
+    sync::atomic::{ AtomicBool, AtomicUsize, Ordering },
+};
+use cortex_m::asm::{ bkpt, delay };
+use embedded_graphics::{
+    prelude::*,
+    fonts,
+    pixelcolor::Rgb565,
+    primitives::Rectangle,
+};
+use mynewt::{
+    hal,
+    kernel::os,
+    sys::{
+        console::{ self, Level },
+        crash,
+    },
+    error, print, println,
+};
+
+/// Log level for this module
+const LOG_LEVEL: Level = Level::Info;
+
+/// Number of CPU cycles to show the crash screen before rebooting: 3 seconds at 64 MHz
+const CRASH_SCREEN_CYCLES: u32 = 3 * 64_000_000;
+
+/// Number of CPU cycles to wait for the Watchdog Timer to reboot, before we reboot ourselves: 40 seconds at 64 MHz.
+/// Must be longer than `WATCHDOG_INTERVAL` in `syscfg.yml`
+const WATCHDOG_WAIT_CYCLES: u32 = 40 * 64_000_000;
+
+/// Max number of characters per line of the crash screen, for the 12x16 font on a 240x240 display
+const CRASH_SCREEN_COLUMNS: usize = 20;
+
+/// Set when a panic is being handled, so that we don't handle a panic inside the panic handler
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Number of tasks that are drawing to the display with druid, counted by `while_drawing()`
+static DRAWING: AtomicUsize = AtomicUsize::new(0);
+
+///  Handle a panic: Record the crash, show the crash screen and reboot. Called by the panic handler in `lib.rs`.
+pub fn handle_panic(info: &PanicInfo) -> ! {
+    //  If we panic again while handling the panic, reboot immediately
+    if PANICKING.swap(true, Ordering::SeqCst) { reboot(); }
+
+    //  Record the crash in retained RAM
+    let (file, line, column) = match info.location() {
+        Some(location) => (location.file(), location.line(), location.column()),
+        None           => ("", 0, 0),
+    };
+    crash::record(file, line, column, info.message());
+
+    //  Display the crash on the Semihosting Console, which is also written to the RAM Log
+    if let Some(crash) = crash::last_crash() {
+        error!("panic at {} line {}: {}", crash.file(), crash.line, crash.message());
+    }
+
+    //  Pause in the debugger, only if a debugger is connected. Without a debugger, `bkpt()` would lock up the watch.
+    if hal::debugger_connected() { bkpt(); }
+
+    //  Show the crash screen and wait a while for the screen to be rendered. If we panicked while drawing,
+    //  the display driver may be in the middle of a transfer, so don't draw again.
+    if DRAWING.load(Ordering::SeqCst) == 0 {
+        show_crash_screen();
+        delay(CRASH_SCREEN_CYCLES);
+    }
+
+    //  Reboot via the Watchdog Timer
+    reboot()
+}
+
+///  Draw to the display with druid. If we panic while drawing, the crash screen won't be shown:
+///  ```
+///  crash::while_drawing(|| druid::draw_to_display(circle));
+///  ```
+pub fn while_drawing<R>(draw: impl FnOnce() -> R) -> R {
+    DRAWING.fetch_add(1, Ordering::SeqCst);
+    let res = draw();
+    DRAWING.fetch_sub(1, Ordering::SeqCst);
+    res
+}
+
+///  Display the last crash on the console, if any. Called at startup.
+pub fn report_last_crash() {
+    crash::format_hung_task();  //  Hung tasks are recorded without the message
+    if let Some(crash) = crash::last_crash() {
+        println!(
+            "last crash at {} line {} col {}, boot {}, uptime {}: {}",
+            crash.file(), crash.line, crash.column, crash.boot_count, crash.uptime, crash.message()
+        );
+        print!("stack at {:08x}:", crash.stack_pointer);
+        for word in crash.stack.iter() {
+            print!(" {:08x}", word);
+        }
+        println!();
+    }
+}
+
+///  Render the crash details to the display: White text on red background
+fn show_crash_screen() {
+    let crash = match crash::last_crash() {
+        Some(crash) => crash,
+        None        => return,
+    };
+    //  Create red background
+    let background = Rectangle::<Rgb565>
+        ::new( Coord::new( 0, 0 ), Coord::new( 239, 239 ) )   //  Rectangle coordinates
+        .fill( Some( Rgb565::from(( 0xff, 0x00, 0x00 )) ) );  //  Red
+    druid::draw_to_display(background);
+
+    //  Show the file name without the path, and the line number
+    let file = crash.file().rsplit('/').next().unwrap_or("");
+    let mut location = heapless::String::<heapless::consts::U32>::new();
+    write!(&mut location, "line {}", crash.line).ok();  //  Truncated if too long
+    draw_line(0, "PANIC");
+    draw_line(2, file);
+    draw_line(3, &location);
+
+    //  Show the panic message, wrapped over the remaining lines
+    let message = crash.message();
+    let mut row = 5;
+    let mut start = 0;
+    while start < message.len() && row < 15 {
+        let mut end = core::cmp::min(start + CRASH_SCREEN_COLUMNS, message.len());
+        while !message.is_char_boundary(end) { end -= 1; }
+        draw_line(row, &message[start..end]);
+        start = end;
+        row += 1;
+    }
+}
+
+///  Render a line of text at the row, truncated to the width of the display
+fn draw_line(row: i32, text: &str) {
+    let mut end = core::cmp::min(text.len(), CRASH_SCREEN_COLUMNS);
+    while !text.is_char_boundary(end) { end -= 1; }
+    let text = fonts::Font12x16::<Rgb565>
+        ::render_str(&text[..end])                             //  Text to be rendered
+        .stroke( Some( Rgb565::from(( 0xff, 0xff, 0xff )) ) )  //  White text
+        .fill(   Some( Rgb565::from(( 0xff, 0x00, 0x00 )) ) )  //  Red background
+        .translate( Coord::new( 0, row * 16 ));                //  Shift the text to the row
+    druid::draw_to_display(text);
+}
+
+///  Disable interrupts and stop tickling the Watchdog Timer, so that the watchdog reboots the watch.
+///  If the watchdog has not been enabled, reboot ourselves.
+fn reboot() -> ! {
+    console::flush();
+    unsafe { os::os_arch_save_sr() };  //  Disable interrupts so that no task will tickle the watchdog
+    delay(WATCHDOG_WAIT_CYCLES);
+    hal::system_reset()
+}
@@ -10,12 +10,24 @@ use embedded_graphics::{
 };
 use mynewt::{
     result::*,
+    spi,
     sys::console,
 };
+use crate::{
+    compositor::{ Compositor, Rect },
+    st7789::{ Rotation, ST7789, LCD_RESET_PIN, WIDTH },
+};
+
+/// PineTime display, drawn with the Rust ST7789 driver instead of the druid display driver
+static mut DISPLAY: ST7789 = ST7789::new(&spi::DISPLAY, LCD_RESET_PIN);
 
-/// Render some graphics and text to the PineTime display. `start_display()` must have been called earlier.
+/// Render some graphics and text to the PineTime display with the Rust ST7789 driver, then draw a colour bar
+/// through the compositor.
 pub fn test_display() -> MynewtResult<()> {
     console::print("Rust test display\n"); console::flush();
+    let display = unsafe { &mut DISPLAY };
+    spi::spi_noblock_init() ? ;        //  Start the non-blocking SPI engine, if druid hasn't started it
+    display.init(Rotation::Deg0) ? ;   //  Reset and configure the display
     
     //  Create black background
     let background = Rectangle::<Rgb565>
@@ -40,11 +52,22 @@ pub fn test_display() -> MynewtResult<()> {
         .translate( Coord::new( 20, 16 ));                     //  Shift the text
 
     //  Render background, circle, square and text to display
-    druid::draw_to_display(background);
-    druid::draw_to_display(circle);
-    druid::draw_to_display(square);
-    druid::draw_to_display(text);    
+    display.draw(background);
+    display.draw(circle);
+    display.draw(square);
+    display.draw(text);
+    display.take_error() ? ;
+
+    //  Draw the colour bar at the bottom. The compositor writes the region in chunks that fit the SPI buffer.
+    let mut compositor = Compositor::new();
+    compositor.invalidate(Rect::new(0, 200, WIDTH - 1, 219));
+    compositor.flush(display, |chunk|
+        chunk.points().map(|(x, _)| color_bar(x))
+    )
+}
 
-    //  Return success to the caller
-    Ok(())
+/// Return the 16-bit colour of the colour bar at column `x`: blue on the left, fading to red on the right
+fn color_bar(x: u16) -> u16 {
+    let red = (x as u32 * 0x1f / (WIDTH as u32 - 1)) as u16;
+    (red << 11) | (0x1f - red)
 }
\ No newline at end of file
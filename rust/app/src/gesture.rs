@@ -0,0 +1,209 @@
+//! Touch Gesture Recognition. Converts the touch points reported by the touch controller (`touch_sensor.rs`) into
+//! Touch Events for the apps: tap, double tap, long press, swipe and drag. The apps receive the Touch Events in
+//! `handle_touch()`:
+//! ```
+//! pub fn handle_touch(event: TouchEvent) {
+//!     match event {
+//!         TouchEvent::Tap { x, y, .. }                      => { /* Press the button at (x, y) */ }
+//!         TouchEvent::Drag { dy, .. }                       => { /* Scroll the list by dy */ }
+//!         TouchEvent::Swipe { direction: Swipe::Left, .. }  => { /* Show the next screen */ }
+//!         _ => {}
+//!     }
+//! }
+//! ```
+//! A double tap is reported as `Tap` followed by `DoubleTap`, so that single taps are not delayed.
+//! Touch points and the long press timer are handled in the Default Event Queue.
+use mynewt::{
+    result::*,
+    kernel::{
+        self,
+        os::{ self, os_time_t },
+        timer::{ Timer, TimerMode },
+    },
+    sys::console::Level,
+    debug,
+};
+use crate::touch_sensor::Action;
+
+/// Log level for gesture diagnostics. Set to `Level::Debug` to display the Touch Events.
+const LOG_LEVEL: Level = Level::Info;
+
+/// Touch Event passed to the apps. `time` is the Mynewt time (`os_time_get()`) when the event was recognised.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TouchEvent {
+    /// Finger touched the screen at (x, y)
+    Down { x: u16, y: u16, time: os_time_t },
+    /// Finger touched and released the screen at (x, y) without moving
+    Tap { x: u16, y: u16, time: os_time_t },
+    /// Second tap near the previous tap, soon after the previous tap
+    DoubleTap { x: u16, y: u16, time: os_time_t },
+    /// Finger touched the screen at (x, y) without moving for `LONG_PRESS_MS`. No tap will be reported when released.
+    LongPress { x: u16, y: u16, time: os_time_t },
+    /// Finger moved quickly from (x, y) and was released
+    Swipe { direction: Swipe, x: u16, y: u16, time: os_time_t },
+    /// Finger moved to (x, y). (dx, dy) is the movement since the last `Down` or `Drag` event.
+    Drag { x: u16, y: u16, dx: i16, dy: i16, time: os_time_t },
+}
+
+/// Direction of a swipe
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Swipe {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// Start the gesture recognition. Must be called before `handle_point()`.
+pub fn start_gesture() -> MynewtResult<()> {
+    LONG_PRESS_TIMER.init(os::eventq_dflt_get() ? , TimerMode::OneShot, handle_long_press)
+}
+
+/// Handle a touch point from the touch controller and pass the recognised Touch Event, if any, to the app.
+/// Called by the touch controller interrupt handler in the Default Event Queue.
+pub fn handle_point(action: Action, x: u16, y: u16) {
+    let now = unsafe { os::os_time_get() };
+    let event = unsafe { GESTURE.update(action, x, y, now) };
+    //  Time the long press while the finger is touching the screen
+    match event {
+        Some(TouchEvent::Down { .. }) => { LONG_PRESS_TIMER.start(kernel::ms_to_ticks(LONG_PRESS_MS)).ok(); }
+        _ if action == Action::Up     => LONG_PRESS_TIMER.stop(),
+        _ => {}
+    }
+    if let Some(event) = event { dispatch(event); }
+}
+
+/// Called when the long press timer expires
+fn handle_long_press() {
+    let now = unsafe { os::os_time_get() };
+    let event = unsafe { GESTURE.long_press(now) };
+    if let Some(event) = event { dispatch(event); }
+}
+
+/// Pass the Touch Event to the app
+fn dispatch(event: TouchEvent) {
+    debug!("{:?}", event);
+    super::handle_touch(event);
+}
+
+/// Gesture recognition state
+struct Gesture {
+    /// Where and when the finger touched the screen, if the finger is touching the screen
+    start:     Option<(u16, u16, os_time_t)>,
+    /// Last position of the finger
+    last:      (u16, u16),
+    /// True if the finger has moved beyond `DRAG_THRESHOLD`
+    dragging:  bool,
+    /// True if a long press has been reported for this touch
+    long_press: bool,
+    /// Where and when the last single tap was reported, for recognising double taps
+    last_tap:  Option<(u16, u16, os_time_t)>,
+}
+
+impl Gesture {
+    /// Return the state with no touch
+    const fn new() -> Self {
+        Gesture {
+            start:      None,
+            last:       (0, 0),
+            dragging:   false,
+            long_press: false,
+            last_tap:   None,
+        }
+    }
+
+    /// Update the state with the touch point at `time` and return the recognised Touch Event, if any
+    fn update(&mut self, action: Action, x: u16, y: u16, time: os_time_t) -> Option<TouchEvent> {
+        //  The controller may report (0, 0) when the finger is released
+        let (x, y) = if action == Action::Up && (x, y) == (0, 0) { self.last } else { (x, y) };
+        match (action, self.start) {
+            //  New touch. A down action while touching means we missed the up action, so start again.
+            (Action::Down, _) | (Action::Contact, None) => {
+                self.start      = Some((x, y, time));
+                self.last       = (x, y);
+                self.dragging   = false;
+                self.long_press = false;
+                Some(TouchEvent::Down { x, y, time })
+            }
+            //  Finger moved
+            (Action::Contact, Some((start_x, start_y, start_time))) => {
+                if !self.dragging && distance(x, y, start_x, start_y) < DRAG_THRESHOLD {
+                    //  Contact reports may arrive before the long press timer
+                    if elapsed(start_time, time) >= kernel::ms_to_ticks(LONG_PRESS_MS) { return self.long_press(time); }
+                    return None;
+                }
+                self.dragging = true;
+                let (last_x, last_y) = self.last;
+                if (x, y) == (last_x, last_y) { return None; }
+                self.last = (x, y);
+                Some(TouchEvent::Drag { x, y, dx: x as i16 - last_x as i16, dy: y as i16 - last_y as i16, time })
+            }
+            //  Finger released
+            (Action::Up, Some((start_x, start_y, start_time))) => {
+                self.start = None;
+                if self.long_press { return None; }
+                if self.dragging {
+                    //  Quick, long movement is a swipe. Slow movement ends the drag.
+                    let (dx, dy) = (x as i32 - start_x as i32, y as i32 - start_y as i32);
+                    if elapsed(start_time, time) > kernel::ms_to_ticks(SWIPE_MAX_MS) ||
+                        distance(x, y, start_x, start_y) < SWIPE_MIN_DISTANCE { return None; }
+                    let direction =
+                        if dx.abs() > dy.abs() { if dx > 0 { Swipe::Right } else { Swipe::Left } }
+                        else                   { if dy > 0 { Swipe::Down  } else { Swipe::Up   } };
+                    return Some(TouchEvent::Swipe { direction, x: start_x, y: start_y, time });
+                }
+                //  Second tap near the last tap is a double tap
+                if let Some((tap_x, tap_y, tap_time)) = self.last_tap.take() {
+                    if elapsed(tap_time, time) <= kernel::ms_to_ticks(DOUBLE_TAP_MS) &&
+                        distance(start_x, start_y, tap_x, tap_y) < DOUBLE_TAP_DISTANCE {
+                        return Some(TouchEvent::DoubleTap { x: start_x, y: start_y, time });
+                    }
+                }
+                self.last_tap = Some((start_x, start_y, time));
+                Some(TouchEvent::Tap { x: start_x, y: start_y, time })
+            }
+            //  Released without touching
+            (Action::Up, None) => None,
+        }
+    }
+
+    /// Return the long press Touch Event if the finger has been touching the screen without moving
+    fn long_press(&mut self, time: os_time_t) -> Option<TouchEvent> {
+        let (x, y, _) = self.start ? ;
+        if self.dragging || self.long_press { return None; }
+        self.long_press = true;
+        self.last_tap   = None;
+        Some(TouchEvent::LongPress { x, y, time })
+    }
+}
+
+/// Return the larger of the horizontal and vertical distances between the points
+fn distance(x1: u16, y1: u16, x2: u16, y2: u16) -> u16 {
+    let dx = (x1 as i32 - x2 as i32).abs();
+    let dy = (y1 as i32 - y2 as i32).abs();
+    dx.max(dy) as u16
+}
+
+/// Return the number of ticks from `start` to `now`
+fn elapsed(start: os_time_t, now: os_time_t) -> os_time_t {
+    now.wrapping_sub(start)
+}
+
+/// Gesture recognition state, updated only in the Default Event Queue
+static mut GESTURE: Gesture = Gesture::new();
+
+/// Timer that reports a long press
+static LONG_PRESS_TIMER: Timer = Timer::new();
+
+/// Movement in pixels before a touch becomes a drag
+const DRAG_THRESHOLD: u16 = 10;
+/// Min movement in pixels for a swipe
+const SWIPE_MIN_DISTANCE: u16 = 60;
+/// Max duration in milliseconds for a swipe
+const SWIPE_MAX_MS: u32 = 500;
+/// Duration in milliseconds before a touch becomes a long press
+const LONG_PRESS_MS: u32 = 600;
+/// Max time in milliseconds between 2 taps of a double tap
+const DOUBLE_TAP_MS: u32 = 400;
+/// Max distance in pixels between 2 taps of a double tap
+const DOUBLE_TAP_DISTANCE: u16 = 30;
@@ -24,6 +24,7 @@
 #![feature(proc_macro_hygiene)]         //  Allow Procedural Macros like `run!()`
 #![feature(specialization)]             //  Allow Specialised Traits for druid UI library
 #![feature(exclusive_range_pattern)]    //  Allow ranges like `0..128` in `match` statements
+#![feature(panic_info_message)]         //  Allow `PanicInfo::message()` for recording the panic message
 
 //  Declare the libraries that contain macros
 extern crate cortex_m;                  //  Declare the external library `cortex_m`
@@ -33,7 +34,13 @@ extern crate macros as mynewt_macros;   //  Declare the Mynewt Procedural Macros
 //  Declare the modules in our application
 mod app_network;    //  Declare `app_network.rs` as Rust module `app_network` for Application Network functions
 mod app_sensor;     //  Declare `app_sensor.rs` as Rust module `app_sensor` for Application Sensor functions
-mod touch_sensor;   //  Declare `touch_sensor.rs` as Rust module `touch_sensor` for Touch Sensor functions
+mod calibration;    //  Declare `calibration.rs` as Rust module `calibration` for Touch Calibration
+pub mod compositor;  //  Declare `compositor.rs` as Rust module `compositor` for Dirty Rectangle Compositor. Public for the tests in `tests/compositor.rs`
+#[cfg_attr(feature = "simulator", allow(dead_code))]  //  Panic handler is not used in the simulator
+mod crash;          //  Declare `crash.rs` as Rust module `crash` for Panic handling
+mod gesture;        //  Declare `gesture.rs` as Rust module `gesture` for Touch Gesture Recognition
+pub mod st7789;     //  Declare `st7789.rs` as Rust module `st7789` for ST7789 Display Driver. Public for the tests in `tests/display.rs`
+pub mod touch_sensor;  //  Declare `touch_sensor.rs` as Rust module `touch_sensor` for Touch Sensor functions. Public for the tests in `tests/touch_sensor.rs`
 
 //  Declare the optional modules depending on the options in `../Cargo.toml`
 #[cfg(feature = "display_app")]  //  If graphics display app is enabled...
@@ -47,18 +54,20 @@ mod ui;                          //  Include the druid UI app
 mod visual;                      //  Include the Visual Rust app
 
 #[cfg(feature = "chip8_app")]    //  If CHIP8 Emulator app is enabled...
-mod chip8;                       //  Include the CHIP8 Emulator app
+pub mod chip8;                   //  Include the CHIP8 Emulator app. Public for the headless tests in `tests/chip8.rs`
 
 #[cfg(feature = "use_float")]    //  If floating-point is enabled...
 mod gps_sensor;                  //  Include the GPS Sensor functions
 
 //  Declare the system modules
+#[cfg(not(feature = "simulator"))]
 use core::panic::PanicInfo; //  Import `PanicInfo` type which is used by `panic()` below
-use cortex_m::asm::bkpt;    //  Import cortex_m assembly function to inject breakpoint
 use mynewt::{
+    hal::backlight,         //  Import Mynewt Backlight API
     kernel::os,             //  Import Mynewt OS API
-    sys::console,           //  Import Mynewt Console API
 };
+#[cfg(not(any(feature = "ui_app", feature = "visual_app", feature = "chip8_app")))]
+use mynewt::sys::console;   //  Import Mynewt Console API for the touch handler that does nothing
 
 //  Select the touch handler depending on the options in `../Cargo.toml`
 #[cfg(feature = "ui_app")]      //  If druid UI app is enabled...
@@ -71,25 +80,32 @@ use visual::handle_touch;       //  Use the touch handler from the Visual Rust a
 use chip8::handle_touch;        //  Use the touch handler from the CHIP8 Emulator app
 
 #[cfg(not(any(feature = "ui_app", feature = "visual_app", feature = "chip8_app")))]  //  If neither druid UI app nor Visual Rust app are enabled...
-pub fn handle_touch(_x: u16, _y: u16) { console::print("touch not handled\n"); console::flush(); }  //  Define a touch handler that does nothing
+pub fn handle_touch(_event: gesture::TouchEvent) { console::print("touch not handled\n"); console::flush(); }  //  Define a touch handler that does nothing
 
 ///  Main program that initialises the sensor, network driver and starts reading and sending sensor data in the background.
 ///  main() will be called at Mynewt startup. It replaces the C version of the main() function.
-#[no_mangle]                 //  Don't mangle the name "main"
-extern "C" fn main() -> ! {  //  Declare extern "C" because it will be called by Mynewt
+///  In the simulator, main() is called by the simulator runner in `../simulator` instead.
+#[cfg_attr(not(feature = "simulator"), no_mangle)]  //  Don't mangle the name "main", except in the simulator where it would clash with the runner's main()
+pub extern "C" fn main() -> ! {                     //  Declare extern "C" because it will be called by Mynewt
     //  Initialise the Mynewt packages and internal temperature sensor driver. Any startup
     //  functions defined in pkg.yml of our custom drivers and libraries will be called by 
     //  sysinit().  Here are the startup functions consolidated by Mynewt:
     //  bin/targets/nrf52_my_sensor/generated/src/nrf52_my_sensor-sysinit-app.c
     mynewt::sysinit();
 
+    //  Display the RAM Log of the previous boot, so that we may diagnose watchdog resets and crashes
+    mynewt::sys::ram_log::dump_previous();
+
+    //  Display the last crash, if any. The crash will also be reported to the CoAP server when sending sensor data.
+    crash::report_last_crash();
+
     //  Start Bluetooth Beacon.  TODO: Create a safe wrapper for starting Bluetooth LE.
     //  extern { fn start_ble() -> i32; }
     //  let rc = unsafe { start_ble() };
     //  assert!(rc == 0, "BLE fail");
 
     //  Start the display
-    druid::start_display()
+    crash::while_drawing(druid::start_display)
         .expect("DSP fail");
 
     //  Test the display
@@ -97,10 +113,20 @@ extern "C" fn main() -> ! {  //  Declare extern "C" because it will be called by
     display::test_display()
         .expect("DSP test fail");
 
+    //  Switch on the backlight. Dim after 10 seconds of inactivity and switch off 20 seconds later.
+    backlight::init()
+        .expect("BKL fail");
+    backlight::start_auto_dim(10_000, 20_000)
+        .expect("BKL dim fail");
+
     //  Start the touch sensor
     touch_sensor::start_touch_sensor()
         .expect("TCH fail");
 
+    //  Start polling the battery
+    app_sensor::start_battery_listener()
+        .expect("BAT fail");
+
     //  Test the touch sensor
     //  touch_sensor::test()
     //      .expect("TCH test fail");
@@ -119,6 +145,11 @@ extern "C" fn main() -> ! {  //  Declare extern "C" because it will be called by
     chip8::on_start()
         .expect("CHIP8 fail");
 
+    //  Load the touch calibration, or show the crosshairs for calibrating on first boot. Started after the apps
+    //  so that the crosshairs are drawn over the app screen.
+    calibration::start()
+        .expect("CAL fail");
+
     //  Main event loop
     loop {                            //  Loop forever...
         os::eventq_run(               //  Processing events...
@@ -129,25 +160,10 @@ extern "C" fn main() -> ! {  //  Declare extern "C" because it will be called by
     //  Never comes here
 }
 
-///  This function is called on panic, like an assertion failure. We display the filename and line number and pause in the debugger. From https://os.phil-opp.com/freestanding-rust-binary/
+///  This function is called on panic, like an assertion failure. We record the crash, display the crash screen
+///  and reboot via the Watchdog Timer. The crash is reported on the next boot. See `crash.rs`
+#[cfg(not(feature = "simulator"))]  //  In the simulator, panics are handled by the Rust standard library
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    //  Display the filename and line number to the Semihosting Console.
-    console::print("panic ");
-    if let Some(location) = info.location() {
-        let file = location.file();
-        let line = location.line();
-        console::print("at ");       console::buffer(&file);
-        console::print(" line ");    console::printint(line as i32);
-        console::print("\n");        console::flush();
-    } else {
-        console::print("no loc\n");  console::flush();
-    }
-    //  Pause in the debugger.
-    bkpt();
-    //  Display the payload.
-    console::print(info.payload().downcast_ref::<&str>().unwrap());
-    console::print("\n");  console::flush();
-    //  Loop forever so that device won't restart.
-    loop {}
+    crash::handle_panic(info)
 }
\ No newline at end of file
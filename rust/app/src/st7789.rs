@@ -0,0 +1,348 @@
+//! Display driver for the ST7789 display controller on PineTime, written in Rust. Implements the embedded-graphics
+//! draw target (`Drawing` in embedded-graphics 0.5), so apps may draw without the druid display driver:
+//! ```
+//! static mut DISPLAY: ST7789 = ST7789::new(&spi::DISPLAY, LCD_RESET_PIN);
+//! ...
+//! spi::spi_noblock_init() ? ;                 //  Start the non-blocking SPI engine
+//! unsafe { DISPLAY.init(Rotation::Deg0) ? };  //  Reset and configure the display
+//! unsafe { DISPLAY.draw(circle) };            //  Draw an embedded-graphics item
+//! unsafe { DISPLAY.take_error() ? };          //  Check for errors while drawing
+//! ```
+//! Commands and pixels are sent by the non-blocking SPI task in `mynewt::spi`, so drawing returns before the
+//! pixels are displayed. Use `set_pixels()` to update a window of pixels without embedded-graphics.
+use embedded_graphics::{
+    drawable::Pixel,
+    pixelcolor::Rgb565,
+    Drawing,
+};
+use embedded_hal::{
+    blocking::delay::DelayMs,
+    digital::v2::OutputPin,
+};
+use mynewt::{
+    result::*,
+    spi::NonBlockingSpi,
+    Delay, GPIO,
+};
+use crate::calibration;
+
+/// LCD_RESET (P0.26): Display reset
+pub const LCD_RESET_PIN: i32 = 26;
+
+/// Display width, in pixels
+pub const WIDTH: u16 = 240;
+/// Display height, in pixels
+pub const HEIGHT: u16 = 240;
+
+/// Orientation of the display, clockwise
+#[derive(Clone, Copy, PartialEq)]
+pub enum Rotation {
+    /// Not rotated
+    Deg0,
+    /// Rotated 90 degrees clockwise
+    Deg90,
+    /// Rotated 180 degrees
+    Deg180,
+    /// Rotated 270 degrees clockwise
+    Deg270,
+}
+
+impl Rotation {
+    /// Return the Memory Data Access Control (MADCTL) bits for the rotation
+    fn madctl(self) -> u8 {
+        match self {
+            Rotation::Deg0   => 0x00,
+            Rotation::Deg90  => MADCTL_MX | MADCTL_MV,
+            Rotation::Deg180 => MADCTL_MX | MADCTL_MY,
+            Rotation::Deg270 => MADCTL_MY | MADCTL_MV,
+        }
+    }
+
+    /// Return the (column, row) offset of the display in the controller memory. The controller memory has
+    /// 320 rows but the display shows only 240, so the offset is needed when the rows are mirrored.
+    fn offset(self) -> (u16, u16) {
+        match self {
+            Rotation::Deg0 | Rotation::Deg90 => (0, 0),
+            Rotation::Deg180                 => (0, RAM_ROWS - HEIGHT),
+            Rotation::Deg270                 => (RAM_ROWS - HEIGHT, 0),
+        }
+    }
+}
+
+/// ST7789 display connected to a non-blocking SPI device
+pub struct ST7789 {
+    /// Non-blocking SPI device for the display
+    spi:       &'static NonBlockingSpi,
+    /// Reset pin, active low
+    reset_pin: i32,
+    /// Orientation of the display
+    rotation:  Rotation,
+    /// True if the colours are inverted
+    inverted:  bool,
+    /// First error while drawing, returned by `take_error()`
+    error:     Option<MynewtError>,
+}
+
+impl ST7789 {
+    /// Create a display driver for the SPI device and reset pin. The SPI device must be initialised before calling `init()`.
+    pub const fn new(spi: &'static NonBlockingSpi, reset_pin: i32) -> Self {
+        ST7789 {
+            spi,
+            reset_pin,
+            rotation: Rotation::Deg0,
+            inverted: false,
+            error:    None,
+        }
+    }
+
+    /// Reset the display and configure it for 16-bit colour with the rotation. Blocks while resetting the display.
+    pub fn init(&mut self, rotation: Rotation) -> MynewtResult<()> {
+        //  Reset the display by switching the reset pin low then high, then wait for the reset to complete
+        let mut reset = GPIO::new();
+        let mut delay = Delay::new();
+        reset.init(self.reset_pin) ? ;
+        reset.set_high() ? ;
+        delay.delay_ms(10);
+        reset.set_low() ? ;
+        delay.delay_ms(10);
+        reset.set_high() ? ;
+        delay.delay_ms(120);
+
+        //  The delays below are done by the SPI task after sending each command
+        self.spi.write_command_with_delay(SWRESET, 150) ? ;
+        self.spi.write_command_with_delay(SLPOUT, 120) ? ;
+        self.spi.write_command(COLMOD) ? ;
+        self.spi.write_data(&[0x55]) ? ;  //  16 bits per pixel (RGB565)
+        self.set_rotation(rotation) ? ;
+        self.invert_colors(false) ? ;
+        self.spi.write_command_with_delay(NORON, 10) ? ;
+        self.spi.write_command_with_delay(DISPON, 10) ? ;
+        self.spi.flush()
+    }
+
+    /// Rotate the display. Pixels already displayed are not redrawn.
+    pub fn set_rotation(&mut self, rotation: Rotation) -> MynewtResult<()> {
+        self.rotation = rotation;
+        calibration::set_rotation(rotation);  //  Rotate the touches to match
+        self.spi.write_command(MADCTL) ? ;
+        self.spi.write_data(&[rotation.madctl()]) ? ;
+        self.spi.flush()
+    }
+
+    /// Return the orientation of the display
+    pub fn rotation(&self) -> Rotation {
+        self.rotation
+    }
+
+    /// Invert the displayed colours if `inverted` is true. The PineTime panel shows inverted colours unless
+    /// display inversion is switched on, so we switch it off to invert the colours.
+    pub fn invert_colors(&mut self, inverted: bool) -> MynewtResult<()> {
+        self.inverted = inverted;
+        self.spi.write_command(if inverted == PANEL_INVERTED { INVOFF } else { INVON }) ? ;
+        self.spi.flush()
+    }
+
+    /// Return true if the colours are inverted
+    pub fn is_inverted(&self) -> bool {
+        self.inverted
+    }
+
+    /// Switch off the display and put the controller to sleep to save power. Pixels are retained.
+    pub fn sleep(&mut self) -> MynewtResult<()> {
+        self.spi.write_command(DISPOFF) ? ;
+        self.spi.write_command_with_delay(SLPIN, 5) ? ;
+        self.spi.flush()
+    }
+
+    /// Wake the controller and switch on the display
+    pub fn wake(&mut self) -> MynewtResult<()> {
+        self.spi.write_command_with_delay(SLPOUT, 120) ? ;
+        self.spi.write_command(DISPON) ? ;
+        self.spi.flush()
+    }
+
+    /// Define the vertical scrolling area, between `top_fixed` rows at the top and `bottom_fixed` rows at the bottom
+    /// that don't scroll. Rows are in the unrotated orientation (`Deg0`).
+    pub fn set_scroll_area(&mut self, top_fixed: u16, bottom_fixed: u16) -> MynewtResult<()> {
+        if top_fixed as u32 + bottom_fixed as u32 >= HEIGHT as u32 { return Err(MynewtError::SYS_EINVAL); }
+        //  The controller memory has more rows than the display. The extra rows are fixed at the bottom.
+        let bottom_fixed = bottom_fixed + RAM_ROWS - HEIGHT;
+        let scroll_rows  = RAM_ROWS - top_fixed - bottom_fixed;
+        self.spi.write_command(VSCRDEF) ? ;
+        self.write_words(&[top_fixed, scroll_rows, bottom_fixed]) ? ;
+        self.spi.flush()
+    }
+
+    /// Scroll the scrolling area so that memory row `row` is displayed at the top of the scrolling area.
+    /// `row` must be within the scrolling area set by `set_scroll_area()`.
+    pub fn scroll_to(&mut self, row: u16) -> MynewtResult<()> {
+        if row >= RAM_ROWS { return Err(MynewtError::SYS_EINVAL); }
+        self.spi.write_command(VSCSAD) ? ;
+        self.write_words(&[row]) ? ;
+        self.spi.flush()
+    }
+
+    /// Set the window for the pixels that will be written next, from (left, top) to (right, bottom) inclusive.
+    /// Starts writing the pixels, which must be written by `write_pixels()`.
+    pub fn set_window(&mut self, left: u16, top: u16, right: u16, bottom: u16) -> MynewtResult<()> {
+        let (width, height) = self.size();
+        if left > right || top > bottom || right >= width || bottom >= height { return Err(MynewtError::SYS_EINVAL); }
+        let (column_offset, row_offset) = self.rotation.offset();
+        self.spi.write_command(CASET) ? ;
+        self.write_words(&[left + column_offset, right + column_offset]) ? ;
+        self.spi.write_command(RASET) ? ;
+        self.write_words(&[top + row_offset, bottom + row_offset]) ? ;
+        self.spi.write_command(RAMWR)
+    }
+
+    /// Write 16-bit colours after `set_window()`. The pixels fill the window left to right, then top to bottom.
+    /// If the colours exceed the SPI buffer, the pixels are split into multiple requests.
+    pub fn write_pixels<I>(&mut self, colors: I) -> MynewtResult<()>
+    where I: IntoIterator<Item = u16> {
+        let mut buf = [0u8; CHUNK_PIXELS * 2];
+        let mut len = 0;
+        for color in colors {
+            buf[len]     = (color >> 8) as u8;
+            buf[len + 1] = color as u8;
+            len += 2;
+            if len == buf.len() {
+                self.write_pixel_bytes(&buf) ? ;
+                len = 0;
+            }
+        }
+        self.write_pixel_bytes(&buf[..len])
+    }
+
+    /// Set the window and write the 16-bit colours to the window. Replaces `druid::set_display_pixels()`.
+    pub fn set_pixels<I>(&mut self, left: u16, top: u16, right: u16, bottom: u16, colors: I) -> MynewtResult<()>
+    where I: IntoIterator<Item = u16> {
+        self.set_window(left, top, right, bottom) ? ;
+        self.write_pixels(colors) ? ;
+        self.spi.flush()
+    }
+
+    /// Return the max number of pixels that may be written in one SPI request
+    pub fn buffer_pixels(&self) -> usize {
+        self.spi.buffer_size() / 2
+    }
+
+    /// Return the first error while drawing with embedded-graphics, and any error from the SPI task
+    pub fn take_error(&mut self) -> MynewtResult<()> {
+        let res = self.spi.flush();
+        match self.error.take() {
+            Some(err) => Err(err),
+            None      => res,
+        }
+    }
+
+    /// Return the (width, height) of the display for the current rotation
+    pub fn size(&self) -> (u16, u16) {
+        match self.rotation {
+            Rotation::Deg0 | Rotation::Deg180  => (WIDTH, HEIGHT),
+            Rotation::Deg90 | Rotation::Deg270 => (HEIGHT, WIDTH),
+        }
+    }
+
+    /// Draw the pixels. Consecutive pixels in the same row are written in one window.
+    fn draw_pixels<T>(&mut self, item_pixels: T) -> MynewtResult<()>
+    where T: IntoIterator<Item = Pixel<Rgb565>> {
+        let (width, height) = self.size();
+        let mut run = PixelRun { left: 0, top: 0, len: 0, colors: [0; CHUNK_PIXELS] };
+        for Pixel(coord, color) in item_pixels {
+            //  Skip pixels outside the display
+            if coord.0 >= width as u32 || coord.1 >= height as u32 { continue; }
+            let (x, y) = (coord.0 as u16, coord.1 as u16);
+            //  If the pixel doesn't continue the run, write the run and start a new run
+            if run.len > 0 && (y != run.top || x != run.left + run.len as u16 || run.len == CHUNK_PIXELS) {
+                self.write_run(&run) ? ;
+                run.len = 0;
+            }
+            if run.len == 0 { run.left = x; run.top = y; }
+            run.colors[run.len] = color.0;
+            run.len += 1;
+        }
+        if run.len > 0 { self.write_run(&run) ? ; }
+        self.spi.flush()
+    }
+
+    /// Write the run of pixels in a row
+    fn write_run(&mut self, run: &PixelRun) -> MynewtResult<()> {
+        self.set_window(run.left, run.top, run.left + run.len as u16 - 1, run.top) ? ;
+        self.write_pixels(run.colors[..run.len].iter().cloned())
+    }
+
+    /// Append the pixel bytes to the pending SPI request. If the request is full, enqueue it and continue
+    /// the pixels in a new request without Command Byte. If out of mbufs, the pending request with the window
+    /// commands has been discarded, so return the error instead of sending the pixels to the wrong window.
+    fn write_pixel_bytes(&mut self, bytes: &[u8]) -> MynewtResult<()> {
+        if bytes.is_empty() { return Ok(()); }
+        match self.spi.write_data(bytes) {
+            Err(MynewtError::SYS_ERANGE) => {
+                self.spi.flush() ? ;
+                self.spi.write_data(bytes)
+            }
+            res => res,
+        }
+    }
+
+    /// Write the 16-bit words as Data Bytes, most significant byte first
+    fn write_words(&mut self, words: &[u16]) -> MynewtResult<()> {
+        for word in words {
+            self.spi.write_data(&[(word >> 8) as u8, *word as u8]) ? ;
+        }
+        Ok(())
+    }
+}
+
+/// Draw embedded-graphics items to the display. Errors are returned by `take_error()`.
+impl Drawing<Rgb565> for ST7789 {
+    fn draw<T>(&mut self, item_pixels: T)
+    where T: IntoIterator<Item = Pixel<Rgb565>> {
+        if let Err(err) = self.draw_pixels(item_pixels) {
+            if self.error.is_none() { self.error = Some(err); }
+        }
+    }
+}
+
+/// Consecutive pixels in a row, written in one window
+struct PixelRun {
+    /// Column of the first pixel
+    left:   u16,
+    /// Row of the pixels
+    top:    u16,
+    /// Number of pixels
+    len:    usize,
+    /// 16-bit colours of the pixels
+    colors: [u16; CHUNK_PIXELS],
+}
+
+/// Max number of pixels that are buffered on the stack before writing to SPI
+const CHUNK_PIXELS: usize = 64;
+
+/// Number of rows in the controller memory
+const RAM_ROWS: u16 = 320;
+
+/// True if the PineTime panel shows inverted colours when display inversion is off
+const PANEL_INVERTED: bool = true;
+
+//  Memory Data Access Control (MADCTL) bits
+const MADCTL_MY: u8 = 0x80;  //  Mirror rows
+const MADCTL_MX: u8 = 0x40;  //  Mirror columns
+const MADCTL_MV: u8 = 0x20;  //  Swap rows and columns
+
+//  ST7789 commands, from the ST7789 datasheet
+const SWRESET: u8 = 0x01;  //  Software reset
+const SLPIN:   u8 = 0x10;  //  Sleep in
+const SLPOUT:  u8 = 0x11;  //  Sleep out
+const NORON:   u8 = 0x13;  //  Normal display mode on
+const INVOFF:  u8 = 0x20;  //  Display inversion off
+const INVON:   u8 = 0x21;  //  Display inversion on
+const DISPOFF: u8 = 0x28;  //  Display off
+const DISPON:  u8 = 0x29;  //  Display on
+const CASET:   u8 = 0x2a;  //  Column address set
+const RASET:   u8 = 0x2b;  //  Row address set
+const RAMWR:   u8 = 0x2c;  //  Memory write
+const VSCRDEF: u8 = 0x33;  //  Vertical scrolling definition
+const MADCTL:  u8 = 0x36;  //  Memory data access control
+const VSCSAD:  u8 = 0x37;  //  Vertical scroll start address
+const COLMOD:  u8 = 0x3a;  //  Interface pixel format
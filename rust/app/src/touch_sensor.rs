@@ -7,13 +7,24 @@ use mynewt::{
     self,
     result::*,
     hw::hal,
-    kernel::os::{
+    hal::{
+        backlight,
+        register::{ Register, RegisterDevice },
+        GpioIrq, Pull, Trigger,
+    },
+    kernel::os,
+    sys::console::{
         self,
-        os_event,
+        Level,
     },
-    sys::console,
     fill_zero,
+    debug, warn, println,
 };
+use crate::{ calibration, gesture };
+
+/// Log level for touch diagnostics. Set to `Level::Debug` to display the touch data,
+/// which may interfere with touch events.
+const LOG_LEVEL: Level = Level::Info;
 
 /// Reset Pin for touch controller. Note: NFC antenna pins must be reassigned as GPIO pins for this to work.
 const TOUCH_RESET_PIN: i32 = 10;  //  P0.10/NFC2: TP_RESET
@@ -33,7 +44,7 @@ type MynewtDelay = mynewt::Delay;
 /// ```rust
 /// let peripherals = nrf52::Peripherals::take().unwrap();
 /// let nfcpins = peripherals.UICR.nfcpins.read().bits();
-/// console::print("nfcpins = "); console::printhex(nfcpins as u8); console::print("\n");
+/// println!("nfcpins = {:02x}", nfcpins as u8);
 /// ```
 pub fn start_touch_sensor() -> MynewtResult<()> {
     console::print("Rust touch sensor\n");
@@ -49,172 +60,229 @@ pub fn start_touch_sensor() -> MynewtResult<()> {
         TOUCH_DELAY.delay_ms(200); TOUCH_DELAY.delay_ms(200);    
     };
 
-    //  Initialise the touch event with the callback function
-    unsafe { TOUCH_EVENT.ev_cb = Some( touch_event_callback ) };
-
-    //  Configure the touch controller interrupt (active when low) to trigger a touch event
-    let rc = unsafe { hal::hal_gpio_irq_init(
-        TOUCH_INTERRUPT_PIN,              //  GPIO pin to be configured
-        Some( touch_interrupt_handler ),  //  Call `touch_interrupt_handler()` upon detecting interrupt
-        core::ptr::null_mut(),            //  No arguments for `touch_interrupt_handler()`
-        hal::hal_gpio_irq_trigger_HAL_GPIO_TRIG_FALLING,  //  Trigger when interrupt goes from high to low
-        hal::hal_gpio_pull_HAL_GPIO_PULL_UP               //  Pull up the GPIO pin
-    ) };
-    assert_eq!(rc, 0, "IRQ init fail");
-
-    //  Start monitoring for touch controller interrupts
-    unsafe { hal::hal_gpio_irq_enable(TOUCH_INTERRUPT_PIN) };
+    //  Start recognising gestures before the first touch
+    gesture::start_gesture() ? ;
+
+    //  Configure the touch controller interrupt (active when low) to call `handle_touch_event()`.
+    //  TODO: Use dedicated Event Queue for higher priority processing.
+    TOUCH_IRQ.init(
+        Trigger::Falling,         //  Trigger when interrupt goes from high to low
+        Pull::Up,                 //  Pull up the GPIO pin
+        os::eventq_dflt_get() ? , //  Handle the touch in the Default Event Queue
+        handle_touch_event        //  Call `handle_touch_event()` upon detecting interrupt
+    ) ? ;
     Ok(())
 }
 
-/// Interrupt handler for the touch controller, triggered when a touch is detected
-extern "C" fn touch_interrupt_handler(arg: *mut core::ffi::c_void) {
-    //  We forward a touch event to the Default Event Queue for deferred processing.  Don't do any processing here.
-    unsafe { TOUCH_EVENT.ev_arg = arg };
-    //  Fetch the Default Event Queue. TODO: Use dedicated Event Queue for higher priority processing.
-    let queue = os::eventq_dflt_get()
-        .expect("GET fail");
-    unsafe { os::os_eventq_put(queue, &mut TOUCH_EVENT) };  //  Trigger the callback function `touch_event_callback()`
-    //console::print("touch\n"); ////
+/// Handler for the touch interrupt that is triggered when a touch is detected. Called by the Default Event Queue.
+fn handle_touch_event(_pin: i32) {
+    debug!("{} touch", unsafe { os::os_time_get() });
+    //  Fetch the touch data from the touch controller
+    let report = read_touchdata();
+    //  Console output is disabled by `LOG_LEVEL` because it may interfere with touch events
+    debug!("gesture: {:?}, fingers: {}, points: {}", report.gesture, report.fingers, report.count);
+    for point in report.points() {
+        debug!("act: {:?}, fin {}, x: {}, y: {}", point.action, point.finger, point.x, point.y);
+    }
+    //  Handle the first touch point. Only one finger is tracked.
+    if let Some(point) = report.points().first() {
+        //  Wake the backlight if it has been dimmed or switched off, and restart the inactivity timer
+        backlight::wake().ok();
+        //  While calibrating, the touch goes to the calibration procedure instead of the UI
+        if calibration::is_calibrating() {
+            calibration::handle_point(point.action, point.x, point.y);
+            return;
+        }
+        //  Map the touch to display coordinates. Touch up at (0, 0) means the last position.
+        let (x, y) =
+            if (point.x, point.y) == (0, 0) { (0, 0) }
+            else { calibration::map(point.x, point.y) };
+        //  Recognise the gesture and pass the Touch Event to the UI
+        gesture::handle_point(point.action, x, y);
+        //  Calibrate again if the finger has been held still for a long time
+        calibration::watch_point(point.action, point.x, point.y);
+
+        //  Display the touch data
+        //  druid::show_touch(x, y).expect("show touch fail");
+    }
+}
+
+/// Read touch controller data. This only works when the screen has been tapped and the touch controller wakes up.
+/// The controller may not acknowledge the register address or the read when it's going to sleep, so the I2C error is
+/// logged and the registers are decoded anyway, like the original driver. Registers that were not read keep the
+/// values from the last touch.
+fn read_touchdata() -> TouchReport {
+    unsafe {
+        let res = TOUCH_CONTROLLER.read_burst(  //  Read the range of I2C registers from the touch controller...
+            0,                                  //  Starting from register 0
+            &mut BUF                            //  Save the read data into `buf`
+        );
+        if res.is_err() { warn!("i2c fail"); }
+        TouchReport::decode(&BUF)
+    }
+}
+
+/// Buffer for raw touch data
+static mut BUF: [u8; POINT_READ_BUF] = [0; POINT_READ_BUF];
+
+/// Touch Controller I2C Address: https://github.com/lupyuen/hynitron_i2c_cst0xxse
+const TOUCH_CONTROLLER_ADDRESS: u8 = 0x15;
+
+/// I2C port for the touch controller and other sensors, configured by the BSP. Time out after 1,000 milliseconds (1 second).
+const TOUCH_I2C_PORT: u8 = 1;
+const TOUCH_I2C_TIMEOUT: u32 = 1000;
+
+/// Touch Controller registers, read via I2C Port 1
+static mut TOUCH_CONTROLLER: RegisterDevice = RegisterDevice::new(
+    mynewt::I2C::from_port(TOUCH_I2C_PORT, TOUCH_I2C_TIMEOUT),
+    TOUCH_CONTROLLER_ADDRESS
+);
+
+/// Touch report decoded from the touch controller registers. Based on https://github.com/lupyuen/hynitron_i2c_cst0xxse/blob/master/cst0xx_core.h#L104-L115
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TouchReport {
+    /// Gesture recognised by the touch controller
+    pub gesture: HardwareGesture,
+    /// Number of fingers touching the screen, reported by the touch controller. 0 when the finger is released.
+    pub fingers: u8,
+    /// Valid touch points, use `points()` to access
+    pub touches: [TouchPoint; CFG_MAX_TOUCH_POINTS],
+    /// Number of valid touch points in `touches`
+    pub count:   u8,
+}
+
+/// Touch point in a touch report
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TouchPoint {
+    /// X coordinate
+    pub x:        u16,
+    /// Y coordinate
+    pub y:        u16,
+    /// Touch down, up or contact
+    pub action:   Action,
+    /// Which finger touched
+    pub finger:   u8,
+    /// Pressure of touch, if reported by the touch controller
+    pub pressure: Option<u8>,
+    /// Area touched, if reported by the touch controller
+    pub area:     Option<u8>,
+}
+
+/// Touch action for a touch point
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Finger touched the screen
+    Down,
+    /// Finger released the screen
+    Up,
+    /// Finger is still touching the screen
+    Contact,
 }
 
-/// Callback for the touch event that is triggered when a touch is detected
-extern "C" fn touch_event_callback(_event: *mut os_event) {
-    //  console::printhex(unsafe { os::os_time_get() } as u8); console::print(" touch\n");
-    unsafe { 
-        //  Fetch the touch data from the touch controller
-        read_touchdata(&mut TOUCH_DATA)
-            .expect("touchdata fail");
-        //  Handle each touch data info
-        for i in 0..TOUCH_DATA.count as usize {
-            let TouchInfo{ x, y, action, .. } = TOUCH_DATA.touches[i];
-            //  Skip invalid responses (see note below)
-            if x == 0 && y == 0 { continue; }
-            //  Handle only touch down and contact actions, not touch up (see note below)
-            if action != 0 && action != 2 { continue; }
-            //  Handle the touch data in the UI        
-            super::handle_touch(x, y);
-
-            //  Display the touch data
-            //  druid::show_touch(x, y).expect("show touch fail");
+/// Gesture recognised by the touch controller, from the gesture ID register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HardwareGesture {
+    /// No gesture
+    None,
+    SlideDown,
+    SlideUp,
+    SlideLeft,
+    SlideRight,
+    SingleClick,
+    DoubleClick,
+    LongPress,
+    /// Gesture ID not known
+    Unknown(u8),
+}
 
+impl TouchReport {
+    /// Decode the touch registers read from register 0. Missing registers are treated as 0.
+    /// Points with an unknown finger ID or action are skipped. The controller sometimes pads the points with
+    /// all-zero points (see below), which are also skipped.
+    pub fn decode(buf: &[u8]) -> Self {
+        let reg = |i: usize| buf.get(i).copied().unwrap_or(0);
+        let mut report = TouchReport {
+            gesture: HardwareGesture::from_id(reg(HYN_GESTURE_ID_POS)),
+            fingers: reg(FT_TOUCH_POINT_NUM) & 0x0F,
+            touches: [TouchPoint::EMPTY; CFG_MAX_TOUCH_POINTS],
+            count:   0,
+        };
+        //  When the finger is released, the finger count is 0 but the first point has the touch up action
+        let points = (report.fingers as usize).max(1).min(CFG_MAX_TOUCH_POINTS);
+        for i in 0..points {
+            let step = HYN_TOUCH_STEP * i;
+            let finger = reg(HYN_TOUCH_ID_POS + step) >> 4;
+            if finger >= HYN_MAX_ID { continue; }
+
+            //  Touch action: 0 = down, 1 = up, 2 = contact
+            let action = match reg(HYN_TOUCH_EVENT_POS + step) >> 6 {
+                0 => Action::Down,
+                1 => Action::Up,
+                2 => Action::Contact,
+                _ => continue,
+            };
+            let x = ((reg(HYN_TOUCH_X_H_POS + step) & 0x0F) as u16) << 8 | reg(HYN_TOUCH_X_L_POS + step) as u16;
+            let y = ((reg(HYN_TOUCH_Y_H_POS + step) & 0x0F) as u16) << 8 | reg(HYN_TOUCH_Y_L_POS + step) as u16;
+
+            //  Skip padding points at (0, 0). Touch up may be reported at (0, 0) for the first point.
             /* Usually we get responses like:
-            touch
             count: 1, pt: 0
             act: 0, fin 0, x: 124, y: 190
 
-            Need to handle unusual responses like: 
-            touch
+            Need to handle unusual responses like:
             count: 5, pt: 1
             act: 2, fin 0, x: 166, y: 62
             act: 0, fin 0, x: 0, y: 0
             act: 0, fin 0, x: 0, y: 0
             act: 0, fin 0, x: 0, y: 0
             act: 0, fin 0, x: 0, y: 0 */
-        }    
-    }
-    /* unsafe {
-        //  Disable the console output because it may interfere with touch events
-        console::print("count: "); console::printint(TOUCH_DATA.count as i32);
-        console::print(", pt: "); console::printint(TOUCH_DATA.point_num as i32); console::print("\n");
-        for i in 0..TOUCH_DATA.count as usize {
-            let TouchInfo{ x, y, action, finger, .. } = TOUCH_DATA.touches[i];
-            console::print("act: "); console::printint(action as i32); 
-            console::print(", fin "); console::printint(finger as i32); 
-            console::print(", x: "); console::printint(x as i32); 
-            console::print(", y: "); console::printint(y as i32); console::print("\n");
+            if x == 0 && y == 0 && !(action == Action::Up && i == 0) { continue; }
+
+            //  Pressure and area are 0 if not reported
+            let pressure = reg(HYN_TOUCH_XY_POS + step);
+            let area     = reg(HYN_TOUCH_MISC   + step) >> 4;
+            report.touches[report.count as usize] = TouchPoint {
+                x, y, action, finger,
+                pressure: if pressure == 0 { None } else { Some(pressure) },
+                area:     if area     == 0 { None } else { Some(area) },
+            };
+            report.count += 1;
         }
-        console::flush();   
-    } */
-}
-
-/// Touch data will be populated here
-static mut TOUCH_DATA: TouchEventInfo = fill_zero!(TouchEventInfo);
+        report
+    }
 
-/// Read touch controller data. This only works when the screen has been tapped and the touch controller wakes up.
-/// Ported from https://github.com/lupyuen/hynitron_i2c_cst0xxse/blob/master/cst0xx_core.c#L407-L466
-fn read_touchdata(data: &mut TouchEventInfo) -> MynewtResult<()> {
-    read_register_range(           //  Read the range of I2C registers...
-        TOUCH_CONTROLLER_ADDRESS,  //  From the touch controller
-        0,                         //  Starting from register 0
-        POINT_READ_BUF as u8,      //  Number of registers to read
-        unsafe { &mut BUF }        //  Save the read data into `buf`
-    ).expect("read touchdata fail");
-    *data = fill_zero!(TouchEventInfo);
-    data.point_num = unsafe { BUF[FT_TOUCH_POINT_NUM] & 0x0F };
-    data.count     = 0;
-
-    //  Populate the first 5 touch points
-    for i in 0..CFG_MAX_TOUCH_POINTS {
-        let pointid = unsafe { BUF[HYN_TOUCH_ID_POS + HYN_TOUCH_STEP * i] } >> 4;
-        if pointid >= HYN_MAX_ID { break; }
-
-        //  Compute X and Y coordinates
-        data.count += 1;
-        let x_high = unsafe { BUF[HYN_TOUCH_X_H_POS + HYN_TOUCH_STEP * i] & 0x0F } as u16;
-        let x_low  = unsafe { BUF[HYN_TOUCH_X_L_POS + HYN_TOUCH_STEP * i] } as u16;
-        data.touches[i].x  = (x_high << 8) | x_low;
-
-        let y_high = unsafe { BUF[HYN_TOUCH_Y_H_POS + HYN_TOUCH_STEP * i] & 0x0F } as u16;
-        let y_low  = unsafe { BUF[HYN_TOUCH_Y_L_POS + HYN_TOUCH_STEP * i] } as u16;
-        data.touches[i].y  = (y_high << 8) | y_low;
-
-        //  Compute touch action (0 = down, 1 = up, 2 = contact) and finger ID
-        data.touches[i].action =
-            unsafe { BUF[HYN_TOUCH_EVENT_POS + HYN_TOUCH_STEP * i] } >> 6;
-        data.touches[i].finger =
-            unsafe { BUF[HYN_TOUCH_ID_POS    + HYN_TOUCH_STEP * i] } >> 4;
-
-        //  Compute touch pressure and area
-        data.touches[i].pressure =
-            unsafe { BUF[HYN_TOUCH_XY_POS + HYN_TOUCH_STEP * i] };  //  Can't be constant value
-        data.touches[i].area =
-            unsafe { BUF[HYN_TOUCH_MISC   + HYN_TOUCH_STEP * i] } >> 4;
-
-        //  If no more touch points, stop
-        if (data.touches[i].action == 0 || data.touches[i].action == 2)  //  If touch is down or contact
-            && (data.point_num == 0) {
-            break;
-        }
+    /// Return the valid touch points
+    pub fn points(&self) -> &[TouchPoint] {
+        &self.touches[..self.count as usize]
     }
-    Ok(())
 }
 
-/// Buffer for raw touch data
-static mut BUF: [u8; POINT_READ_BUF] = [0; POINT_READ_BUF];
-
-/// Touch Controller I2C Address: https://github.com/lupyuen/hynitron_i2c_cst0xxse
-const TOUCH_CONTROLLER_ADDRESS: u8 = 0x15;
-
-/// Touch Event Info for multiple touches. Based on https://github.com/lupyuen/hynitron_i2c_cst0xxse/blob/master/cst0xx_core.h#L104-L115
-struct TouchEventInfo {
-    /// Array of touch points
-    touches:   [TouchInfo; HYN_MAX_POINTS],
-    /// How many touch points
-    count:     u8,
-    point_num: u8,
+impl TouchPoint {
+    /// Unused touch point
+    const EMPTY: TouchPoint = TouchPoint { x: 0, y: 0, action: Action::Up, finger: 0, pressure: None, area: None };
 }
 
-/// Touch Info for a single touch. Based on https://github.com/lupyuen/hynitron_i2c_cst0xxse/blob/master/cst0xx_core.h#L104-L115
-struct TouchInfo {
-    /// X coordinate
-    x:          u16,
-    /// Y coordinate
-    y:          u16,
-    /// Action: 0 = down, 1 = up, 2 = contact
-    action:     u8,
-    /// Which finger touched
-    finger:     u8,         
-    /// Pressure of touch
-    pressure:   u8,
-    /// Area touched
-    area:       u8,
+impl HardwareGesture {
+    /// Return the gesture for the gesture ID register value
+    pub fn from_id(id: u8) -> Self {
+        match id {
+            0x00 => HardwareGesture::None,
+            0x01 => HardwareGesture::SlideDown,
+            0x02 => HardwareGesture::SlideUp,
+            0x03 => HardwareGesture::SlideLeft,
+            0x04 => HardwareGesture::SlideRight,
+            0x05 => HardwareGesture::SingleClick,
+            0x0B => HardwareGesture::DoubleClick,
+            0x0C => HardwareGesture::LongPress,
+            _    => HardwareGesture::Unknown(id),
+        }
+    }
 }
 
 //  Touch Controller Constants. Based on https://github.com/lupyuen/hynitron_i2c_cst0xxse/blob/master/cst0xx_core.h
 
 /// Max touch points for the touch controller
-const CFG_MAX_TOUCH_POINTS: usize = 5;
+pub const CFG_MAX_TOUCH_POINTS: usize = 5;
 
 /// Max touch channels for the touch controller
 const HYN_MAX_POINTS: usize = 10;
@@ -222,6 +290,7 @@ const HYN_MAX_POINTS: usize = 10;
 const HYN_MAX_ID: u8             = 0x0F;
 const HYN_TOUCH_STEP: usize      = 6;
 // const HYN_FACE_DETECT_POS: usize = 1;
+const HYN_GESTURE_ID_POS: usize  = 1;
 const HYN_TOUCH_X_H_POS: usize   = 3;
 const HYN_TOUCH_X_L_POS: usize   = 4;
 const HYN_TOUCH_Y_H_POS: usize   = 5;
@@ -231,106 +300,41 @@ const HYN_TOUCH_ID_POS: usize    = 5;
 const FT_TOUCH_POINT_NUM: usize  = 2;
 const HYN_TOUCH_XY_POS: usize    = 7;
 const HYN_TOUCH_MISC: usize      = 8;
-const POINT_READ_BUF: usize      = 3 + ( HYN_TOUCH_STEP * HYN_MAX_POINTS );
-
-/// Event that will be forwarded to the Event Queue when a touch interrupt is triggered
-static mut TOUCH_EVENT: os_event = fill_zero!(os_event);  //  Init all fields to 0 or NULL
-
-/// Read a range of I2C registers from the I2C address `addr` (7-bit address), starting at `start_register` for count `num_registers`. Save into `buffer`.
-fn read_register_range(addr: u8, start_register: u8, num_registers: u8, buffer: &mut[u8]) -> MynewtResult<()> {
-    assert!(buffer.len() >= num_registers as usize, "i2c buf");  //  Buffer too small
-    assert!(start_register + num_registers < 128, "i2c addr");   //  Not 7-bit address
-    //  Step 1: Prepare to read I2C Device Registers:
-    //    System sends Clock Signal on SCL to sync Microcontroller with I2C Device
-    //  Step 2: Transmit the I2C Address and the starting Register Number:
-    //    Send the Start Condition (High to Low SDA Transition)...
-    //    Followed by I2C Address (7 bits)...
-    //    Followed by Write Mode (1 bit, value 0)...
-    //    Followed by starting Register Number (8 bits)
-    unsafe { 
-        I2C_BUFFER[0] = start_register;  //  I2C Packet buffer contains starting Register Number (1 byte)
-        I2C_DATA.address = addr;         //  I2C Packet address (7 bits)
-        I2C_DATA.len = I2C_BUFFER.len() as u16;     //  I2C Packet data size is 1 byte
-        I2C_DATA.buffer = I2C_BUFFER.as_mut_ptr();  //  I2C Packet data points to packet buffer
-    };
-    let _rc1 = unsafe { hal::hal_i2c_master_write(1, &mut I2C_DATA, 1000, 0) };  //  No stop yet, must continue even if we hit an error
-    //  Step 3: Prepare to receive the stream of I2C Device Register values...
-    //    Send the Start Condition (High to Low SDA Transition)...
-    //    Followed by I2C Address (7 bits)...
-    //    Followed by Read Mode (1 bit, value 1)
-    unsafe { 
-        I2C_BUFFER[0] = 0x00;     //  I2C Packet buffer should be empty (provided by caller)
-        I2C_DATA.address = addr;  //  I2C Packet address (7 bits)
-        I2C_DATA.len = num_registers as u16;    //  I2C Packet data size is number of Registers to read
-        I2C_DATA.buffer = buffer.as_mut_ptr();  //  I2C Packet data points to packet buffer
-    };
-    //  Step 4: Receive the requested number of Register values from I2C Device (1 byte per register)
-    //  Step 5: Send the Stop Condition (Low to High SDA Transition)
-    let rc2 = unsafe { hal::hal_i2c_master_read(1, &mut I2C_DATA, 1000, 1) };
-    if rc2 == hal::HAL_I2C_ERR_ADDR_NACK as i32 {
-        ////assert!(false, "i2c fail");  //  I2C read failed
-        console::print("i2c fail\n"); ////
-        return Ok(());               //  TODO: Return an error
-    }
-    Ok(())
-}
+/// Number of touch registers read from the touch controller
+pub const POINT_READ_BUF: usize  = 3 + ( HYN_TOUCH_STEP * HYN_MAX_POINTS );
+
+/// Touch controller interrupt, deferred to the Default Event Queue
+static TOUCH_IRQ: GpioIrq = GpioIrq::new(TOUCH_INTERRUPT_PIN);
 
 /// Read the I2C register for the specified I2C address (7-bit address)
 #[allow(dead_code)]
 fn read_register(addr: u8, register: u8) -> MynewtResult<()> {
-    assert!(register < 128, "i2c addr");  //  Not 7-bit address
-    //  First the register number must be sent in write mode (I2C address xxxxxxx0). 
-    unsafe { 
-        I2C_BUFFER[0] = register;
-        I2C_DATA.address = addr;
-        I2C_DATA.len = I2C_BUFFER.len() as u16;
-        I2C_DATA.buffer = I2C_BUFFER.as_mut_ptr();
-    };
-    //  Then either a stop or a repeated start condition must be generated. 
-    let _rc1 = unsafe { hal::hal_i2c_master_write(1, &mut I2C_DATA, 1000, 0) };  //  No stop yet, must continue even if we hit an error
-    //  After this the slave is addressed in read mode (RW = ‘1’) at I2C address xxxxxxx1, 
-    unsafe { 
-        I2C_BUFFER[0] = 0x00;
-        I2C_DATA.address = addr;
-        I2C_DATA.len = I2C_BUFFER.len() as u16;
-        I2C_DATA.buffer = I2C_BUFFER.as_mut_ptr();
-    };
-    //  After which the slave sends out data from auto-incremented register addresses until a NOACKM and stop condition occurs.
-    let rc2 = unsafe { hal::hal_i2c_master_read(1, &mut I2C_DATA, 1000, 1) };
-    if rc2 == hal::HAL_I2C_ERR_ADDR_NACK as i32 {
-        return Ok(());
+    let mut device = RegisterDevice::new(
+        mynewt::I2C::from_port(TOUCH_I2C_PORT, TOUCH_I2C_TIMEOUT),
+        addr
+    );
+    match device.read(Register::<u8>::new(register)) {
+        Ok(value) => println!("addr: 0x{:02x}, reg: 0x{:02x} = 0x{:02x}", addr, register, value),
+        Err(MynewtError::HAL_I2C_ERR_ADDR_NACK) => {}  //  Device not responding
+        Err(err) => return Err(err),
     }
-    console::print("addr: 0x"); console::printhex(addr); 
-    console::print(", reg: 0x"); console::printhex(register); 
-    console::print(" = 0x"); console::printhex(unsafe { I2C_BUFFER[0] }); 
-    console::print("\n"); console::flush();
     Ok(())
 }
 
-/// I2C packet to be sent
-static mut I2C_DATA: hal::hal_i2c_master_data = hal::hal_i2c_master_data {
-    address: 0,
-    len:     0,
-    buffer:  core::ptr::null_mut(),
-};
-
-/// Buffer containing I2C read/write data
-static mut I2C_BUFFER: [u8; 1] =  [ 0 ];
-
 /// Probe the I2C bus to discover I2C devices
 #[allow(dead_code)]
 pub fn probe() -> MynewtResult<()> {
     //  For each I2C address 0 to 127...
     for addr in 0..128 {
         //  Probe the I2C address at I2C Port 1. Time out after 1,000 milliseconds (1 second).
-        let rc = unsafe { hal::hal_i2c_master_probe(1, addr, 1000) };
+        let rc = unsafe { hal::hal_i2c_master_probe(TOUCH_I2C_PORT, addr, TOUCH_I2C_TIMEOUT) };
         //  If we received an acknowledgement...
         if rc != hal::HAL_I2C_ERR_ADDR_NACK as i32 {
             //  I2C device found
-            console::print("0x"); console::printhex(addr); console::print("\n"); console::flush();
+            println!("0x{:02x}", addr);
         }
     }
-    console::print("Done\n"); console::flush();
+    println!("Done");
     Ok(())
 }
 /* I2C devices found:
@@ -363,7 +367,7 @@ pub fn test() -> MynewtResult<()> {
                 read_register(*addr, *register) ? ;
             }
         }
-        console::print("Done\n"); console::flush();
+        println!("Done");
     }
     Ok(())
 }
@@ -0,0 +1,173 @@
+//! Golden tests for the CHIP8 Emulator, run headless on the host:
+//! ```text
+//! cargo test --target x86_64-unknown-linux-gnu --manifest-path rust/app/Cargo.toml --features chip8_app,simulator --test chip8
+//! ```
+//! Each test runs a ROM for a fixed number of emulator steps, rendering to a mock display instead of the
+//! PineTime display. The framebuffer and the list of rendered regions are compared with the snapshot in
+//! `tests/golden`. If the snapshot is missing or different, the new snapshot is written to `<name>.txt.new`
+//! and the test fails. Review the new snapshot, then rename it or rerun with `UPDATE_GOLDEN=1` to accept it.
+use std::{
+    env,
+    fmt::Write,
+    fs,
+    path::PathBuf,
+    sync::atomic::{ AtomicBool, Ordering },
+    thread,
+};
+use app::chip8::{ self, PixelIterator, Sink, PHYSICAL_HEIGHT, PHYSICAL_WIDTH, SCREEN_HEIGHT, SCREEN_WIDTH };
+
+/// Run Blinky until the maze has been drawn and the player has started moving
+#[test]
+fn blinky() {
+    check_golden("blinky", include_bytes!("../roms/blinky.ch8"), 20_000);
+}
+
+/// Run Pong until the ball has crossed the court
+#[test]
+fn pong() {
+    check_golden("pong", include_bytes!("../roms/pong.ch8"), 20_000);
+}
+
+/// Mock display that records the rendered regions and keeps the rendered pixels in a framebuffer
+struct MockDisplay {
+    /// Physical Pixels as 16-bit colours, row by row from the top left
+    framebuffer: Vec<u16>,
+    /// Rendered regions of Physical Pixels: (left, top, right, bottom) inclusive
+    regions:     Vec<(u8, u8, u8, u8)>,
+    /// Emulated clock in nanoseconds
+    clock:       u64,
+    /// Number of emulator steps so far
+    steps:       u32,
+    /// Stop the emulator after this number of steps
+    max_steps:   u32,
+}
+
+impl MockDisplay {
+    /// Return a blank mock display that stops the emulator after `max_steps` steps
+    fn new(max_steps: u32) -> Self {
+        MockDisplay {
+            framebuffer: vec![0; PHYSICAL_WIDTH * PHYSICAL_HEIGHT],
+            regions:     Vec::new(),
+            clock:       0,
+            steps:       0,
+            max_steps,
+        }
+    }
+
+    /// Return the snapshot of the framebuffer and regions as text, so that differences are easy to review
+    fn snapshot(&self, name: &str) -> String {
+        let mut text = String::new();
+        writeln!(text, "# CHIP8 golden snapshot: {}, {} steps", name, self.max_steps).unwrap();
+        writeln!(text, "regions: {}", self.regions.len()).unwrap();
+        for (left, top, right, bottom) in &self.regions {
+            writeln!(text, "  {}, {}, {}, {}", left, top, right, bottom).unwrap();
+        }
+        writeln!(text, "framebuffer: {:016x}", fnv1a(&self.framebuffer)).unwrap();
+        //  Sample the top left Physical Pixel of each Virtual Pixel. Not meaningful for the curved surface, which
+        //  is covered by the framebuffer hash.
+        let (pixel_width, pixel_height) = (PHYSICAL_WIDTH / SCREEN_WIDTH, PHYSICAL_HEIGHT / SCREEN_HEIGHT);
+        for y in 0..SCREEN_HEIGHT {
+            text.push('|');
+            for x in 0..SCREEN_WIDTH {
+                let color = self.framebuffer[y * pixel_height * PHYSICAL_WIDTH + x * pixel_width];
+                text.push(shade(color));
+            }
+            text.push_str("|\n");
+        }
+        text
+    }
+}
+
+impl Sink for MockDisplay {
+    /// Record the region and copy the Physical Pixels into the framebuffer
+    fn set_pixels(&mut self, left: u8, top: u8, right: u8, bottom: u8, pixels: &mut PixelIterator) {
+        assert!(left <= right && top <= bottom, "empty region");
+        assert!((right as usize) < PHYSICAL_WIDTH && (bottom as usize) < PHYSICAL_HEIGHT, "region overflow");
+        self.regions.push((left, top, right, bottom));
+        let mut count = 0;
+        for y in top..=bottom {
+            for x in left..=right {
+                let color = pixels.next().expect("too few pixels");
+                self.framebuffer[y as usize * PHYSICAL_WIDTH + x as usize] = color;
+                count += 1;
+            }
+        }
+        assert!(pixels.next().is_none(), "too many pixels after {}", count);
+    }
+
+    /// Advance the clock by 1 millisecond per call, so that the timers run the same way in every test
+    fn clock(&mut self) -> u64 {
+        self.clock += 1_000_000;
+        self.clock
+    }
+
+    /// Stop after the max number of steps
+    fn step(&mut self) -> bool {
+        self.steps += 1;
+        self.steps >= self.max_steps
+    }
+}
+
+/// Run the ROM on the mock display and compare the snapshot with the golden snapshot
+fn check_golden(name: &str, rom: &[u8], max_steps: u32) {
+    //  The emulator keeps the Virtual Screen in a static buffer, so run one emulator at a time
+    let _running = Running::start();
+    let mut display = MockDisplay::new(max_steps);
+    chip8::run(&mut display, rom);
+    assert!(!display.regions.is_empty(), "{}: nothing rendered", name);
+    let actual = display.snapshot(name);
+
+    let suffix = if cfg!(feature = "chip8_curve") { "_curve" } else { "" };
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}{}.txt", name, suffix));
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, &actual).expect("can't write snapshot");
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_default();
+    if actual != expected {
+        let new_path = path.with_extension("txt.new");
+        fs::write(&new_path, &actual).expect("can't write snapshot");
+        panic!("{}: snapshot differs from {}, new snapshot written to {}. Review it and rerun with UPDATE_GOLDEN=1 to accept it.",
+            name, path.display(), new_path.display());
+    }
+}
+
+/// Return the character for the 16-bit colour from `convert_color()`: `#` for white, `+` for greenish,
+/// `.` for dark blue (fading to black), space for black
+fn shade(color: u16) -> char {
+    let red   = color >> 11;
+    let green = (color >> 5) & 0x3f;
+    if color == 0 { ' ' }
+    else if green == 0 { '.' }
+    else if red >= 0x1e { '#' }
+    else { '+' }
+}
+
+/// Return the FNV-1a hash of the framebuffer
+fn fnv1a(pixels: &[u16]) -> u64 {
+    pixels.iter()
+        .flat_map(|color| color.to_le_bytes().to_vec())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3))
+}
+
+/// Runs one emulator at a time, since the emulator uses static buffers. Stops running when dropped.
+struct Running;
+
+impl Running {
+    /// Wait for the running emulator to stop, then start running
+    fn start() -> Self {
+        while RUNNING.compare_and_swap(false, true, Ordering::Acquire) { thread::yield_now(); }
+        Running
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.store(false, Ordering::Release);
+    }
+}
+
+/// True if an emulator is running
+static RUNNING: AtomicBool = AtomicBool::new(false);
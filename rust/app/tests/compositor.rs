@@ -0,0 +1,108 @@
+//! Tests for splitting and merging the dirty rectangles of the compositor, run on the host:
+//! ```text
+//! cargo test --target x86_64-unknown-linux-gnu --manifest-path rust/app/Cargo.toml --features simulator --test compositor
+//! ```
+use app::compositor::{ Compositor, Rect };
+
+/// Narrow rectangles are split into chunks of whole rows. The last chunk may have fewer rows.
+#[test]
+fn chunks_of_whole_rows() {
+    let chunks: Vec<Rect> = Rect::new(10, 20, 19, 26).chunks(30).collect();
+    assert_eq!(chunks, vec![
+        Rect::new(10, 20, 19, 22),
+        Rect::new(10, 23, 19, 25),
+        Rect::new(10, 26, 19, 26),
+    ]);
+}
+
+/// Rectangles wider than the chunk are split into parts of each row
+#[test]
+fn chunks_of_partial_rows() {
+    let chunks: Vec<Rect> = Rect::new(0, 5, 63, 6).chunks(25).collect();
+    assert_eq!(chunks, vec![
+        Rect::new(0, 5, 24, 5), Rect::new(25, 5, 49, 5), Rect::new(50, 5, 63, 5),
+        Rect::new(0, 6, 24, 6), Rect::new(25, 6, 49, 6), Rect::new(50, 6, 63, 6),
+    ]);
+}
+
+/// Chunks cover every pixel of the rectangle exactly once, and never exceed the max number of pixels
+#[test]
+fn chunks_cover_rectangle() {
+    let rect = Rect::new(3, 7, 66, 38);
+    for &max_pixels in &[0, 1, 25, 64, 160, 2048, 4096] {
+        let mut covered = Vec::new();
+        for chunk in rect.chunks(max_pixels) {
+            assert!(chunk.area() as usize <= max_pixels.max(1), "chunk {:?} exceeds {} pixels", chunk, max_pixels);
+            covered.extend(chunk.points());
+        }
+        let expected: Vec<(u16, u16)> = rect.points().collect();
+        covered.sort_by_key(|&(x, y)| (y, x));
+        assert_eq!(covered, expected, "max_pixels: {}", max_pixels);
+    }
+}
+
+/// Overlapping and adjacent rectangles are merged. Rectangles that are apart stay separate.
+#[test]
+fn invalidate_merges_touching_rects() {
+    let mut compositor = Compositor::new();
+    assert!(!compositor.is_dirty());
+    compositor.invalidate(Rect::new(0, 0, 9, 9));
+    compositor.invalidate(Rect::new(5, 5, 14, 14));    //  Overlaps
+    compositor.invalidate(Rect::new(15, 0, 19, 4));    //  Adjacent on the right
+    compositor.invalidate(Rect::new(30, 30, 39, 39));  //  Apart
+    assert_eq!(sorted(compositor.dirty_rects()), vec![
+        Rect::new(0, 0, 19, 14),
+        Rect::new(30, 30, 39, 39),
+    ]);
+}
+
+/// A rectangle that joins two dirty rectangles merges all three
+#[test]
+fn invalidate_merges_transitively() {
+    let mut compositor = Compositor::new();
+    compositor.invalidate(Rect::new(0, 0, 4, 4));
+    compositor.invalidate(Rect::new(10, 0, 14, 4));
+    compositor.invalidate(Rect::new(5, 0, 9, 0));  //  Touches both
+    assert_eq!(compositor.dirty_rects(), &[Rect::new(0, 0, 14, 4)]);
+}
+
+/// When there are too many dirty rectangles, the new rectangle is merged with the one that adds the fewest pixels
+#[test]
+fn invalidate_merges_when_full() {
+    let mut compositor = Compositor::new();
+    for i in 0..8 {
+        compositor.invalidate(Rect::new(i * 10, 0, i * 10, 0));
+    }
+    assert_eq!(compositor.dirty_rects().len(), 8);
+    compositor.invalidate(Rect::new(72, 0, 72, 0));  //  Closest to the rectangle at column 70
+    assert_eq!(compositor.dirty_rects().len(), 8);
+    assert!(compositor.dirty_rects().contains(&Rect::new(70, 0, 72, 0)));
+}
+
+/// With 1 dirty rectangle, the changes are merged into their bounding box
+#[test]
+fn invalidate_bounding_box() {
+    let mut compositor = Compositor::with_max_rects(1);
+    compositor.invalidate(Rect::new(2, 3, 2, 3));
+    compositor.invalidate(Rect::new(40, 20, 41, 20));
+    compositor.invalidate(Rect::new(10, 1, 10, 1));
+    assert_eq!(compositor.dirty_rects(), &[Rect::new(2, 1, 41, 20)]);
+}
+
+/// Flushing writes the clipped chunks and clears the dirty rectangles
+#[test]
+fn flush_chunks_clips_and_clears() {
+    let mut compositor = Compositor::new();
+    compositor.invalidate(Rect::new(60, 30, 69, 39));
+    let mut written = Vec::new();
+    compositor.flush_chunks(64, 32, 8, |chunk| { written.push(chunk); Ok(()) }).unwrap();
+    assert_eq!(written, vec![Rect::new(60, 30, 63, 31)]);
+    assert!(!compositor.is_dirty());
+}
+
+/// Return the rectangles sorted by position, since the compositor may reorder them
+fn sorted(rects: &[Rect]) -> Vec<Rect> {
+    let mut rects = rects.to_vec();
+    rects.sort_by_key(|rect| (rect.top, rect.left));
+    rects
+}
@@ -0,0 +1,89 @@
+//! Simulator test for the ST7789 display driver and the dirty-rectangle compositor, run on the host:
+//! ```text
+//! cargo test --target x86_64-unknown-linux-gnu --manifest-path rust/app/Cargo.toml --features simulator --test display
+//! ```
+//! A Mynewt task in the simulator draws the dirty rectangles with `Compositor::flush()` through the ST7789 driver.
+//! The test checks the pixels in the framebuffer of the simulated ST7789 controller.
+use std::{
+    sync::atomic::{ AtomicI32, Ordering },
+    thread,
+    time::Duration,
+};
+use app::{
+    compositor::{ Compositor, Rect },
+    st7789::{ Rotation, ST7789, HEIGHT, LCD_RESET_PIN, WIDTH },
+};
+use mynewt::{
+    result::*,
+    kernel::os,
+    sim,
+    spi,
+};
+
+/// Display drawn by the test task
+static mut DISPLAY: ST7789 = ST7789::new(&spi::DISPLAY, LCD_RESET_PIN);
+
+/// Result of drawing: 0 while drawing, 1 after all requests have been sent, else the negated error code
+static RESULT: AtomicI32 = AtomicI32::new(0);
+
+/// Dirty rectangles drawn by the test task. Far apart so that they are not merged.
+const DIRTY_RECTS: [Rect; 3] = [
+    Rect { left: 10,  top: 20,  right: 49,  bottom: 59  },  //  Fits in one chunk
+    Rect { left: 0,   top: 150, right: 239, bottom: 199 },  //  Split into chunks of the SPI buffer
+    Rect { left: 200, top: 100, right: 300, bottom: 120 },  //  Clipped at the right edge
+];
+
+/// Rectangles that are drawn and flushed appear in the framebuffer. Pixels outside the rectangles are not written.
+#[test]
+fn compositor_flushes_to_display() {
+    sim::run(draw_task, "wait 500").unwrap();
+    for _ in 0..50 {
+        if RESULT.load(Ordering::SeqCst) != 0 { break; }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(RESULT.load(Ordering::SeqCst), 1, "drawing failed or timed out");
+
+    let pixels = sim::display::framebuffer();
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let inside = DIRTY_RECTS.iter()
+                .any(|rect| x >= rect.left && x <= rect.right && y >= rect.top && y <= rect.bottom);
+            let expected = if inside { color_at(x, y) } else { 0 };
+            assert_eq!(pixels[y as usize * WIDTH as usize + x as usize], expected, "pixel ({}, {})", x, y);
+        }
+    }
+}
+
+/// Task that draws the dirty rectangles, then sleeps forever
+extern "C" fn draw_task() -> ! {
+    if let Err(err) = draw() { RESULT.store(-(err as i32), Ordering::SeqCst); }
+    loop { unsafe { os::os_time_delay(1000) }; }
+}
+
+/// Initialise the display and flush the dirty rectangles. `RESULT` is set after the last request has been sent.
+fn draw() -> MynewtResult<()> {
+    let display = unsafe { &mut DISPLAY };
+    spi::spi_noblock_init() ? ;
+    display.init(Rotation::Deg0) ? ;
+    let mut compositor = Compositor::new();
+    for rect in DIRTY_RECTS.iter() { compositor.invalidate(*rect); }
+    compositor.flush(display, |chunk|
+        chunk.points().map(|(x, y)| color_at(x, y))
+    ) ? ;
+    //  Requests are sent in order, so the callback of an empty request is called after the pixels have been sent
+    spi::DISPLAY.write(None, &[], Some(drawn))
+}
+
+/// Called by the SPI task after the pixels have been sent
+fn drawn(res: MynewtResult<()>) {
+    let result = match res {
+        Ok(())   => 1,
+        Err(err) => -(err as i32),
+    };
+    RESULT.store(result, Ordering::SeqCst);
+}
+
+/// Return a different 16-bit colour for every pixel, so that misplaced pixels are detected
+fn color_at(x: u16, y: u16) -> u16 {
+    (x << 8) | y
+}
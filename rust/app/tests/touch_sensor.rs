@@ -0,0 +1,131 @@
+//! Tests for decoding the CST816S touch controller data, run on the host:
+//! ```text
+//! cargo test --target x86_64-unknown-linux-gnu --manifest-path rust/app/Cargo.toml --features simulator --test touch_sensor
+//! ```
+//! The raw registers are rebuilt from the responses captured on PineTime, noted in `src/touch_sensor.rs`.
+use app::touch_sensor::{ Action, HardwareGesture, TouchPoint, TouchReport, POINT_READ_BUF };
+
+/// Return the touch registers, starting at register 0, with the gesture ID, finger count and touch points.
+/// Each point is (action, finger, x, y, pressure, area).
+fn registers(gesture: u8, fingers: u8, points: &[(u8, u8, u16, u16, u8, u8)]) -> [u8; POINT_READ_BUF] {
+    let mut buf = [0; POINT_READ_BUF];
+    buf[1] = gesture;
+    buf[2] = fingers;
+    for (i, &(action, finger, x, y, pressure, area)) in points.iter().enumerate() {
+        let reg = &mut buf[3 + 6 * i..];
+        reg[0] = action << 6 | (x >> 8) as u8;
+        reg[1] = x as u8;
+        reg[2] = finger << 4 | (y >> 8) as u8;
+        reg[3] = y as u8;
+        reg[4] = pressure;
+        reg[5] = area << 4;
+    }
+    buf
+}
+
+/// Usual response: `count: 1, pt: 0` and `act: 0, fin 0, x: 124, y: 190`
+#[test]
+fn touch_down() {
+    let report = TouchReport::decode(&registers(0x00, 0, &[(0, 0, 124, 190, 0, 0)]));
+    assert_eq!(report.gesture, HardwareGesture::None);
+    assert_eq!(report.fingers, 0);
+    assert_eq!(report.points(), &[
+        TouchPoint { x: 124, y: 190, action: Action::Down, finger: 0, pressure: None, area: None },
+    ]);
+}
+
+/// Unusual response: `count: 5, pt: 1` with a contact at (166, 62), padded with 4 points at (0, 0)
+#[test]
+fn contact_padded_with_zero_points() {
+    let report = TouchReport::decode(&registers(0x00, 1, &[
+        (2, 0, 166, 62, 0, 0),
+        (0, 0, 0, 0, 0, 0),
+        (0, 0, 0, 0, 0, 0),
+        (0, 0, 0, 0, 0, 0),
+        (0, 0, 0, 0, 0, 0),
+    ]));
+    assert_eq!(report.fingers, 1);
+    assert_eq!(report.points(), &[
+        TouchPoint { x: 166, y: 62, action: Action::Contact, finger: 0, pressure: None, area: None },
+    ]);
+}
+
+/// Zero points after the real point are skipped when the controller reports more than one finger
+#[test]
+fn fingers_padded_with_zero_points() {
+    let report = TouchReport::decode(&registers(0x00, 5, &[
+        (2, 0, 166, 62, 0, 0),
+        (0, 0, 0, 0, 0, 0),
+        (0, 0, 0, 0, 0, 0),
+        (0, 0, 0, 0, 0, 0),
+        (0, 0, 0, 0, 0, 0),
+    ]));
+    assert_eq!(report.fingers, 5);
+    assert_eq!(report.count, 1);
+    assert_eq!(report.points(), &[
+        TouchPoint { x: 166, y: 62, action: Action::Contact, finger: 0, pressure: None, area: None },
+    ]);
+}
+
+/// All-zero response when the controller is waking up: a touch down at (0, 0) is not a touch
+#[test]
+fn all_zero_response() {
+    let report = TouchReport::decode(&[0; POINT_READ_BUF]);
+    assert_eq!(report.gesture, HardwareGesture::None);
+    assert_eq!(report.count, 0);
+    assert!(report.points().is_empty());
+}
+
+/// Touch up has no fingers touching, and may be reported at (0, 0)
+#[test]
+fn touch_up() {
+    let report = TouchReport::decode(&registers(0x05, 0, &[(1, 0, 0, 0, 0, 0)]));
+    assert_eq!(report.fingers, 0);
+    assert_eq!(report.points(), &[
+        TouchPoint { x: 0, y: 0, action: Action::Up, finger: 0, pressure: None, area: None },
+    ]);
+}
+
+/// Gesture ID register, captured as `reg: 0x01 = 0x05` (single click) and `reg: 0x01 = 0x04` (slide right)
+#[test]
+fn gesture_id() {
+    let single_click = TouchReport::decode(&[0x00, 0x05]);
+    assert_eq!(single_click.gesture, HardwareGesture::SingleClick);
+    let slide_right = TouchReport::decode(&[0x00, 0x04]);
+    assert_eq!(slide_right.gesture, HardwareGesture::SlideRight);
+    for &(id, gesture) in &[
+        (0x01, HardwareGesture::SlideDown),
+        (0x02, HardwareGesture::SlideUp),
+        (0x03, HardwareGesture::SlideLeft),
+        (0x0B, HardwareGesture::DoubleClick),
+        (0x0C, HardwareGesture::LongPress),
+        (0x42, HardwareGesture::Unknown(0x42)),
+    ] {
+        assert_eq!(HardwareGesture::from_id(id), gesture);
+    }
+}
+
+/// Pressure and area are decoded when reported, and coordinates above 255 use the high bits
+#[test]
+fn pressure_area_and_high_bits() {
+    let report = TouchReport::decode(&registers(0x00, 1, &[(2, 0, 0x123, 0x0EF, 0x20, 0x1)]));
+    assert_eq!(report.points(), &[
+        TouchPoint { x: 0x123, y: 0x0EF, action: Action::Contact, finger: 0, pressure: Some(0x20), area: Some(0x1) },
+    ]);
+}
+
+/// Multiple fingers are decoded in order. Points with an invalid finger ID (0x0F) or action (3) are skipped.
+#[test]
+fn multiple_fingers() {
+    let report = TouchReport::decode(&registers(0x00, 4, &[
+        (0, 0, 10, 20, 0, 0),
+        (2, 1, 30, 40, 0, 0),
+        (0, 0x0F, 50, 60, 0, 0),
+        (3, 2, 70, 80, 0, 0),
+    ]));
+    assert_eq!(report.fingers, 4);
+    assert_eq!(report.points(), &[
+        TouchPoint { x: 10, y: 20, action: Action::Down,    finger: 0, pressure: None, area: None },
+        TouchPoint { x: 30, y: 40, action: Action::Contact, finger: 1, pressure: None, area: None },
+    ]);
+}
@@ -0,0 +1,67 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *  http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing,
+ * software distributed under the License is distributed on an
+ * "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+ * KIND, either express or implied.  See the License for the
+ * specific language governing permissions and limitations
+ * under the License.
+ */
+//! Mynewt Macro that hashes OS function names for the Dispatch Table
+extern crate proc_macro;
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{
+    parse::{ Parse, ParseStream },
+    parse_macro_input,
+    Ident,
+    LitStr,
+};
+
+/// FNV-1a offset basis. Must match `libs/mynewt_rust/src/dispatch.c`
+const FNV_OFFSET: u32 = 0x811c_9dc5;
+
+/// FNV-1a prime. Must match `libs/mynewt_rust/src/dispatch.c`
+const FNV_PRIME: u32 = 0x0100_0193;
+
+/// Return the 32-bit FNV-1a hash of the function name
+pub fn hash_name(name: &str) -> u32 {
+    name.bytes().fold(FNV_OFFSET, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Function name passed to `dispatch_hash!()`, either a string or an identifier
+struct FunctionName(String);
+
+impl Parse for FunctionName {
+    /// Parse `"console_buffer"` or `console_buffer`. Identifiers are accepted so that `dispatch_hash!()` may be called in `macro_rules!`.
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(LitStr) {
+            let name: LitStr = input.parse()?;
+            Ok(FunctionName(name.value()))
+        } else {
+            let name: Ident = input.parse()?;
+            Ok(FunctionName(name.to_string()))
+        }
+    }
+}
+
+/// Given the function name `"console_buffer"` or `console_buffer`, return the hash as a `u32` literal
+pub fn dispatch_hash_internal(item: TokenStream) -> TokenStream {
+    //  Parse the macro input as a function name e.g. `"console_buffer"`.
+    let input = parse_macro_input!(item as FunctionName);
+    let hash = hash_name(&input.0);
+    //  Return the hash as a literal.
+    let expanded = quote! { #hash };
+    TokenStream::from(expanded)
+}
@@ -25,6 +25,8 @@
 
 mod safe_wrap;   //  Include safe_wrap.rs
 mod infer_type;  //  Include infer_type.rs
+mod task;        //  Include task.rs
+mod dispatch;    //  Include dispatch.rs
 
 extern crate proc_macro;
 use proc_macro::TokenStream;
@@ -59,6 +61,33 @@ pub fn out(item: TokenStream) -> TokenStream {
     expanded.parse().unwrap()
 }
 
+/// Declare a Mynewt task, its stack and its priority in one place, then start the task.
+/// Fails to compile if the stack size is not aligned to `OS_STACK_ALIGNMENT`. Returns `MynewtResult<TaskHandle>`.
+/// ```
+/// let handle = task!(
+///     name:     "spi",          //  Name of task
+///     func:     spi_task_func,  //  Function to execute when task starts
+///     priority: 10,             //  Task priority: highest is 0, lowest is 255 (main task is 127)
+///     stack:    256,            //  Size of the stack (in 4-byte units)
+///     arg:      NULL,           //  Optional: Argument to be passed to above function
+///     sanity:   1000,           //  Optional: Sanity checking interval in ticks. Default is `OS_WAIT_FOREVER`
+/// ) ? ;
+/// ```
+#[proc_macro]
+pub fn task(item: TokenStream) -> TokenStream {
+    task::task_internal(item)
+}
+
+/// Return the hash of an OS function name for looking up the Dispatch Table, as a `u32` literal.
+/// Same as the hashes in `libs/mynewt_rust/src/dispatch.c`.
+/// ```
+/// let addr = get_dispatch_address(dispatch_hash!("console_buffer")) ? ;
+/// ```
+#[proc_macro]
+pub fn dispatch_hash(item: TokenStream) -> TokenStream {
+    dispatch::dispatch_hash_internal(item)
+}
+
 /// Create a `Strn` containing a null-terminated byte string that's suitable for passing to Mynewt APIs.
 /// `strn!("network")` expands to `&Strn::new( b"network\0" )`.
 /// `strn!(())` expands to `&Strn::new( b"\0" )`.
@@ -0,0 +1,140 @@
+/*
+ * Licensed to the Apache Software Foundation (ASF) under one
+ * or more contributor license agreements.  See the NOTICE file
+ * distributed with this work for additional information
+ * regarding copyright ownership.  The ASF licenses this file
+ * to you under the Apache License, Version 2.0 (the
+ * "License"); you may not use this file except in compliance
+ * with the License.  You may obtain a copy of the License at
+ *
+ *  http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing,
+ * software distributed under the License is distributed on an
+ * "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+ * KIND, either express or implied.  See the License for the
+ * specific language governing permissions and limitations
+ * under the License.
+ */
+//! Mynewt Macro that declares a task, its stack and its priority in one place
+extern crate proc_macro;
+use proc_macro::TokenStream;
+use quote::{quote, quote_spanned};
+use syn::{
+    parse::Parser,
+    punctuated::Punctuated,
+    spanned::Spanned,
+    token::Comma,
+    Expr,
+    FieldValue,
+    Lit,
+    Member,
+};
+
+/// Stack alignment in bytes, same as `OS_STACK_ALIGNMENT` in Mynewt
+const OS_STACK_ALIGNMENT: u64 = 8;
+
+/// Size of each stack element `os_stack_t` in bytes
+const OS_STACK_T_SIZE: u64 = 4;
+
+/// Given the task fields `name: "spi", func: spi_task_func, priority: 10, stack: 256`, return the tokens
+/// that declare the task and stack as `static` items and start the task.
+pub fn task_internal(item: TokenStream) -> TokenStream {
+    //  Parse the macro input as a list of fields like `name: "spi", ...`
+    let parser = Punctuated::<FieldValue, Comma>::parse_terminated;
+    let fields = match parser.parse(item) {
+        Ok(fields) => fields,
+        Err(err)   => return err.to_compile_error().into(),
+    };
+    //  Collect the field values
+    let mut name:     Option<Expr> = None;
+    let mut func:     Option<Expr> = None;
+    let mut priority: Option<Expr> = None;
+    let mut stack:    Option<Expr> = None;
+    let mut arg:      Option<Expr> = None;
+    let mut sanity:   Option<Expr> = None;
+    for field in fields {
+        let key = match &field.member {
+            Member::Named(ident) => ident.to_string(),
+            Member::Unnamed(_)   => String::new(),
+        };
+        match key.as_str() {
+            "name"     => { name     = Some(field.expr); }
+            "func"     => { func     = Some(field.expr); }
+            "priority" => { priority = Some(field.expr); }
+            "stack"    => { stack    = Some(field.expr); }
+            "arg"      => { arg      = Some(field.expr); }
+            "sanity"   => { sanity   = Some(field.expr); }
+            _ => {
+                //  Unknown field
+                let span = field.member.span();
+                return quote_spanned!(span=> compile_error!("task!() fields are: name, func, priority, stack, arg, sanity")).into();
+            }
+        }
+    }
+    //  `name`, `func`, `priority` and `stack` are mandatory
+    let span = proc_macro2::Span::call_site();
+    let (name, func, priority, stack) = match (name, func, priority, stack) {
+        (Some(name), Some(func), Some(priority), Some(stack)) => (name, func, priority, stack),
+        _ => return quote_spanned!(span=> compile_error!("task!() requires name, func, priority and stack")).into(),
+    };
+    //  Name must be a string literal, which will be converted to a null-terminated byte string
+    let name_bytes = match &name {
+        Expr::Lit(lit) => match &lit.lit {
+            Lit::Str(s) => syn::LitByteStr::new((s.value() + "\0").as_bytes(), s.span()),
+            _ => return quote_spanned!(lit.span()=> compile_error!("task!() name must be a string")).into(),
+        }
+        _ => return quote_spanned!(name.span()=> compile_error!("task!() name must be a string")).into(),
+    };
+    //  If the stack size is a literal number, check the alignment now so that we get a friendlier error
+    if let Expr::Lit(lit) = &stack {
+        if let Lit::Int(size) = &lit.lit {
+            let size: u64 = size.base10_parse().unwrap_or(0);
+            if size == 0 || (size * OS_STACK_T_SIZE) % OS_STACK_ALIGNMENT != 0 {
+                return quote_spanned!(lit.span()=> compile_error!("task!() stack size must be a non-zero multiple of 2 (8-byte aligned)")).into();
+            }
+        }
+    }
+    //  Optional fields
+    let arg = match arg {
+        Some(arg) => quote! { #arg },
+        None      => quote! { mynewt::NULL },
+    };
+    let sanity = match sanity {
+        Some(sanity) => quote! { #sanity },
+        None         => quote! { mynewt::kernel::os::OS_WAIT_FOREVER as u32 },  //  Don't do sanity / watchdog checking
+    };
+    //  Compose the expanded tokens. The `static` items are scoped inside the block so they won't clash.
+    let expanded = quote! {
+        {
+            /// Size of the stack (in 4-byte units)
+            const TASK_STACK_SIZE: usize = #stack;
+            /// Fails to compile if the stack size is not aligned to `OS_STACK_ALIGNMENT`
+            #[allow(dead_code)]
+            const TASK_STACK_ALIGNED: [(); 0] = [();
+                (TASK_STACK_SIZE * core::mem::size_of::<mynewt::kernel::os::os_stack_t>())
+                % (mynewt::kernel::os::OS_STACK_ALIGNMENT as usize)
+            ];
+            /// Task object
+            static mut TASK: mynewt::kernel::os::os_task = mynewt::fill_zero!(mynewt::kernel::os::os_task);
+            /// Stack space for the task, initialised to 0
+            static mut TASK_STACK: [mynewt::kernel::os::os_stack_t; TASK_STACK_SIZE] = [0; TASK_STACK_SIZE];
+            /// Name of the task
+            static TASK_NAME: mynewt::Strn = mynewt::Strn {
+                rep: mynewt::StrnRep::ByteStr(#name_bytes)
+            };
+            //  Create the task and start it. Returns `MynewtResult<TaskHandle>`
+            mynewt::kernel::task::TaskHandle::spawn(
+                unsafe { &mut TASK },  //  Task object will be saved here
+                &TASK_NAME,            //  Name of task
+                Some( #func ),         //  Function to execute when task starts
+                #arg,                  //  Argument to be passed to above function
+                #priority,             //  Task priority: highest is 0, lowest is 255 (main task is 127)
+                #sanity,               //  Sanity / watchdog checking interval
+                unsafe { &mut TASK_STACK }  //  Stack space for the task
+            )
+        }
+    };
+    //  Return the expanded tokens back to the compiler.
+    TokenStream::from(expanded)
+}
@@ -662,7 +662,7 @@ macro_rules! json_rep_set_array {
     unsafe {
       mynewt::libs::mynewt_rust::json_helper_set_array(
         $context.to_void_ptr(),
-        $context.key_to_cstr(key_with_null.as_bytes())
+        $context.key_to_cstr(key_with_null.as_bytes()) as *const ::cty::c_char
       ); 
     };
   }};
@@ -679,7 +679,7 @@ macro_rules! json_rep_set_array {
     unsafe {
       mynewt::libs::mynewt_rust::json_helper_set_array(
         $context.to_void_ptr(),
-        $context.key_to_cstr(key_with_opt_null)
+        $context.key_to_cstr(key_with_opt_null) as *const ::cty::c_char
       ); 
     };
   }};
@@ -701,7 +701,7 @@ macro_rules! json_rep_close_array {
     unsafe { 
       mynewt::libs::mynewt_rust::json_helper_close_array(
         $context.to_void_ptr(),
-        $context.key_to_cstr(key_with_null.as_bytes())
+        $context.key_to_cstr(key_with_null.as_bytes()) as *const ::cty::c_char
       ) 
     };
   }};
@@ -716,7 +716,7 @@ macro_rules! json_rep_close_array {
     unsafe { 
       mynewt::libs::mynewt_rust::json_helper_close_array(
         $context.to_void_ptr(),
-        $context.key_to_cstr(key_with_opt_null)
+        $context.key_to_cstr(key_with_opt_null) as *const ::cty::c_char
       ) 
     };
   }};
@@ -738,7 +738,7 @@ macro_rules! json_rep_object_array_start_item {
     let key_with_null: &str = $crate::stringify_null!($context);    //  TODO
     unsafe { 
       mynewt::libs::mynewt_rust::json_helper_object_array_start_item(
-        $context.key_to_cstr(key_with_null.as_bytes())
+        $context.key_to_cstr(key_with_null.as_bytes()) as *const ::cty::c_char
       ) 
     };
   }};
@@ -753,7 +753,7 @@ macro_rules! json_rep_object_array_start_item {
     let key_with_opt_null: &[u8] = $context.to_bytes_optional_nul();  //  TODO
     unsafe { 
       mynewt::libs::mynewt_rust::json_helper_object_array_start_item(
-        $context.key_to_cstr(key_with_opt_null)
+        $context.key_to_cstr(key_with_opt_null) as *const ::cty::c_char
       ) 
     };
   }};
@@ -774,7 +774,7 @@ macro_rules! json_rep_object_array_end_item {
     let key_with_null: &str = $crate::stringify_null!($context);  //  TODO
     unsafe { 
       mynewt::libs::mynewt_rust::json_helper_object_array_end_item(
-        $context.key_to_cstr(key_with_null.as_bytes())
+        $context.key_to_cstr(key_with_null.as_bytes()) as *const ::cty::c_char
       ) 
     };
   }};
@@ -788,7 +788,7 @@ macro_rules! json_rep_object_array_end_item {
     let key_with_opt_null: &[u8] = $context.to_bytes_optional_nul();  //  TODO
     unsafe { 
       mynewt::libs::mynewt_rust::json_helper_object_array_end_item(
-        $context.key_to_cstr(key_with_opt_null)
+        $context.key_to_cstr(key_with_opt_null) as *const ::cty::c_char
       ) 
     };
   }};
@@ -811,7 +811,7 @@ macro_rules! json_rep_set_int {
     unsafe {
       mynewt::libs::mynewt_rust::json_helper_set_int(
         $context.to_void_ptr(),
-        $context.key_to_cstr(key_with_null.as_bytes()),
+        $context.key_to_cstr(key_with_null.as_bytes()) as *const ::cty::c_char,
         value
       )
     };
@@ -831,7 +831,7 @@ macro_rules! json_rep_set_int {
     unsafe {
       mynewt::libs::mynewt_rust::json_helper_set_int(
         $context.to_void_ptr(), 
-        $context.key_to_cstr(key_with_opt_null),
+        $context.key_to_cstr(key_with_opt_null) as *const ::cty::c_char,
         value
       )
     };
@@ -868,8 +868,8 @@ macro_rules! json_rep_set_text_string {
     unsafe {
       mynewt::libs::mynewt_rust::json_helper_set_text_string(
         $context.to_void_ptr(), 
-        $context.key_to_cstr(key_with_opt_null),
-        $context.value_to_cstr(value_with_opt_null)
+        $context.key_to_cstr(key_with_opt_null) as *const ::cty::c_char,
+        $context.value_to_cstr(value_with_opt_null) as *const ::cty::c_char
       )
     };
   }};
@@ -0,0 +1,387 @@
+//! Async Executor that runs Rust Futures on Mynewt Event Queues. Each spawned Future is polled by the task
+//! that runs the Event Queue, e.g. the main task for the Default Event Queue. Wakers post an event to the
+//! Event Queue via `os_eventq_put()`, so they may be called from interrupt handlers.
+//! Futures are stored in a static pool, so no heap is needed:
+//! ```
+//! async fn blink() {
+//!     loop {
+//!         led.set_high().unwrap();
+//!         executor::delay(500).await;
+//!         led.set_low().unwrap();
+//!         executor::delay(500).await;
+//!     }
+//! }
+//! executor::spawn(os::eventq_dflt_get() ?, blink()) ? ;
+//! ```
+use core::{
+    cell::{ Cell, UnsafeCell },
+    future::Future,
+    marker::PhantomPinned,
+    pin::Pin,
+    task::{ Context, Poll, RawWaker, RawWakerVTable, Waker },
+};
+use crate::{
+    result::*,
+    hal::{ GpioIrq, Pull, Trigger },
+    kernel::{
+        os,
+        sync::{ critical_section, Semaphore },
+    },
+    Ptr,
+};
+
+/// Max number of Futures that may be spawned at the same time
+const MAX_ASYNC_TASKS: usize = 4;
+
+/// Max size of each spawned Future, in bytes. Increase this if `spawn()` fails with `SYS_EINVAL` for a large Future.
+const ASYNC_TASK_SIZE: usize = 1024;
+
+/// Storage for a spawned Future, aligned to 8 bytes
+#[repr(C, align(8))]
+struct AsyncTaskStorage([u8; ASYNC_TASK_SIZE]);
+
+/// A spawned Future and the event that will poll it
+struct AsyncTask {
+    /// Event that will be posted to the Event Queue to poll the Future
+    event:    os::os_event,
+    /// Event Queue that will poll the Future
+    queue:    *mut os::os_eventq,
+    /// True if the slot is used by a spawned Future, or reserved by `spawn()`
+    reserved: bool,
+    /// Function that polls the Future. `None` until the Future is ready to be polled, and after it has completed.
+    poll:     Option<unsafe fn(*mut u8, &mut Context) -> Poll<()>>,
+    /// Function that drops the Future
+    drop:     Option<unsafe fn(*mut u8)>,
+    /// The Future, stored as bytes
+    storage:  AsyncTaskStorage,
+}
+
+/// Pool of spawned Futures. All fields are 0, `false` or `None` when unused.
+static mut ASYNC_TASKS: [AsyncTask; MAX_ASYNC_TASKS] = fill_zero!([AsyncTask; MAX_ASYNC_TASKS]);
+
+/// Spawn the Future `future` and poll it on the Event Queue `queue`. The Future will be polled for the first time
+/// when the Event Queue is next run. Returns `SYS_ENOMEM` if there are too many spawned Futures, or `SYS_EINVAL`
+/// if the Future is too big or too strictly aligned for the storage.
+pub fn spawn<F>(queue: *mut os::os_eventq, future: F) -> MynewtResult<()>
+where F: Future<Output = ()> + 'static {
+    if core::mem::size_of::<F>()  > ASYNC_TASK_SIZE { return Err(MynewtError::SYS_EINVAL); }  //  Increase ASYNC_TASK_SIZE
+    if core::mem::align_of::<F>() > core::mem::align_of::<AsyncTaskStorage>() { return Err(MynewtError::SYS_EINVAL); }
+    if queue.is_null() { return Err(MynewtError::SYS_EINVAL); }
+
+    //  Reserve an unused slot. Skip slots whose event is still in an Event Queue, since zeroing a queued event
+    //  would corrupt the queue. `poll` stays `None`, so stale Wakers of the previous Future won't post the event.
+    let slot = critical_section(|| {
+        for i in 0..MAX_ASYNC_TASKS {
+            let task = unsafe { &mut ASYNC_TASKS[i] };
+            if !task.reserved && task.event.ev_queued == 0 {
+                task.reserved = true;
+                return Some(i);
+            }
+        }
+        None
+    });
+    let task = match slot {
+        Some(i) => unsafe { &mut ASYNC_TASKS[i] },
+        None    => return Err(MynewtError::SYS_ENOMEM),  //  Increase MAX_ASYNC_TASKS
+    };
+
+    //  Move the Future into the slot. The Future will never move again, so it's pinned.
+    unsafe { core::ptr::write(task.storage.0.as_mut_ptr() as *mut F, future) };
+    task.drop  = Some(drop_future::<F>);
+    task.queue = queue;
+    task.event = fill_zero!(os::os_event);
+    task.event.ev_cb  = Some(async_task_callback);
+    task.event.ev_arg = task as *mut AsyncTask as Ptr;
+
+    //  Publish the Future and poll it for the first time
+    critical_section(|| {
+        task.poll = Some(poll_future::<F>);
+        unsafe { os::os_eventq_put(queue, &mut task.event) };
+    });
+    Ok(())
+}
+
+/// Poll the Future of type `F` stored at `ptr`
+unsafe fn poll_future<F: Future<Output = ()>>(ptr: *mut u8, cx: &mut Context) -> Poll<()> {
+    Pin::new_unchecked(&mut *(ptr as *mut F))
+        .poll(cx)
+}
+
+/// Drop the Future of type `F` stored at `ptr`
+unsafe fn drop_future<F>(ptr: *mut u8) {
+    core::ptr::drop_in_place(ptr as *mut F)
+}
+
+/// Callback for the event that polls a spawned Future
+extern "C" fn async_task_callback(event: *mut os::os_event) {
+    let task = unsafe { &mut *((*event).ev_arg as *mut AsyncTask) };
+    let poll = match task.poll {
+        Some(poll) => poll,
+        None       => return,  //  Future has completed, ignore stale wakeups
+    };
+    //  Poll the Future with a Waker that will post the event again
+    let waker = unsafe { Waker::from_raw(new_raw_waker(task)) };
+    let mut cx = Context::from_waker(&waker);
+    let ptr = task.storage.0.as_mut_ptr();
+    if let Poll::Ready(()) = unsafe { poll(ptr, &mut cx) } {
+        //  Future has completed. Drop it and free the slot. If the Future woke itself before completing, its event
+        //  is still in the Event Queue, so remove the event before the slot is reused.
+        if let Some(drop) = task.drop.take() { unsafe { drop(ptr) }; }
+        critical_section(|| {
+            unsafe { os::os_eventq_remove(task.queue, &mut task.event) };
+            task.poll = None;
+            task.reserved = false;
+        });
+    }
+}
+
+/// Return a `RawWaker` that will post the event for the spawned Future
+fn new_raw_waker(task: *const AsyncTask) -> RawWaker {
+    RawWaker::new(task as *const (), &WAKER_VTABLE)
+}
+
+/// Functions that implement the Waker for spawned Futures
+static WAKER_VTABLE: RawWakerVTable = RawWakerVTable::new(
+    waker_clone,
+    waker_wake,
+    waker_wake,  //  `wake_by_ref` is the same as `wake` because we don't hold any resources
+    waker_drop,
+);
+
+/// Clone the Waker
+unsafe fn waker_clone(data: *const ()) -> RawWaker {
+    new_raw_waker(data as *const AsyncTask)
+}
+
+/// Wake the spawned Future by posting its event to the Event Queue. Safe to call from interrupt handlers.
+unsafe fn waker_wake(data: *const ()) {
+    let task = &mut *(data as *mut AsyncTask);
+    //  Check and post atomically, so that the event is not posted after the slot has been freed
+    critical_section(|| {
+        if task.poll.is_none() { return; }  //  Future has completed
+        //  If the event is already in the queue, Mynewt will not add it again
+        os::os_eventq_put(task.queue, &mut task.event);
+    });
+}
+
+/// Drop the Waker. Nothing to free.
+unsafe fn waker_drop(_data: *const ()) {}
+
+/// Stores the Waker of a pending Future, so that tasks and interrupt handlers may wake it
+pub struct WakerCell {
+    /// Waker of the pending Future
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+impl WakerCell {
+    /// Create an empty `WakerCell`.
+    pub const fn new() -> Self {
+        WakerCell { waker: UnsafeCell::new(None) }
+    }
+
+    /// Remember the Waker of the pending Future. Replaces any Waker that was registered earlier.
+    pub fn register(&self, waker: &Waker) {
+        let waker = waker.clone();
+        critical_section(|| unsafe { *self.waker.get() = Some(waker) });
+    }
+
+    /// Wake the pending Future, if any. Safe to call from interrupt handlers.
+    pub fn wake(&self) {
+        let waker = critical_section(|| unsafe { (*self.waker.get()).take() });
+        if let Some(waker) = waker { waker.wake(); }
+    }
+}
+
+///  Allow tasks and interrupt handlers to share the `WakerCell`, since access is protected by critical sections.
+unsafe impl Sync for WakerCell {}
+
+/// Return a Future that completes after `ticks` ticks. The Timer event is posted to the Default Event Queue.
+pub fn delay(ticks: os::os_time_t) -> Delay {
+    Delay {
+        ticks,
+        callout: fill_zero!(os::os_callout),
+        started: false,
+        fired:   Cell::new(false),
+        waker:   WakerCell::new(),
+        _pin:    PhantomPinned,
+    }
+}
+
+/// Future returned by `delay()`. Based on Mynewt `os_callout`.
+pub struct Delay {
+    /// Number of ticks to wait
+    ticks:   os::os_time_t,
+    /// Mynewt Callout that will fire after the delay
+    callout: os::os_callout,
+    /// True if the Callout has been started
+    started: bool,
+    /// True if the Callout has fired
+    fired:   Cell<bool>,
+    /// Waker of the pending Future
+    waker:   WakerCell,
+    /// Mynewt keeps a pointer to the Callout, so the Future must not move after it's polled
+    _pin:    PhantomPinned,
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    /// Start the Callout when first polled. Complete when the Callout has fired.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.fired.get() || this.ticks == 0 { return Poll::Ready(()); }
+        this.waker.register(cx.waker());
+        if !this.started {
+            //  Post to the Default Event Queue when the Callout fires
+            let queue = match os::eventq_dflt_get() {
+                Ok(queue) => queue,
+                Err(_)    => return Poll::Ready(()),  //  OS not started, don't wait
+            };
+            let arg = this as *mut Delay as Ptr;
+            unsafe { os::os_callout_init(&mut this.callout, queue, Some(delay_callback), arg) };
+            unsafe { os::os_callout_reset(&mut this.callout, this.ticks) };
+            this.started = true;
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Delay {
+    /// Stop the Callout if the Future is dropped before the delay has elapsed
+    fn drop(&mut self) {
+        if self.started && !self.fired.get() {
+            unsafe { os::os_callout_stop(&mut self.callout) };
+        }
+    }
+}
+
+/// Callback for the event that is posted when the `Delay` Callout fires
+extern "C" fn delay_callback(event: *mut os::os_event) {
+    let delay = unsafe { &*((*event).ev_arg as *const Delay) };
+    delay.fired.set(true);
+    delay.waker.wake();
+}
+
+/// Semaphore that may be awaited by a spawned Future. Only one Future should wait on the Semaphore at a time.
+/// `release()` may be called from tasks or interrupt handlers.
+pub struct AsyncSemaphore {
+    /// Mynewt Semaphore
+    sem:   Semaphore,
+    /// Waker of the waiting Future
+    waker: WakerCell,
+}
+
+impl AsyncSemaphore {
+    /// Create a new Semaphore with `tokens` initial tokens.
+    pub const fn new(tokens: u16) -> Self {
+        AsyncSemaphore {
+            sem:   Semaphore::new(tokens),
+            waker: WakerCell::new(),
+        }
+    }
+
+    /// Return a Future that completes when a token has been taken from the Semaphore
+    pub fn pend(&self) -> SemaphorePend {
+        SemaphorePend { sem: self }
+    }
+
+    /// Return a token to the Semaphore and wake the waiting Future
+    pub fn release(&self) -> MynewtResult<()> {
+        self.sem.release() ? ;
+        self.waker.wake();
+        Ok(())
+    }
+}
+
+/// Future returned by `AsyncSemaphore::pend()`
+pub struct SemaphorePend<'a> {
+    /// Semaphore to be awaited
+    sem: &'a AsyncSemaphore,
+}
+
+impl<'a> Future for SemaphorePend<'a> {
+    type Output = MynewtResult<()>;
+
+    /// Take a token without blocking. If no token is available, wait for `release()`.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<MynewtResult<()>> {
+        //  Register the Waker before checking, so that we won't miss a `release()`
+        self.sem.waker.register(cx.waker());
+        match self.sem.sem.pend(0) {
+            Ok(())                         => Poll::Ready(Ok(())),
+            Err(MynewtError::SYS_ETIMEOUT) => Poll::Pending,
+            Err(err)                       => Poll::Ready(Err(err)),
+        }
+    }
+}
+
+/// GPIO interrupt that may be awaited by a spawned Future. Built on `GpioIrq`, but wakes the Future in the interrupt
+/// context instead of deferring to an Event Queue. Must be `static` because Mynewt keeps a pointer to it.
+/// ```
+/// static BUTTON: GpioInterrupt = GpioInterrupt::new(13);
+/// BUTTON.init(Trigger::Falling, Pull::Up) ? ;
+/// loop { BUTTON.wait().await; ... }
+/// ```
+pub struct GpioInterrupt {
+    /// GPIO pin and interrupt
+    irq:     GpioIrq,
+    /// True if the interrupt has been triggered but not awaited
+    pending: Cell<bool>,
+    /// Waker of the waiting Future
+    waker:   WakerCell,
+}
+
+impl GpioInterrupt {
+    /// Create a GPIO interrupt for the pin.
+    pub const fn new(pin: i32) -> Self {
+        GpioInterrupt {
+            irq:     GpioIrq::new(pin),
+            pending: Cell::new(false),
+            waker:   WakerCell::new(),
+        }
+    }
+
+    /// Configure the GPIO pin to trigger an interrupt and start monitoring for interrupts
+    pub fn init(&'static self, trigger: Trigger, pull: Pull) -> MynewtResult<()> {
+        //  Call `gpio_interrupt_handler()` with this struct upon detecting interrupt
+        self.irq.init_interrupt(trigger, pull, gpio_interrupt_handler, self as *const GpioInterrupt as Ptr)
+    }
+
+    /// Return the GPIO pin and interrupt, e.g. to disable the interrupt or read the pin
+    pub fn irq(&self) -> &GpioIrq {
+        &self.irq
+    }
+
+    /// Return a Future that completes when the interrupt is triggered
+    pub fn wait(&'static self) -> GpioWait {
+        GpioWait { irq: self }
+    }
+}
+
+///  Allow tasks and interrupt handlers to share the GPIO interrupt, since access is protected by critical sections.
+unsafe impl Sync for GpioInterrupt {}
+
+/// Future returned by `GpioInterrupt::wait()`
+pub struct GpioWait {
+    /// GPIO interrupt to be awaited
+    irq: &'static GpioInterrupt,
+}
+
+impl Future for GpioWait {
+    type Output = ();
+
+    /// Complete if the interrupt has been triggered, else wait for the interrupt
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        self.irq.waker.register(cx.waker());
+        let irq = self.irq;
+        let triggered = critical_section(|| irq.pending.replace(false));
+        if triggered { Poll::Ready(()) }
+        else         { Poll::Pending }
+    }
+}
+
+/// Interrupt handler for `GpioInterrupt`. Don't do any processing here, just wake the waiting Future.
+extern "C" fn gpio_interrupt_handler(arg: Ptr) {
+    let irq = unsafe { &*(arg as *const GpioInterrupt) };
+    irq.pending.set(true);
+    irq.waker.wake();
+}
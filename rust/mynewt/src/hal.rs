@@ -1,6 +1,25 @@
-use crate::{hw::hal, kernel::os, result::*};
+use core::cell::{ Cell, UnsafeCell };
+use crate::{
+    hw::hal,
+    kernel::{
+        os,
+        sync::{ Mutex, MutexGuard },
+    },
+    result::*,
+    Ptr,
+};
 use embedded_hal;
 
+pub mod watchdog;  //  Export `hal/watchdog.rs` as Rust module `mynewt::hal::watchdog`
+
+pub mod register;  //  Export `hal/register.rs` as Rust module `mynewt::hal::register`
+
+pub mod saadc;  //  Export `hal/saadc.rs` as Rust module `mynewt::hal::saadc`
+
+pub mod backlight;  //  Export `hal/backlight.rs` as Rust module `mynewt::hal::backlight`
+
+pub mod flash;  //  Export `hal/flash.rs` as Rust module `mynewt::hal::flash`
+
 /// Rust Embedded HAL interface for Mynewt I2C
 impl I2C {
     /// Create a new I2C port
@@ -8,6 +27,11 @@ impl I2C {
         I2C { i2c_num: 0, timeout: 1000 }
     }
 
+    /// Use an I2C port that has already been configured and enabled, e.g. by the BSP.
+    pub const fn from_port(i2c_num: u8, operation_timeout_in_ticks: u32) -> Self {
+        I2C { i2c_num, timeout: operation_timeout_in_ticks }
+    }
+
     /// Initiaise the I2C port
     pub fn init(
         &mut self,
@@ -89,53 +113,219 @@ fn check_i2c_return_code(rc: i32) -> crate::result::MynewtResult<()> {
     }
 }
 
-/// Rust Embedded HAL interface for Mynewt SPI
+/// Rust Embedded HAL interface for Mynewt SPI. Several devices may share the same SPI port, each with its own
+/// Chip Select pin and SPI settings. The SPI port is locked and reconfigured for the device during each transfer:
+/// ```
+/// let mut flash = SPI::new();
+/// flash.init_device(0, 5, SpiConfig { mode: SpiMode::Mode0, baudrate: 8000, bit_order: BitOrder::MsbFirst }) ? ;
+/// let mut id = [0; 3];
+/// flash.write_then_read(&[0x9f], &mut id) ? ;  //  Read JEDEC ID
+/// ```
 impl SPI {
     /// Create a new SPI port
     pub fn new() -> Self {
         SPI {
             spi_num: 0,
             cs_pin:  0,
+            config:  None,
         }
     }
 
-    /// Initiaise the SPI port
+    /// Initiaise the SPI port with the Mynewt SPI settings. The SPI port is configured now.
     pub fn init(&mut self, spi_num: i32, cs_pin: i32, spi_settings: *mut hal::hal_spi_settings) 
         -> MynewtResult<()> {
-        let rc = unsafe { hal::hal_spi_config(spi_num, spi_settings) };
-        assert_eq!(rc, 0, "spi config fail");
-
-        let rc = unsafe { hal::hal_spi_enable(spi_num) };
-        assert_eq!(rc, 0, "spi enable fail");
+        if spi_settings.is_null() { return Err(MynewtError::SYS_EINVAL); }
+        let config = SpiConfig::from_settings(unsafe { &*spi_settings });
+        self.init_device(spi_num, cs_pin, config) ? ;
+        //  Configure the SPI port now
+        let _bus = lock_spi_bus(spi_num, &config) ? ;
+        Ok(())
+    }
 
+    /// Initialise a device on the SPI port with its Chip Select pin and SPI settings.
+    /// The SPI port is configured for the device when the device is selected for a transfer.
+    pub fn init_device(&mut self, spi_num: i32, cs_pin: i32, config: SpiConfig) -> MynewtResult<()> {
+        if spi_num < 0 || spi_num as usize >= SPI_PORT_COUNT { return Err(MynewtError::SYS_EINVAL); }
+        //  Set Chip Select to high to disable the device
         let rc = unsafe { hal::hal_gpio_init_out(cs_pin, 1) };
-        assert_eq!(rc, 0, "spi init fail");
+        if rc != 0 { return Err(MynewtError::SYS_EINVAL); }
         self.spi_num = spi_num;
         self.cs_pin  = cs_pin;
+        self.config  = Some(config);
         Ok(())
     }
+
+    /// Read from the device into `words`. Sends `0xff` while reading.
+    pub fn read(&mut self, words: &mut [u8]) -> MynewtResult<()> {
+        for word in words.iter_mut() { *word = 0xff; }
+        self.select(|spi_num| spi_txrx(spi_num, words.as_ptr(), words.as_mut_ptr(), words.len()))
+    }
+
+    /// Write the command in `tx`, then read the response into `rx`, keeping the device selected in between.
+    /// Used for devices like SPI flash that receive a command and return a response.
+    pub fn write_then_read(&mut self, tx: &[u8], rx: &mut [u8]) -> MynewtResult<()> {
+        for word in rx.iter_mut() { *word = 0xff; }
+        self.select(|spi_num| {
+            spi_txrx(spi_num, tx.as_ptr(), core::ptr::null_mut(), tx.len()) ? ;
+            spi_txrx(spi_num, rx.as_ptr(), rx.as_mut_ptr(), rx.len())
+        })
+    }
+
+    /// Lock and configure the SPI port for the device, select the device, run `f` and deselect the device
+    fn select<R>(&mut self, f: impl FnOnce(i32) -> MynewtResult<R>) -> MynewtResult<R> {
+        let config = match self.config {
+            Some(config) => config,
+            None         => return Err(MynewtError::SYS_EINVAL),  //  `init()` not called
+        };
+        let _bus = lock_spi_bus(self.spi_num, &config) ? ;
+        //  Select the device
+        unsafe { hal::hal_gpio_write(self.cs_pin, 0) };
+        let result = f(self.spi_num);
+        //  De-select the device, even if the transfer failed
+        unsafe { hal::hal_gpio_write(self.cs_pin, 1) };
+        result
+    }
 }
 
 /// Rust Embedded HAL interface for Mynewt SPI
 impl embedded_hal::blocking::spi::Write<u8> for SPI {
     /// Write to the SPI port
     fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
-        //  Select the device
-        unsafe { hal::hal_gpio_write(self.cs_pin, 0) };
-        //  Send the data
-        unsafe { hal::hal_spi_txrx(self.spi_num, 
-            core::mem::transmute(words.as_ptr()),  //  TX Buffer
-            core::ptr::null_mut(),                 //  RX Buffer (don't receive)
-            words.len() as i32) };                 //  Length
-        //  De-select the device
-        unsafe { hal::hal_gpio_write(self.cs_pin, 1) };
-        Ok(())
+        self.select(|spi_num| spi_txrx(spi_num, words.as_ptr(), core::ptr::null_mut(), words.len()))
+    }
+
+    /// Reuse Mynewt error codes
+    type Error = crate::result::MynewtError;
+}
+
+/// Rust Embedded HAL interface for Mynewt SPI
+impl embedded_hal::blocking::spi::Transfer<u8> for SPI {
+    /// Write `words` to the SPI port and replace them by the words received at the same time
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        self.select(|spi_num| spi_txrx(spi_num, words.as_ptr(), words.as_mut_ptr(), words.len())) ? ;
+        Ok(words)
     }
 
     /// Reuse Mynewt error codes
     type Error = crate::result::MynewtError;
 }
 
+/// Send `len` bytes from `tx` and receive into `rx` (may be null) with `hal_spi_txrx()`. Blocks until done.
+fn spi_txrx(spi_num: i32, tx: *const u8, rx: *mut u8, len: usize) -> MynewtResult<()> {
+    if len == 0 { return Ok(()); }
+    if len > i32::max_value() as usize { return Err(MynewtError::SYS_EINVAL); }
+    let rc = unsafe { hal::hal_spi_txrx(
+        spi_num,
+        tx as Ptr,    //  TX Buffer
+        rx as Ptr,    //  RX Buffer, or null to discard
+        len as i32    //  Length
+    ) };
+    if rc != 0 { return Err(MynewtError::SYS_EIO); }
+    Ok(())
+}
+
+/// SPI clock polarity and phase
+#[derive(Clone, Copy, PartialEq)]
+pub enum SpiMode {
+    /// Clock idle low, sample on leading edge
+    Mode0,
+    /// Clock idle low, sample on trailing edge
+    Mode1,
+    /// Clock idle high, sample on leading edge
+    Mode2,
+    /// Clock idle high, sample on trailing edge
+    Mode3,
+}
+
+/// Order of bits in each SPI word
+#[derive(Clone, Copy, PartialEq)]
+pub enum BitOrder {
+    /// Most significant bit first
+    MsbFirst,
+    /// Least significant bit first
+    LsbFirst,
+}
+
+/// SPI settings for a device. Converted to Mynewt `hal_spi_settings` with 8-bit words.
+#[derive(Clone, Copy, PartialEq)]
+pub struct SpiConfig {
+    /// SPI clock polarity and phase
+    pub mode:      SpiMode,
+    /// Baud rate in kHz
+    pub baudrate:  u32,
+    /// Order of bits in each word
+    pub bit_order: BitOrder,
+}
+
+impl SpiConfig {
+    /// Convert the Mynewt SPI settings
+    pub fn from_settings(settings: &hal::hal_spi_settings) -> Self {
+        SpiConfig {
+            mode: match settings.data_mode as u32 {
+                hal::HAL_SPI_MODE1 => SpiMode::Mode1,
+                hal::HAL_SPI_MODE2 => SpiMode::Mode2,
+                hal::HAL_SPI_MODE3 => SpiMode::Mode3,
+                _                  => SpiMode::Mode0,
+            },
+            baudrate:  settings.baudrate,
+            bit_order: if settings.data_order as u32 == hal::HAL_SPI_LSB_FIRST { BitOrder::LsbFirst }
+                       else { BitOrder::MsbFirst },
+        }
+    }
+
+    /// Convert to Mynewt SPI settings
+    pub fn to_settings(&self) -> hal::hal_spi_settings {
+        hal::hal_spi_settings {
+            data_mode: match self.mode {
+                SpiMode::Mode0 => hal::HAL_SPI_MODE0,
+                SpiMode::Mode1 => hal::HAL_SPI_MODE1,
+                SpiMode::Mode2 => hal::HAL_SPI_MODE2,
+                SpiMode::Mode3 => hal::HAL_SPI_MODE3,
+            } as u8,
+            data_order: match self.bit_order {
+                BitOrder::MsbFirst => hal::HAL_SPI_MSB_FIRST,
+                BitOrder::LsbFirst => hal::HAL_SPI_LSB_FIRST,
+            } as u8,
+            word_size: hal::HAL_SPI_WORD_SIZE_8BIT as u8,
+            baudrate:  self.baudrate,
+        }
+    }
+}
+
+/// Number of SPI ports that may be shared: SPI0 to SPI2 on nRF52
+pub(crate) const SPI_PORT_COUNT: usize = 3;
+
+/// Lock for each SPI port, which remembers the settings that the port is configured with
+static SPI_BUSES: [Mutex<Option<SpiConfig>>; SPI_PORT_COUNT] = [
+    Mutex::new(None), Mutex::new(None), Mutex::new(None),
+];
+
+/// Returned by `lock_spi_bus()`. Unlocks the SPI port when dropped.
+pub type SpiBusGuard = MutexGuard<'static, Option<SpiConfig>>;
+
+/// Lock the SPI port for exclusive use by a device, waiting until the port is available. Reconfigure the port
+/// if it was last used with different settings. The port is unlocked when the returned guard is dropped.
+pub fn lock_spi_bus(spi_num: i32, config: &SpiConfig) -> MynewtResult<SpiBusGuard> {
+    if spi_num < 0 { return Err(MynewtError::SYS_EINVAL); }
+    let bus = match SPI_BUSES.get(spi_num as usize) {
+        Some(bus) => bus,
+        None      => return Err(MynewtError::SYS_EINVAL),
+    };
+    let mut current = bus.lock() ? ;
+    if *current != Some(*config) {
+        //  Port must be disabled while configuring
+        *current = None;
+        let mut settings = config.to_settings();
+        unsafe { hal::hal_spi_disable(spi_num) };
+        let rc = unsafe { hal::hal_spi_config(spi_num, &mut settings) };
+        if rc != 0 { return Err(MynewtError::SYS_EINVAL); }
+        let rc = unsafe { hal::hal_spi_enable(spi_num) };
+        if rc != 0 { return Err(MynewtError::SYS_EIO); }
+        *current = Some(*config);
+    }
+    Ok(current)
+}
+
 /// Rust Embedded HAL interface for Mynewt GPIO
 impl GPIO {
     /// Create a new output GPIO pin
@@ -156,6 +346,33 @@ impl GPIO {
     }
 }
 
+/// Rust Embedded HAL interface for Mynewt GPIO
+impl GPIO {
+    /// Initialise the input GPIO pin with the pull up / pull down setting
+    pub fn init_in(&mut self, pin: i32, pull: Pull) -> MynewtResult<()> {
+        let rc = unsafe { hal::hal_gpio_init_in(pin, pull.into()) };
+        if rc != 0 { return Err(MynewtError::SYS_EINVAL); }
+        self.pin = pin;
+        Ok(())
+    }
+}
+
+/// Rust Embedded HAL interface for Mynewt GPIO
+impl embedded_hal::digital::v2::InputPin for GPIO {
+    /// Return true if the GPIO pin is high
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(unsafe { hal::hal_gpio_read(self.pin) } != 0)
+    }
+
+    /// Return true if the GPIO pin is low
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(unsafe { hal::hal_gpio_read(self.pin) } == 0)
+    }
+
+    /// Reuse Mynewt error codes
+    type Error = crate::result::MynewtError;
+}
+
 /// Rust Embedded HAL interface for Mynewt GPIO
 impl embedded_hal::digital::v2::OutputPin for GPIO {
     /// Set the GPIO pin to low
@@ -174,6 +391,146 @@ impl embedded_hal::digital::v2::OutputPin for GPIO {
     type Error = crate::result::MynewtError;
 }
 
+/// GPIO pull up / pull down setting
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pull {
+    /// No pull up or pull down
+    None,
+    /// Pull up
+    Up,
+    /// Pull down
+    Down,
+}
+
+impl From<Pull> for hal::hal_gpio_pull_t {
+    /// Convert to Mynewt GPIO pull setting
+    fn from(pull: Pull) -> Self {
+        match pull {
+            Pull::None => hal::hal_gpio_pull_HAL_GPIO_PULL_NONE,
+            Pull::Up   => hal::hal_gpio_pull_HAL_GPIO_PULL_UP,
+            Pull::Down => hal::hal_gpio_pull_HAL_GPIO_PULL_DOWN,
+        }
+    }
+}
+
+/// GPIO interrupt trigger
+#[derive(Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// Trigger when the pin goes from low to high
+    Rising,
+    /// Trigger when the pin goes from high to low
+    Falling,
+    /// Trigger on both edges
+    Both,
+    /// Trigger while the pin is low
+    Low,
+    /// Trigger while the pin is high
+    High,
+}
+
+impl From<Trigger> for hal::hal_gpio_irq_trig_t {
+    /// Convert to Mynewt GPIO interrupt trigger
+    fn from(trigger: Trigger) -> Self {
+        match trigger {
+            Trigger::Rising  => hal::hal_gpio_irq_trigger_HAL_GPIO_TRIG_RISING,
+            Trigger::Falling => hal::hal_gpio_irq_trigger_HAL_GPIO_TRIG_FALLING,
+            Trigger::Both    => hal::hal_gpio_irq_trigger_HAL_GPIO_TRIG_BOTH,
+            Trigger::Low     => hal::hal_gpio_irq_trigger_HAL_GPIO_TRIG_LOW,
+            Trigger::High    => hal::hal_gpio_irq_trigger_HAL_GPIO_TRIG_HIGH,
+        }
+    }
+}
+
+/// Safe interface for Mynewt GPIO interrupts. When the interrupt is triggered, the handler is called
+/// by the task that runs the event queue, not in the interrupt context:
+/// ```
+/// static BUTTON: GpioIrq = GpioIrq::new(13);
+/// BUTTON.init(Trigger::Rising, Pull::Down, os::eventq_dflt_get() ?, handle_button) ? ;
+/// ...
+/// fn handle_button(pin: i32) { ... }
+/// ```
+impl GpioIrq {
+    /// Create a GPIO interrupt for the pin.
+    pub const fn new(pin: i32) -> Self {
+        GpioIrq {
+            pin,
+            event: UnsafeCell::new(os::os_event {
+                ev_queued: 0,
+                ev_cb:     None,
+                ev_arg:    core::ptr::null_mut(),
+                ev_next:   os::os_event__bindgen_ty_1 { stqe_next: core::ptr::null_mut() },
+            }),
+            queue:   Cell::new(core::ptr::null_mut()),
+            handler: Cell::new(None),
+        }
+    }
+
+    /// Configure the GPIO pin to trigger an interrupt and start monitoring for interrupts.
+    /// When triggered, `handler` will be called with the pin number by the task that runs `queue`.
+    pub fn init(&'static self, trigger: Trigger, pull: Pull, queue: *mut os::os_eventq, handler: fn(i32)) -> MynewtResult<()> {
+        if queue.is_null() { return Err(MynewtError::SYS_EINVAL); }
+        unsafe {
+            let event = &mut *self.event.get();
+            event.ev_cb  = Some(gpio_irq_event_callback);
+            event.ev_arg = self as *const GpioIrq as Ptr;
+        }
+        self.queue.set(queue);
+        self.handler.set(Some(handler));
+        self.init_interrupt(trigger, pull, gpio_irq_handler, self as *const GpioIrq as Ptr)
+    }
+
+    /// Configure the GPIO pin to trigger an interrupt and start monitoring for interrupts. When triggered, `isr` will be
+    /// called with `arg` in the interrupt context. Used by `init()`, and by `executor::GpioInterrupt` to wake Futures.
+    pub(crate) fn init_interrupt(&self, trigger: Trigger, pull: Pull, isr: extern "C" fn(Ptr), arg: Ptr) -> MynewtResult<()> {
+        let rc = unsafe { hal::hal_gpio_irq_init(
+            self.pin,        //  GPIO pin to be configured
+            Some(isr),       //  Call `isr` upon detecting interrupt
+            arg,             //  Pass `arg` to `isr`
+            trigger.into(),  //  Trigger when interrupt goes from high to low, etc.
+            pull.into()      //  Pull up or down the GPIO pin
+        ) };
+        if rc != 0 { return Err(MynewtError::SYS_EINVAL); }
+        self.enable();
+        Ok(())
+    }
+
+    /// Start monitoring for interrupts
+    pub fn enable(&self) {
+        unsafe { hal::hal_gpio_irq_enable(self.pin) };
+    }
+
+    /// Stop monitoring for interrupts
+    pub fn disable(&self) {
+        unsafe { hal::hal_gpio_irq_disable(self.pin) };
+    }
+
+    /// Return true if the GPIO pin is high
+    pub fn is_high(&self) -> bool {
+        unsafe { hal::hal_gpio_read(self.pin) != 0 }
+    }
+
+    /// Return the GPIO pin number
+    pub fn pin(&self) -> i32 {
+        self.pin
+    }
+}
+
+///  Allow tasks and interrupt handlers to share the GPIO interrupt. The handler and queue are only changed by `init()`.
+unsafe impl Sync for GpioIrq {}
+
+/// Interrupt handler for `GpioIrq`. Don't do any processing here, just forward the event to the event queue.
+/// If the event is already queued, Mynewt ignores the event.
+extern "C" fn gpio_irq_handler(arg: Ptr) {
+    let irq = unsafe { &*(arg as *const GpioIrq) };
+    unsafe { os::os_eventq_put(irq.queue.get(), irq.event.get()) };
+}
+
+/// Event callback for `GpioIrq`, called by the task that runs the event queue. Calls the Rust handler.
+extern "C" fn gpio_irq_event_callback(event: *mut os::os_event) {
+    let irq = unsafe { &*((*event).ev_arg as *const GpioIrq) };
+    if let Some(handler) = irq.handler.get() { handler(irq.pin); }
+}
+
 /// Rust Embedded HAL interface for Mynewt Delay
 impl Delay {
     /// Create a new delay
@@ -200,6 +557,8 @@ pub struct SPI {
     spi_num: i32,
     /// Mynewt GPIO pin number for Chip Select
     cs_pin: i32,
+    /// SPI settings for the device. `None` if not initialised.
+    config: Option<SpiConfig>,
 }
 
 /// Rust Embedded HAL interface for Mynewt I2C
@@ -216,5 +575,39 @@ pub struct GPIO {
     pin: i32,
 }
 
+/// Safe interface for Mynewt GPIO interrupts that are deferred to an event queue
+pub struct GpioIrq {
+    /// Mynewt GPIO pin number
+    pin:     i32,
+    /// Event that will be forwarded to the event queue when the interrupt is triggered
+    event:   UnsafeCell<os::os_event>,
+    /// Event queue that will call the handler
+    queue:   Cell<*mut os::os_eventq>,
+    /// Rust handler for the interrupt
+    handler: Cell<Option<fn(i32)>>,
+}
+
 /// Rust Embedded HAL interface for Mynewt Delay
 pub struct Delay {}
+
+/// Return true if a debugger is connected. Without a debugger, a breakpoint would lock up the device.
+pub fn debugger_connected() -> bool {
+    unsafe { hal_debugger_connected() != 0 }
+}
+
+/// Reset the system immediately, without waiting for the Watchdog Timer
+pub fn system_reset() -> ! {
+    unsafe { hal_system_reset() };
+    loop {}
+}
+
+///  Import the Mynewt HAL functions for the system
+extern "C" {
+    /// Returns non-zero if a debugger is connected.
+    /// C API: `int hal_debugger_connected(void)`
+    fn hal_debugger_connected() -> i32;
+
+    /// Resets the system.
+    /// C API: `void hal_system_reset(void)`
+    fn hal_system_reset();
+}
@@ -0,0 +1,390 @@
+//! Backlight control for the PineTime display. The backlight has 3 discrete levels, selected by the active-low pins
+//! `LCD_BACKLIGHT_{LOW,MID,HIGH}` (P0.14, 22, 23). For smooth brightness between the levels, we drive
+//! `LCD_BACKLIGHT_HIGH` with the nRF52 PWM. Brightness may be faded in and out, and the backlight may be
+//! dimmed and switched off automatically after a period of inactivity:
+//! ```
+//! backlight::init() ? ;                            //  Switch the backlight to high
+//! backlight::fade_to(128, 500) ? ;                 //  Fade to half brightness in 500 milliseconds
+//! backlight::start_auto_dim(10_000, 20_000) ? ;    //  Dim after 10 seconds, switch off 20 seconds later
+//! ...
+//! backlight::wake() ? ;                            //  Upon touch, restore the brightness and restart the inactivity timer
+//! ```
+//! The fade and inactivity timers run in the Default Event Queue, so the functions here should be called by the
+//! task that runs the Default Event Queue, i.e. the main task and the event handlers.
+//! Don't enable the Mynewt PWM driver (`PWM_0` in `syscfg.yml`) at the same time, since both will use PWM0.
+use core::sync::atomic::{ compiler_fence, Ordering };
+use crate::{
+    result::*,
+    hw::hal,
+    kernel::{
+        self, os,
+        timer::{ Timer, TimerMode },
+    },
+};
+
+/// Discrete backlight levels
+#[derive(Clone, Copy, PartialEq)]
+pub enum Level {
+    /// Backlight off
+    Off,
+    /// Low brightness, `LCD_BACKLIGHT_LOW`
+    Low,
+    /// Medium brightness, `LCD_BACKLIGHT_MID`
+    Mid,
+    /// High brightness, `LCD_BACKLIGHT_HIGH`
+    High,
+}
+
+impl Level {
+    /// Return the approximate brightness (0 to 255) of the level, used as the starting point when fading from the level
+    pub fn brightness(self) -> u8 {
+        match self {
+            Level::Off  => 0,
+            Level::Low  => 64,
+            Level::Mid  => 128,
+            Level::High => 255,
+        }
+    }
+}
+
+/// Initialise the backlight pins and timers, and switch the backlight to high
+pub fn init() -> MynewtResult<()> {
+    if unsafe { STATE.initialised } { return Err(MynewtError::SYS_EALREADY); }
+    //  Configure the backlight pins as outputs, initially high (off)
+    for pin in [BACKLIGHT_LOW_PIN, BACKLIGHT_MID_PIN, BACKLIGHT_HIGH_PIN].iter() {
+        let rc = unsafe { hal::hal_gpio_init_out(*pin, 1) };
+        if rc != 0 { return Err(MynewtError::SYS_EINVAL); }
+    }
+    let queue = os::eventq_dflt_get() ? ;
+    FADE_TIMER.init(queue, TimerMode::Periodic, handle_fade) ? ;
+    IDLE_TIMER.init(queue, TimerMode::OneShot,  handle_idle) ? ;
+    unsafe { STATE.initialised = true };
+    set_level(Level::High)
+}
+
+/// Switch the backlight to the discrete level. Stops any fade in progress.
+/// Switching off keeps the previous brightness, which will be restored by `wake()` and `fade_in()`.
+pub fn set_level(level: Level) -> MynewtResult<()> {
+    check_init() ? ;
+    FADE_TIMER.stop();
+    if level != Level::Off { unsafe { STATE.active = level.brightness() }; }
+    drive_level(level)
+}
+
+/// Set the backlight brightness from 0 (off) to 255 (high). Stops any fade in progress.
+/// Brightness between 0 and 255 is produced by PWM on `LCD_BACKLIGHT_HIGH`.
+pub fn set_brightness(brightness: u8) -> MynewtResult<()> {
+    check_init() ? ;
+    FADE_TIMER.stop();
+    if brightness != 0 { unsafe { STATE.active = brightness }; }
+    apply_brightness(brightness)
+}
+
+/// Return the current backlight brightness from 0 (off) to 255 (high)
+pub fn brightness() -> u8 {
+    unsafe { STATE.brightness }
+}
+
+/// Fade the backlight from the current brightness to `target` in `duration_ms` milliseconds.
+/// Returns immediately, the brightness is updated by a timer.
+pub fn fade_to(target: u8, duration_ms: u32) -> MynewtResult<()> {
+    check_init() ? ;
+    if target != 0 { unsafe { STATE.active = target }; }
+    start_fade(target, duration_ms)
+}
+
+/// Fade the backlight to the brightness last set by `set_level()`, `set_brightness()` or `fade_to()`
+pub fn fade_in(duration_ms: u32) -> MynewtResult<()> {
+    check_init() ? ;
+    start_fade(unsafe { STATE.active }, duration_ms)
+}
+
+/// Fade the backlight to off. The brightness will be restored by `fade_in()` and `wake()`.
+pub fn fade_out(duration_ms: u32) -> MynewtResult<()> {
+    check_init() ? ;
+    start_fade(0, duration_ms)
+}
+
+/// Dim the backlight after `dim_after_ms` milliseconds of inactivity, then switch off the backlight
+/// `off_after_ms` milliseconds later. Call `wake()` upon user activity to restart the inactivity timer.
+pub fn start_auto_dim(dim_after_ms: u32, off_after_ms: u32) -> MynewtResult<()> {
+    check_init() ? ;
+    if dim_after_ms == 0 || off_after_ms == 0 { return Err(MynewtError::SYS_EINVAL); }
+    unsafe {
+        STATE.dim_ticks = kernel::ms_to_ticks(dim_after_ms);
+        STATE.off_ticks = kernel::ms_to_ticks(off_after_ms);
+        STATE.idle      = Idle::Awake;
+    }
+    IDLE_TIMER.start(unsafe { STATE.dim_ticks })
+}
+
+/// Stop the inactivity timer. If the backlight was dimmed or switched off, the brightness is restored.
+pub fn stop_auto_dim() -> MynewtResult<()> {
+    check_init() ? ;
+    IDLE_TIMER.stop();
+    let idle = unsafe { STATE.idle };
+    unsafe { STATE.idle = Idle::Disabled };
+    if idle == Idle::Dimmed || idle == Idle::Off {
+        start_fade(unsafe { STATE.active }, WAKE_FADE_MS) ? ;
+    }
+    Ok(())
+}
+
+/// Called upon user activity, like touch. If the backlight was dimmed or switched off by the inactivity timer,
+/// restore the brightness. Restart the inactivity timer.
+pub fn wake() -> MynewtResult<()> {
+    check_init() ? ;
+    match unsafe { STATE.idle } {
+        Idle::Disabled => return Ok(()),
+        Idle::Awake    => {},
+        Idle::Dimmed | Idle::Off => {
+            unsafe { STATE.idle = Idle::Awake };
+            start_fade(unsafe { STATE.active }, WAKE_FADE_MS) ? ;
+        }
+    }
+    IDLE_TIMER.start(unsafe { STATE.dim_ticks })
+}
+
+/// Return true if the backlight has been switched off by the inactivity timer
+pub fn is_idle_off() -> bool {
+    unsafe { STATE.idle == Idle::Off }
+}
+
+/// Return `SYS_ENODEV` if `init()` has not been called
+fn check_init() -> MynewtResult<()> {
+    if unsafe { !STATE.initialised } { return Err(MynewtError::SYS_ENODEV); }
+    Ok(())
+}
+
+/// Start the fade timer to change the brightness from the current brightness to `target`
+fn start_fade(target: u8, duration_ms: u32) -> MynewtResult<()> {
+    FADE_TIMER.stop();
+    let current = unsafe { STATE.brightness };
+    if current == target { return Ok(()); }
+    //  Compute the change in brightness for each step, at least 1
+    let steps = (duration_ms / FADE_INTERVAL_MS).max(1);
+    let diff  = (target as i32 - current as i32).abs() as u32;
+    unsafe {
+        STATE.fade_target = target;
+        STATE.fade_step   = ((diff + steps - 1) / steps) as u8;
+    }
+    FADE_TIMER.start(kernel::ms_to_ticks(FADE_INTERVAL_MS))
+}
+
+/// Called by the fade timer to step the brightness towards the target
+fn handle_fade() {
+    let (current, target, step) = unsafe { (STATE.brightness, STATE.fade_target, STATE.fade_step) };
+    let next =
+        if current < target { current.saturating_add(step).min(target) }
+        else                { current.saturating_sub(step).max(target) };
+    if next == target { FADE_TIMER.stop(); }
+    apply_brightness(next).ok();
+}
+
+/// Called by the inactivity timer to dim and then switch off the backlight
+fn handle_idle() {
+    match unsafe { STATE.idle } {
+        Idle::Awake => {
+            unsafe { STATE.idle = Idle::Dimmed };
+            let dim = DIM_BRIGHTNESS.min(unsafe { STATE.active });
+            start_fade(dim, DIM_FADE_MS).ok();
+            IDLE_TIMER.start(unsafe { STATE.off_ticks }).ok();
+        }
+        Idle::Dimmed => {
+            unsafe { STATE.idle = Idle::Off };
+            start_fade(0, DIM_FADE_MS).ok();
+        }
+        Idle::Disabled | Idle::Off => {}
+    }
+}
+
+/// Set the brightness. Off and high are set with the discrete pins, other brightness with PWM.
+fn apply_brightness(brightness: u8) -> MynewtResult<()> {
+    match brightness {
+        0   => drive_level(Level::Off),
+        255 => drive_level(Level::High),
+        _   => {
+            unsafe {
+                hal::hal_gpio_write(BACKLIGHT_LOW_PIN, 1);
+                hal::hal_gpio_write(BACKLIGHT_MID_PIN, 1);
+                STATE.brightness = brightness;
+            }
+            pwm_start(BACKLIGHT_HIGH_PIN, brightness);
+            Ok(())
+        }
+    }
+}
+
+/// Stop the PWM and switch on only the pin for the level. Pins are active low.
+fn drive_level(level: Level) -> MynewtResult<()> {
+    let res = pwm_stop();
+    unsafe {
+        hal::hal_gpio_write(BACKLIGHT_LOW_PIN,  if level == Level::Low  { 0 } else { 1 });
+        hal::hal_gpio_write(BACKLIGHT_MID_PIN,  if level == Level::Mid  { 0 } else { 1 });
+        hal::hal_gpio_write(BACKLIGHT_HIGH_PIN, if level == Level::High { 0 } else { 1 });
+        STATE.brightness = level.brightness();
+    }
+    res
+}
+
+/// Start or update the PWM on the pin. The pin is low (backlight on) for `duty` out of 255 counts.
+fn pwm_start(pin: i32, duty: u8) {
+    //  PWM reads the duty cycle from RAM via EasyDMA. Bit 15 is 0, so the pin starts low in each period.
+    unsafe { PWM_DUTY = duty as u16 };
+    if unsafe { !STATE.pwm_on } {
+        write_reg(PSEL_OUT0,     pin as u32);
+        write_reg(ENABLE,        1);
+        write_reg(MODE,          0);    //  Count up
+        write_reg(COUNTERTOP,    255);  //  255 counts per period
+        write_reg(PRESCALER,     4);    //  1 MHz counter, so the period is about 4 kHz
+        write_reg(DECODER,       0);    //  Same duty cycle for all channels, next value after each period
+        write_reg(LOOP,          0);    //  Play the sequence once, then keep the last duty cycle
+        write_reg(SEQ0_PTR,      unsafe { &PWM_DUTY as *const u16 as u32 });
+        write_reg(SEQ0_CNT,      1);
+        write_reg(SEQ0_REFRESH,  0);
+        write_reg(SEQ0_ENDDELAY, 0);
+        unsafe { STATE.pwm_on = true };
+    }
+    //  Play the sequence to load the new duty cycle
+    compiler_fence(Ordering::SeqCst);
+    write_reg(TASKS_SEQSTART0, 1);
+}
+
+/// Stop the PWM and disconnect the pin, so that the pin is controlled by GPIO again.
+/// Returns `SYS_ETIMEOUT` if the PWM doesn't stop, but the PWM is disabled anyway.
+fn pwm_stop() -> MynewtResult<()> {
+    if unsafe { !STATE.pwm_on } { return Ok(()); }
+    write_reg(EVENTS_STOPPED, 0);
+    write_reg(TASKS_STOP, 1);
+    let mut res = Err(MynewtError::SYS_ETIMEOUT);
+    for _ in 0..PWM_TIMEOUT_LOOPS {
+        if read_reg(EVENTS_STOPPED) != 0 { res = Ok(()); break; }
+    }
+    write_reg(EVENTS_STOPPED, 0);
+    write_reg(ENABLE, 0);
+    write_reg(PSEL_OUT0, PSEL_DISCONNECTED);
+    unsafe { STATE.pwm_on = false };
+    res
+}
+
+/// Write the PWM register at the offset
+#[cfg(not(feature = "simulator"))]
+fn write_reg(offset: usize, value: u32) {
+    unsafe { core::ptr::write_volatile((PWM_BASE + offset) as *mut u32, value) };
+}
+
+/// Read the PWM register at the offset
+#[cfg(not(feature = "simulator"))]
+fn read_reg(offset: usize) -> u32 {
+    unsafe { core::ptr::read_volatile((PWM_BASE + offset) as *const u32) }
+}
+
+/// Write the simulated PWM register at the offset
+#[cfg(feature = "simulator")]
+fn write_reg(offset: usize, value: u32) {
+    crate::sim::peripheral::write(PWM_BASE + offset, value);
+}
+
+/// Read the simulated PWM register at the offset
+#[cfg(feature = "simulator")]
+fn read_reg(offset: usize) -> u32 {
+    crate::sim::peripheral::read(PWM_BASE + offset)
+}
+
+/// Inactivity state of the backlight
+#[derive(Clone, Copy, PartialEq)]
+enum Idle {
+    /// Inactivity timer not started
+    Disabled,
+    /// Backlight at the active brightness
+    Awake,
+    /// Backlight dimmed after inactivity
+    Dimmed,
+    /// Backlight switched off after inactivity
+    Off,
+}
+
+/// Backlight state. Only accessed by the task that runs the Default Event Queue.
+struct State {
+    /// True if `init()` has been called
+    initialised: bool,
+    /// Current brightness
+    brightness:  u8,
+    /// Brightness to be restored by `wake()` and `fade_in()`
+    active:      u8,
+    /// Brightness at the end of the fade
+    fade_target: u8,
+    /// Change in brightness for each fade step
+    fade_step:   u8,
+    /// True if PWM is driving `LCD_BACKLIGHT_HIGH`
+    pwm_on:      bool,
+    /// Inactivity state
+    idle:        Idle,
+    /// Ticks of inactivity before dimming
+    dim_ticks:   os::os_time_t,
+    /// Ticks after dimming before switching off
+    off_ticks:   os::os_time_t,
+}
+
+/// Backlight state
+static mut STATE: State = State {
+    initialised: false,
+    brightness:  0,
+    active:      255,
+    fade_target: 0,
+    fade_step:   1,
+    pwm_on:      false,
+    idle:        Idle::Disabled,
+    dim_ticks:   0,
+    off_ticks:   0,
+};
+
+/// PWM duty cycle, read by the PWM via EasyDMA. Must be static because the PWM reads it after `pwm_start()` returns.
+static mut PWM_DUTY: u16 = 0;
+
+/// Timer that steps the brightness while fading
+static FADE_TIMER: Timer = Timer::new();
+
+/// Timer that dims and switches off the backlight after inactivity
+static IDLE_TIMER: Timer = Timer::new();
+
+/// LCD_BACKLIGHT_LOW (P0.14): Low backlight (active low)
+const BACKLIGHT_LOW_PIN: i32 = 14;
+/// LCD_BACKLIGHT_MID (P0.22): Medium backlight (active low)
+const BACKLIGHT_MID_PIN: i32 = 22;
+/// LCD_BACKLIGHT_HIGH (P0.23): High backlight (active low), driven by PWM for brightness between the levels
+const BACKLIGHT_HIGH_PIN: i32 = 23;
+
+/// Brightness when dimmed after inactivity
+const DIM_BRIGHTNESS: u8 = 32;
+/// Milliseconds between fade steps
+const FADE_INTERVAL_MS: u32 = 10;
+/// Milliseconds to fade when dimming and switching off after inactivity
+const DIM_FADE_MS: u32 = 500;
+/// Milliseconds to fade when waking up, short so that the display responds quickly to touch
+const WAKE_FADE_MS: u32 = 100;
+
+/// Number of times to check for the PWM stopped event before timing out
+const PWM_TIMEOUT_LOOPS: u32 = 100_000;
+
+/// Base address of the PWM0 registers on nRF52
+const PWM_BASE: usize = 0x4001_c000;
+
+/// Value for `PSEL_OUT0` that disconnects the pin
+const PSEL_DISCONNECTED: u32 = 0xffff_ffff;
+
+//  Offsets of the PWM registers, from the nRF52832 Product Specification
+const TASKS_STOP:      usize = 0x004;
+const TASKS_SEQSTART0: usize = 0x008;
+const EVENTS_STOPPED:  usize = 0x104;
+const ENABLE:          usize = 0x500;
+const MODE:            usize = 0x504;
+const COUNTERTOP:      usize = 0x508;
+const PRESCALER:       usize = 0x50c;
+const DECODER:         usize = 0x510;
+const LOOP:            usize = 0x514;
+const SEQ0_PTR:        usize = 0x520;
+const SEQ0_CNT:        usize = 0x524;
+const SEQ0_REFRESH:    usize = 0x528;
+const SEQ0_ENDDELAY:   usize = 0x52c;
+const PSEL_OUT0:       usize = 0x560;
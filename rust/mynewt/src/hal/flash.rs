@@ -0,0 +1,111 @@
+//! Read, erase and write a Mynewt flash area through the `flash_area` API. The flash areas are defined by
+//! `bsp.flash_map` in `hw/bsp/nrf52/bsp.yml`, so settings stored here won't collide with the firmware images or NFFS.
+//! Used for storing small settings that must survive power off, like the touch calibration:
+//! ```
+//! let area = FlashArea::open(SETTINGS_AREA) ? ;
+//! area.erase() ? ;                       //  All bytes become 0xff
+//! area.write(0, &settings) ? ;
+//! area.read(0, &mut settings) ? ;
+//! ```
+//! Writing can only change bits from 1 to 0, so erase the area before writing new values. The CPU is halted while
+//! erasing (about 85 milliseconds per page), so don't erase while time-critical tasks are running.
+use crate::result::*;
+
+/// ID of the first user flash area. The flash area with `user_id: n` in `bsp.yml` has ID `FLASH_AREA_USER_ID_MIN + n`.
+pub const FLASH_AREA_USER_ID_MIN: u8 = 16;
+
+/// Flash area opened with the Mynewt `flash_area` API. Closed when dropped.
+pub struct FlashArea {
+    /// Mynewt flash area, owned by the flash map
+    area: *const flash_area,
+}
+
+impl FlashArea {
+    /// Open the flash area with the ID. Returns `SYS_ENOENT` if the flash map has no such area.
+    pub fn open(id: u8) -> MynewtResult<Self> {
+        let mut area = core::ptr::null();
+        let rc = unsafe { flash_area_open(id, &mut area) };
+        if rc != 0 || area.is_null() { return Err(MynewtError::SYS_ENOENT); }
+        Ok(FlashArea { area })
+    }
+
+    /// Return the size of the flash area in bytes
+    pub fn size(&self) -> u32 {
+        unsafe { (*self.area).fa_size }
+    }
+
+    /// Read the bytes at the offset from the start of the flash area
+    pub fn read(&self, offset: u32, buf: &mut [u8]) -> MynewtResult<()> {
+        self.check_range(offset, buf.len()) ? ;
+        let rc = unsafe { flash_area_read(self.area, offset, buf.as_mut_ptr() as *mut ::cty::c_void, buf.len() as u32) };
+        if rc != 0 { return Err(MynewtError::SYS_EIO); }
+        Ok(())
+    }
+
+    /// Write the bytes at the offset from the start of the flash area. The bytes must have been erased.
+    pub fn write(&self, offset: u32, buf: &[u8]) -> MynewtResult<()> {
+        self.check_range(offset, buf.len()) ? ;
+        let rc = unsafe { flash_area_write(self.area, offset, buf.as_ptr() as *const ::cty::c_void, buf.len() as u32) };
+        if rc != 0 { return Err(MynewtError::SYS_EIO); }
+        Ok(())
+    }
+
+    /// Erase the entire flash area. All bytes become `0xff`.
+    pub fn erase(&self) -> MynewtResult<()> {
+        let rc = unsafe { flash_area_erase(self.area, 0, self.size()) };
+        if rc != 0 { return Err(MynewtError::SYS_EIO); }
+        Ok(())
+    }
+
+    /// Return `SYS_EINVAL` if the bytes at the offset don't fit in the flash area
+    fn check_range(&self, offset: u32, len: usize) -> MynewtResult<()> {
+        if offset as u64 + len as u64 > self.size() as u64 { return Err(MynewtError::SYS_EINVAL); }
+        Ok(())
+    }
+}
+
+impl Drop for FlashArea {
+    /// Close the flash area
+    fn drop(&mut self) {
+        unsafe { flash_area_close(self.area) };
+    }
+}
+
+/// Flash area in the flash map, from `sys/flash_map/include/flash_map/flash_map.h`
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub struct flash_area {
+    /// Flash area ID
+    pub fa_id:        u8,
+    /// Flash device that contains the area
+    pub fa_device_id: u8,
+    /// Padding
+    pub pad16:        u16,
+    /// Offset of the area from the start of the flash device
+    pub fa_off:       u32,
+    /// Size of the area in bytes
+    pub fa_size:      u32,
+}
+
+///  Import the Mynewt Flash Map functions
+extern "C" {
+    /// Find the flash area with the ID.
+    /// C API: `int flash_area_open(uint8_t id, const struct flash_area **fa)`
+    fn flash_area_open(id: u8, fa: *mut *const flash_area) -> i32;
+
+    /// Release the flash area.
+    /// C API: `void flash_area_close(const struct flash_area *fa)`
+    fn flash_area_close(fa: *const flash_area);
+
+    /// Read `len` bytes at offset `off` of the flash area into `dst`.
+    /// C API: `int flash_area_read(const struct flash_area *fa, uint32_t off, void *dst, uint32_t len)`
+    fn flash_area_read(fa: *const flash_area, off: u32, dst: *mut ::cty::c_void, len: u32) -> i32;
+
+    /// Write `len` bytes from `src` at offset `off` of the flash area.
+    /// C API: `int flash_area_write(const struct flash_area *fa, uint32_t off, const void *src, uint32_t len)`
+    fn flash_area_write(fa: *const flash_area, off: u32, src: *const ::cty::c_void, len: u32) -> i32;
+
+    /// Erase the sectors that contain `len` bytes at offset `off` of the flash area.
+    /// C API: `int flash_area_erase(const struct flash_area *fa, uint32_t off, uint32_t len)`
+    fn flash_area_erase(fa: *const flash_area, off: u32, len: u32) -> i32;
+}
@@ -0,0 +1,177 @@
+//! Register access for I2C devices like the touch controller, accelerometer and heart rate sensor.
+//! Registers are declared with their width, so that 8-bit and 16-bit registers are read and written as `u8` and `u16`:
+//! ```
+//! const CHIP_ID:  Register<u8>  = Register::new(0xa7);
+//! const TEMP:     Register<u16> = Register::new(0x22);
+//! static mut TOUCH: RegisterDevice = RegisterDevice::new(I2C::from_port(1, 1000), 0x15);
+//! ...
+//! let id = TOUCH.read(CHIP_ID) ? ;
+//! TOUCH.update_bits(CTRL, 0x0c, 0x04) ? ;  //  Set bits 2-3 to `01`
+//! TOUCH.read_burst(0, &mut buf) ? ;        //  Read consecutive registers starting at 0
+//! ```
+//! `RegisterDevice` works with any I2C port that implements the embedded-hal traits `Write` and `WriteRead`.
+use core::marker::PhantomData;
+use embedded_hal::blocking::i2c::{ Write, WriteRead };
+use crate::{
+    result::*,
+    I2C,
+};
+
+/// Byte order of registers that are wider than 8 bits
+#[derive(Clone, Copy, PartialEq)]
+pub enum ByteOrder {
+    /// Most significant byte at the lower register address
+    BigEndian,
+    /// Least significant byte at the lower register address
+    LittleEndian,
+}
+
+/// Value stored in a device register: `u8` or `u16`
+pub trait RegisterValue: Copy {
+    /// Number of bytes in the register
+    const SIZE: usize;
+    /// Convert the bytes read from the device to the value
+    fn from_bytes(bytes: &[u8], order: ByteOrder) -> Self;
+    /// Convert the value to bytes for writing to the device
+    fn to_bytes(self, bytes: &mut [u8], order: ByteOrder);
+    /// Return `(self & !mask) | (bits & mask)`
+    fn merge(self, mask: Self, bits: Self) -> Self;
+}
+
+impl RegisterValue for u8 {
+    const SIZE: usize = 1;
+
+    fn from_bytes(bytes: &[u8], _order: ByteOrder) -> Self {
+        bytes[0]
+    }
+
+    fn to_bytes(self, bytes: &mut [u8], _order: ByteOrder) {
+        bytes[0] = self;
+    }
+
+    fn merge(self, mask: Self, bits: Self) -> Self {
+        (self & !mask) | (bits & mask)
+    }
+}
+
+impl RegisterValue for u16 {
+    const SIZE: usize = 2;
+
+    fn from_bytes(bytes: &[u8], order: ByteOrder) -> Self {
+        match order {
+            ByteOrder::BigEndian    => u16::from_be_bytes([bytes[0], bytes[1]]),
+            ByteOrder::LittleEndian => u16::from_le_bytes([bytes[0], bytes[1]]),
+        }
+    }
+
+    fn to_bytes(self, bytes: &mut [u8], order: ByteOrder) {
+        let b = match order {
+            ByteOrder::BigEndian    => self.to_be_bytes(),
+            ByteOrder::LittleEndian => self.to_le_bytes(),
+        };
+        bytes[..2].copy_from_slice(&b);
+    }
+
+    fn merge(self, mask: Self, bits: Self) -> Self {
+        (self & !mask) | (bits & mask)
+    }
+}
+
+/// Device register at address `addr` that contains a value of type `T` (`u8` or `u16`)
+pub struct Register<T: RegisterValue> {
+    /// Register address
+    addr:  u8,
+    /// Type of the register value
+    _type: PhantomData<T>,
+}
+
+impl<T: RegisterValue> Register<T> {
+    /// Declare a register at address `addr`.
+    pub const fn new(addr: u8) -> Self {
+        Register { addr, _type: PhantomData }
+    }
+
+    /// Return the register address
+    pub fn addr(&self) -> u8 {
+        self.addr
+    }
+}
+
+impl<T: RegisterValue> Clone for Register<T> {
+    fn clone(&self) -> Self { Register::new(self.addr) }
+}
+
+impl<T: RegisterValue> Copy for Register<T> {}
+
+/// I2C device whose registers may be read and written. `P` is the I2C port, by default the Mynewt I2C port.
+pub struct RegisterDevice<P = I2C> {
+    /// I2C port
+    port:  P,
+    /// I2C address of the device (7-bit address)
+    addr:  u8,
+    /// Byte order of 16-bit registers
+    order: ByteOrder,
+}
+
+impl<P> RegisterDevice<P> {
+    /// Create a device at I2C address `addr` (7-bit address) on the I2C port. 16-bit registers are big endian.
+    pub const fn new(port: P, addr: u8) -> Self {
+        RegisterDevice { port, addr, order: ByteOrder::BigEndian }
+    }
+
+    /// Create a device like `new()`, with the byte order for 16-bit registers
+    pub const fn with_byte_order(port: P, addr: u8, order: ByteOrder) -> Self {
+        RegisterDevice { port, addr, order }
+    }
+
+    /// Return the I2C address of the device
+    pub fn addr(&self) -> u8 {
+        self.addr
+    }
+}
+
+impl<P, E> RegisterDevice<P>
+where
+    P: Write<Error = E> + WriteRead<Error = E>,
+    E: Into<MynewtError>,
+{
+    /// Read the register
+    pub fn read<T: RegisterValue>(&mut self, reg: Register<T>) -> MynewtResult<T> {
+        let mut bytes = [0u8; 2];
+        self.read_burst(reg.addr, &mut bytes[..T::SIZE]) ? ;
+        Ok(T::from_bytes(&bytes, self.order))
+    }
+
+    /// Write the value to the register
+    pub fn write<T: RegisterValue>(&mut self, reg: Register<T>, value: T) -> MynewtResult<()> {
+        //  Send the register address followed by the value
+        let mut bytes = [0u8; 3];
+        bytes[0] = reg.addr;
+        value.to_bytes(&mut bytes[1..], self.order);
+        self.check_addr() ? ;
+        self.port.write(self.addr, &bytes[..1 + T::SIZE])
+            .map_err(|err| err.into())
+    }
+
+    /// Read the register, change the bits in `mask` to the corresponding `bits` and write the register
+    pub fn update_bits<T: RegisterValue>(&mut self, reg: Register<T>, mask: T, bits: T) -> MynewtResult<()> {
+        let value = self.read(reg) ? ;
+        self.write(reg, value.merge(mask, bits))
+    }
+
+    /// Read consecutive registers starting at `start_register` into `buffer`, one byte per register.
+    /// The device must auto-increment the register address.
+    pub fn read_burst(&mut self, start_register: u8, buffer: &mut [u8]) -> MynewtResult<()> {
+        self.check_addr() ? ;
+        if buffer.len() > u16::max_value() as usize { return Err(MynewtError::SYS_EINVAL); }  //  Too large for Mynewt
+        //  Send the starting register, then read the registers
+        self.port.write_read(self.addr, &[start_register], buffer)
+            .map_err(|err| err.into())
+    }
+
+    /// Return `SYS_EINVAL` if the I2C address is not a 7-bit address
+    fn check_addr(&self) -> MynewtResult<()> {
+        if self.addr >= 128 { return Err(MynewtError::SYS_EINVAL); }
+        Ok(())
+    }
+}
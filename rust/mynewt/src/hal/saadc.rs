@@ -0,0 +1,186 @@
+//! Blocking reads of the nRF52 SAADC (Successive Approximation Analog-to-Digital Converter).
+//! The Mynewt ADC driver for nRF52 is not in our bindings, so we program the SAADC registers directly.
+//! Each read samples one analog input on channel 0 with 12-bit resolution and the internal 0.6 V reference,
+//! then disables the SAADC so that it doesn't draw power while idle:
+//! ```
+//! let raw = saadc::read(AnalogInput::Ain7, Gain::Gain1_6) ? ;  //  Sample P0.31
+//! let millivolts = Gain::Gain1_6.to_millivolts(raw);
+//! ```
+//! Don't enable the Mynewt ADC driver (`ADC_0` in `syscfg.yml`) at the same time, since both will use the SAADC.
+use core::sync::atomic::{ compiler_fence, Ordering };
+use crate::{
+    result::*,
+    kernel::sync::Mutex,
+};
+
+/// Analog input pin to be sampled
+#[derive(Clone, Copy, PartialEq)]
+pub enum AnalogInput {
+    /// AIN0 (P0.02)
+    Ain0 = 1,
+    /// AIN1 (P0.03)
+    Ain1 = 2,
+    /// AIN2 (P0.04)
+    Ain2 = 3,
+    /// AIN3 (P0.05)
+    Ain3 = 4,
+    /// AIN4 (P0.28)
+    Ain4 = 5,
+    /// AIN5 (P0.29)
+    Ain5 = 6,
+    /// AIN6 (P0.30)
+    Ain6 = 7,
+    /// AIN7 (P0.31)
+    Ain7 = 8,
+    /// Supply voltage VDD
+    Vdd  = 9,
+}
+
+/// Gain of the SAADC input. The input range is 0.6 V divided by the gain, e.g. 3.6 V for `Gain1_6`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Gain {
+    /// Gain 1/6, input range 3.6 V
+    Gain1_6 = 0,
+    /// Gain 1/5, input range 3.0 V
+    Gain1_5 = 1,
+    /// Gain 1/4, input range 2.4 V
+    Gain1_4 = 2,
+    /// Gain 1/3, input range 1.8 V
+    Gain1_3 = 3,
+    /// Gain 1/2, input range 1.2 V
+    Gain1_2 = 4,
+    /// Gain 1, input range 0.6 V
+    Gain1   = 5,
+    /// Gain 2, input range 0.3 V
+    Gain2   = 6,
+    /// Gain 4, input range 0.15 V
+    Gain4   = 7,
+}
+
+impl Gain {
+    /// Return the input range in millivolts, i.e. the input voltage for the max sample value
+    pub fn range_millivolts(self) -> u32 {
+        match self {
+            Gain::Gain1_6 => 3600,
+            Gain::Gain1_5 => 3000,
+            Gain::Gain1_4 => 2400,
+            Gain::Gain1_3 => 1800,
+            Gain::Gain1_2 => 1200,
+            Gain::Gain1   => 600,
+            Gain::Gain2   => 300,
+            Gain::Gain4   => 150,
+        }
+    }
+
+    /// Convert the sample returned by `read()` to millivolts. Negative samples (caused by noise) are treated as 0.
+    pub fn to_millivolts(self, sample: i16) -> u32 {
+        if sample <= 0 { return 0; }
+        sample as u32 * self.range_millivolts() / SAADC_MAX_SAMPLE
+    }
+}
+
+/// Sample the analog input with the gain and return the 12-bit sample. Blocks until the sample is ready.
+/// Returns `SYS_ETIMEOUT` if the SAADC doesn't respond.
+pub fn read(input: AnalogInput, gain: Gain) -> MynewtResult<i16> {
+    //  Only one task may use the SAADC at a time
+    let _lock = SAADC_LOCK.lock() ? ;
+    //  SAADC writes the sample to RAM via EasyDMA
+    let mut sample: i16 = 0;
+    let res = sample_channel(input, gain, &mut sample);
+    //  Disconnect the input and disable the SAADC, even if sampling failed
+    write_reg(CH0_PSELP, 0);
+    write_reg(ENABLE, 0);
+    res ? ;
+    Ok(sample)
+}
+
+/// Configure channel 0 for the input and gain, then sample the channel into `sample`
+fn sample_channel(input: AnalogInput, gain: Gain, sample: &mut i16) -> MynewtResult<()> {
+    //  Configure the SAADC: Single-ended input, internal 0.6 V reference, 10 microseconds acquisition time
+    //  for high impedance sources like voltage dividers
+    write_reg(ENABLE,     1);
+    write_reg(RESOLUTION, 2);  //  12 bits
+    write_reg(OVERSAMPLE, 0);  //  No oversampling
+    write_reg(SAMPLERATE, 0);  //  Sample when triggered by `TASKS_SAMPLE`
+    write_reg(CH0_PSELN,  0);  //  Not connected
+    write_reg(CH0_CONFIG, (gain as u32) << 8 | 2 << 16);
+    write_reg(CH0_PSELP,  input as u32);
+    write_reg(RESULT_PTR, sample as *mut i16 as u32);
+    write_reg(RESULT_MAXCNT, 1);
+
+    //  Start the SAADC, take the sample and stop the SAADC
+    compiler_fence(Ordering::SeqCst);  //  Sample buffer will be written by EasyDMA
+    trigger(TASKS_START,  EVENTS_STARTED) ? ;
+    trigger(TASKS_SAMPLE, EVENTS_END) ? ;
+    #[cfg(feature = "simulator")]  //  In the simulator, EasyDMA can't write to a 64-bit address, so fetch the sample from the model
+    { *sample = crate::sim::peripheral::saadc_result(); }
+    trigger(TASKS_STOP,   EVENTS_STOPPED) ? ;
+    compiler_fence(Ordering::SeqCst);
+    Ok(())
+}
+
+/// Start the SAADC task and wait for the event. Returns `SYS_ETIMEOUT` if the event doesn't happen.
+fn trigger(task: usize, event: usize) -> MynewtResult<()> {
+    write_reg(event, 0);
+    write_reg(task, 1);
+    for _ in 0..SAADC_TIMEOUT_LOOPS {
+        if read_reg(event) != 0 {
+            write_reg(event, 0);
+            return Ok(());
+        }
+    }
+    Err(MynewtError::SYS_ETIMEOUT)
+}
+
+/// Write the SAADC register at the offset
+#[cfg(not(feature = "simulator"))]
+fn write_reg(offset: usize, value: u32) {
+    unsafe { core::ptr::write_volatile((SAADC_BASE + offset) as *mut u32, value) };
+}
+
+/// Read the SAADC register at the offset
+#[cfg(not(feature = "simulator"))]
+fn read_reg(offset: usize) -> u32 {
+    unsafe { core::ptr::read_volatile((SAADC_BASE + offset) as *const u32) }
+}
+
+/// Write the simulated SAADC register at the offset
+#[cfg(feature = "simulator")]
+fn write_reg(offset: usize, value: u32) {
+    crate::sim::peripheral::write(SAADC_BASE + offset, value);
+}
+
+/// Read the simulated SAADC register at the offset
+#[cfg(feature = "simulator")]
+fn read_reg(offset: usize) -> u32 {
+    crate::sim::peripheral::read(SAADC_BASE + offset)
+}
+
+/// Lock that prevents tasks from using the SAADC at the same time
+static SAADC_LOCK: Mutex<()> = Mutex::new(());
+
+/// Sample value for the max input voltage, for 12-bit resolution
+const SAADC_MAX_SAMPLE: u32 = 4096;
+
+/// Number of times to check for an SAADC event before timing out. A sample takes about 12 microseconds.
+const SAADC_TIMEOUT_LOOPS: u32 = 100_000;
+
+/// Base address of the SAADC registers on nRF52
+const SAADC_BASE: usize = 0x4000_7000;
+
+//  Offsets of the SAADC registers, from the nRF52832 Product Specification
+const TASKS_START:    usize = 0x000;
+const TASKS_SAMPLE:   usize = 0x004;
+const TASKS_STOP:     usize = 0x008;
+const EVENTS_STARTED: usize = 0x100;
+const EVENTS_END:     usize = 0x104;
+const EVENTS_STOPPED: usize = 0x114;
+const ENABLE:         usize = 0x500;
+const CH0_PSELP:      usize = 0x510;
+const CH0_PSELN:      usize = 0x514;
+const CH0_CONFIG:     usize = 0x518;
+const RESOLUTION:     usize = 0x5f0;
+const OVERSAMPLE:     usize = 0x5f4;
+const SAMPLERATE:     usize = 0x5f8;
+const RESULT_PTR:     usize = 0x62c;
+const RESULT_MAXCNT:  usize = 0x630;
@@ -0,0 +1,154 @@
+//! Safe Rust wrappers for the Mynewt Watchdog Timer, plus a supervisor that checks that each task is alive.
+//! Mynewt initialises and enables the Watchdog Timer at startup. The Watchdog Timer is tickled by the idle task
+//! via the Mynewt sanity checker (`os_sanity_run()`), so tasks that never block should also call `tickle()`.
+//!
+//! To supervise a task, declare a `Watch` and register it with a deadline. The task must call `checkin()`
+//! before the deadline expires, else the supervisor records the task as hung and Mynewt resets:
+//! ```
+//! static SPI_WATCH: Watch = Watch::new("spi");
+//! ...
+//! SPI_WATCH.register(5000) ? ;  //  Task must check in every 5 seconds
+//! loop {
+//!     SPI_WATCH.sleep();        //  Don't supervise while waiting for work
+//!     let event = wait_for_event();
+//!     SPI_WATCH.checkin() ? ;   //  Supervise until the next `sleep()`
+//!     ...
+//! }
+//! ```
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{ AtomicBool, Ordering },
+};
+use crate::{
+    result::*,
+    kernel::{
+        os,
+        sync::check_os_error,
+    },
+    sys::crash,
+    Ptr,
+};
+
+/// Initialise the Watchdog Timer to expire after `expire_msecs` milliseconds. Mynewt calls this at startup
+/// with `WATCHDOG_INTERVAL` from `syscfg.yml`, so this is only needed to change the interval.
+pub fn init(expire_msecs: u32) -> MynewtResult<()> {
+    let rc = unsafe { hal_watchdog_init(expire_msecs) };
+    if rc != 0 { return Err(MynewtError::SYS_EINVAL); }
+    Ok(())
+}
+
+/// Start the Watchdog Timer. Mynewt calls this when the OS starts.
+pub fn enable() {
+    unsafe { hal_watchdog_enable() };
+}
+
+/// Tickle the Watchdog Timer so that it doesn't expire. This needs to be done periodically, before the
+/// interval configured in `init()` expires.
+pub fn tickle() {
+    unsafe { hal_watchdog_tickle() };
+}
+
+/// Supervises a task: the task must call `checkin()` within the deadline, unless it's sleeping.
+/// Based on Mynewt `os_sanity_check`, which is run periodically by the Mynewt sanity checker.
+pub struct Watch {
+    /// Mynewt sanity check. `sc_checkin_last` is updated by `checkin()`
+    check:    UnsafeCell<os::os_sanity_check>,
+    /// Name of the task, for logging
+    name:     &'static str,
+    /// True if the task is waiting for work and should not be supervised
+    sleeping: AtomicBool,
+    /// True if the task has been logged as hung
+    hung:     AtomicBool,
+}
+
+impl Watch {
+    /// Create a new Watch for the task named `name`.
+    pub const fn new(name: &'static str) -> Self {
+        Watch {
+            check: UnsafeCell::new(os::os_sanity_check {
+                sc_checkin_last: 0,
+                sc_checkin_itvl: 0,
+                sc_func:         None,
+                sc_arg:          core::ptr::null_mut(),
+                sc_next:         os::os_sanity_check__bindgen_ty_1 { sle_next: core::ptr::null_mut() },
+            }),
+            name,
+            sleeping: AtomicBool::new(false),
+            hung:     AtomicBool::new(false),
+        }
+    }
+
+    /// Start supervising the task. The task must call `checkin()` within `deadline_ms` milliseconds.
+    pub fn register(&'static self, deadline_ms: u32) -> MynewtResult<()> {
+        let check = self.check.get();
+        let rc = unsafe { os::os_sanity_check_init(check) };
+        check_os_error(rc as os::os_error_t) ? ;
+        let mut deadline: os::os_time_t = 0;
+        let rc = unsafe { os::os_time_ms_to_ticks(deadline_ms, &mut deadline) };
+        check_os_error(rc as os::os_error_t) ? ;
+        unsafe {
+            (*check).sc_checkin_itvl = deadline;
+            (*check).sc_func = Some(watch_callback);
+            (*check).sc_arg  = self as *const Watch as Ptr;
+        }
+        //  Start the deadline now, before the sanity checker sees the check
+        let rc = unsafe { os::os_sanity_check_reset(check) };
+        check_os_error(rc as os::os_error_t) ? ;
+        let rc = unsafe { os::os_sanity_check_register(check) };
+        check_os_error(rc as os::os_error_t)
+    }
+
+    /// Tell the supervisor that the task is alive and busy. The task must check in again before the deadline.
+    pub fn checkin(&self) -> MynewtResult<()> {
+        let rc = unsafe { os::os_sanity_check_reset(self.check.get()) };
+        check_os_error(rc as os::os_error_t) ? ;
+        self.sleeping.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Tell the supervisor that the task is about to wait for work, e.g. for an event. The task is not supervised
+    /// until the next `checkin()`.
+    pub fn sleep(&self) {
+        self.sleeping.store(true, Ordering::SeqCst);
+    }
+
+    /// Return the name of the supervised task
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+///  Allow tasks to share the Watch, since the sanity check is updated by Mynewt with interrupts disabled.
+unsafe impl Sync for Watch {}
+
+/// Called periodically by the Mynewt sanity checker. Return 0 if the task is sleeping, so that Mynewt treats
+/// the task as checked in. Else return non-zero so that Mynewt checks the deadline, and if the deadline has
+/// expired, record the hung task before Mynewt resets. Runs on the idle task, whose stack is only
+/// `OS_IDLE_STACK_SIZE` words, so don't log or format here. The hung task is logged on the next boot.
+extern "C" fn watch_callback(check: *mut os::os_sanity_check, arg: Ptr) -> i32 {
+    let watch = unsafe { &*(arg as *const Watch) };
+    if watch.sleeping.load(Ordering::SeqCst) { return 0; }  //  Waiting for work, not hung
+    let (last, itvl) = unsafe { ((*check).sc_checkin_last, (*check).sc_checkin_itvl) };
+    let now = unsafe { os::os_time_get() };
+    if now.wrapping_sub(last) as i32 > itvl as i32 &&
+        !watch.hung.swap(true, Ordering::SeqCst) {
+        //  Deadline expired. Mynewt will reset after this callback, so record the task now.
+        crash::record_hung_task(watch.name, file!(), line!());
+    }
+    os::os_error_OS_TIMEOUT as i32
+}
+
+///  Import the Mynewt HAL functions for the Watchdog Timer
+extern "C" {
+    /// Set a recurring watchdog timer to fire no sooner than in `expire_msecs` milliseconds.
+    /// C API: `int hal_watchdog_init(uint32_t expire_msecs)`
+    fn hal_watchdog_init(expire_msecs: u32) -> i32;
+
+    /// Starts the watchdog.
+    /// C API: `void hal_watchdog_enable(void)`
+    fn hal_watchdog_enable();
+
+    /// Tickles the watchdog so that the Watchdog Timer doesn't expire.
+    /// C API: `void hal_watchdog_tickle(void)`
+    fn hal_watchdog_tickle();
+}
@@ -0,0 +1,226 @@
+//! Battery sensor for nRF52 devices like PineTime. Measures the battery voltage on an SAADC input, converts the
+//! voltage to charge percentage with a discharge curve, and reads the charging and power present status from
+//! GPIO pins. The sensor is registered with the Mynewt Sensor Framework, so it may be polled and subscribed
+//! like the temperature sensor:
+//! ```
+//! static BATTERY_DEVICE: Strn = init_strn!("battery_0");
+//! ...
+//! battery::init(&BATTERY_DEVICE, BATTERY_CONFIG) ? ;
+//! sensor::set_poll_rate_ms(&BATTERY_DEVICE, 60_000) ? ;
+//! let listener = sensor::new_sensor_listener(&BATTERY_KEY, sensor::SENSOR_TYPE_BATTERY, handle_battery) ? ;
+//! let battery = sensor_mgr::find_bydevname(&BATTERY_DEVICE).next().ok_or(MynewtError::SYS_ENODEV) ? ;
+//! sensor::register_listener(battery, listener) ? ;
+//! ```
+//! The listener receives `SensorValueType::Battery`. To read the battery without the Sensor Framework, call `read()`.
+use ::cty::{ c_char, c_int, c_void };
+use crate::{
+    result::*,
+    hal::{
+        saadc::{ self, AnalogInput, Gain },
+        Pull,
+    },
+    hw::{
+        hal,
+        sensor::{
+            self,
+            sensor_battery_data, sensor_cfg, sensor_data_func_t, sensor_driver, sensor_type_t,
+            SENSOR_TYPE_BATTERY,
+        },
+    },
+    kernel::os,
+    fill_zero,
+    NULL, Strn, GPIO,
+};
+
+/// Battery voltage (millivolts) and charge (percent) of a typical Lithium Polymer battery while discharging.
+/// Voltages are in descending order. Charge is interpolated between the points.
+pub const LIPO_DISCHARGE_CURVE: &[(u32, u8)] = &[
+    (4180, 100),
+    (4100,  90),
+    (4000,  80),
+    (3920,  70),
+    (3870,  60),
+    (3820,  50),
+    (3790,  40),
+    (3770,  30),
+    (3740,  20),
+    (3680,  10),
+    (3450,   5),
+    (3300,   0),
+];
+
+/// Battery sensor settings
+#[derive(Clone, Copy)]
+pub struct BatteryConfig {
+    /// Analog input connected to the battery voltage divider
+    pub input:        AnalogInput,
+    /// SAADC gain. The input range for the gain must cover the max divided battery voltage.
+    pub gain:         Gain,
+    /// The battery voltage is divided by this number before the analog input, e.g. 2 for two equal resistors
+    pub divider:      u32,
+    /// Pin that is low while charging, if any
+    pub charging_pin: Option<i32>,
+    /// Pin that is low while plugged into power, if any
+    pub power_pin:    Option<i32>,
+    /// Battery voltage and charge while discharging, e.g. `LIPO_DISCHARGE_CURVE`
+    pub curve:        &'static [(u32, u8)],
+}
+
+/// Battery status returned by `read()`
+#[derive(Clone, Copy)]
+pub struct BatteryStatus {
+    /// Battery voltage in millivolts
+    pub millivolts: u32,
+    /// Battery charge in percent, 0 to 100
+    pub percent:    u8,
+    /// True if the battery is charging
+    pub charging:   bool,
+    /// True if plugged into power
+    pub plugged:    bool,
+}
+
+/// Create the battery sensor with the device name `devname` and register it with the Sensor Manager.
+/// Must be called after the OS has started, e.g. in `main()`. There is only one battery sensor.
+pub fn init(devname: &'static Strn, config: BatteryConfig) -> MynewtResult<()> {
+    devname.validate();
+    if config.divider == 0 || config.curve.is_empty() { return Err(MynewtError::SYS_EINVAL); }
+    if unsafe { BATTERY_CONFIG.is_some() } { return Err(MynewtError::SYS_EALREADY); }
+
+    //  Configure the status pins as inputs. The pins are driven by the charger, so no pull up or pull down.
+    for pin in [config.charging_pin, config.power_pin].iter() {
+        if let Some(pin) = pin {
+            GPIO::new().init_in(*pin, Pull::None) ? ;
+        }
+    }
+    unsafe { BATTERY_CONFIG = Some(config) };
+
+    //  Create the device. Since the OS has started, Mynewt calls `battery_dev_init()` to register the sensor.
+    let rc = unsafe { os::os_dev_create(
+        &mut BATTERY_DEVICE.dev,
+        devname.as_ptr() as *const c_char,
+        os::OS_DEV_INIT_PRIMARY as u8,
+        os::OS_DEV_INIT_PRIO_DEFAULT as u8,
+        Some(battery_dev_init),
+        NULL
+    ) };
+    if rc != 0 {
+        unsafe { BATTERY_CONFIG = None };
+        return Err(MynewtError::from(rc));
+    }
+    Ok(())
+}
+
+/// Read the battery voltage and charging status. Blocks while sampling the battery voltage.
+/// Returns `SYS_ENODEV` if `init()` has not been called.
+pub fn read() -> MynewtResult<BatteryStatus> {
+    let config = match unsafe { BATTERY_CONFIG } {
+        Some(config) => config,
+        None         => return Err(MynewtError::SYS_ENODEV),
+    };
+    let sample = saadc::read(config.input, config.gain) ? ;
+    let millivolts = config.gain.to_millivolts(sample) * config.divider;
+    Ok(BatteryStatus {
+        millivolts,
+        percent:  percent_from_millivolts(millivolts, config.curve),
+        charging: is_pin_low(config.charging_pin),
+        plugged:  is_pin_low(config.power_pin),
+    })
+}
+
+/// Convert the battery voltage to charge percentage with the discharge curve, interpolating between the points
+pub fn percent_from_millivolts(millivolts: u32, curve: &[(u32, u8)]) -> u8 {
+    let (first, last) = match (curve.first(), curve.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _                         => return 0,
+    };
+    if millivolts >= first.0 { return first.1; }
+    for points in curve.windows(2) {
+        let (high, low) = (points[0], points[1]);
+        if millivolts >= low.0 && high.0 > low.0 {
+            let percent = low.1 as u32 +
+                (millivolts - low.0) * high.1.saturating_sub(low.1) as u32 / (high.0 - low.0);
+            return percent as u8;
+        }
+    }
+    last.1
+}
+
+/// Return true if the pin is configured and low
+fn is_pin_low(pin: Option<i32>) -> bool {
+    match pin {
+        Some(pin) => unsafe { hal::hal_gpio_read(pin) == 0 },
+        None      => false,
+    }
+}
+
+/// Mynewt device for the battery sensor. `os_dev` must be first, because Mynewt passes the `os_dev` to `battery_dev_init()`.
+#[repr(C)]
+struct BatteryDevice {
+    /// Mynewt device
+    dev:    os::os_dev,
+    /// Mynewt sensor
+    sensor: sensor::sensor,
+}
+
+/// The battery sensor device. Must be static because Mynewt keeps pointers to the device and sensor.
+static mut BATTERY_DEVICE: BatteryDevice = fill_zero!(BatteryDevice);
+
+/// Settings for the battery sensor, set by `init()`
+static mut BATTERY_CONFIG: Option<BatteryConfig> = None;
+
+/// Functions called by the Sensor Framework to read the battery sensor
+static mut BATTERY_DRIVER: sensor_driver = sensor_driver {
+    sd_read:       Some(battery_sensor_read),
+    sd_get_config: Some(battery_sensor_get_config),
+    ..fill_zero!(sensor_driver)
+};
+
+/// Called by Mynewt to initialise the device. Register the battery sensor with the Sensor Manager.
+extern "C" fn battery_dev_init(dev: *mut os::os_dev, _arg: *mut c_void) -> c_int {
+    let device = dev as *mut BatteryDevice;
+    let sensor = unsafe { &mut (*device).sensor };
+    let rc = unsafe { sensor::sensor_init(sensor, dev) };
+    if rc != 0 { return rc; }
+
+    //  Set the driver and sensor types, like `sensor_set_driver()` and `sensor_set_type_mask()`
+    sensor.s_funcs = unsafe { &mut BATTERY_DRIVER };
+    sensor.s_types = SENSOR_TYPE_BATTERY;
+    sensor.s_mask  = SENSOR_TYPE_BATTERY;
+    unsafe { sensor::sensor_mgr_register(sensor) }
+}
+
+/// Called by the Sensor Framework to read the battery sensor. Pass the battery status to `data_func`.
+extern "C" fn battery_sensor_read(sensor: *mut sensor::sensor, sensor_type: sensor_type_t,
+    data_func: sensor_data_func_t, data_arg: *mut c_void, _timeout: u32) -> c_int {
+    //  We only allow reading of battery status
+    if sensor_type & SENSOR_TYPE_BATTERY == 0 { return os::SYS_EINVAL; }
+    let status = match read() {
+        Ok(status) => status,
+        Err(err)   => return err as c_int,
+    };
+    let mut data = sensor_battery_data {
+        sbd_millivolts: status.millivolts,
+        sbd_percent:    status.percent,
+        sbd_charging:   status.charging as u8,
+        sbd_plugged:    status.plugged  as u8,
+        sbd_is_valid:   1,
+    };
+    //  Call the Listener Function to process the sensor data
+    match data_func {
+        Some(data_func) => unsafe { data_func(
+            sensor,
+            data_arg,
+            &mut data as *mut sensor_battery_data as *mut c_void,
+            SENSOR_TYPE_BATTERY
+        ) },
+        None => 0,
+    }
+}
+
+/// Called by the Sensor Framework to get the type of sensor value returned by the battery sensor
+extern "C" fn battery_sensor_get_config(_sensor: *mut sensor::sensor, sensor_type: sensor_type_t,
+    cfg: *mut sensor_cfg) -> c_int {
+    if sensor_type & SENSOR_TYPE_BATTERY == 0 { return os::SYS_EINVAL; }
+    unsafe { (*cfg).sc_valtype = sensor::SENSOR_VALUE_TYPE_OPAQUE as u8 };
+    0
+}
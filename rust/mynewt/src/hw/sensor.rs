@@ -57,6 +57,18 @@ fn convert_sensor_data(sensor_data: sensor_data_ptr, sensor_key: &'static Strn,
                     }
                 } else { SensorValueType::None }  //  Geolocation data is invalid.  Maybe GPS is not ready.                 
             }
+            SENSOR_TYPE_BATTERY => {  //  If sensor data is battery status...
+                //  Battery sensor is implemented in Rust, so we may interpret the sensor data directly.
+                let battery = unsafe { &*(sensor_data as *const sensor_battery_data) };
+                if battery.sbd_is_valid != 0 {
+                    SensorValueType::Battery {
+                        millivolts: battery.sbd_millivolts,
+                        percent:    battery.sbd_percent,
+                        charging:   battery.sbd_charging != 0,
+                        plugged:    battery.sbd_plugged  != 0,
+                    }
+                } else { SensorValueType::None }  //  Battery data is invalid
+            }
             //  TODO: Convert other sensor types
             _ => { assert!(false, "sensor type"); SensorValueType::None }  //  Unknown type of sensor value
         }
@@ -218,7 +230,7 @@ fn null_sensor_value_func(
     { Ok(()) }
 
 ///  Import the custom interop helper library at `libs/mynewt_rust`
-#[link(name = "libs_mynewt_rust")]  //  Functions below are located in the Mynewt build output `libs_mynewt_rust.a`
+#[cfg_attr(not(feature = "simulator"), link(name = "libs_mynewt_rust"))]  //  Functions below are located in the Mynewt build output `libs_mynewt_rust.a`
 extern {
     ///  Interpret `sensor_data` as a `sensor_temp_raw_data` struct that contains raw temp.
     ///  Copy the sensor data into `dest`.  Return 0 if successful.
@@ -258,6 +270,9 @@ pub const SENSOR_TYPE_AMBIENT_TEMPERATURE_RAW: sensor_type_t =
     crate::libs::mynewt_rust::sensor_type_t_SENSOR_TYPE_USER_DEFINED_1;
 pub const SENSOR_TYPE_GEOLOCATION: sensor_type_t =
     crate::libs::mynewt_rust::sensor_type_t_SENSOR_TYPE_USER_DEFINED_2;
+///  Sensor type for battery voltage and charging status, returned by the battery sensor in `hw::battery`.
+pub const SENSOR_TYPE_BATTERY: sensor_type_t =
+    crate::libs::mynewt_rust::sensor_type_t_SENSOR_TYPE_USER_DEFINED_3;
 
 ///  Represents a decoded sensor data value. Since temperature may be integer (raw)
 ///  or float (computed), we use the struct to return both integer and float values.
@@ -296,6 +311,8 @@ pub enum SensorValueType {
     ///  Geolocation
     #[cfg(feature = "use_float")]  //  If floating-point is enabled...
     Geolocation { latitude: f64, longitude: f64, altitude: f64 },
+    ///  Battery voltage in millivolts, charge percentage, true if charging and true if plugged into power
+    Battery { millivolts: u32, percent: u8, charging: bool, plugged: bool },
 }
 
 ///  Represents a single temperature sensor raw value.
@@ -327,6 +344,21 @@ pub struct sensor_geolocation_data {
     pub sgd_altitude_is_valid: u8, 
 }
 
+///  Represents the battery status. Returned by the battery sensor in `hw::battery`, which is implemented in Rust.
+#[repr(C)]
+pub struct sensor_battery_data {
+    ///  Battery voltage (millivolts)
+    pub sbd_millivolts: u32,
+    ///  Battery charge (percent, 0 to 100)
+    pub sbd_percent: u8,
+    ///  1 if battery is charging
+    pub sbd_charging: u8,
+    ///  1 if plugged into power
+    pub sbd_plugged: u8,
+    ///  1 if data is valid
+    pub sbd_is_valid: u8,
+}
+
 /// Points to a `sensor`.  Needed because `sensor` also refers to a namespace.
 pub type sensor_ptr = *mut sensor;
 /// Points to sensor arg passed by Mynewt to sensor listener
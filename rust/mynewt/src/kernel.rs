@@ -7,3 +7,28 @@ use crate::{
 
 /// Contains Rust bindings for Mynewt OS API `kernel/os`
 pub mod os;  // Export `kernel/os.rs` as Rust module `mynewt::kernel::os`
+
+/// Safe wrappers for Mynewt Mutexes and Semaphores
+pub mod sync;  // Export `kernel/sync.rs` as Rust module `mynewt::kernel::sync`
+
+/// Typed Timer API based on Mynewt Callouts
+pub mod timer;  // Export `kernel/timer.rs` as Rust module `mynewt::kernel::timer`
+
+/// Typed handle for Mynewt tasks created by `task!()`
+pub mod task;  // Export `kernel/task.rs` as Rust module `mynewt::kernel::task`
+
+/// Safe wrappers for Mynewt Mbufs and Mbuf Queues
+pub mod mbuf;  // Export `kernel/mbuf.rs` as Rust module `mynewt::kernel::mbuf`
+
+/// Fixed-block Memory Pool based on Mynewt Memory Pools
+pub mod pool;  // Export `kernel/pool.rs` as Rust module `mynewt::kernel::pool`
+
+/// Convert milliseconds to OS ticks at the tick rate of the build (`OS_TICKS_PER_SEC` in syscfg, 128 on nRF52).
+/// Don't use `os::OS_TICKS_PER_SEC` for this, because the bindings were generated with a tick rate of 1000.
+/// Returns the maximum number of ticks if the conversion overflows.
+pub fn ms_to_ticks(ms: u32) -> os::os_time_t {
+    let mut ticks: os::os_time_t = 0;
+    let rc = unsafe { os::os_time_ms_to_ticks(ms, &mut ticks) };
+    if rc != 0 { return os::os_time_t::max_value(); }
+    ticks
+}
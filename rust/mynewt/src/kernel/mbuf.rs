@@ -0,0 +1,319 @@
+//! Safe Rust wrappers for Mynewt Mbufs (`os_mbuf`) and Mbuf Queues (`os_mqueue`). An `Mbuf` owns an mbuf chain
+//! and frees the chain when dropped, unless the chain has been passed to Mynewt or to an `MbufQueue`:
+//! ```
+//! static PACKET_QUEUE: MbufQueue = MbufQueue::new();
+//! ...
+//! PACKET_QUEUE.init(Some(packet_callback), NULL) ? ;
+//! let mut mbuf = Mbuf::new_pkthdr(16) ? ;  //  Allocate from the system mbuf pool (MSYS)
+//! mbuf.append(b"hello") ? ;
+//! PACKET_QUEUE.put(os::eventq_dflt_get() ?, mbuf) ? ;
+//! ...
+//! while let Some(mbuf) = PACKET_QUEUE.get() {
+//!     for segment in mbuf.segments() { ... }
+//! }  //  Mbuf is freed here
+//! ```
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    marker::PhantomData,
+};
+use crate::{
+    result::*,
+    kernel::{
+        os,
+        sync::check_os_error,
+    },
+    Ptr,
+};
+
+/// Read bytes from a buffer, like `std::io::Read`
+pub trait Read {
+    /// Read up to `buf.len()` bytes into `buf`. Returns the number of bytes read, or 0 if there are no more bytes.
+    fn read(&mut self, buf: &mut [u8]) -> MynewtResult<usize>;
+}
+
+/// Write bytes to a buffer, like `std::io::Write`
+pub trait Write {
+    /// Write the bytes in `data`. Returns the number of bytes written.
+    fn write(&mut self, data: &[u8]) -> MynewtResult<usize>;
+}
+
+/// Owned mbuf chain. The chain is freed when the `Mbuf` is dropped.
+pub struct Mbuf {
+    /// First mbuf in the chain. Never null.
+    mbuf: *mut os::os_mbuf,
+}
+
+impl Mbuf {
+    /// Allocate a packet header mbuf from the system mbuf pool (MSYS), big enough for `len` bytes.
+    /// Packet header mbufs are needed for `MbufQueue`. Returns `SYS_ENOMEM` if out of mbufs.
+    pub fn new_pkthdr(len: u16) -> MynewtResult<Mbuf> {
+        let mbuf = unsafe { os::os_msys_get_pkthdr(len, 0) };
+        unsafe { Mbuf::from_raw(mbuf) }
+            .ok_or(MynewtError::SYS_ENOMEM)
+    }
+
+    /// Allocate an mbuf from the system mbuf pool (MSYS), big enough for `len` bytes. Returns `SYS_ENOMEM` if out of mbufs.
+    pub fn new(len: u16) -> MynewtResult<Mbuf> {
+        let mbuf = unsafe { os::os_msys_get(len, 0) };
+        unsafe { Mbuf::from_raw(mbuf) }
+            .ok_or(MynewtError::SYS_ENOMEM)
+    }
+
+    /// Allocate a packet header mbuf from the mbuf pool `pool`, with `user_hdr_len` bytes of user header.
+    /// Returns `SYS_ENOMEM` if the pool is empty.
+    pub fn from_pool(pool: *mut os::os_mbuf_pool, user_hdr_len: u8) -> MynewtResult<Mbuf> {
+        if pool.is_null() { return Err(MynewtError::SYS_EINVAL); }
+        let mbuf = unsafe { os::os_mbuf_get_pkthdr(pool, user_hdr_len) };
+        unsafe { Mbuf::from_raw(mbuf) }
+            .ok_or(MynewtError::SYS_ENOMEM)
+    }
+
+    /// Take ownership of the mbuf chain `mbuf` returned by Mynewt. Returns `None` if `mbuf` is null.
+    /// Unsafe because the chain must not be freed elsewhere.
+    pub unsafe fn from_raw(mbuf: *mut os::os_mbuf) -> Option<Mbuf> {
+        if mbuf.is_null() { return None; }
+        Some(Mbuf { mbuf })
+    }
+
+    /// Give up ownership of the mbuf chain, e.g. when passing the chain to a Mynewt API that frees the chain.
+    pub fn into_raw(self) -> *mut os::os_mbuf {
+        let mbuf = self.mbuf;
+        core::mem::forget(self);  //  Don't free the chain
+        mbuf
+    }
+
+    /// Return the first mbuf in the chain
+    pub fn as_ptr(&self) -> *mut os::os_mbuf {
+        self.mbuf
+    }
+
+    /// Return the total number of bytes in the mbuf chain
+    pub fn len(&self) -> usize {
+        unsafe { os::os_mbuf_len(self.mbuf) as usize }
+    }
+
+    /// Return true if the mbuf chain contains no bytes
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Return true if the first mbuf has a packet header
+    pub fn is_pkthdr(&self) -> bool {
+        let pkthdr_len = unsafe { (*self.mbuf).om_pkthdr_len };
+        pkthdr_len >= core::mem::size_of::<os::os_mbuf_pkthdr>() as u8
+    }
+
+    /// Append `data` to the end of the mbuf chain. More mbufs will be allocated if needed.
+    /// Returns `SYS_ENOMEM` if out of mbufs.
+    pub fn append(&mut self, data: &[u8]) -> MynewtResult<()> {
+        if data.is_empty() { return Ok(()); }
+        if data.len() > u16::max_value() as usize { return Err(MynewtError::SYS_EINVAL); }
+        let rc = unsafe { os::os_mbuf_append(
+            self.mbuf,
+            data.as_ptr() as *const ::cty::c_void,
+            data.len() as u16
+        ) };
+        if rc != 0 { return Err(MynewtError::SYS_ENOMEM); }
+        Ok(())
+    }
+
+    /// Insert `data` at the front of the mbuf chain and return the new chain. Same as `os_mbuf_prepend()`,
+    /// the chain is freed if out of mbufs, so this consumes the `Mbuf`.
+    pub fn prepend(self, data: &[u8]) -> MynewtResult<Mbuf> {
+        if data.is_empty() { return Ok(self); }
+        if data.len() > u16::max_value() as usize { return Err(MynewtError::SYS_EINVAL); }
+        //  Mynewt frees the chain upon failure, so give up ownership first
+        let mbuf = unsafe { os::os_mbuf_prepend(self.into_raw(), data.len() as i32) };
+        let mut mbuf = unsafe { Mbuf::from_raw(mbuf) }
+            .ok_or(MynewtError::SYS_ENOMEM) ? ;
+        mbuf.copy_from(0, data) ? ;
+        Ok(mbuf)
+    }
+
+    /// Append the mbuf chain `other` to the end of this chain. `other` must not have a packet header.
+    pub fn concat(&mut self, other: Mbuf) {
+        unsafe { os::os_mbuf_concat(self.mbuf, other.into_raw()) };
+    }
+
+    /// Overwrite the bytes at `offset` with `data`, extending the chain if needed
+    pub fn copy_from(&mut self, offset: usize, data: &[u8]) -> MynewtResult<()> {
+        let rc = unsafe { os::os_mbuf_copyinto(
+            self.mbuf,
+            offset as i32,
+            data.as_ptr() as *const ::cty::c_void,
+            data.len() as i32
+        ) };
+        if rc != 0 { return Err(MynewtError::SYS_ENOMEM); }
+        Ok(())
+    }
+
+    /// Copy `buf.len()` bytes at `offset` into `buf`. Returns `SYS_EINVAL` if the chain is too short.
+    pub fn copy_to(&self, offset: usize, buf: &mut [u8]) -> MynewtResult<()> {
+        let rc = unsafe { os::os_mbuf_copydata(
+            self.mbuf,
+            offset as i32,
+            buf.len() as i32,
+            buf.as_mut_ptr() as Ptr
+        ) };
+        if rc != 0 { return Err(MynewtError::SYS_EINVAL); }
+        Ok(())
+    }
+
+    /// Remove `len` bytes from the front of the chain. If `len` is negative, remove `-len` bytes from the back.
+    pub fn trim(&mut self, len: i32) {
+        unsafe { os::os_mbuf_adj(self.mbuf, len) };
+    }
+
+    /// Return a copy of the mbuf chain. Returns `SYS_ENOMEM` if out of mbufs.
+    pub fn try_clone(&self) -> MynewtResult<Mbuf> {
+        let mbuf = unsafe { os::os_mbuf_dup(self.mbuf) };
+        unsafe { Mbuf::from_raw(mbuf) }
+            .ok_or(MynewtError::SYS_ENOMEM)
+    }
+
+    /// Return an iterator over the data in each mbuf of the chain, without copying
+    pub fn segments(&self) -> Segments {
+        Segments { mbuf: self.mbuf, _chain: PhantomData }
+    }
+
+    /// Return a reader that reads the mbuf chain from the start
+    pub fn reader(&self) -> MbufReader {
+        MbufReader { mbuf: self, offset: 0 }
+    }
+}
+
+impl Drop for Mbuf {
+    /// Free the mbuf chain
+    fn drop(&mut self) {
+        unsafe { os::os_mbuf_free_chain(self.mbuf) };
+    }
+}
+
+impl Write for Mbuf {
+    /// Append `data` to the mbuf chain
+    fn write(&mut self, data: &[u8]) -> MynewtResult<usize> {
+        self.append(data) ? ;
+        Ok(data.len())
+    }
+}
+
+impl fmt::Write for Mbuf {
+    /// Append the string to the mbuf chain, so that `write!()` may be used
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.append(s.as_bytes())
+            .map_err(|_| fmt::Error)
+    }
+}
+
+///  Allow the Mbuf to be sent to another task, since the chain is owned by the `Mbuf`.
+unsafe impl Send for Mbuf {}
+
+/// Iterator over the data in each mbuf of a chain. Returned by `Mbuf::segments()`.
+pub struct Segments<'a> {
+    /// Next mbuf in the chain
+    mbuf:   *mut os::os_mbuf,
+    /// Segments may not outlive the chain
+    _chain: PhantomData<&'a Mbuf>,
+}
+
+impl<'a> Iterator for Segments<'a> {
+    type Item = &'a [u8];
+
+    /// Return the data in the next mbuf of the chain
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.mbuf.is_null() { return None; }
+        let m = unsafe { &*self.mbuf };
+        self.mbuf = m.om_next.sle_next;  //  Fetch next mbuf in the chain
+        Some(unsafe { core::slice::from_raw_parts(m.om_data, m.om_len as usize) })
+    }
+}
+
+/// Reads an mbuf chain sequentially. Returned by `Mbuf::reader()`.
+pub struct MbufReader<'a> {
+    /// Mbuf chain to be read
+    mbuf:   &'a Mbuf,
+    /// Offset of the next byte to be read
+    offset: usize,
+}
+
+impl<'a> Read for MbufReader<'a> {
+    /// Copy the next bytes of the mbuf chain into `buf`
+    fn read(&mut self, buf: &mut [u8]) -> MynewtResult<usize> {
+        let remaining = self.mbuf.len() - self.offset;
+        let len = core::cmp::min(remaining, buf.len());
+        if len == 0 { return Ok(0); }
+        self.mbuf.copy_to(self.offset, &mut buf[..len]) ? ;
+        self.offset += len;
+        Ok(len)
+    }
+}
+
+/// Packet type that may be passed through an `MbufQueue`. The packet must be stored in a packet header mbuf chain.
+pub trait MbufPacket: Sized {
+    /// Convert the packet into an mbuf chain
+    fn into_mbuf(self) -> Mbuf;
+    /// Convert the mbuf chain back into a packet
+    fn from_mbuf(mbuf: Mbuf) -> Self;
+}
+
+impl MbufPacket for Mbuf {
+    fn into_mbuf(self) -> Mbuf { self }
+    fn from_mbuf(mbuf: Mbuf) -> Self { mbuf }
+}
+
+/// Queue of packets for passing between tasks. When a packet is added, an event is posted to the receiving task's
+/// Event Queue. Based on Mynewt `os_mqueue`. Must be `static` because Mynewt keeps a pointer to the queue.
+pub struct MbufQueue<T: MbufPacket = Mbuf> {
+    /// Mynewt Mbuf Queue
+    queue:   UnsafeCell<os::os_mqueue>,
+    /// Type of packets in the queue
+    _packet: PhantomData<T>,
+}
+
+impl<T: MbufPacket> MbufQueue<T> {
+    /// Create an empty queue. `init()` must be called before use.
+    pub const fn new() -> Self {
+        MbufQueue {
+            queue: UnsafeCell::new(os::os_mqueue {
+                mq_head: os::os_mqueue__bindgen_ty_1 {
+                    stqh_first: core::ptr::null_mut(),
+                    stqh_last:  core::ptr::null_mut(),
+                },
+                mq_ev: os::os_event {
+                    ev_queued: 0,
+                    ev_cb:     None,
+                    ev_arg:    core::ptr::null_mut(),
+                    ev_next:   os::os_event__bindgen_ty_1 { stqe_next: core::ptr::null_mut() },
+                },
+            }),
+            _packet: PhantomData,
+        }
+    }
+
+    /// Initialise the queue. `callback` will be called by the receiving task when packets are available, with `arg` as the event argument.
+    pub fn init(&'static self, callback: os::os_event_fn, arg: Ptr) -> MynewtResult<()> {
+        let rc = unsafe { os::os_mqueue_init(self.queue.get(), callback, arg) };
+        check_os_error(rc as os::os_error_t)
+    }
+
+    /// Add the packet to the queue and post an event to the Event Queue `eventq`. If the packet can't be queued, it's dropped.
+    pub fn put(&self, eventq: *mut os::os_eventq, packet: T) -> MynewtResult<()> {
+        let mbuf = packet.into_mbuf();
+        if !mbuf.is_pkthdr() { return Err(MynewtError::SYS_EINVAL); }  //  Mbuf Queues only accept packet header mbufs
+        let rc = unsafe { os::os_mqueue_put(self.queue.get(), eventq, mbuf.as_ptr()) };
+        check_os_error(rc as os::os_error_t) ? ;
+        core::mem::forget(mbuf);  //  Chain is now owned by the queue
+        Ok(())
+    }
+
+    /// Remove and return the next packet in the queue. Returns `None` if the queue is empty. Does not block.
+    pub fn get(&self) -> Option<T> {
+        let mbuf = unsafe { os::os_mqueue_get(self.queue.get()) };
+        unsafe { Mbuf::from_raw(mbuf) }
+            .map(T::from_mbuf)
+    }
+}
+
+///  Allow tasks and interrupt handlers to share the queue, since `os_mqueue` is protected by critical sections.
+unsafe impl<T: MbufPacket + Send> Sync for MbufQueue<T> {}
@@ -0,0 +1,165 @@
+//! Fixed-block Memory Pool based on Mynewt `os_mempool`. A `Pool` statically reserves space for `N` items of type `T`
+//! and hands out `PoolBox` smart pointers that return the block to the pool when dropped. No heap is needed:
+//! ```
+//! static SAMPLE_POOL: Pool<SensorSample, heapless::consts::U8> = Pool::new();
+//! ...
+//! SAMPLE_POOL.init(&init_strn!("samples")) ? ;
+//! let sample = SAMPLE_POOL.alloc(SensorSample { ... }) ? ;  //  Returns `SYS_ENOMEM` if all blocks are used
+//! ```
+//! The pool is registered with Mynewt, so its usage will appear in `os_mempool_info_get_next()`.
+use core::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    mem::MaybeUninit,
+    ops::{ Deref, DerefMut },
+    ptr::NonNull,
+};
+use heapless::ArrayLength;
+use crate::{
+    result::*,
+    kernel::{
+        os,
+        sync::check_os_error,
+    },
+    Ptr, Strn,
+};
+
+/// Memory block that contains one item of type `T`. Aligned to `OS_ALIGNMENT` (4 bytes) and at least 4 bytes,
+/// because Mynewt stores the free list pointer inside free blocks.
+#[repr(C, align(4))]
+pub struct PoolBlock<T> {
+    /// Item stored in the block, uninitialised when the block is free
+    value: MaybeUninit<T>,
+}
+
+/// Usage statistics for a `Pool`
+#[derive(Clone, Copy)]
+pub struct PoolStats {
+    /// Size of each block in bytes
+    pub block_size: u32,
+    /// Total number of blocks
+    pub num_blocks: u16,
+    /// Number of free blocks
+    pub num_free:   u16,
+    /// Lowest number of free blocks seen so far
+    pub min_free:   u16,
+}
+
+/// Pool of `N` fixed-size blocks for items of type `T`. `N` is a `heapless::consts` size like `U8`.
+/// Must be `static` because Mynewt keeps a pointer to the pool.
+pub struct Pool<T, N>
+where N: ArrayLength<PoolBlock<T>> {
+    /// Mynewt Memory Pool
+    pool:   UnsafeCell<os::os_mempool>,
+    /// Space reserved for the blocks. The Vec stays empty, we only use its buffer.
+    blocks: UnsafeCell<heapless::Vec<PoolBlock<T>, N>>,
+}
+
+impl<T, N> Pool<T, N>
+where N: ArrayLength<PoolBlock<T>> {
+    /// Create a new pool. `init()` must be called before use.
+    pub const fn new() -> Self {
+        Pool {
+            pool: UnsafeCell::new(os::os_mempool {
+                mp_block_size:  0,
+                mp_num_blocks:  0,
+                mp_num_free:    0,
+                mp_min_free:    0,
+                mp_flags:       0,
+                mp_membuf_addr: 0,
+                mp_list:          os::os_mempool__bindgen_ty_1 { stqe_next: core::ptr::null_mut() },
+                __bindgen_anon_1: os::os_mempool__bindgen_ty_2 { slh_first: core::ptr::null_mut() },
+                name:           core::ptr::null_mut(),
+            }),
+            blocks: UnsafeCell::new(heapless::Vec(heapless::i::Vec::new())),
+        }
+    }
+
+    /// Initialise the pool and register it with Mynewt as `name`. Returns `SYS_EALREADY` if already initialised.
+    pub fn init(&'static self, name: &'static Strn) -> MynewtResult<()> {
+        assert!(core::mem::size_of::<T>() > 0, "zero size");  //  Zero-sized items don't need a pool
+        let pool = self.pool.get();
+        if unsafe { (*pool).mp_num_blocks } != 0 { return Err(MynewtError::SYS_EALREADY); }
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let num_blocks = blocks.capacity();
+        if num_blocks == 0 || num_blocks > u16::max_value() as usize { return Err(MynewtError::SYS_EINVAL); }
+        let rc = unsafe { os::os_mempool_init(
+            pool,                          //  Memory Pool to be initialised
+            num_blocks as u16,             //  Number of blocks
+            core::mem::size_of::<PoolBlock<T>>() as u32,  //  Size of each block, already aligned
+            blocks.as_mut_ptr() as Ptr,    //  Space reserved for the blocks
+            name.as_ptr() as *mut ::cty::c_char  //  Name of the pool
+        ) };
+        check_os_error(rc)
+    }
+
+    /// Move `value` into a free block and return a `PoolBox` that owns the block.
+    /// Returns `SYS_ENOMEM` if all blocks are used. May be called from interrupt handlers.
+    pub fn alloc(&'static self, value: T) -> MynewtResult<PoolBox<T>> {
+        let block = unsafe { os::os_memblock_get(self.pool.get()) } as *mut PoolBlock<T>;
+        let block = NonNull::new(block)
+            .ok_or(MynewtError::SYS_ENOMEM) ? ;
+        unsafe { (*block.as_ptr()).value = MaybeUninit::new(value) };
+        Ok(PoolBox {
+            block,
+            pool: self.pool.get(),
+            _marker: PhantomData,
+        })
+    }
+
+    /// Return the usage statistics for the pool
+    pub fn stats(&self) -> PoolStats {
+        let pool = unsafe { &*self.pool.get() };
+        PoolStats {
+            block_size: pool.mp_block_size,
+            num_blocks: pool.mp_num_blocks,
+            num_free:   pool.mp_num_free,
+            min_free:   pool.mp_min_free,
+        }
+    }
+}
+
+///  Allow tasks and interrupt handlers to share the pool, since `os_mempool` is protected by critical sections.
+unsafe impl<T: Send, N> Sync for Pool<T, N>
+where N: ArrayLength<PoolBlock<T>> {}
+
+/// Smart pointer to an item stored in a `Pool`. The block is returned to the pool when the `PoolBox` is dropped.
+pub struct PoolBox<T> {
+    /// Block that contains the item
+    block:   NonNull<PoolBlock<T>>,
+    /// Pool that owns the block
+    pool:    *mut os::os_mempool,
+    /// `PoolBox` owns the item
+    _marker: PhantomData<T>,
+}
+
+impl<T> Deref for PoolBox<T> {
+    type Target = T;
+    /// Return a reference to the item
+    fn deref(&self) -> &T {
+        unsafe { &*(*self.block.as_ptr()).value.as_ptr() }
+    }
+}
+
+impl<T> DerefMut for PoolBox<T> {
+    /// Return a mutable reference to the item
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *(*self.block.as_ptr()).value.as_mut_ptr() }
+    }
+}
+
+impl<T> Drop for PoolBox<T> {
+    /// Drop the item and return the block to the pool
+    fn drop(&mut self) {
+        let block = self.block.as_ptr();
+        unsafe { core::ptr::drop_in_place((*block).value.as_mut_ptr()) };
+        let rc = unsafe { os::os_memblock_put(self.pool, block as Ptr) };
+        assert_eq!(rc, os::os_error_OS_OK, "pool put fail");  //  Can't return an error from `drop()`
+    }
+}
+
+///  Allow the `PoolBox` to be sent to another task if the item may be sent.
+unsafe impl<T: Send> Send for PoolBox<T> {}
+
+///  Allow tasks to share the `PoolBox` if the item may be shared.
+unsafe impl<T: Sync> Sync for PoolBox<T> {}
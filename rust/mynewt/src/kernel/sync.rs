@@ -0,0 +1,197 @@
+//! Safe Rust wrappers for Mynewt Mutexes and Semaphores. Both types may be declared as `static` items
+//! without `fill_zero!`, so that tasks can share state without `static mut`:
+//! ```
+//! static COUNTER: Mutex<u32> = Mutex::new(0);
+//! static SPI_SEM: Semaphore  = Semaphore::new(0);
+//! ...
+//! *COUNTER.lock() ? += 1;
+//! SPI_SEM.pend(1000) ? ;
+//! ```
+use core::{
+    cell::UnsafeCell,
+    ops::{ Deref, DerefMut },
+};
+use crate::{
+    result::*,
+    kernel::os,
+};
+
+/// Mutex that protects the shared data of type `T`. Based on Mynewt `os_mutex`.
+/// Locking the Mutex returns a `MutexGuard` that releases the Mutex when dropped.
+pub struct Mutex<T> {
+    /// Mynewt Mutex
+    mutex: UnsafeCell<os::os_mutex>,
+    /// Data protected by the Mutex
+    data:  UnsafeCell<T>,
+}
+
+impl<T> Mutex<T> {
+    /// Create a new Mutex that protects `data`.
+    /// A zeroed `os_mutex` is identical to one initialised by `os_mutex_init()`, so we don't need to call `os_mutex_init()`.
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            mutex: UnsafeCell::new(os::os_mutex {
+                mu_head:  os::os_mutex__bindgen_ty_1 { slh_first: core::ptr::null_mut() },
+                _pad:     0,
+                mu_prio:  0,
+                mu_level: 0,
+                mu_owner: core::ptr::null_mut(),
+            }),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Lock the Mutex, waiting forever until the Mutex is available.
+    pub fn lock(&self) -> MynewtResult<MutexGuard<T>> {
+        self.lock_timeout(os::OS_TIMEOUT_NEVER)
+    }
+
+    /// Lock the Mutex without waiting. Returns `SYS_ETIMEOUT` if the Mutex is owned by another task.
+    pub fn try_lock(&self) -> MynewtResult<MutexGuard<T>> {
+        self.lock_timeout(0)
+    }
+
+    /// Lock the Mutex, waiting up to `timeout` ticks. Returns `SYS_ETIMEOUT` if the Mutex is still not available after the timeout.
+    /// Returns `SYS_EALREADY` if the current task has already locked the Mutex: `os_mutex` is recursive, so locking
+    /// again would succeed and create a second `MutexGuard` with mutable access to the same data.
+    pub fn lock_timeout(&self, timeout: os::os_time_t) -> MynewtResult<MutexGuard<T>> {
+        let owner = critical_section(|| unsafe { (*self.mutex.get()).mu_owner });
+        if !owner.is_null() && owner == unsafe { os::os_sched_get_current_task() } {
+            return Err(MynewtError::SYS_EALREADY);
+        }
+        let rc = unsafe { os::os_mutex_pend(self.mutex.get(), timeout) };
+        check_os_error(rc) ? ;
+        Ok(MutexGuard { mutex: self })
+    }
+
+    /// Return a mutable reference to the data. No locking is needed because we have exclusive access to the Mutex.
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+}
+
+///  Allow tasks to share the Mutex, since access to the data is protected by `os_mutex`.
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+///  Allow the Mutex to be sent to another task if the data may be sent.
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+/// Returned by `Mutex::lock()`. Allows access to the protected data and releases the Mutex when dropped.
+pub struct MutexGuard<'a, T> {
+    /// Mutex that has been locked
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+    /// Return a reference to the protected data
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    /// Return a mutable reference to the protected data
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    /// Release the Mutex when the guard goes out of scope
+    fn drop(&mut self) {
+        let rc = unsafe { os::os_mutex_release(self.mutex.mutex.get()) };
+        assert_eq!(rc, 0, "mutex release fail");  //  Can't return an error from `drop()`
+    }
+}
+
+/// Counting Semaphore based on Mynewt `os_sem`.
+pub struct Semaphore {
+    /// Mynewt Semaphore
+    sem: UnsafeCell<os::os_sem>,
+}
+
+impl Semaphore {
+    /// Create a new Semaphore with `tokens` initial tokens.
+    /// The struct created here is identical to one initialised by `os_sem_init()`, so we don't need to call `os_sem_init()`.
+    pub const fn new(tokens: u16) -> Self {
+        Semaphore {
+            sem: UnsafeCell::new(os::os_sem {
+                sem_head:   os::os_sem__bindgen_ty_1 { slh_first: core::ptr::null_mut() },
+                _pad:       0,
+                sem_tokens: tokens,
+            }),
+        }
+    }
+
+    /// Take a token from the Semaphore, waiting up to `timeout` ticks for the token to be available.
+    /// Use `os::OS_TIMEOUT_NEVER` to wait forever. Returns `SYS_ETIMEOUT` if no token is available after the timeout.
+    pub fn pend(&self, timeout: os::os_time_t) -> MynewtResult<()> {
+        let rc = unsafe { os::os_sem_pend(self.sem.get(), timeout) };
+        check_os_error(rc)
+    }
+
+    /// Return a token to the Semaphore. May be called from an interrupt handler.
+    pub fn release(&self) -> MynewtResult<()> {
+        let rc = unsafe { os::os_sem_release(self.sem.get()) };
+        check_os_error(rc)
+    }
+
+    /// Take a token from the Semaphore, waiting up to `timeout` ticks. Returns a `SemaphoreGuard` that returns the token when dropped.
+    pub fn acquire(&self, timeout: os::os_time_t) -> MynewtResult<SemaphoreGuard> {
+        self.pend(timeout) ? ;
+        Ok(SemaphoreGuard { sem: self })
+    }
+
+    /// Return the number of tokens currently available
+    pub fn tokens(&self) -> u16 {
+        unsafe { (*self.sem.get()).sem_tokens }
+    }
+}
+
+///  Allow tasks and interrupt handlers to share the Semaphore, since `os_sem` is thread-safe.
+unsafe impl Sync for Semaphore {}
+
+///  Allow the Semaphore to be sent to another task.
+unsafe impl Send for Semaphore {}
+
+/// Returned by `Semaphore::acquire()`. Returns the token to the Semaphore when dropped.
+pub struct SemaphoreGuard<'a> {
+    /// Semaphore that has been acquired
+    sem: &'a Semaphore,
+}
+
+impl<'a> Drop for SemaphoreGuard<'a> {
+    /// Return the token when the guard goes out of scope
+    fn drop(&mut self) {
+        self.sem.release().expect("sem release fail");  //  Can't return an error from `drop()`
+    }
+}
+
+/// Map the Mynewt `os_error_t` returned by the kernel to `MynewtResult`
+pub(crate) fn check_os_error(rc: os::os_error_t) -> MynewtResult<()> {
+    match rc {
+        os::os_error_OS_OK            => Ok(()),
+        os::os_error_OS_ENOMEM        => Err(MynewtError::SYS_ENOMEM),
+        os::os_error_OS_EINVAL        => Err(MynewtError::SYS_EINVAL),
+        os::os_error_OS_INVALID_PARM  => Err(MynewtError::SYS_EINVAL),
+        os::os_error_OS_MEM_NOT_ALIGNED => Err(MynewtError::SYS_EINVAL),
+        os::os_error_OS_BAD_MUTEX     => Err(MynewtError::SYS_EACCES),
+        os::os_error_OS_TIMEOUT       => Err(MynewtError::SYS_ETIMEOUT),
+        os::os_error_OS_ERR_IN_ISR    => Err(MynewtError::SYS_EACCES),
+        os::os_error_OS_ERR_PRIV      => Err(MynewtError::SYS_EACCES),
+        os::os_error_OS_NOT_STARTED   => Err(MynewtError::SYS_EAGAIN),
+        os::os_error_OS_ENOENT        => Err(MynewtError::SYS_ENOENT),
+        os::os_error_OS_EBUSY         => Err(MynewtError::SYS_EBUSY),
+        _                             => Err(MynewtError::SYS_EUNKNOWN),
+    }
+}
+
+/// Run the closure `f` with interrupts disabled. Same as `OS_ENTER_CRITICAL()` and `OS_EXIT_CRITICAL()` in C.
+/// Used for sharing state between tasks and interrupt handlers. Keep the closure short.
+pub fn critical_section<R>(f: impl FnOnce() -> R) -> R {
+    let sr = unsafe { os::os_arch_save_sr() };
+    let result = f();
+    unsafe { os::os_arch_restore_sr(sr) };
+    result
+}
@@ -0,0 +1,145 @@
+//! Typed handle for Mynewt tasks. Tasks should be created with the `task!()` macro, which declares the
+//! task object, its stack and its priority in one place:
+//! ```
+//! let handle = task!(
+//!     name:     "spi",          //  Name of task
+//!     func:     spi_task_func,  //  Function to execute when task starts
+//!     priority: 10,             //  Task priority: highest is 0, lowest is 255 (main task is 127)
+//!     stack:    256,            //  Size of the stack (in 4-byte units)
+//! ) ? ;
+//! let info = handle.info() ? ;
+//! ```
+use crate::{
+    result::*,
+    kernel::os,
+    Out, Ptr, Strn,
+};
+
+/// Handle to a Mynewt task that has been started by `task!()`
+#[derive(Clone, Copy)]
+pub struct TaskHandle {
+    /// Mynewt task object
+    task: *mut os::os_task,
+}
+
+/// State of a Mynewt task
+#[derive(Clone, Copy, PartialEq)]
+pub enum TaskState {
+    /// Task is ready to run
+    Ready,
+    /// Task is sleeping, e.g. waiting for an event or a semaphore
+    Sleep,
+    /// Unknown state returned by Mynewt
+    Unknown(u8),
+}
+
+/// Information about a task. Based on Mynewt `os_task_info`.
+#[derive(Clone, Copy)]
+pub struct TaskInfo {
+    /// Task priority
+    pub priority:    u8,
+    /// Task ID
+    pub task_id:     u8,
+    /// Task state
+    pub state:       TaskState,
+    /// Stack high-water mark, i.e. the max stack used so far (in 4-byte units)
+    pub stack_usage: u16,
+    /// Stack size (in 4-byte units)
+    pub stack_size:  u16,
+    /// Number of context switches
+    pub context_switches: u32,
+    /// Total run time in ticks
+    pub run_time:    u32,
+    /// Last time this task checked in with the sanity task
+    pub last_checkin: os::os_time_t,
+    /// Next time this task is scheduled to check in with the sanity task
+    pub next_checkin: os::os_time_t,
+}
+
+impl TaskHandle {
+    /// Create a new task and start it. Called by the `task!()` macro, which allocates `task` and `stack` as `static` items.
+    /// `sanity_itvl` is the interval in ticks for checking in with the sanity task, or `OS_WAIT_FOREVER` to disable sanity checking.
+    /// Returns `SYS_EALREADY` if the task has already been started.
+    pub fn spawn(
+        task:        Out<os::os_task>,
+        name:        &Strn,
+        func:        os::os_task_func_t,
+        arg:         Ptr,
+        priority:    u8,
+        sanity_itvl: os::os_time_t,
+        stack:       Out<[os::os_stack_t]>,
+    ) -> MynewtResult<TaskHandle> {
+        //  Stack must be aligned to `OS_STACK_ALIGNMENT` bytes. `task!()` checks this at compile time.
+        let stack_size = stack.len();
+        assert_eq!((stack_size * core::mem::size_of::<os::os_stack_t>()) % os::OS_STACK_ALIGNMENT as usize, 0, "stack align");
+        assert!(stack_size <= u16::max_value() as usize, "stack size");
+        //  Don't start the same task twice
+        if task.t_func.is_some() { return Err(MynewtError::SYS_EALREADY); }
+        let ptr: *mut os::os_task = task;
+        os::task_init(     //  Create a new task and start it...
+            task,          //  Task object will be saved here
+            name,          //  Name of task
+            func,          //  Function to execute when task starts
+            arg,           //  Argument to be passed to above function
+            priority,      //  Task priority: highest is 0, lowest is 255 (main task is 127)
+            sanity_itvl,   //  Sanity / watchdog checking interval
+            stack,         //  Stack space for the task
+            stack_size as u16  //  Size of the stack (in 4-byte units)
+        ) ? ;              //  `?` means check for error
+        Ok(TaskHandle { task: ptr })
+    }
+
+    /// Return a handle for the task that is currently running
+    pub fn current() -> TaskHandle {
+        TaskHandle { task: unsafe { os::os_sched_get_current_task() } }
+    }
+
+    /// Return the Mynewt task object
+    pub fn as_ptr(&self) -> *mut os::os_task {
+        self.task
+    }
+
+    /// Return the task information from `os_task_info_get_next()`. Returns `SYS_ENOENT` if the task is not found.
+    pub fn info(&self) -> MynewtResult<TaskInfo> {
+        let mut oti = os::os_task_info::default();
+        let mut prev: *const os::os_task = core::ptr::null();
+        loop {  //  For each task...
+            //  Fetch the next task and its information
+            let next = unsafe { os::os_task_info_get_next(prev, &mut oti) };
+            if next.is_null() { return Err(MynewtError::SYS_ENOENT); }  //  No more tasks
+            if next == self.task { break; }  //  Found our task
+            prev = next;
+        }
+        Ok(TaskInfo {
+            priority:    oti.oti_prio,
+            task_id:     oti.oti_taskid,
+            state:       match oti.oti_state as u32 {
+                os::os_task_state_OS_TASK_READY => TaskState::Ready,
+                os::os_task_state_OS_TASK_SLEEP => TaskState::Sleep,
+                _ => TaskState::Unknown(oti.oti_state),
+            },
+            stack_usage: oti.oti_stkusage,
+            stack_size:  oti.oti_stksize,
+            context_switches: oti.oti_cswcnt,
+            run_time:    oti.oti_runtime,
+            last_checkin: oti.oti_last_checkin,
+            next_checkin: oti.oti_next_checkin,
+        })
+    }
+
+    /// Return the stack high-water mark, i.e. the max stack used so far (in 4-byte units)
+    pub fn stack_high_water_mark(&self) -> MynewtResult<u16> {
+        Ok(self.info() ?.stack_usage)
+    }
+
+    /// Return the task state
+    pub fn state(&self) -> MynewtResult<TaskState> {
+        Ok(self.info() ?.state)
+    }
+}
+
+///  Allow tasks to share the handle, since the task object is `static`.
+unsafe impl Send for TaskHandle {}
+
+///  Allow tasks to share the handle, since the task object is `static`.
+unsafe impl Sync for TaskHandle {}
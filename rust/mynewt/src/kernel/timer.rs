@@ -0,0 +1,129 @@
+//! Typed Timer API based on Mynewt Callouts (`os_callout`). When the Timer expires, an event is posted to
+//! the chosen Event Queue and the handler is called by the task that runs the Event Queue:
+//! ```
+//! static TICK_TIMER: Timer = Timer::new();
+//! ...
+//! TICK_TIMER.init(os::eventq_dflt_get() ?, TimerMode::Periodic, || { /* Update watch face */ }) ? ;
+//! TICK_TIMER.start(kernel::ms_to_ticks(1000)) ? ;  //  Fire every second
+//! ```
+use core::cell::{ Cell, UnsafeCell };
+use crate::{
+    result::*,
+    kernel::os,
+    Ptr,
+};
+
+/// Whether the Timer fires once or repeatedly
+#[derive(Clone, Copy, PartialEq)]
+pub enum TimerMode {
+    /// Fire once after the timeout
+    OneShot,
+    /// Fire repeatedly, every timeout
+    Periodic,
+}
+
+/// Handler that will be called when the Timer expires. Accepts Rust functions and closures that don't capture any variables.
+pub type TimerHandler = fn();
+
+/// Timer that posts an event to an Event Queue upon expiry. Based on Mynewt `os_callout`.
+/// The Timer must be `static` because Mynewt keeps a pointer to the Timer while it's running.
+pub struct Timer {
+    /// Mynewt Callout
+    callout: UnsafeCell<os::os_callout>,
+    /// Function to be called when the Timer expires
+    handler: Cell<Option<TimerHandler>>,
+    /// One-shot or periodic
+    mode:    Cell<TimerMode>,
+    /// Number of ticks between expiries, set by `start()`
+    ticks:   Cell<os::os_time_t>,
+}
+
+impl Timer {
+    /// Create a new Timer. `init()` must be called before starting the Timer.
+    pub const fn new() -> Self {
+        Timer {
+            callout: UnsafeCell::new(os::os_callout {
+                c_ev: os::os_event {
+                    ev_queued: 0,
+                    ev_cb:     None,
+                    ev_arg:    core::ptr::null_mut(),
+                    ev_next:   os::os_event__bindgen_ty_1 { stqe_next: core::ptr::null_mut() },
+                },
+                c_evq:   core::ptr::null_mut(),
+                c_ticks: 0,
+                c_next:  os::os_callout__bindgen_ty_1 {
+                    tqe_next: core::ptr::null_mut(),
+                    tqe_prev: core::ptr::null_mut(),
+                },
+            }),
+            handler: Cell::new(None),
+            mode:    Cell::new(TimerMode::OneShot),
+            ticks:   Cell::new(0),
+        }
+    }
+
+    /// Initialise the Timer to post to the Event Queue `queue` and call `handler` when the Timer expires.
+    /// `handler` will be called by the task that runs the Event Queue, not by the interrupt handler.
+    pub fn init(&'static self, queue: *mut os::os_eventq, mode: TimerMode, handler: TimerHandler) -> MynewtResult<()> {
+        if queue.is_null() { return Err(MynewtError::SYS_EINVAL); }
+        if self.is_active() { return Err(MynewtError::SYS_EBUSY); }
+        self.handler.set(Some(handler));
+        self.mode.set(mode);
+        unsafe { os::os_callout_init(
+            self.callout.get(),       //  Callout to be initialised
+            queue,                    //  Event Queue that will receive the event
+            Some(timer_event_callback),  //  Call `timer_event_callback()` when the event is processed
+            self as *const Timer as Ptr  //  Pass the Timer to `timer_event_callback()`
+        ) };
+        Ok(())
+    }
+
+    /// Start the Timer to fire after `ticks` ticks. For periodic Timers, the Timer will fire every `ticks` ticks.
+    /// If the Timer is already running, it will be restarted.
+    pub fn start(&'static self, ticks: os::os_time_t) -> MynewtResult<()> {
+        if self.handler.get().is_none() { return Err(MynewtError::SYS_EINVAL); }  //  `init()` not called
+        if ticks == 0 && self.mode.get() == TimerMode::Periodic { return Err(MynewtError::SYS_EINVAL); }
+        self.ticks.set(ticks);
+        self.reset()
+    }
+
+    /// Restart the Timer with the same number of ticks passed to `start()`. Used for debouncing and inactivity timeouts.
+    pub fn reset(&'static self) -> MynewtResult<()> {
+        let rc = unsafe { os::os_callout_reset(self.callout.get(), self.ticks.get()) };
+        if rc != 0 { return Err(MynewtError::SYS_EINVAL); }
+        Ok(())
+    }
+
+    /// Stop the Timer. Any pending event will be removed from the Event Queue.
+    pub fn stop(&'static self) {
+        unsafe { os::os_callout_stop(self.callout.get()) };
+    }
+
+    /// Return true if the Timer is running. Same as `os_callout_queued()` in C.
+    pub fn is_active(&self) -> bool {
+        unsafe { !(*self.callout.get()).c_next.tqe_prev.is_null() }
+    }
+
+    /// Return the number of ticks before the Timer expires
+    pub fn remaining_ticks(&self) -> os::os_time_t {
+        if !self.is_active() { return 0; }
+        unsafe { os::os_callout_remaining_ticks(self.callout.get(), os::os_time_get()) }
+    }
+}
+
+///  Allow tasks to share the Timer. The Timer is only updated by the task that owns it and by the Event Queue task.
+unsafe impl Sync for Timer {}
+
+/// Callback for the event that is posted when the Timer expires
+extern "C" fn timer_event_callback(event: *mut os::os_event) {
+    //  Fetch the Timer from the event argument
+    let timer = unsafe { &*((*event).ev_arg as *const Timer) };
+    //  For periodic Timers, restart the Timer before calling the handler to reduce drift
+    if timer.mode.get() == TimerMode::Periodic {
+        unsafe { os::os_callout_reset(timer.callout.get(), timer.ticks.get()) };
+    }
+    //  Call the handler
+    if let Some(handler) = timer.handler.get() {
+        handler();
+    }
+}
@@ -5,9 +5,13 @@
 #![feature(const_transmute)]      //  Allow `transmute` for initialising Mynewt structs
 #![feature(trace_macros)]         //  Enable tracing of macros
 #![feature(proc_macro_hygiene)]   //  Allow proc macros to be unhygienic
+#![feature(const_fn)]             //  Allow `const fn` with function pointers and trait bounds for initialising `static` items
 
 extern crate macros as mynewt_macros;  //  Import Procedural Macros from `macros` library
 
+#[cfg(feature = "simulator")]     //  If the simulator is enabled...
+extern crate std;                 //  Simulate the Mynewt functions with the standard Rust library
+
 #[allow(non_camel_case_types)]    //  Allow type names to have non-camel case
 #[allow(non_upper_case_globals)]  //  Allow globals to have lowercase letters
 pub mod kernel;                   //  Mynewt Kernel API. Export folder `kernel` as Rust module `mynewt::kernel`
@@ -32,13 +36,19 @@ pub mod util;                     //  Mynewt Utility API. Export folder `encodin
 #[allow(non_upper_case_globals)]  //  Allow globals to have lowercase letters
 pub mod libs;                     //  Mynewt Custom API. Export folder `libs` as Rust module `mynewt::libs`
 
-mod hal;                            //  Import module `hal` for Embedded HAL functions but don't export it
-pub use hal::{ Delay, GPIO, SPI, I2C };  //  Export `hal` types GPIO, SPI and I2C
+pub mod hal;                        //  Export module `hal` for Embedded HAL functions and `mynewt::hal::watchdog`
+pub use hal::{ Delay, GPIO, GpioIrq, SPI, I2C };  //  Export `hal` types GPIO, SPI and I2C
 
 pub mod spi;  //  Export Non-Blocking SPI API
 
+pub mod executor;  //  Export Async Executor API
+
+#[cfg(feature = "simulator")]  //  If the simulator is enabled...
+pub mod sim;                   //  Export the Host Simulator API
+
 ///  Initialise the Mynewt system.  Start the Mynewt drivers and libraries.  Equivalent to `sysinit()` macro in C.
 pub fn sysinit() {
+    sys::ram_log::init();  //  Keep the RAM Log of the previous boot before any console output
     unsafe { rust_sysinit(); }
     sys::console::flush();
 }
@@ -249,7 +259,7 @@ pub type Ptr = *mut ::cty::c_void;
 pub const NULL: Ptr = core::ptr::null_mut();
 
 ///  Import the custom interop helper library at `libs/mynewt_rust`
-#[link(name = "libs_mynewt_rust")]  //  Functions below are located in the Mynewt build output `libs_mynewt_rust.a`
+#[cfg_attr(not(feature = "simulator"), link(name = "libs_mynewt_rust"))]  //  Functions below are located in the Mynewt build output `libs_mynewt_rust.a`
 extern {
     ///  Initialise the Mynewt system.  Start the Mynewt drivers and libraries.  Equivalent to `sysinit()` macro in C.
     ///  C API: `void rust_sysinit()`
@@ -193,65 +193,8 @@ pub type __uint8_t = ::cty::c_uchar;
 pub type __uint16_t = ::cty::c_ushort;
 pub type __uint32_t = ::cty::c_ulong;
 pub type __uint64_t = ::cty::c_ulonglong;
-#[doc = " A mbuf pool from which to allocate mbufs. This contains a pointer to the os"]
-#[doc = " mempool to allocate mbufs out of, the total number of elements in the pool,"]
-#[doc = " and the amount of \"user\" data in a non-packet header mbuf. The total pool"]
-#[doc = " size, in bytes, should be:"]
-#[doc = "  os_mbuf_count * (omp_databuf_len + sizeof(struct os_mbuf))"]
-#[repr(C)]
-pub struct os_mbuf_pool {
-    #[doc = " Total length of the databuf in each mbuf.  This is the size of the"]
-    #[doc = " mempool block, minus the mbuf header"]
-    pub omp_databuf_len: u16,
-    #[doc = " The memory pool which to allocate mbufs out of"]
-    pub omp_pool: *mut os_mempool,
-    pub omp_next: os_mbuf_pool__bindgen_ty_1,
-}
-#[repr(C)]
-pub struct os_mbuf_pool__bindgen_ty_1 {
-    pub stqe_next: *mut os_mbuf_pool,
-}
-impl Default for os_mbuf_pool__bindgen_ty_1 {
-    fn default() -> Self {
-        unsafe { ::core::mem::zeroed() }
-    }
-}
-impl Default for os_mbuf_pool {
-    fn default() -> Self {
-        unsafe { ::core::mem::zeroed() }
-    }
-}
-#[doc = " Chained memory buffer."]
-#[repr(C)]
-pub struct os_mbuf {
-    #[doc = " Current pointer to data in the structure"]
-    pub om_data: *mut u8,
-    #[doc = " Flags associated with this buffer, see OS_MBUF_F_* defintions"]
-    pub om_flags: u8,
-    #[doc = " Length of packet header"]
-    pub om_pkthdr_len: u8,
-    #[doc = " Length of data in this buffer"]
-    pub om_len: u16,
-    #[doc = " The mbuf pool this mbuf was allocated out of"]
-    pub om_omp: *mut os_mbuf_pool,
-    pub om_next: os_mbuf__bindgen_ty_1,
-    #[doc = " Pointer to the beginning of the data, after this buffer"]
-    pub om_databuf: __IncompleteArrayField<u8>,
-}
-#[repr(C)]
-pub struct os_mbuf__bindgen_ty_1 {
-    pub sle_next: *mut os_mbuf,
-}
-impl Default for os_mbuf__bindgen_ty_1 {
-    fn default() -> Self {
-        unsafe { ::core::mem::zeroed() }
-    }
-}
-impl Default for os_mbuf {
-    fn default() -> Self {
-        unsafe { ::core::mem::zeroed() }
-    }
-}
+//  Mbufs are declared in `kernel/os.rs`. Reuse them so that `Mbuf` may be passed to the Sensor CoAP API.
+pub use crate::kernel::os::{ os_mbuf, os_mbuf__bindgen_ty_1, os_mbuf_pool, os_mbuf_pool__bindgen_ty_1 };
 #[doc = " A memory block structure. This simply contains a pointer to the free list"]
 #[doc = " chain and is only used when the block is on the free list. When the block"]
 #[doc = " has been removed from the free list the entire memory block is usable by the"]
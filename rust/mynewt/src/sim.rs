@@ -0,0 +1,98 @@
+//! PineTime Simulator for testing apps on the host, e.g. in CI on Linux. When the `simulator` feature is enabled,
+//! the Mynewt functions called by Rust (`os_eventq_run`, `hal_spi_*`, `hal_i2c_*`, `console_buffer`, the Sensor
+//! Framework, ...) are implemented here in Rust instead of being linked from the Mynewt build:
+//! ```
+//! let script = "wait 1000 \n touch 120 120 \n wait 500 \n save screen.png";
+//! mynewt::sim::run(app::main, script) ? ;
+//! ```
+//! Mynewt tasks run as host threads, with 1,000 ticks per second of host time. The simulated PineTime has
+//! - an ST7789 display on SPI port 0 that renders into a 240x240 RGB565 framebuffer, see `display`
+//! - a CST816S touch controller on I2C port 1 that reports the touches injected by the script, see `touch`
+//! - a battery on the SAADC and a backlight on the PWM, see `peripheral`
+//! - internal flash for stored settings, kept in memory, see `flash`
+//!
+//! Networking (CoAP, CBOR, JSON) and memory pools are not simulated. See `rust/simulator` for the command-line runner.
+use std::{
+    boxed::Box,
+    cell::UnsafeCell,
+    format,
+    string::String,
+    sync::Once,
+    thread,
+};
+
+pub mod display;     //  Export `sim/display.rs` as Rust module `mynewt::sim::display`
+pub mod flash;       //  Export `sim/flash.rs` as Rust module `mynewt::sim::flash`
+pub mod peripheral;  //  Export `sim/peripheral.rs` as Rust module `mynewt::sim::peripheral`
+pub mod script;      //  Export `sim/script.rs` as Rust module `mynewt::sim::script`
+pub mod touch;       //  Export `sim/touch.rs` as Rust module `mynewt::sim::touch`
+
+mod console;  //  Simulated Semihosting Console
+mod hal;      //  Simulated GPIO, SPI, I2C and Watchdog
+#[allow(unused_unsafe)]  //  `fill_zero!()` is called inside the simulated OS functions, which are already unsafe
+mod kernel;   //  Simulated tasks, Event Queues, Callouts, Semaphores and Mutexes
+mod mbuf;     //  Simulated Mbufs and Mbuf Queues
+mod png;      //  PNG encoder for the framebuffer
+#[allow(unused_unsafe)]  //  `fill_zero!()` is called inside the simulated Sensor Framework functions, which are already unsafe
+mod sensor;   //  Simulated Sensor Framework
+
+/// Start the simulator, run the app's `main()` in a Mynewt task and execute the script. Returns when the script
+/// ends, while the app keeps running. Returns an error if the script is invalid or fails.
+pub fn run(app_main: extern "C" fn() -> !, script: &str) -> Result<(), String> {
+    let script = script::Script::parse(script) ? ;
+    kernel::start();
+    peripheral::start();
+    thread::Builder::new()
+        .name("main".into())
+        .spawn(move || {
+            kernel::set_current_task("main");
+            app_main()
+        })
+        .map_err(|err| format!("can't start main task: {}", err)) ? ;
+    script.run()
+}
+
+/// Simulator state of type `T`, created upon first use. Needed because `std::sync::Mutex::new()` is not a
+/// `const fn`, so the state can't be stored in a `static` directly.
+pub(crate) struct Global<T> {
+    /// Ensures that the state is created once
+    once:  Once,
+    /// The state, leaked so that it lives forever
+    value: UnsafeCell<*const T>,
+    /// Function that creates the state
+    init:  fn() -> T,
+}
+
+impl<T> Global<T> {
+    /// Create the holder for the state.
+    pub const fn new(init: fn() -> T) -> Self {
+        Global { once: Once::new(), value: UnsafeCell::new(core::ptr::null()), init }
+    }
+
+    /// Return the state, creating it if necessary
+    pub fn get(&self) -> &T {
+        self.once.call_once(|| unsafe {
+            *self.value.get() = Box::into_raw(Box::new((self.init)()));
+        });
+        unsafe { &**self.value.get() }
+    }
+}
+
+///  Allow threads to share the state, which is only written once by `Once`
+unsafe impl<T: Send + Sync> Sync for Global<T> {}
+
+/// Pointer to a Mynewt object that may be kept in the simulator state. The object is protected by the lock of the state.
+pub(crate) struct SendPtr<T>(pub *mut T);
+
+impl<T> Clone for SendPtr<T> {
+    fn clone(&self) -> Self { SendPtr(self.0) }
+}
+
+impl<T> Copy for SendPtr<T> {}
+
+impl<T> PartialEq for SendPtr<T> {
+    fn eq(&self, other: &Self) -> bool { self.0 == other.0 }
+}
+
+///  Allow the pointer to be sent to other threads, since the object is protected by a lock
+unsafe impl<T> Send for SendPtr<T> {}
@@ -0,0 +1,47 @@
+//! Simulated Semihosting Console. The output is written to the host's standard output.
+use std::{
+    io::{ self, Write },
+    print,
+    slice,
+};
+
+//  Simulated Semihosting Console API. See `sys/console.rs` for the documentation.
+
+#[no_mangle]
+pub unsafe extern "C" fn console_buffer(buffer: *const u8, length: u32) {
+    if buffer.is_null() || length == 0 { return; }
+    io::stdout().write_all(slice::from_raw_parts(buffer, length as usize)).ok();
+}
+
+#[no_mangle]
+pub extern "C" fn console_printhex(v: u8) {
+    print!("{:02x}", v);
+}
+
+#[no_mangle]
+pub extern "C" fn console_printint(i: i32) {
+    print!("{}", i);
+}
+
+#[no_mangle]
+pub extern "C" fn console_printfloat(f: f32) {
+    print!("{:.2}", f);
+}
+
+#[no_mangle]
+pub extern "C" fn console_printdouble(d: f64) {
+    print!("{:.6}", d);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn console_dump(buffer: *const u8, len: u32) {
+    if buffer.is_null() { return; }
+    for b in slice::from_raw_parts(buffer, len as usize) {
+        print!("{:02x} ", b);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn console_flush() {
+    io::stdout().flush().ok();
+}
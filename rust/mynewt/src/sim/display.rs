@@ -0,0 +1,224 @@
+//! Simulated ST7789 display controller, connected to SPI port 0. The model decodes the commands and pixels sent
+//! by the display driver, including the window (`CASET`, `RASET`), rotation (`MADCTL`), vertical scrolling
+//! (`VSCRDEF`, `VSCSAD`), inversion and sleep. The displayed pixels may be fetched or saved as a PNG file:
+//! ```
+//! let pixels = mynewt::sim::display::framebuffer();  //  240 x 240 RGB565 colours, row by row
+//! mynewt::sim::display::save_png("screen.png") ? ;
+//! ```
+//! Like the PineTime panel, colours are shown inverted unless display inversion is switched on.
+use std::{
+    fs,
+    string::{ String, ToString },
+    sync::Mutex,
+    vec,
+    vec::Vec,
+};
+use super::{ png, Global };
+
+/// Return the displayed pixels as 16-bit RGB565 colours, row by row from the top left. The pixels are black
+/// while the display is off or asleep.
+pub fn framebuffer() -> Vec<u16> {
+    let display = lock_display();
+    let mut pixels = vec![0; WIDTH * HEIGHT];
+    if !display.display_on || display.sleeping { return pixels; }
+    for y in 0..HEIGHT {
+        let row = display.memory_row(y);
+        for x in 0..WIDTH {
+            let color = display.ram[row * RAM_COLUMNS + x];
+            //  The panel inverts the colours unless display inversion is on
+            pixels[y * WIDTH + x] = if display.inversion { color } else { !color };
+        }
+    }
+    pixels
+}
+
+/// Save the displayed pixels to a PNG file
+pub fn save_png(path: &str) -> Result<(), String> {
+    let rgb: Vec<u8> = framebuffer().iter()
+        .flat_map(|&color| {
+            //  Expand RGB565 to RGB888
+            let r = ((color >> 11) & 0x1f) as u32;
+            let g = ((color >> 5)  & 0x3f) as u32;
+            let b = (color         & 0x1f) as u32;
+            vec![(r * 255 / 31) as u8, (g * 255 / 63) as u8, (b * 255 / 31) as u8]
+        })
+        .collect();
+    let data = png::encode(WIDTH as u32, HEIGHT as u32, &rgb);
+    fs::write(path, data).map_err(|err| err.to_string())
+}
+
+/// Handle the bytes sent on the SPI bus while the display is selected. `is_data` is the level of the
+/// data/command pin: Command bytes if false, parameter or pixel bytes if true.
+pub(crate) fn receive(bytes: &[u8], is_data: bool) {
+    let mut display = lock_display();
+    for &byte in bytes {
+        if is_data { display.data(byte); }
+        else       { display.command(byte); }
+    }
+}
+
+/// Reset the display controller. Called when the reset pin goes low.
+pub(crate) fn reset() {
+    *lock_display() = Display::new();
+}
+
+/// Lock the display. A thread that panicked while holding the lock doesn't stop the simulator.
+fn lock_display() -> std::sync::MutexGuard<'static, Display> {
+    DISPLAY.get().lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// State of the ST7789 display controller
+struct Display {
+    /// Controller memory: 240 columns by 320 rows
+    ram:        Vec<u16>,
+    /// Last command received
+    command:    u8,
+    /// Parameters received for the command
+    params:     Vec<u8>,
+    /// First byte of the pixel being written, if any
+    pixel_high: Option<u8>,
+    /// Memory Data Access Control bits
+    madctl:     u8,
+    /// Window columns, inclusive
+    columns:    (u16, u16),
+    /// Window rows, inclusive
+    rows:       (u16, u16),
+    /// Next (column, row) to be written in the window
+    cursor:     (u16, u16),
+    /// Vertical scrolling: (top fixed rows, scrolling rows, bottom fixed rows)
+    scroll_def: (u16, u16, u16),
+    /// Memory row shown at the top of the scrolling area
+    scroll_row: u16,
+    /// True if display inversion is on
+    inversion:  bool,
+    /// True if the display is on
+    display_on: bool,
+    /// True if the controller is asleep
+    sleeping:   bool,
+}
+
+impl Display {
+    /// Return the controller state after reset
+    fn new() -> Self {
+        Display {
+            ram:        vec![0; RAM_COLUMNS * RAM_ROWS],
+            command:    NOP,
+            params:     Vec::new(),
+            pixel_high: None,
+            madctl:     0,
+            columns:    (0, RAM_COLUMNS as u16 - 1),
+            rows:       (0, RAM_ROWS as u16 - 1),
+            cursor:     (0, 0),
+            scroll_def: (0, RAM_ROWS as u16, 0),
+            scroll_row: 0,
+            inversion:  false,
+            display_on: false,
+            sleeping:   true,
+        }
+    }
+
+    /// Handle a command byte
+    fn command(&mut self, cmd: u8) {
+        self.command    = cmd;
+        self.params.clear();
+        self.pixel_high = None;
+        match cmd {
+            SWRESET => { *self = Display { ram: core::mem::replace(&mut self.ram, Vec::new()), ..Display::new() }; }
+            SLPIN   => self.sleeping   = true,
+            SLPOUT  => self.sleeping   = false,
+            INVOFF  => self.inversion  = false,
+            INVON   => self.inversion  = true,
+            DISPOFF => self.display_on = false,
+            DISPON  => self.display_on = true,
+            RAMWR   => self.cursor     = (self.columns.0, self.rows.0),
+            _ => {}
+        }
+    }
+
+    /// Handle a parameter or pixel byte for the last command
+    fn data(&mut self, byte: u8) {
+        if self.command == RAMWR {
+            //  Pixels are sent as 2 bytes, high byte first
+            match self.pixel_high.take() {
+                None       => self.pixel_high = Some(byte),
+                Some(high) => self.write_pixel((high as u16) << 8 | byte as u16),
+            }
+            return;
+        }
+        self.params.push(byte);
+        let p = &self.params;
+        let word = |i: usize| (p[i] as u16) << 8 | p[i + 1] as u16;
+        match (self.command, p.len()) {
+            (CASET,   4) => self.columns    = (word(0), word(2)),
+            (RASET,   4) => self.rows       = (word(0), word(2)),
+            (VSCRDEF, 6) => self.scroll_def = (word(0), word(2), word(4)),
+            (VSCSAD,  2) => self.scroll_row = word(0),
+            (MADCTL,  1) => self.madctl     = p[0],
+            _ => {}
+        }
+    }
+
+    /// Write the pixel at the cursor and move the cursor left to right, then top to bottom within the window
+    fn write_pixel(&mut self, color: u16) {
+        let (column, row) = self.cursor;
+        if let Some(index) = self.ram_index(column, row) { self.ram[index] = color; }
+        if column < self.columns.1 { self.cursor.0 = column + 1; }
+        else {
+            self.cursor.0 = self.columns.0;
+            self.cursor.1 = if row < self.rows.1 { row + 1 } else { self.rows.0 };
+        }
+    }
+
+    /// Return the index in the controller memory for the column and row, which are swapped and mirrored
+    /// according to `MADCTL`. Returns `None` if outside the memory.
+    fn ram_index(&self, column: u16, row: u16) -> Option<usize> {
+        let (mut x, mut y) = (column as usize, row as usize);
+        if self.madctl & MADCTL_MV != 0 { core::mem::swap(&mut x, &mut y); }
+        if x >= RAM_COLUMNS || y >= RAM_ROWS { return None; }
+        if self.madctl & MADCTL_MX != 0 { x = RAM_COLUMNS - 1 - x; }
+        if self.madctl & MADCTL_MY != 0 { y = RAM_ROWS - 1 - y; }
+        Some(y * RAM_COLUMNS + x)
+    }
+
+    /// Return the memory row shown on the display row, after vertical scrolling
+    fn memory_row(&self, y: usize) -> usize {
+        let (top, scroll, _) = self.scroll_def;
+        let (top, scroll) = (top as usize, scroll as usize);
+        if y < top || y >= top + scroll || scroll == 0 { return y; }
+        //  Rows in the scrolling area wrap around within the area
+        let start = (self.scroll_row as usize).max(top) - top;
+        let row = top + (start + y - top) % scroll;
+        row.min(RAM_ROWS - 1)
+    }
+}
+
+/// Simulated display controller
+static DISPLAY: Global<Mutex<Display>> = Global::new(|| Mutex::new(Display::new()));
+
+/// Size of the display
+const WIDTH:  usize = 240;
+const HEIGHT: usize = 240;
+/// Size of the controller memory
+const RAM_COLUMNS: usize = 240;
+const RAM_ROWS:    usize = 320;
+
+//  Memory Data Access Control (MADCTL) bits
+const MADCTL_MY: u8 = 0x80;  //  Mirror rows
+const MADCTL_MX: u8 = 0x40;  //  Mirror columns
+const MADCTL_MV: u8 = 0x20;  //  Swap rows and columns
+
+//  ST7789 commands, from the ST7789 datasheet
+const NOP:     u8 = 0x00;  //  No operation
+const SWRESET: u8 = 0x01;  //  Software reset
+const SLPIN:   u8 = 0x10;  //  Sleep in
+const SLPOUT:  u8 = 0x11;  //  Sleep out
+const INVOFF:  u8 = 0x20;  //  Display inversion off
+const INVON:   u8 = 0x21;  //  Display inversion on
+const DISPOFF: u8 = 0x28;  //  Display off
+const DISPON:  u8 = 0x29;  //  Display on
+const CASET:   u8 = 0x2a;  //  Column address set
+const RASET:   u8 = 0x2b;  //  Row address set
+const RAMWR:   u8 = 0x2c;  //  Memory write
+const VSCRDEF: u8 = 0x33;  //  Vertical scrolling definition
+const MADCTL:  u8 = 0x36;  //  Memory data access control
+const VSCSAD:  u8 = 0x37;  //  Vertical scroll start address
@@ -0,0 +1,85 @@
+//! Simulated Mynewt Flash Map for `hal/flash.rs`, with the user flash areas of `hw/bsp/nrf52/bsp.yml`. The flash
+//! starts erased (all bits 1) and is kept in memory, so the stored settings are lost when the simulator exits.
+//! Like real flash, writing can only change bits from 1 to 0.
+use std::{
+    slice,
+    sync::Mutex,
+    vec,
+    vec::Vec,
+};
+use ::cty::c_void;
+use crate::{
+    hal::flash::{ flash_area, FLASH_AREA_USER_ID_MIN },
+    kernel::os,
+};
+use super::Global;
+
+#[no_mangle]
+pub unsafe extern "C" fn flash_area_open(id: u8, fa: *mut *const flash_area) -> i32 {
+    match FLASH_AREAS.iter().find(|area| area.fa_id == id) {
+        Some(area) => { *fa = area; 0 }
+        None => os::SYS_ENOENT,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn flash_area_close(_fa: *const flash_area) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn flash_area_read(fa: *const flash_area, off: u32, dst: *mut c_void, len: u32) -> i32 {
+    let start = match area_range(&*fa, off, len) { Some(start) => start, None => return os::SYS_EINVAL };
+    let dst = slice::from_raw_parts_mut(dst as *mut u8, len as usize);
+    dst.copy_from_slice(&lock_flash()[start..start + len as usize]);
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn flash_area_write(fa: *const flash_area, off: u32, src: *const c_void, len: u32) -> i32 {
+    let start = match area_range(&*fa, off, len) { Some(start) => start, None => return os::SYS_EINVAL };
+    let src = slice::from_raw_parts(src as *const u8, len as usize);
+    for (byte, value) in lock_flash()[start..].iter_mut().zip(src) {
+        *byte &= value;  //  Bits that are already 0 stay 0
+    }
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn flash_area_erase(fa: *const flash_area, off: u32, len: u32) -> i32 {
+    let start = match area_range(&*fa, off, len) { Some(start) => start, None => return os::SYS_EINVAL };
+    //  Erase the whole pages that contain the range
+    let page_start = start - start % PAGE_SIZE;
+    let page_end = (start + len as usize + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+    for byte in &mut lock_flash()[page_start..page_end] { *byte = ERASED; }
+    0
+}
+
+/// Return the flash address of the bytes at the offset of the flash area, or `None` if they don't fit in the area
+fn area_range(area: &flash_area, off: u32, len: u32) -> Option<usize> {
+    if off as u64 + len as u64 > area.fa_size as u64 { return None; }
+    Some((area.fa_off + off) as usize)
+}
+
+/// Lock the flash. A thread that panicked while holding the lock doesn't stop the simulator.
+fn lock_flash() -> std::sync::MutexGuard<'static, Vec<u8>> {
+    FLASH.get().lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Contents of the flash
+static FLASH: Global<Mutex<Vec<u8>>> = Global::new(|| Mutex::new(vec![ERASED; FLASH_SIZE]));
+
+/// User flash areas in `bsp.flash_map` of `hw/bsp/nrf52/bsp.yml`
+static FLASH_AREAS: [flash_area; 3] = [
+    //  FLASH_AREA_REBOOT_LOG
+    flash_area { fa_id: FLASH_AREA_USER_ID_MIN,     fa_device_id: 0, pad16: 0, fa_off: 0x0000_4000, fa_size: 16 * 1024 },
+    //  FLASH_AREA_NFFS
+    flash_area { fa_id: FLASH_AREA_USER_ID_MIN + 1, fa_device_id: 0, pad16: 0, fa_off: 0x0007_d000, fa_size: 8 * 1024 },
+    //  FLASH_AREA_CALIBRATION
+    flash_area { fa_id: FLASH_AREA_USER_ID_MIN + 2, fa_device_id: 0, pad16: 0, fa_off: 0x0007_f000, fa_size: 4 * 1024 },
+];
+
+/// Size of the nRF52832 flash in bytes
+const FLASH_SIZE: usize = 512 * 1024;
+/// Size of a flash page in bytes
+const PAGE_SIZE: usize = 4096;
+/// Value of an erased flash byte
+const ERASED: u8 = 0xff;
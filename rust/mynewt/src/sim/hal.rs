@@ -0,0 +1,336 @@
+//! Simulated Mynewt HAL: GPIO, SPI, I2C, Watchdog and system functions. SPI port 0 is connected to the simulated
+//! ST7789 display, I2C port 1 to the simulated CST816S touch controller. GPIO pins keep their levels, and
+//! `drive_pin()` lets the simulated devices drive input pins and trigger GPIO interrupts.
+use std::{
+    io::Write,
+    process,
+    slice,
+    sync::Mutex,
+};
+use ::cty::{ c_int, c_void };
+use crate::hw::hal::{
+    self,
+    hal_gpio_irq_handler_t, hal_gpio_irq_trig_t, hal_gpio_pull_t, hal_i2c_master_data, hal_i2c_settings,
+    hal_spi_settings, hal_spi_txrx_cb,
+};
+use super::{ display, touch, Global, SendPtr };
+
+/// Drive the input pin to the level, like an external device. Triggers the pin's GPIO interrupt if enabled.
+/// The interrupt handler is called in the current thread.
+pub(crate) fn drive_pin(pin: i32, level: bool) {
+    let irq = {
+        let mut pins = lock_pins();
+        let state = match pins.get_mut(pin as usize) { Some(state) => state, None => return };
+        let before = state.level();
+        state.external = Some(level);
+        let after = state.level();
+        match state.irq {
+            Some(irq) if irq.enabled && triggers(irq.trig, before, after) => Some(irq),
+            _ => None,
+        }
+    };
+    //  Call the handler after releasing the lock, since the handler may read the pin
+    if let Some(irq) = irq {
+        if let Some(handler) = irq.handler { unsafe { handler(irq.arg.0) }; }
+    }
+}
+
+/// Return true if the change of level from `before` to `after` triggers the interrupt
+fn triggers(trig: hal_gpio_irq_trig_t, before: bool, after: bool) -> bool {
+    match trig {
+        hal::hal_gpio_irq_trigger_HAL_GPIO_TRIG_RISING  => !before && after,
+        hal::hal_gpio_irq_trigger_HAL_GPIO_TRIG_FALLING => before && !after,
+        hal::hal_gpio_irq_trigger_HAL_GPIO_TRIG_BOTH    => before != after,
+        hal::hal_gpio_irq_trigger_HAL_GPIO_TRIG_LOW     => !after,
+        hal::hal_gpio_irq_trigger_HAL_GPIO_TRIG_HIGH    => after,
+        _ => false,
+    }
+}
+
+/// Lock the GPIO pins. A thread that panicked while holding the lock doesn't stop the simulator.
+fn lock_pins() -> std::sync::MutexGuard<'static, [Pin; PIN_COUNT]> {
+    PINS.get().lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Return the level of the output pin, or `None` if the pin is not an output
+fn output_level(pin: i32) -> Option<bool> {
+    let pins = lock_pins();
+    let state = pins.get(pin as usize) ? ;
+    if state.output { Some(state.level) } else { None }
+}
+
+/// Return true if the output pin is low, i.e. the chip select is active
+fn is_selected(pin: i32) -> bool {
+    output_level(pin) == Some(false)
+}
+
+/// State of a GPIO pin
+#[derive(Clone, Copy)]
+struct Pin {
+    /// Level written by the app, if the pin is an output
+    level:    bool,
+    /// True if the pin is an output
+    output:   bool,
+    /// Pull-up or pull-down of the input pin
+    pull:     hal_gpio_pull_t,
+    /// Level driven by an external device, if any
+    external: Option<bool>,
+    /// GPIO interrupt, if configured
+    irq:      Option<Irq>,
+}
+
+impl Pin {
+    /// Return the level that will be read from the pin
+    fn level(&self) -> bool {
+        if self.output { return self.level; }
+        match self.external {
+            Some(level) => level,
+            None        => self.pull == hal::hal_gpio_pull_HAL_GPIO_PULL_UP,
+        }
+    }
+}
+
+/// GPIO interrupt configured by `hal_gpio_irq_init()`
+#[derive(Clone, Copy)]
+struct Irq {
+    /// Interrupt handler
+    handler: hal_gpio_irq_handler_t,
+    /// Argument for the handler
+    arg:     SendPtr<c_void>,
+    /// Trigger (rising, falling, ...)
+    trig:    hal_gpio_irq_trig_t,
+    /// True if enabled by `hal_gpio_irq_enable()`
+    enabled: bool,
+}
+
+/// Number of GPIO pins on the nRF52832
+const PIN_COUNT: usize = 48;
+
+/// SPI port connected to the display
+const DISPLAY_SPI_PORT: c_int = 0;
+/// Chip select pin of the display (P0.25)
+const DISPLAY_CS_PIN: i32 = 25;
+/// Data/command pin of the display (P0.18)
+const DISPLAY_DC_PIN: i32 = 18;
+/// Reset pin of the display (P0.26)
+const DISPLAY_RESET_PIN: i32 = 26;
+
+/// I2C port connected to the touch controller
+const TOUCH_I2C_PORT: u8 = 1;
+/// I2C address of the touch controller
+const TOUCH_I2C_ADDRESS: u8 = 0x15;
+
+/// GPIO pins, all inputs without pull at startup
+static PINS: Global<Mutex<[Pin; PIN_COUNT]>> = Global::new(|| Mutex::new([
+    Pin { level: false, output: false, pull: hal::hal_gpio_pull_HAL_GPIO_PULL_NONE, external: None, irq: None };
+    PIN_COUNT
+]));
+
+/// SPI completion callbacks set by `hal_spi_set_txrx_cb()`, by SPI port
+static SPI_CALLBACKS: Global<Mutex<[(hal_spi_txrx_cb, SendPtr<c_void>); 4]>> =
+    Global::new(|| Mutex::new([(None, SendPtr(core::ptr::null_mut())); 4]));
+
+/// Send the SPI bytes to the device selected on the SPI port
+unsafe fn spi_transfer(spi_num: c_int, txbuf: *mut c_void, cnt: c_int) {
+    if spi_num != DISPLAY_SPI_PORT || txbuf.is_null() || cnt <= 0 { return; }
+    if !is_selected(DISPLAY_CS_PIN) { return; }
+    let data = slice::from_raw_parts(txbuf as *const u8, cnt as usize);
+    let is_data = output_level(DISPLAY_DC_PIN).unwrap_or(true);
+    display::receive(data, is_data);
+}
+
+//  Simulated Mynewt HAL API. See `hw/hal.rs` for the documentation.
+
+#[no_mangle]
+pub extern "C" fn hal_gpio_init_in(pin: c_int, pull: hal_gpio_pull_t) -> c_int {
+    let mut pins = lock_pins();
+    match pins.get_mut(pin as usize) {
+        Some(state) => { state.output = false; state.pull = pull; 0 }
+        None => -1,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn hal_gpio_init_out(pin: c_int, val: c_int) -> c_int {
+    {
+        let mut pins = lock_pins();
+        match pins.get_mut(pin as usize) {
+            Some(state) => { state.output = true; }
+            None => return -1,
+        }
+    }
+    hal_gpio_write(pin, val);
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn hal_gpio_deinit(pin: c_int) -> c_int {
+    hal_gpio_init_in(pin, hal::hal_gpio_pull_HAL_GPIO_PULL_NONE)
+}
+
+#[no_mangle]
+pub extern "C" fn hal_gpio_write(pin: c_int, val: c_int) {
+    let falling = {
+        let mut pins = lock_pins();
+        let state = match pins.get_mut(pin as usize) { Some(state) => state, None => return };
+        let falling = state.level && val == 0;
+        state.level = val != 0;
+        falling
+    };
+    //  Pulling the reset pin low resets the display
+    if pin == DISPLAY_RESET_PIN && falling { display::reset(); }
+}
+
+#[no_mangle]
+pub extern "C" fn hal_gpio_read(pin: c_int) -> c_int {
+    let pins = lock_pins();
+    match pins.get(pin as usize) {
+        Some(state) => state.level() as c_int,
+        None => 0,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn hal_gpio_toggle(pin: c_int) -> c_int {
+    let level = hal_gpio_read(pin) ^ 1;
+    hal_gpio_write(pin, level);
+    level
+}
+
+#[no_mangle]
+pub extern "C" fn hal_gpio_irq_init(pin: c_int, handler: hal_gpio_irq_handler_t, arg: *mut c_void,
+    trig: hal_gpio_irq_trig_t, pull: hal_gpio_pull_t) -> c_int {
+    let mut pins = lock_pins();
+    match pins.get_mut(pin as usize) {
+        Some(state) => {
+            state.output = false;
+            state.pull   = pull;
+            state.irq    = Some(Irq { handler, arg: SendPtr(arg), trig, enabled: false });
+            0
+        }
+        None => -1,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn hal_gpio_irq_release(pin: c_int) {
+    if let Some(state) = lock_pins().get_mut(pin as usize) { state.irq = None; }
+}
+
+#[no_mangle]
+pub extern "C" fn hal_gpio_irq_enable(pin: c_int) {
+    if let Some(state) = lock_pins().get_mut(pin as usize) {
+        if let Some(irq) = state.irq.as_mut() { irq.enabled = true; }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn hal_gpio_irq_disable(pin: c_int) {
+    if let Some(state) = lock_pins().get_mut(pin as usize) {
+        if let Some(irq) = state.irq.as_mut() { irq.enabled = false; }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn hal_spi_config(_spi_num: c_int, _psettings: *mut hal_spi_settings) -> c_int { 0 }
+
+#[no_mangle]
+pub extern "C" fn hal_spi_enable(_spi_num: c_int) -> c_int { 0 }
+
+#[no_mangle]
+pub extern "C" fn hal_spi_disable(_spi_num: c_int) -> c_int { 0 }
+
+#[no_mangle]
+pub extern "C" fn hal_spi_abort(_spi_num: c_int) -> c_int { 0 }
+
+#[no_mangle]
+pub extern "C" fn hal_spi_set_txrx_cb(spi_num: c_int, txrx_cb: hal_spi_txrx_cb, arg: *mut c_void) -> c_int {
+    let mut callbacks = SPI_CALLBACKS.get().lock().unwrap_or_else(|err| err.into_inner());
+    match callbacks.get_mut(spi_num as usize) {
+        Some(callback) => { *callback = (txrx_cb, SendPtr(arg)); 0 }
+        None => -1,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hal_spi_tx_val(spi_num: c_int, val: u16) -> u16 {
+    let mut byte = val as u8;
+    spi_transfer(spi_num, &mut byte as *mut u8 as *mut c_void, 1);
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hal_spi_txrx(spi_num: c_int, txbuf: *mut c_void, rxbuf: *mut c_void, cnt: c_int) -> c_int {
+    if txbuf.is_null() { return -1; }
+    spi_transfer(spi_num, txbuf, cnt);
+    //  Devices don't respond, so the received bytes are 0
+    if !rxbuf.is_null() && cnt > 0 { core::ptr::write_bytes(rxbuf as *mut u8, 0, cnt as usize); }
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hal_spi_txrx_noblock(spi_num: c_int, txbuf: *mut c_void, rxbuf: *mut c_void, cnt: c_int) -> c_int {
+    let rc = hal_spi_txrx(spi_num, txbuf, rxbuf, cnt);
+    if rc != 0 { return rc; }
+    //  The transfer completes immediately, so call the completion callback now like an interrupt
+    let callback = SPI_CALLBACKS.get().lock().unwrap_or_else(|err| err.into_inner())
+        .get(spi_num as usize).copied();
+    if let Some((Some(cb), arg)) = callback { cb(arg.0, cnt); }
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn hal_i2c_config(_i2c_num: u8, _cfg: *const hal_i2c_settings) -> c_int { 0 }
+
+#[no_mangle]
+pub extern "C" fn hal_i2c_enable(_i2c_num: u8) -> c_int { 0 }
+
+#[no_mangle]
+pub extern "C" fn hal_i2c_disable(_i2c_num: u8) -> c_int { 0 }
+
+#[no_mangle]
+pub unsafe extern "C" fn hal_i2c_master_write(i2c_num: u8, pdata: *mut hal_i2c_master_data, _timeout: u32, _last_op: u8) -> c_int {
+    if pdata.is_null() { return hal::HAL_I2C_ERR_INVAL as c_int; }
+    if i2c_num != TOUCH_I2C_PORT || (*pdata).address != TOUCH_I2C_ADDRESS { return hal::HAL_I2C_ERR_ADDR_NACK as c_int; }
+    let data = if (*pdata).buffer.is_null() { &[][..] }
+        else { slice::from_raw_parts((*pdata).buffer, (*pdata).len as usize) };
+    touch::write(data);
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn hal_i2c_master_read(i2c_num: u8, pdata: *mut hal_i2c_master_data, _timeout: u32, _last_op: u8) -> c_int {
+    if pdata.is_null() || (*pdata).buffer.is_null() { return hal::HAL_I2C_ERR_INVAL as c_int; }
+    if i2c_num != TOUCH_I2C_PORT || (*pdata).address != TOUCH_I2C_ADDRESS { return hal::HAL_I2C_ERR_ADDR_NACK as c_int; }
+    touch::read(slice::from_raw_parts_mut((*pdata).buffer, (*pdata).len as usize));
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn hal_i2c_master_probe(i2c_num: u8, address: u8, _timeout: u32) -> c_int {
+    if i2c_num == TOUCH_I2C_PORT && address == TOUCH_I2C_ADDRESS { 0 }
+    else { hal::HAL_I2C_ERR_ADDR_NACK as c_int }
+}
+
+#[no_mangle]
+pub extern "C" fn hal_watchdog_init(_expire_msecs: u32) -> i32 { 0 }
+
+#[no_mangle]
+pub extern "C" fn hal_watchdog_enable() {}
+
+#[no_mangle]
+pub extern "C" fn hal_watchdog_tickle() {}
+
+#[no_mangle]
+pub extern "C" fn hal_debugger_connected() -> i32 { 0 }
+
+#[no_mangle]
+pub extern "C" fn hal_system_reset() -> ! {
+    //  Rebooting is not simulated, so stop the simulator
+    std::println!("hal_system_reset: stopping simulator");
+    std::io::stdout().flush().ok();
+    process::exit(2)
+}
+
+#[no_mangle]
+pub extern "C" fn rust_sysinit() {}
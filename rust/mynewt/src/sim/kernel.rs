@@ -0,0 +1,570 @@
+//! Simulated Mynewt kernel: time, tasks, Event Queues, Callouts, Semaphores, Mutexes and critical sections.
+//! Each Mynewt task runs as a host thread. The kernel objects are kept in the Mynewt structs as usual, but they are
+//! protected by one kernel lock. Blocked threads wait on one condition variable, which is notified whenever a
+//! kernel object changes. Time is the host time since `start()`, at 1,000 ticks per second.
+use std::{
+    boxed::Box,
+    cell::Cell,
+    ffi::{ CStr, CString },
+    string::String,
+    sync::{ Condvar, Mutex, MutexGuard },
+    thread::{ self, ThreadId },
+    time::{ Duration, Instant },
+    vec::Vec,
+};
+use ::cty::{ c_char, c_int, c_void };
+use crate::{
+    kernel::os::{
+        self,
+        os_callout, os_dev, os_dev_init_func_t, os_error_t, os_event, os_event_fn, os_eventq, os_mutex,
+        os_sanity_check, os_sem, os_sr_t, os_stack_t, os_task, os_task_func_t, os_task_info, os_time_t,
+    },
+    fill_zero,
+};
+use super::{ Global, SendPtr };
+
+/// Start the Callout timer thread. Called by `sim::run()`.
+pub(crate) fn start() {
+    KERNEL.get();  //  Start the clock
+    thread::Builder::new()
+        .name("callout".into())
+        .spawn(run_callouts)
+        .expect("callout thread");
+}
+
+/// Register the current thread as a Mynewt task with the name
+pub(crate) fn set_current_task(name: &str) {
+    let task = new_task(name);
+    CURRENT_TASK.with(|current| current.set(task));
+}
+
+/// Run `f` while holding the kernel lock, then wake the blocked threads. Kernel objects may be changed by `f`.
+pub(crate) fn locked<R>(f: impl FnOnce() -> R) -> R {
+    let kernel = KERNEL.get();
+    let _state = lock(kernel);
+    let result = f();
+    kernel.changed.notify_all();
+    result
+}
+
+/// Add the event to the end of the Event Queue, unless already queued. The kernel lock must be held.
+pub(crate) unsafe fn queue_event(evq: *mut os_eventq, ev: *mut os_event) {
+    if evq.is_null() || ev.is_null() || (*ev).ev_queued != 0 { return; }
+    (*ev).ev_queued = 1;
+    (*ev).ev_next.stqe_next = core::ptr::null_mut();
+    //  Walk to the end of the list. `stqh_last` is not used by the simulator.
+    let mut link: *mut *mut os_event = &mut (*evq).evq_list.stqh_first;
+    while !(*link).is_null() { link = &mut (**link).ev_next.stqe_next; }
+    *link = ev;
+}
+
+/// Remove the event from the Event Queue, if queued. The kernel lock must be held.
+unsafe fn remove_event(evq: *mut os_eventq, ev: *mut os_event) {
+    if evq.is_null() || ev.is_null() || (*ev).ev_queued == 0 { return; }
+    let mut link: *mut *mut os_event = &mut (*evq).evq_list.stqh_first;
+    while !(*link).is_null() {
+        if *link == ev {
+            *link = (*ev).ev_next.stqe_next;
+            break;
+        }
+        link = &mut (**link).ev_next.stqe_next;
+    }
+    (*ev).ev_queued = 0;
+    (*ev).ev_next.stqe_next = core::ptr::null_mut();
+}
+
+/// Remove and return the first event in the Event Queue, or null if empty. The kernel lock must be held.
+unsafe fn take_event(evq: *mut os_eventq) -> *mut os_event {
+    let ev = (*evq).evq_list.stqh_first;
+    if !ev.is_null() { remove_event(evq, ev); }
+    ev
+}
+
+/// Return the current time in ticks
+fn now() -> os_time_t {
+    KERNEL.get().start.elapsed().as_millis() as os_time_t
+}
+
+/// Return the deadline for a timeout in ticks, or `None` to wait forever
+fn deadline(timeout: os_time_t) -> Option<Instant> {
+    if timeout == os::OS_TIMEOUT_NEVER { None }
+    else { Some(Instant::now() + Duration::from_millis(timeout as u64)) }
+}
+
+/// Wait until a kernel object changes or the deadline passes. Returns the guard and true if the deadline has passed.
+fn wait<'a>(kernel: &'a Kernel, state: MutexGuard<'a, State>, deadline: Option<Instant>) -> (MutexGuard<'a, State>, bool) {
+    match deadline {
+        None => (kernel.changed.wait(state).unwrap_or_else(|err| err.into_inner()), false),
+        Some(deadline) => {
+            let now = Instant::now();
+            if now >= deadline { return (state, true); }
+            let (state, _) = kernel.changed.wait_timeout(state, deadline - now)
+                .unwrap_or_else(|err| err.into_inner());
+            (state, Instant::now() >= deadline)
+        }
+    }
+}
+
+/// Acquire the kernel lock. A thread that panicked while holding the lock doesn't stop the other threads.
+fn lock(kernel: &Kernel) -> MutexGuard<State> {
+    kernel.state.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Return the Mynewt task for the current thread. Threads not created by `os_task_init()` are registered
+/// as tasks upon first use, named after the thread.
+fn current_task() -> *mut os_task {
+    CURRENT_TASK.with(|current| {
+        if current.get().is_null() {
+            let name = String::from(thread::current().name().unwrap_or("thread"));
+            current.set(new_task(&name));
+        }
+        current.get()
+    })
+}
+
+/// Allocate and register a Mynewt task for a thread that was not created by `os_task_init()`
+fn new_task(name: &str) -> *mut os_task {
+    let task = Box::into_raw(Box::new(fill_zero!(os_task)));
+    let name = CString::new(name).unwrap_or_default();
+    unsafe {
+        (*task).t_name  = name.into_raw();  //  Leaked since the task lives forever
+        (*task).t_prio  = IDLE_PRIO;
+        (*task).t_state = os::os_task_state_OS_TASK_READY as u8;
+    }
+    register_task(task);
+    task
+}
+
+/// Add the task to the task list and assign the task ID
+fn register_task(task: *mut os_task) {
+    let kernel = KERNEL.get();
+    let mut state = lock(kernel);
+    unsafe { (*task).t_taskid = state.tasks.len() as u8 };
+    state.tasks.push(SendPtr(task));
+}
+
+/// Body of the Callout timer thread. Post the events of the expired Callouts, then sleep until the next Callout expires.
+fn run_callouts() {
+    let kernel = KERNEL.get();
+    let mut state = lock(kernel);
+    loop {
+        //  Deactivate the expired Callouts
+        let now = now();
+        let mut expired = Vec::new();
+        state.callouts.retain(|callout| {
+            let c = callout.0;
+            if (unsafe { (*c).c_ticks }.wrapping_sub(now) as i32) > 0 { return true; }
+            unsafe { (*c).c_next.tqe_prev = core::ptr::null_mut() };
+            expired.push(*callout);
+            false
+        });
+        //  Post the events. Without an Event Queue, call the handler directly, like Mynewt.
+        let mut handlers = Vec::new();
+        for callout in expired.iter() {
+            let c = callout.0;
+            unsafe {
+                if (*c).c_evq.is_null() { handlers.push(SendPtr(&mut (*c).c_ev as *mut os_event)); }
+                else { queue_event((*c).c_evq, &mut (*c).c_ev); }
+            }
+        }
+        if !expired.is_empty() { kernel.changed.notify_all(); }
+        if !handlers.is_empty() {
+            drop(state);
+            for ev in handlers {
+                unsafe { if let Some(cb) = (*ev.0).ev_cb { cb(ev.0); } }
+            }
+            state = lock(kernel);
+            continue;
+        }
+        //  Sleep until the next Callout expires or the Callouts change
+        let next = state.callouts.iter()
+            .map(|callout| (unsafe { (*callout.0).c_ticks }.wrapping_sub(now) as i32).max(0) as u64)
+            .min();
+        state = match next {
+            Some(ticks) => kernel.changed.wait_timeout(state, Duration::from_millis(ticks))
+                .unwrap_or_else(|err| err.into_inner()).0,
+            None => kernel.changed.wait(state)
+                .unwrap_or_else(|err| err.into_inner()),
+        };
+    }
+}
+
+/// Simulated kernel state
+struct Kernel {
+    /// Kernel objects not stored in Mynewt structs, protected by the kernel lock
+    state:   Mutex<State>,
+    /// Notified whenever a kernel object changes
+    changed: Condvar,
+    /// Time when the simulator started, i.e. tick 0
+    start:   Instant,
+}
+
+/// Kernel objects not stored in Mynewt structs
+struct State {
+    /// Active Callouts
+    callouts: Vec<SendPtr<os_callout>>,
+    /// All tasks, in order of creation
+    tasks:    Vec<SendPtr<os_task>>,
+}
+
+/// The simulated kernel
+static KERNEL: Global<Kernel> = Global::new(|| Kernel {
+    state:   Mutex::new(State { callouts: Vec::new(), tasks: Vec::new() }),
+    changed: Condvar::new(),
+    start:   Instant::now(),
+});
+
+/// Owner of the critical section and the nesting level
+static CRITICAL: Global<(Mutex<(Option<ThreadId>, os_sr_t)>, Condvar)> = Global::new(||
+    (Mutex::new((None, 0)), Condvar::new())
+);
+
+/// The Default Event Queue
+static mut DEFAULT_EVENTQ: os_eventq = fill_zero!(os_eventq);
+
+/// Priority of threads that were not created by `os_task_init()`
+const IDLE_PRIO: u8 = 255;
+
+std::thread_local! {
+    /// Mynewt task for the current thread
+    static CURRENT_TASK: Cell<*mut os_task> = Cell::new(core::ptr::null_mut());
+}
+
+/// Top of the stack for the Crash Record in `sys::crash`. Placed below the thread stacks, so no stack is snapshotted.
+#[no_mangle]
+pub static __StackTop: u32 = 0;
+
+//  Simulated Mynewt Kernel API. See `kernel/os.rs` for the documentation.
+
+#[no_mangle]
+pub extern "C" fn os_time_get() -> os_time_t {
+    now()
+}
+
+#[no_mangle]
+pub extern "C" fn os_time_delay(osticks: os_time_t) {
+    thread::sleep(Duration::from_millis(osticks as u64));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_time_ms_to_ticks(ms: u32, out_ticks: *mut os_time_t) -> c_int {
+    *out_ticks = ms;
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_time_ticks_to_ms(ticks: os_time_t, out_ms: *mut u32) -> c_int {
+    *out_ms = ticks;
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn os_started() -> c_int {
+    1
+}
+
+#[no_mangle]
+pub extern "C" fn os_arch_save_sr() -> os_sr_t {
+    let (owner, released) = CRITICAL.get();
+    let me = thread::current().id();
+    let mut owner = owner.lock().unwrap_or_else(|err| err.into_inner());
+    while owner.0.is_some() && owner.0 != Some(me) {
+        owner = released.wait(owner).unwrap_or_else(|err| err.into_inner());
+    }
+    owner.0 = Some(me);
+    owner.1 += 1;
+    owner.1 - 1
+}
+
+#[no_mangle]
+pub extern "C" fn os_arch_restore_sr(sr: os_sr_t) {
+    let (owner, released) = CRITICAL.get();
+    let mut owner = owner.lock().unwrap_or_else(|err| err.into_inner());
+    if owner.0 != Some(thread::current().id()) { return; }
+    owner.1 = sr;
+    if sr == 0 {
+        owner.0 = None;
+        released.notify_all();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn os_arch_in_critical() -> c_int {
+    let (owner, _) = CRITICAL.get();
+    let owner = owner.lock().unwrap_or_else(|err| err.into_inner());
+    (owner.0 == Some(thread::current().id())) as c_int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_eventq_dflt_get() -> *mut os_eventq {
+    &mut DEFAULT_EVENTQ
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_eventq_init(evq: *mut os_eventq) {
+    locked(|| {
+        *evq = fill_zero!(os_eventq);
+        (*evq).evq_list.stqh_last = &mut (*evq).evq_list.stqh_first;
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_eventq_inited(evq: *const os_eventq) -> c_int {
+    (evq == &DEFAULT_EVENTQ as *const os_eventq || !(*evq).evq_list.stqh_last.is_null()) as c_int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_eventq_put(evq: *mut os_eventq, ev: *mut os_event) {
+    locked(|| queue_event(evq, ev));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_eventq_remove(evq: *mut os_eventq, ev: *mut os_event) {
+    locked(|| remove_event(evq, ev));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_eventq_get_no_wait(evq: *mut os_eventq) -> *mut os_event {
+    locked(|| take_event(evq))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_eventq_get(evq: *mut os_eventq) -> *mut os_event {
+    let me = current_task();
+    let kernel = KERNEL.get();
+    let mut state = lock(kernel);
+    (*evq).evq_task = me;
+    loop {
+        let ev = take_event(evq);
+        if !ev.is_null() {
+            (*evq).evq_task = core::ptr::null_mut();
+            return ev;
+        }
+        state = wait(kernel, state, None).0;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_eventq_run(evq: *mut os_eventq) {
+    let ev = os_eventq_get(evq);
+    if let Some(cb) = (*ev).ev_cb { cb(ev); }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_callout_init(c: *mut os_callout, evq: *mut os_eventq, ev_cb: os_event_fn, ev_arg: *mut c_void) {
+    os_callout_stop(c);
+    *c = fill_zero!(os_callout);
+    (*c).c_ev.ev_cb  = ev_cb;
+    (*c).c_ev.ev_arg = ev_arg;
+    (*c).c_evq       = evq;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_callout_reset(c: *mut os_callout, ticks: os_time_t) -> c_int {
+    if (ticks as i32) < 0 { return os::os_error_OS_EINVAL as c_int; }
+    let kernel = KERNEL.get();
+    let mut state = lock(kernel);
+    stop_callout(&mut state, c);
+    (*c).c_ticks = now().wrapping_add(ticks);
+    //  Callout is active while `tqe_prev` is set, like Mynewt
+    (*c).c_next.tqe_prev = &mut (*c).c_next.tqe_next;
+    state.callouts.push(SendPtr(c));
+    kernel.changed.notify_all();
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_callout_stop(c: *mut os_callout) {
+    let kernel = KERNEL.get();
+    let mut state = lock(kernel);
+    stop_callout(&mut state, c);
+    kernel.changed.notify_all();
+}
+
+/// Deactivate the Callout and remove its event from the Event Queue. The kernel lock must be held.
+unsafe fn stop_callout(state: &mut State, c: *mut os_callout) {
+    state.callouts.retain(|callout| callout.0 != c);
+    (*c).c_next.tqe_prev = core::ptr::null_mut();
+    remove_event((*c).c_evq, &mut (*c).c_ev);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_callout_remaining_ticks(c: *mut os_callout, now: os_time_t) -> os_time_t {
+    locked(|| {
+        if (*c).c_next.tqe_prev.is_null() { return 0; }
+        ((*c).c_ticks.wrapping_sub(now) as i32).max(0) as os_time_t
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_sem_init(sem: *mut os_sem, tokens: u16) -> os_error_t {
+    if sem.is_null() { return os::os_error_OS_INVALID_PARM; }
+    locked(|| {
+        (*sem).sem_head.slh_first = core::ptr::null_mut();
+        (*sem).sem_tokens = tokens;
+    });
+    os::os_error_OS_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_sem_release(sem: *mut os_sem) -> os_error_t {
+    if sem.is_null() { return os::os_error_OS_INVALID_PARM; }
+    locked(|| (*sem).sem_tokens = (*sem).sem_tokens.wrapping_add(1));
+    os::os_error_OS_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_sem_pend(sem: *mut os_sem, timeout: os_time_t) -> os_error_t {
+    if sem.is_null() { return os::os_error_OS_INVALID_PARM; }
+    let kernel = KERNEL.get();
+    let deadline = deadline(timeout);
+    let mut state = lock(kernel);
+    loop {
+        if (*sem).sem_tokens > 0 {
+            (*sem).sem_tokens -= 1;
+            return os::os_error_OS_OK;
+        }
+        let (next, timed_out) = wait(kernel, state, deadline);
+        state = next;
+        if timed_out && (*sem).sem_tokens == 0 { return os::os_error_OS_TIMEOUT; }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mutex_init(mu: *mut os_mutex) -> os_error_t {
+    if mu.is_null() { return os::os_error_OS_INVALID_PARM; }
+    locked(|| {
+        (*mu).mu_owner = core::ptr::null_mut();
+        (*mu).mu_level = 0;
+    });
+    os::os_error_OS_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mutex_pend(mu: *mut os_mutex, timeout: os_time_t) -> os_error_t {
+    if mu.is_null() { return os::os_error_OS_INVALID_PARM; }
+    let me = current_task();
+    let kernel = KERNEL.get();
+    let deadline = deadline(timeout);
+    let mut state = lock(kernel);
+    loop {
+        if (*mu).mu_owner.is_null() || (*mu).mu_owner == me {
+            //  Mutex is free or already owned by us
+            (*mu).mu_owner = me;
+            (*mu).mu_level += 1;
+            return os::os_error_OS_OK;
+        }
+        let (next, timed_out) = wait(kernel, state, deadline);
+        state = next;
+        if timed_out && !(*mu).mu_owner.is_null() { return os::os_error_OS_TIMEOUT; }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mutex_release(mu: *mut os_mutex) -> os_error_t {
+    if mu.is_null() { return os::os_error_OS_INVALID_PARM; }
+    let me = current_task();
+    locked(|| {
+        if (*mu).mu_level == 0 || (*mu).mu_owner != me { return os::os_error_OS_BAD_MUTEX; }
+        (*mu).mu_level -= 1;
+        if (*mu).mu_level == 0 { (*mu).mu_owner = core::ptr::null_mut(); }
+        os::os_error_OS_OK
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_task_init(t: *mut os_task, name: *const c_char, func: os_task_func_t, arg: *mut c_void,
+    prio: u8, _sanity_itvl: os_time_t, stack_bottom: *mut os_stack_t, stack_size: u16) -> c_int {
+    if t.is_null() || func.is_none() { return os::os_error_OS_INVALID_PARM as c_int; }
+    *t = fill_zero!(os_task);
+    (*t).t_name      = name;
+    (*t).t_func      = func;
+    (*t).t_arg       = arg;
+    (*t).t_prio      = prio;
+    (*t).t_state     = os::os_task_state_OS_TASK_READY as u8;
+    (*t).t_stacksize = stack_size;
+    (*t).t_stacktop  = stack_bottom.wrapping_add(stack_size as usize);
+    register_task(t);
+    //  Run the task in a thread. The task stack is not used, the thread has its own stack.
+    let name = if name.is_null() { String::from("task") }
+        else { CStr::from_ptr(name).to_string_lossy().into_owned() };
+    let (task, arg) = (SendPtr(t), SendPtr(arg));
+    let spawned = thread::Builder::new()
+        .name(name)
+        .spawn(move || {
+            let (task, arg) = (task, arg);
+            CURRENT_TASK.with(|current| current.set(task.0));
+            if let Some(func) = func { func(arg.0); }
+        });
+    if spawned.is_err() { return os::os_error_OS_ENOMEM as c_int; }
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn os_sched_get_current_task() -> *mut os_task {
+    current_task()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_task_info_get_next(prev: *const os_task, oti: *mut os_task_info) -> *mut os_task {
+    let kernel = KERNEL.get();
+    let state = lock(kernel);
+    let index = if prev.is_null() { 0 }
+        else {
+            match state.tasks.iter().position(|task| task.0 as *const os_task == prev) {
+                Some(index) => index + 1,
+                None        => return core::ptr::null_mut(),
+            }
+        };
+    let task = match state.tasks.get(index) {
+        Some(task) => task.0,
+        None       => return core::ptr::null_mut(),
+    };
+    *oti = fill_zero!(os_task_info);
+    (*oti).oti_prio    = (*task).t_prio;
+    (*oti).oti_taskid  = (*task).t_taskid;
+    (*oti).oti_state   = (*task).t_state;
+    (*oti).oti_stksize = (*task).t_stacksize;
+    if !(*task).t_name.is_null() {
+        let name = CStr::from_ptr((*task).t_name).to_bytes();
+        for (i, byte) in name.iter().take((*oti).oti_name.len() - 1).enumerate() {
+            (*oti).oti_name[i] = *byte as c_char;
+        }
+    }
+    task
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_sanity_check_init(sc: *mut os_sanity_check) -> c_int {
+    *sc = fill_zero!(os_sanity_check);
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn os_sanity_check_register(_sc: *mut os_sanity_check) -> c_int {
+    0  //  Tasks are not supervised in the simulator
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_sanity_check_reset(sc: *mut os_sanity_check) -> c_int {
+    (*sc).sc_checkin_last = now();
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_dev_create(dev: *mut os_dev, name: *const c_char, stage: u8, priority: u8,
+    od_init: os_dev_init_func_t, arg: *mut c_void) -> c_int {
+    *dev = fill_zero!(os_dev);
+    (*dev).od_name     = name;
+    (*dev).od_stage    = stage;
+    (*dev).od_priority = priority;
+    (*dev).od_init     = od_init;
+    (*dev).od_init_arg = arg;
+    //  The OS has started, so initialise the device now, like Mynewt
+    let rc = match od_init {
+        Some(init) => init(dev, arg),
+        None       => 0,
+    };
+    if rc == 0 { (*dev).od_flags |= os::OS_DEV_F_STATUS_READY as u8; }
+    rc
+}
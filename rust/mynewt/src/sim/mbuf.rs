@@ -0,0 +1,321 @@
+//! Simulated Mynewt Mbufs and Mbuf Queues. Each mbuf is allocated on the host heap with the same layout as Mynewt:
+//! the `os_mbuf` header, followed by the packet header (if any) and the data buffer. Mbufs are never exhausted,
+//! so `SYS_ENOMEM` is not simulated.
+use std::alloc::{ alloc_zeroed, dealloc, Layout };
+use ::cty::{ c_int, c_void };
+use crate::kernel::os::{ self, os_event_fn, os_eventq, os_mbuf, os_mbuf_pkthdr, os_mbuf_pool, os_mqueue };
+use super::kernel;
+
+/// Allocate an mbuf with a packet header of `pkthdr_len` bytes (0 for none) and `leadingspace` bytes before the data
+unsafe fn alloc_mbuf(pkthdr_len: usize, leadingspace: usize) -> *mut os_mbuf {
+    if pkthdr_len > u8::max_value() as usize || pkthdr_len + leadingspace > MBUF_BUF_SIZE { return core::ptr::null_mut(); }
+    let om = alloc_zeroed(mbuf_layout()) as *mut os_mbuf;
+    if om.is_null() { return om; }
+    (*om).om_pkthdr_len = pkthdr_len as u8;
+    (*om).om_data = databuf(om).add(pkthdr_len + leadingspace);
+    om
+}
+
+/// Free one mbuf
+unsafe fn free_mbuf(om: *mut os_mbuf) {
+    dealloc(om as *mut u8, mbuf_layout());
+}
+
+/// Layout of an mbuf: header followed by the data buffer
+fn mbuf_layout() -> Layout {
+    Layout::from_size_align(core::mem::size_of::<os_mbuf>() + MBUF_BUF_SIZE, 8)
+        .expect("mbuf layout")
+}
+
+/// Return the start of the data buffer of the mbuf, where the packet header is stored
+unsafe fn databuf(om: *const os_mbuf) -> *mut u8 {
+    (om as *mut u8).add(core::mem::size_of::<os_mbuf>())
+}
+
+/// Return the packet header of the mbuf, or null if none
+unsafe fn pkthdr(om: *const os_mbuf) -> *mut os_mbuf_pkthdr {
+    if ((*om).om_pkthdr_len as usize) < core::mem::size_of::<os_mbuf_pkthdr>() { return core::ptr::null_mut(); }
+    databuf(om) as *mut os_mbuf_pkthdr
+}
+
+/// Return the mbuf that contains the packet header
+unsafe fn from_pkthdr(hdr: *mut os_mbuf_pkthdr) -> *mut os_mbuf {
+    (hdr as *mut u8).sub(core::mem::size_of::<os_mbuf>()) as *mut os_mbuf
+}
+
+/// Return the number of unused bytes before the data
+unsafe fn leading_space(om: *const os_mbuf) -> usize {
+    (*om).om_data as usize - (databuf(om) as usize + (*om).om_pkthdr_len as usize)
+}
+
+/// Return the number of unused bytes after the data
+unsafe fn trailing_space(om: *const os_mbuf) -> usize {
+    databuf(om) as usize + MBUF_BUF_SIZE - ((*om).om_data as usize + (*om).om_len as usize)
+}
+
+/// Add `len` to the packet length, if the chain has a packet header
+unsafe fn adjust_pktlen(om: *mut os_mbuf, len: isize) {
+    let hdr = pkthdr(om);
+    if !hdr.is_null() { (*hdr).omp_len = ((*hdr).omp_len as isize + len) as u16; }
+}
+
+/// Size of the data buffer in each mbuf, including the packet header
+const MBUF_BUF_SIZE: usize = 256;
+
+//  Simulated Mynewt Mbuf API. See `kernel/os.rs` for the documentation.
+
+#[no_mangle]
+pub unsafe extern "C" fn os_msys_get(_dsize: u16, leadingspace: u16) -> *mut os_mbuf {
+    alloc_mbuf(0, leadingspace as usize)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_msys_get_pkthdr(_dsize: u16, user_hdr_len: u16) -> *mut os_mbuf {
+    alloc_mbuf(core::mem::size_of::<os_mbuf_pkthdr>() + user_hdr_len as usize, 0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mbuf_get(_omp: *mut os_mbuf_pool, leadingspace: u16) -> *mut os_mbuf {
+    alloc_mbuf(0, leadingspace as usize)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mbuf_get_pkthdr(_omp: *mut os_mbuf_pool, user_hdr_len: u8) -> *mut os_mbuf {
+    alloc_mbuf(core::mem::size_of::<os_mbuf_pkthdr>() + user_hdr_len as usize, 0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mbuf_free(om: *mut os_mbuf) -> c_int {
+    if om.is_null() { return os::os_error_OS_INVALID_PARM as c_int; }
+    free_mbuf(om);
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mbuf_free_chain(om: *mut os_mbuf) -> c_int {
+    let mut om = om;
+    while !om.is_null() {
+        let next = (*om).om_next.sle_next;
+        free_mbuf(om);
+        om = next;
+    }
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mbuf_len(om: *const os_mbuf) -> u16 {
+    let mut len = 0u16;
+    let mut om = om;
+    while !om.is_null() {
+        len = len.wrapping_add((*om).om_len);
+        om = (*om).om_next.sle_next;
+    }
+    len
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mbuf_append(m: *mut os_mbuf, data: *const c_void, len: u16) -> c_int {
+    if m.is_null() { return os::os_error_OS_INVALID_PARM as c_int; }
+    //  Fill the trailing space of the last mbuf, then add mbufs as needed
+    let mut last = m;
+    while !(*last).om_next.sle_next.is_null() { last = (*last).om_next.sle_next; }
+    let mut src = data as *const u8;
+    let mut remaining = len as usize;
+    while remaining > 0 {
+        let space = trailing_space(last);
+        if space == 0 {
+            let om = alloc_mbuf(0, 0);
+            if om.is_null() { return os::os_error_OS_ENOMEM as c_int; }
+            (*last).om_next.sle_next = om;
+            last = om;
+            continue;
+        }
+        let count = core::cmp::min(space, remaining);
+        core::ptr::copy(src, (*last).om_data.add((*last).om_len as usize), count);
+        (*last).om_len += count as u16;
+        src = src.add(count);
+        remaining -= count;
+    }
+    adjust_pktlen(m, len as isize);
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mbuf_prepend(om: *mut os_mbuf, len: c_int) -> *mut os_mbuf {
+    let mut om = om;
+    let mut remaining = len.max(0) as usize;
+    loop {
+        //  Use the leading space of the first mbuf
+        let count = core::cmp::min(remaining, leading_space(om));
+        (*om).om_data = (*om).om_data.sub(count);
+        (*om).om_len += count as u16;
+        remaining -= count;
+        if remaining == 0 { break; }
+        //  Add an mbuf in front. The packet header moves to the new mbuf, like Mynewt.
+        let pkthdr_len = (*om).om_pkthdr_len as usize;
+        let p = alloc_mbuf(pkthdr_len, MBUF_BUF_SIZE - pkthdr_len);
+        if p.is_null() {
+            os_mbuf_free_chain(om);
+            return core::ptr::null_mut();
+        }
+        core::ptr::copy_nonoverlapping(databuf(om), databuf(p), pkthdr_len);
+        (*om).om_pkthdr_len = 0;
+        (*p).om_next.sle_next = om;
+        om = p;
+    }
+    adjust_pktlen(om, len.max(0) as isize);
+    om
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mbuf_concat(first: *mut os_mbuf, second: *mut os_mbuf) {
+    let mut last = first;
+    while !(*last).om_next.sle_next.is_null() { last = (*last).om_next.sle_next; }
+    (*last).om_next.sle_next = second;
+    adjust_pktlen(first, os_mbuf_len(second) as isize);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mbuf_copydata(m: *const os_mbuf, off: c_int, len: c_int, dst: *mut c_void) -> c_int {
+    if off < 0 || len < 0 { return -1; }
+    let (mut off, mut len) = (off as usize, len as usize);
+    let mut dst = dst as *mut u8;
+    let mut m = m;
+    while len > 0 {
+        if m.is_null() { return -1; }  //  Chain is too short
+        let om_len = (*m).om_len as usize;
+        if off < om_len {
+            let count = core::cmp::min(om_len - off, len);
+            core::ptr::copy((*m).om_data.add(off), dst, count);
+            dst = dst.add(count);
+            len -= count;
+            off = 0;
+        } else {
+            off -= om_len;
+        }
+        m = (*m).om_next.sle_next;
+    }
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mbuf_copyinto(om: *mut os_mbuf, off: c_int, src: *const c_void, len: c_int) -> c_int {
+    if off < 0 || len < 0 { return os::os_error_OS_EINVAL as c_int; }
+    if off as usize > os_mbuf_len(om) as usize { return os::os_error_OS_EINVAL as c_int; }
+    //  Overwrite the existing bytes, then append the rest
+    let (mut off, mut len) = (off as usize, len as usize);
+    let mut src = src as *const u8;
+    let mut m = om;
+    while len > 0 && !m.is_null() {
+        let om_len = (*m).om_len as usize;
+        if off < om_len {
+            let count = core::cmp::min(om_len - off, len);
+            core::ptr::copy(src, (*m).om_data.add(off), count);
+            src = src.add(count);
+            len -= count;
+            off = 0;
+        } else {
+            off -= om_len;
+        }
+        m = (*m).om_next.sle_next;
+    }
+    if len == 0 { return 0; }
+    os_mbuf_append(om, src as *const c_void, len as u16)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mbuf_adj(mp: *mut os_mbuf, req_len: c_int) {
+    if mp.is_null() { return; }
+    let total = os_mbuf_len(mp) as usize;
+    let count = core::cmp::min(req_len.abs() as usize, total);
+    if req_len >= 0 {
+        //  Trim from the front
+        let mut remaining = count;
+        let mut m = mp;
+        while remaining > 0 && !m.is_null() {
+            let trim = core::cmp::min((*m).om_len as usize, remaining);
+            (*m).om_data = (*m).om_data.add(trim);
+            (*m).om_len -= trim as u16;
+            remaining -= trim;
+            m = (*m).om_next.sle_next;
+        }
+    } else {
+        //  Trim from the back: keep the first `total - count` bytes
+        let mut keep = total - count;
+        let mut m = mp;
+        while !m.is_null() {
+            let om_len = (*m).om_len as usize;
+            if keep >= om_len { keep -= om_len; }
+            else {
+                (*m).om_len = keep as u16;
+                keep = 0;
+            }
+            m = (*m).om_next.sle_next;
+        }
+    }
+    adjust_pktlen(mp, -(count as isize));
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mbuf_dup(m: *mut os_mbuf) -> *mut os_mbuf {
+    let mut head: *mut os_mbuf = core::ptr::null_mut();
+    let mut last: *mut os_mbuf = core::ptr::null_mut();
+    let mut m = m;
+    while !m.is_null() {
+        //  Copy the packet header, leading space and data
+        let pkthdr_len = (*m).om_pkthdr_len as usize;
+        let copy = alloc_mbuf(pkthdr_len, leading_space(m));
+        if copy.is_null() {
+            os_mbuf_free_chain(head);
+            return core::ptr::null_mut();
+        }
+        core::ptr::copy_nonoverlapping(databuf(m), databuf(copy), pkthdr_len);
+        core::ptr::copy_nonoverlapping((*m).om_data, (*copy).om_data, (*m).om_len as usize);
+        (*copy).om_len = (*m).om_len;
+        if head.is_null() { head = copy; }
+        else { (*last).om_next.sle_next = copy; }
+        last = copy;
+        m = (*m).om_next.sle_next;
+    }
+    //  The copy is not in any queue
+    if !head.is_null() && !pkthdr(head).is_null() { (*pkthdr(head)).omp_next.stqe_next = core::ptr::null_mut(); }
+    head
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mqueue_init(mq: *mut os_mqueue, ev_cb: os_event_fn, arg: *mut c_void) -> c_int {
+    kernel::locked(|| {
+        (*mq).mq_head.stqh_first = core::ptr::null_mut();
+        (*mq).mq_head.stqh_last  = &mut (*mq).mq_head.stqh_first;
+        (*mq).mq_ev = core::mem::zeroed();
+        (*mq).mq_ev.ev_cb  = ev_cb;
+        (*mq).mq_ev.ev_arg = arg;
+    });
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mqueue_put(mq: *mut os_mqueue, evq: *mut os_eventq, m: *mut os_mbuf) -> c_int {
+    let hdr = pkthdr(m);
+    if hdr.is_null() { return os::os_error_OS_EINVAL as c_int; }
+    kernel::locked(|| {
+        //  Append the packet to the queue, then post the event
+        (*hdr).omp_next.stqe_next = core::ptr::null_mut();
+        let mut link: *mut *mut os_mbuf_pkthdr = &mut (*mq).mq_head.stqh_first;
+        while !(*link).is_null() { link = &mut (**link).omp_next.stqe_next; }
+        *link = hdr;
+        kernel::queue_event(evq, &mut (*mq).mq_ev);
+    });
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn os_mqueue_get(mq: *mut os_mqueue) -> *mut os_mbuf {
+    kernel::locked(|| {
+        let hdr = (*mq).mq_head.stqh_first;
+        if hdr.is_null() { return core::ptr::null_mut(); }
+        (*mq).mq_head.stqh_first = (*hdr).omp_next.stqe_next;
+        (*hdr).omp_next.stqe_next = core::ptr::null_mut();
+        from_pkthdr(hdr)
+    })
+}
@@ -0,0 +1,120 @@
+//! Simulated nRF52 peripheral registers for the drivers that program the registers directly: the SAADC in
+//! `hal/saadc.rs`, which samples the simulated battery, and the PWM in `hal/backlight.rs`. Tasks complete
+//! immediately, so the drivers see their events set right after triggering the tasks:
+//! ```
+//! mynewt::sim::peripheral::set_battery_millivolts(3700);  //  Battery is now at 3.7 V
+//! mynewt::sim::peripheral::set_power(true);               //  Plug into power and start charging
+//! ```
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+};
+use super::{ hal, Global };
+
+/// Write the peripheral register at the address. Triggering a task sets the task's events.
+pub fn write(addr: usize, value: u32) {
+    let mut registers = lock_registers();
+    registers.insert(addr, value);
+    if value == 0 { return; }
+    match addr {
+        //  SAADC tasks
+        SAADC_TASKS_START  => { registers.insert(SAADC_EVENTS_STARTED, 1); }
+        SAADC_TASKS_SAMPLE => { registers.insert(SAADC_EVENTS_END,     1); }
+        SAADC_TASKS_STOP   => { registers.insert(SAADC_EVENTS_STOPPED, 1); }
+        //  PWM tasks
+        PWM_TASKS_STOP     => { registers.insert(PWM_EVENTS_STOPPED,   1); }
+        _ => {}
+    }
+}
+
+/// Read the peripheral register at the address. Registers that have not been written are 0.
+pub fn read(addr: usize) -> u32 {
+    lock_registers().get(&addr).copied().unwrap_or(0)
+}
+
+/// Return the SAADC sample for the configured input and gain, which would have been written by EasyDMA
+pub fn saadc_result() -> i16 {
+    let millivolts = match read(SAADC_CH0_PSELP) {
+        AIN7 => battery_millivolts() / BATTERY_DIVIDER,  //  Battery voltage divider on P0.31
+        VDD  => VDD_MILLIVOLTS,
+        _    => 0,
+    };
+    let gain = (read(SAADC_CH0_CONFIG) >> 8) & 0x7;
+    let range = RANGE_MILLIVOLTS[gain as usize];
+    let sample = millivolts * SAADC_MAX_SAMPLE / range;
+    sample.min(SAADC_MAX_SAMPLE - 1) as i16
+}
+
+/// Set the battery voltage in millivolts
+pub fn set_battery_millivolts(millivolts: u32) {
+    *lock_battery() = millivolts;
+}
+
+/// Return the battery voltage in millivolts
+pub fn battery_millivolts() -> u32 {
+    *lock_battery()
+}
+
+/// Plug the PineTime into power (and start charging) if `on` is true, else unplug. The charge indication and
+/// power presence pins are low when plugged in.
+pub fn set_power(on: bool) {
+    hal::drive_pin(CHARGING_PIN, !on);
+    hal::drive_pin(POWER_PIN,    !on);
+}
+
+/// Set the battery voltage and power pins at startup. Called by `sim::run()`.
+pub(crate) fn start() {
+    set_battery_millivolts(DEFAULT_BATTERY_MILLIVOLTS);
+    set_power(false);
+}
+
+/// Lock the registers. A thread that panicked while holding the lock doesn't stop the simulator.
+fn lock_registers() -> std::sync::MutexGuard<'static, HashMap<usize, u32>> {
+    REGISTERS.get().lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Lock the battery voltage
+fn lock_battery() -> std::sync::MutexGuard<'static, u32> {
+    BATTERY.get().lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Peripheral registers by address
+static REGISTERS: Global<Mutex<HashMap<usize, u32>>> = Global::new(|| Mutex::new(HashMap::new()));
+
+/// Battery voltage in millivolts
+static BATTERY: Global<Mutex<u32>> = Global::new(|| Mutex::new(DEFAULT_BATTERY_MILLIVOLTS));
+
+/// Battery voltage at startup
+const DEFAULT_BATTERY_MILLIVOLTS: u32 = 3900;
+/// The battery voltage is halved by the voltage divider before the analog input
+const BATTERY_DIVIDER: u32 = 2;
+/// Supply voltage
+const VDD_MILLIVOLTS: u32 = 3300;
+/// P0.12: Charge indication, low when charging
+const CHARGING_PIN: i32 = 12;
+/// P0.19: Power presence, low when plugged in
+const POWER_PIN: i32 = 19;
+
+/// Max 12-bit sample value plus 1
+const SAADC_MAX_SAMPLE: u32 = 4096;
+/// SAADC input range in millivolts for each gain, see `Gain::range_millivolts()`
+const RANGE_MILLIVOLTS: [u32; 8] = [3600, 3000, 2400, 1800, 1200, 600, 300, 150];
+/// `PSELP` values for AIN7 and VDD
+const AIN7: u32 = 8;
+const VDD:  u32 = 9;
+
+/// SAADC registers, see `hal/saadc.rs`
+const SAADC_BASE: usize = 0x4000_7000;
+const SAADC_TASKS_START:    usize = SAADC_BASE + 0x000;
+const SAADC_TASKS_SAMPLE:   usize = SAADC_BASE + 0x004;
+const SAADC_TASKS_STOP:     usize = SAADC_BASE + 0x008;
+const SAADC_EVENTS_STARTED: usize = SAADC_BASE + 0x100;
+const SAADC_EVENTS_END:     usize = SAADC_BASE + 0x104;
+const SAADC_EVENTS_STOPPED: usize = SAADC_BASE + 0x114;
+const SAADC_CH0_PSELP:      usize = SAADC_BASE + 0x510;
+const SAADC_CH0_CONFIG:     usize = SAADC_BASE + 0x518;
+
+/// PWM registers, see `hal/backlight.rs`
+const PWM_BASE: usize = 0x4001_c000;
+const PWM_TASKS_STOP:     usize = PWM_BASE + 0x004;
+const PWM_EVENTS_STOPPED: usize = PWM_BASE + 0x104;
@@ -0,0 +1,104 @@
+//! Scripts for driving the simulator, one command per line. `#` starts a comment.
+//! ```text
+//! wait 2000             # Wait 2,000 milliseconds
+//! touch 120 120         # Tap at (120, 120)
+//! down 10 100           # Touch down at (10, 100), or move the touch
+//! up 230 100            # Touch up at (230, 100)
+//! battery 3600          # Set the battery voltage to 3,600 millivolts
+//! power on              # Plug into power (or "power off" to unplug)
+//! save screen.png       # Save the display to a PNG file
+//! ```
+use std::{
+    format,
+    string::{ String, ToString },
+    thread,
+    time::Duration,
+    vec::Vec,
+};
+use super::{ display, peripheral, touch };
+
+/// Parsed script
+pub struct Script {
+    /// Commands with their line numbers
+    commands: Vec<(usize, Command)>,
+}
+
+impl Script {
+    /// Parse the script. Returns an error with the line number if a command is invalid.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut commands = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() { continue; }
+            let command = Command::parse(line)
+                .map_err(|err| format!("line {}: {}: {}", i + 1, err, line)) ? ;
+            commands.push((i + 1, command));
+        }
+        Ok(Script { commands })
+    }
+
+    /// Run the commands in order. Returns an error with the line number if a command fails.
+    pub fn run(&self) -> Result<(), String> {
+        for (line, command) in &self.commands {
+            command.run().map_err(|err| format!("line {}: {}", line, err)) ? ;
+        }
+        Ok(())
+    }
+}
+
+/// Script command
+enum Command {
+    /// Wait for the milliseconds
+    Wait(u64),
+    /// Tap at (x, y)
+    Touch(u16, u16),
+    /// Touch down at (x, y)
+    Down(u16, u16),
+    /// Touch up at (x, y)
+    Up(u16, u16),
+    /// Set the battery voltage in millivolts
+    Battery(u32),
+    /// Plug into power if true, else unplug
+    Power(bool),
+    /// Save the display to the PNG file
+    Save(String),
+}
+
+impl Command {
+    /// Parse the command line
+    fn parse(line: &str) -> Result<Self, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = match words.as_slice() {
+            ["wait", ms]       => Command::Wait(number(ms) ? ),
+            ["touch", x, y]    => Command::Touch(number(x) ? , number(y) ? ),
+            ["down", x, y]     => Command::Down(number(x) ? , number(y) ? ),
+            ["up", x, y]       => Command::Up(number(x) ? , number(y) ? ),
+            ["battery", mv]    => Command::Battery(number(mv) ? ),
+            ["power", "on"]    => Command::Power(true),
+            ["power", "off"]   => Command::Power(false),
+            ["save", path]     => Command::Save(path.to_string()),
+            _ => return Err("unknown command".into()),
+        };
+        Ok(command)
+    }
+
+    /// Run the command
+    fn run(&self) -> Result<(), String> {
+        match self {
+            Command::Wait(ms)       => thread::sleep(Duration::from_millis(*ms)),
+            Command::Touch(x, y)    => touch::tap(*x, *y),
+            Command::Down(x, y)     => touch::down(*x, *y),
+            Command::Up(x, y)       => touch::up(*x, *y),
+            Command::Battery(mv)    => peripheral::set_battery_millivolts(*mv),
+            Command::Power(on)      => peripheral::set_power(*on),
+            Command::Save(path)     => display::save_png(path)
+                .map_err(|err| format!("can't save {}: {}", path, err)) ? ,
+        }
+        Ok(())
+    }
+}
+
+/// Parse the number
+fn number<T: core::str::FromStr>(word: &str) -> Result<T, String> {
+    word.parse().map_err(|_| format!("invalid number {}", word))
+}
@@ -0,0 +1,239 @@
+//! Simulated Mynewt Sensor Framework and the sensor helpers in `libs/mynewt_rust`. Sensor drivers like
+//! `hw/battery.rs` register with the simulated Sensor Manager as usual. Polled sensors are read by a Callout on the
+//! default Event Queue, and the sensor data is passed to the listeners, like Mynewt.
+use std::{
+    ffi::CStr,
+    sync::Mutex,
+    vec::Vec,
+};
+use ::cty::{ c_char, c_int, c_void };
+use crate::{
+    hw::sensor::{
+        sensor, sensor_data_func_t, sensor_data_ptr, sensor_geolocation_data, sensor_listener, sensor_ptr,
+        sensor_temp_data, sensor_temp_raw_data, sensor_type_t,
+    },
+    kernel::os::{ self, os_callout, os_dev, os_event, os_eventq, os_time_t },
+    fill_zero,
+};
+use super::{ kernel, Global, SendPtr };
+
+/// Poll the sensors that are due, then schedule the next poll. Called by the Callout on the default Event Queue.
+extern "C" fn poll_sensors(_ev: *mut os_event) {
+    let now = kernel::os_time_get();
+    let due: Vec<SendPtr<sensor>> = lock_sensors().iter()
+        .filter(|s| unsafe { (*s.0).s_poll_rate > 0 && ticks_until((*s.0).s_next_run, now) <= 0 })
+        .copied()
+        .collect();
+    for s in due {
+        unsafe {
+            (*s.0).s_next_run = now.wrapping_add(ms_to_ticks((*s.0).s_poll_rate));
+            sensor_read(s.0, (*s.0).s_mask, None, core::ptr::null_mut(), os::OS_TIMEOUT_NEVER);
+        }
+    }
+    schedule_poll();
+}
+
+/// Reset the poll Callout to fire when the next sensor is due
+fn schedule_poll() {
+    let now = kernel::os_time_get();
+    let next = lock_sensors().iter()
+        .filter(|s| unsafe { (*s.0).s_poll_rate > 0 })
+        .map(|s| unsafe { ticks_until((*s.0).s_next_run, now).max(0) as os_time_t })
+        .min();
+    if let Some(ticks) = next {
+        unsafe { kernel::os_callout_reset(&mut POLL_CALLOUT, ticks) };
+    }
+}
+
+/// Return the number of ticks from `now` to `time`, negative if `time` has passed
+fn ticks_until(time: os_time_t, now: os_time_t) -> i32 {
+    time.wrapping_sub(now) as i32
+}
+
+/// Convert milliseconds to ticks
+fn ms_to_ticks(ms: u32) -> os_time_t {
+    ms * os::OS_TICKS_PER_SEC / 1000
+}
+
+/// Lock the registered sensors
+fn lock_sensors() -> std::sync::MutexGuard<'static, Vec<SendPtr<sensor>>> {
+    SENSORS.get().lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Return the device name of the sensor, or `None` if unnamed
+unsafe fn devname(s: *mut sensor) -> Option<&'static CStr> {
+    if s.is_null() || (*s).s_dev.is_null() || (*(*s).s_dev).od_name.is_null() { return None; }
+    Some(CStr::from_ptr((*(*s).s_dev).od_name))
+}
+
+/// Context for `read_data_func()`: The data function and argument passed to `sensor_read()`
+struct ReadContext {
+    /// Data function of the caller
+    data_func: sensor_data_func_t,
+    /// Argument for the data function
+    arg:       *mut c_void,
+}
+
+/// Data function passed to the sensor driver by `sensor_read()`. Passes the sensor data to the listeners for
+/// the sensor type, then to the caller's data function.
+unsafe extern "C" fn read_data_func(s: *mut sensor, arg: *mut c_void, data: *mut c_void, stype: sensor_type_t) -> c_int {
+    let ctx = &*(arg as *const ReadContext);
+    //  Copy the matching listeners, since the listeners may call the Sensor Framework
+    let listeners: Vec<(sensor_data_func_t, SendPtr<c_void>)> = kernel::locked(|| {
+        let mut listeners = Vec::new();
+        let mut listener = (*s).s_listener_list.slh_first;
+        while !listener.is_null() {
+            if (*listener).sl_sensor_type & stype != 0 {
+                listeners.push(((*listener).sl_func, SendPtr((*listener).sl_arg)));
+            }
+            listener = (*listener).sl_next.sle_next;
+        }
+        listeners
+    });
+    for (func, listener_arg) in listeners {
+        if let Some(func) = func { func(s, listener_arg.0, data, stype); }
+    }
+    match ctx.data_func {
+        Some(func) => func(s, ctx.arg, data, stype),
+        None => 0,
+    }
+}
+
+/// Sensors registered with the Sensor Manager
+static SENSORS: Global<Mutex<Vec<SendPtr<sensor>>>> = Global::new(|| Mutex::new(Vec::new()));
+
+/// Callout that polls the sensors
+static mut POLL_CALLOUT: os_callout = fill_zero!(os_callout);
+
+/// Set when `POLL_CALLOUT` has been initialised
+static POLL_INIT: std::sync::Once = std::sync::Once::new();
+
+//  Simulated Mynewt Sensor Framework API. See `hw/sensor/bindings.rs` for the documentation.
+
+#[no_mangle]
+pub unsafe extern "C" fn sensor_init(s: *mut sensor, dev: *mut os_dev) -> c_int {
+    if s.is_null() { return os::SYS_EINVAL; }
+    *s = fill_zero!(sensor);
+    (*s).s_dev = dev;
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sensor_mgr_register(s: *mut sensor) -> c_int {
+    if s.is_null() { return os::SYS_EINVAL; }
+    let mut sensors = lock_sensors();
+    if !sensors.contains(&SendPtr(s)) { sensors.push(SendPtr(s)); }
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sensor_mgr_evq_get() -> *mut os_eventq {
+    kernel::os_eventq_dflt_get()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sensor_mgr_find_next_bydevname(name: *const c_char, prev_cursor: *mut sensor) -> *mut sensor {
+    if name.is_null() { return core::ptr::null_mut(); }
+    let name = CStr::from_ptr(name);
+    let sensors = lock_sensors();
+    //  Start after the previous sensor, if any
+    let start = match sensors.iter().position(|s| s.0 == prev_cursor) {
+        Some(i) if !prev_cursor.is_null() => i + 1,
+        _ => 0,
+    };
+    sensors[start..].iter()
+        .find(|s| devname(s.0) == Some(name))
+        .map_or(core::ptr::null_mut(), |s| s.0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sensor_set_poll_rate_ms(name: *const c_char, poll_rate: u32) -> c_int {
+    let s = sensor_mgr_find_next_bydevname(name, core::ptr::null_mut());
+    if s.is_null() { return os::SYS_EINVAL; }
+    (*s).s_poll_rate = poll_rate;
+    (*s).s_next_run  = kernel::os_time_get().wrapping_add(ms_to_ticks(poll_rate));
+    POLL_INIT.call_once(|| {
+        kernel::os_callout_init(&mut POLL_CALLOUT, kernel::os_eventq_dflt_get(), Some(poll_sensors), core::ptr::null_mut());
+    });
+    schedule_poll();
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sensor_register_listener(s: *mut sensor, listener: *mut sensor_listener) -> c_int {
+    if s.is_null() || listener.is_null() { return os::SYS_EINVAL; }
+    kernel::locked(|| {
+        (*listener).sl_next.sle_next = (*s).s_listener_list.slh_first;
+        (*s).s_listener_list.slh_first = listener;
+    });
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sensor_unregister_listener(s: *mut sensor, listener: *mut sensor_listener) -> c_int {
+    if s.is_null() || listener.is_null() { return os::SYS_EINVAL; }
+    kernel::locked(|| {
+        let mut link: *mut *mut sensor_listener = &mut (*s).s_listener_list.slh_first;
+        while !(*link).is_null() {
+            if *link == listener {
+                *link = (*listener).sl_next.sle_next;
+                return 0;
+            }
+            link = &mut (**link).sl_next.sle_next;
+        }
+        os::SYS_ENOENT
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sensor_read(s: *mut sensor, stype: sensor_type_t, data_func: sensor_data_func_t,
+    arg: *mut c_void, timeout: u32) -> c_int {
+    if s.is_null() || (*s).s_funcs.is_null() { return os::SYS_EINVAL; }
+    let read = match (*(*s).s_funcs).sd_read { Some(read) => read, None => return os::SYS_EINVAL };
+    let mut ctx = ReadContext { data_func, arg };
+    read(s, stype, Some(read_data_func), &mut ctx as *mut ReadContext as *mut c_void, timeout)
+}
+
+//  Simulated sensor helpers in `libs/mynewt_rust`. See `hw/sensor.rs` for the documentation.
+
+#[no_mangle]
+pub unsafe extern "C" fn get_temp_raw_data(sensor_data: sensor_data_ptr, dest: *mut sensor_temp_raw_data) -> i32 {
+    if sensor_data.is_null() || dest.is_null() { return os::SYS_EINVAL; }
+    core::ptr::copy_nonoverlapping(sensor_data as *const sensor_temp_raw_data, dest, 1);
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn get_temp_data(sensor_data: sensor_data_ptr, dest: *mut sensor_temp_data) -> i32 {
+    if sensor_data.is_null() || dest.is_null() { return os::SYS_EINVAL; }
+    core::ptr::copy_nonoverlapping(sensor_data as *const sensor_temp_data, dest, 1);
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn get_geolocation_data(sensor_data: sensor_data_ptr, dest: *mut sensor_geolocation_data) -> i32 {
+    if sensor_data.is_null() || dest.is_null() { return os::SYS_EINVAL; }
+    core::ptr::copy_nonoverlapping(sensor_data as *const sensor_geolocation_data, dest, 1);
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sensor_get_device(s: sensor_ptr) -> *mut os_dev {
+    if s.is_null() { return core::ptr::null_mut(); }
+    (*s).s_dev
+}
+
+#[no_mangle]
+pub extern "C" fn null_sensor() -> sensor_ptr {
+    core::ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn is_null_sensor(s: sensor_ptr) -> bool {
+    s.is_null()
+}
+
+#[no_mangle]
+pub extern "C" fn is_null_sensor_data(sensor_data: sensor_data_ptr) -> bool {
+    sensor_data.is_null()
+}
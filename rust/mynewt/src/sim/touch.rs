@@ -0,0 +1,138 @@
+//! Simulated CST816S touch controller, connected to I2C port 1 at address 0x15. Injected touches are stored in
+//! the touch registers, then the controller pulses the interrupt pin (P0.28) low, like the real controller:
+//! ```
+//! mynewt::sim::touch::tap(120, 120);  //  Tap the centre of the screen
+//! mynewt::sim::touch::down(10, 100);  //  Swipe right
+//! mynewt::sim::touch::down(120, 100);
+//! mynewt::sim::touch::up(230, 100);
+//! ```
+//! Each call waits until the app has read the touch registers, or until the timeout.
+use std::{
+    sync::{ Condvar, Mutex },
+    time::Duration,
+};
+use super::{ hal, Global };
+
+/// Touch down at (x, y), then touch up
+pub fn tap(x: u16, y: u16) {
+    down(x, y);
+    up(x, y);
+}
+
+/// Touch down at (x, y). If the screen is already touched, the touch moves to (x, y).
+pub fn down(x: u16, y: u16) {
+    let event = if lock_touch().touching { EVENT_CONTACT } else { EVENT_DOWN };
+    inject(event, x, y);
+}
+
+/// Touch up at (x, y)
+pub fn up(x: u16, y: u16) {
+    inject(EVENT_UP, x, y);
+}
+
+/// Handle the bytes written on the I2C bus: The register address, followed by the values to be written
+pub(crate) fn write(data: &[u8]) {
+    let mut touch = lock_touch();
+    if let Some((&reg, values)) = data.split_first() {
+        touch.reg = reg;
+        for &value in values {
+            let reg = touch.reg;
+            touch.registers[reg as usize] = value;
+            touch.reg = reg.wrapping_add(1);
+        }
+    }
+}
+
+/// Handle the bytes read on the I2C bus: The values of consecutive registers, starting at the register
+/// address written last
+pub(crate) fn read(buf: &mut [u8]) {
+    let mut touch = lock_touch();
+    for byte in buf.iter_mut() {
+        let reg = touch.reg;
+        *byte = touch.registers[reg as usize];
+        touch.reg = reg.wrapping_add(1);
+    }
+    //  Reading the touch data acknowledges the touch
+    if touch.pending {
+        touch.pending = false;
+        TOUCH.get().1.notify_all();
+    }
+}
+
+/// Store the touch event in the registers, trigger the interrupt and wait for the app to read the registers
+fn inject(event: u8, x: u16, y: u16) {
+    {
+        let mut touch = lock_touch();
+        let x = x.min(MAX_COORD);
+        let y = y.min(MAX_COORD);
+        let r = &mut touch.registers;
+        r[REG_GESTURE]     = 0;
+        r[REG_POINT_NUM]   = if event == EVENT_UP { 0 } else { 1 };
+        r[REG_X_HIGH]      = event << 6 | (x >> 8) as u8;
+        r[REG_X_LOW]       = x as u8;
+        r[REG_Y_HIGH]      = TOUCH_ID << 4 | (y >> 8) as u8;
+        r[REG_Y_LOW]       = y as u8;
+        r[REG_PRESSURE]    = if event == EVENT_UP { 0 } else { PRESSURE };
+        r[REG_AREA]        = if event == EVENT_UP { 0 } else { AREA << 4 };
+        touch.touching = event != EVENT_UP;
+        touch.pending  = true;
+    }
+    //  Pulse the interrupt pin low
+    hal::drive_pin(INTERRUPT_PIN, false);
+    hal::drive_pin(INTERRUPT_PIN, true);
+    //  Wait for the app to read the touch data
+    let (lock, read) = TOUCH.get();
+    let touch = lock.lock().unwrap_or_else(|err| err.into_inner());
+    let _ = read.wait_timeout_while(touch, READ_TIMEOUT, |touch| touch.pending);
+}
+
+/// Lock the touch controller. A thread that panicked while holding the lock doesn't stop the simulator.
+fn lock_touch() -> std::sync::MutexGuard<'static, Touch> {
+    TOUCH.get().0.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// State of the CST816S touch controller
+struct Touch {
+    /// Registers
+    registers: [u8; 256],
+    /// Register address for the next read or write
+    reg:       u8,
+    /// True if the screen is touched
+    touching:  bool,
+    /// True if the last touch has not been read by the app
+    pending:   bool,
+}
+
+/// Simulated touch controller and the condition variable that is notified when the touch data is read
+static TOUCH: Global<(Mutex<Touch>, Condvar)> = Global::new(|| (
+    Mutex::new(Touch { registers: [0; 256], reg: 0, touching: false, pending: false }),
+    Condvar::new(),
+));
+
+/// Interrupt pin of the touch controller (P0.28), active low
+const INTERRUPT_PIN: i32 = 28;
+/// Max time to wait for the app to read the touch data
+const READ_TIMEOUT: Duration = Duration::from_millis(1000);
+
+//  Touch registers of the first touch point
+const REG_GESTURE:   usize = 0x01;
+const REG_POINT_NUM: usize = 0x02;
+const REG_X_HIGH:    usize = 0x03;  //  Event (bits 6-7) and X high bits
+const REG_X_LOW:     usize = 0x04;
+const REG_Y_HIGH:    usize = 0x05;  //  Touch ID (bits 4-7) and Y high bits
+const REG_Y_LOW:     usize = 0x06;
+const REG_PRESSURE:  usize = 0x07;
+const REG_AREA:      usize = 0x08;  //  Area (bits 4-7)
+
+//  Touch events
+const EVENT_DOWN:    u8 = 0;
+const EVENT_UP:      u8 = 1;
+const EVENT_CONTACT: u8 = 2;
+
+/// Touch ID of the finger
+const TOUCH_ID: u8 = 0;
+/// Pressure and area reported for touches
+const PRESSURE: u8 = 0x20;
+const AREA:     u8 = 0x1;
+/// Max X and Y coordinate
+const MAX_COORD: u16 = 239;
@@ -1,363 +1,779 @@
 //! Experimental Non-Blocking SPI Transfer API. Uses a background task to send SPI requests sequentially.
-//! Request data is copied into Mbuf Queues before transmitting. 
+//! Request data is copied into Mbuf Queues before transmitting. Each SPI device is a `NonBlockingSpi` instance
+//! with its own port, pins, settings and queue. All instances are served by the same background task, and
+//! devices on the same SPI port take turns to lock the port:
+//! ```
+//! static FLASH: NonBlockingSpi = NonBlockingSpi::new(0, 5, None, FLASH_CONFIG, 2, 256);
+//! ...
+//! FLASH.init() ? ;
+//! FLASH.write(Some(0x06), &[], None) ? ;                     //  Write Enable
+//! FLASH.write(Some(0x02), &page, Some(page_written)) ? ;     //  Page Program, then call `page_written()`
+//! FLASH.wait_idle().await ? ;                               //  Wait for all requests to be sent
+//! ```
+//! Errors from the SPI task are passed to the completion callback. If the request has no callback, the error is
+//! returned by the next `flush()`. Failed requests are retried and the queue is aborted according to the
+//! `SpiErrorPolicy` of the device. Failures are counted in `SpiStats`.
+//!
+//! The `spi_noblock_*` functions write to the PineTime display, which is also a `NonBlockingSpi` instance.
+use core::{
+    cell::Cell,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{ AtomicI32, AtomicU32, Ordering },
+    task::{ Context, Poll },
+};
 use crate::{
     self as mynewt,
     result::*,
     hw::hal,
-    kernel::os,
-    NULL, Ptr, Strn,
+    hal::{
+        lock_spi_bus,
+        watchdog::{ self, Watch },
+        BitOrder, SpiConfig, SpiMode, SPI_PORT_COUNT,
+    },
+    kernel::{
+        self, os,
+        mbuf::{ Mbuf, MbufPacket, MbufQueue },
+        sync::{ critical_section, Mutex, Semaphore },
+    },
+    executor::WakerCell,
+    NULL, Ptr,
 };
 use mynewt_macros::{
-    init_strn,
+    task,
 };
 
-//  TODO: Remove SPI settings for ST7789 display controller
-const DISPLAY_SPI: i32  =  0;  //  Mynewt SPI port 0
-const DISPLAY_CS: i32   = 25;  //  LCD_CS (P0.25): Chip select
-const DISPLAY_DC: i32   = 18;  //  LCD_RS (P0.18): Clock/data pin (CD)
-//  const DISPLAY_RST: i32  = 26;  //  LCD_RESET (P0.26): Display reset
-//  const DISPLAY_HIGH: i32 = 23;  //  LCD_BACKLIGHT_{LOW,MID,HIGH} (P0.14, 22, 23): Backlight (active low)
-
-const SPI_NUM: i32    = DISPLAY_SPI;
-const SPI_SS_PIN: i32 = DISPLAY_CS;
-const SPI_DC_PIN: i32 = DISPLAY_DC;
-
-/// TODO: Remove SPI settings for ST7789 display controller
-static mut SPI_SETTINGS: hal::hal_spi_settings = hal::hal_spi_settings {
-    data_order: hal::HAL_SPI_MSB_FIRST as u8,
-    data_mode:  hal::HAL_SPI_MODE3 as u8,  //  SPI must be used in mode 3. Mode 0 (the default) won't work.
-    baudrate:   8000,  //  In kHZ. Use SPI at 8MHz (the fastest clock available on the nRF52832) because otherwise refreshing will be super slow.
-    word_size:  hal::HAL_SPI_WORD_SIZE_8BIT as u8,
+/// ST7789 display controller on the PineTime, written by the `spi_noblock_*` functions and by display drivers
+pub static DISPLAY: NonBlockingSpi = NonBlockingSpi::new(
+    0,         //  Mynewt SPI port 0
+    25,        //  LCD_CS (P0.25): Chip select
+    Some(18),  //  LCD_RS (P0.18): Clock/data pin (CD)
+    SpiConfig {
+        mode:      SpiMode::Mode3,  //  SPI must be used in mode 3. Mode 0 (the default) won't work.
+        baudrate:  8000,  //  In kHZ. Use SPI at 8MHz (the fastest clock available on the nRF52832) because otherwise refreshing will be super slow.
+        bit_order: BitOrder::MsbFirst,
+    },
+    2,         //  Only max 2 requests queued, the next request will block
+    8192,      //  Max size of Data Bytes per request
+);
+//  LCD_RESET (P0.26): Display reset
+//  LCD_BACKLIGHT_{LOW,MID,HIGH} (P0.14, 22, 23): Backlight (active low)
+
+/// Max number of Data Bytes per request, limited by the size of an mbuf chain
+const MAX_REQUEST_SIZE: usize = u16::max_value() as usize - REQUEST_HEADER_SIZE;
+
+/// Semaphore for each SPI port that is signalled for every completed SPI transfer. Init to 0 tokens, so caller will block until SPI transfer is completed.
+static SPI_DONE: [Semaphore; SPI_PORT_COUNT] = [
+    Semaphore::new(0), Semaphore::new(0), Semaphore::new(0),
+];
+
+/// Supervises the SPI task, which must complete each SPI transfer within `SPI_TASK_DEADLINE` milliseconds
+static SPI_WATCH: Watch = Watch::new("spi");
+
+/// Deadline for the SPI task to complete each SPI transfer, in milliseconds
+const SPI_TASK_DEADLINE: u32 = 5000;
+
+/// Timeout for each SPI transfer, in milliseconds. The SPI task checks in before each transfer, so that
+/// a transfer that has timed out is retried before the `SPI_TASK_DEADLINE` expires.
+const SPI_TRANSFER_TIMEOUT: u32 = 1000;
+
+/// Timeout for the caller to wait for a free slot in the queue of a device, in milliseconds. Well below
+/// the deadline of the calling task, so that a stuck SPI task is reported as `SYS_ETIMEOUT` instead.
+const SPI_ENQUEUE_TIMEOUT: u32 = 1000;
+
+/// True if the SPI task has been started. Locked while starting the task, so that the SPI Event Queue is
+/// initialised once, before any device uses it.
+static SPI_TASK_STARTED: Mutex<bool> = Mutex::new(false);
+
+/// Event Queue that contains the pending non-blocking SPI requests for all devices
+static mut SPI_EVENT_QUEUE: os::os_eventq = fill_zero!(os::os_eventq);
+
+/// Called by the SPI task with the result of a request, after the request has been sent. Must not block.
+pub type SpiCompletion = fn(result: MynewtResult<()>);
+
+/// What the SPI task does with the queued requests when a request has failed after retrying
+#[derive(Clone, Copy, PartialEq)]
+pub enum SpiFailureAction {
+    /// Drop the failed request and continue with the next request
+    Skip,
+    /// Drop the failed request and discard the requests queued before the next `flush()`
+    Abort,
+}
+
+/// Retry and abort policy for failed requests
+#[derive(Clone, Copy)]
+pub struct SpiErrorPolicy {
+    /// Number of times to resend a failed request
+    pub retries:    u8,
+    /// What to do when the request still fails after retrying
+    pub on_failure: SpiFailureAction,
+}
+
+/// Default policy: Resend the failed request once, then abort. For displays, the remaining requests for the frame are useless.
+pub const DEFAULT_ERROR_POLICY: SpiErrorPolicy = SpiErrorPolicy {
+    retries:    1,
+    on_failure: SpiFailureAction::Abort,
 };
 
-/// Max size of pending Command Bytes
-type PendingCmdSize = heapless::consts::U1;
-/// Max size of pending Data Bytes
-type PendingDataSize = heapless::consts::U8192;
+/// Counters for the requests sent by a `NonBlockingSpi` device, returned by `stats()`
+#[derive(Clone, Copy, Default)]
+pub struct SpiStats {
+    /// Number of requests sent successfully
+    pub sent:     u32,
+    /// Number of requests that failed after retrying
+    pub failures: u32,
+    /// Number of times a failed request was resent
+    pub retries:  u32,
+    /// Number of queued requests discarded because an earlier request failed
+    pub aborted:  u32,
+}
 
-/// Pending SPI Command Byte to be written
-static mut PENDING_CMD: heapless::Vec<u8, PendingCmdSize> = heapless::Vec(heapless::i::Vec::new());
-/// Pending SPI Data Bytes to be written
-static mut PENDING_DATA: heapless::Vec<u8, PendingDataSize> = heapless::Vec(heapless::i::Vec::new());
+/// Counters updated by the SPI task, copied into `SpiStats` by `stats()`
+struct SpiCounters {
+    /// Number of requests sent successfully
+    sent:     AtomicU32,
+    /// Number of requests that failed after retrying
+    failures: AtomicU32,
+    /// Number of times a failed request was resent
+    retries:  AtomicU32,
+    /// Number of queued requests discarded because an earlier request failed
+    aborted:  AtomicU32,
+}
 
-/// Semaphore that is signalled for every completed SPI request
-static mut SPI_SEM: os::os_sem = fill_zero!(os::os_sem);
+impl SpiCounters {
+    /// Create counters set to 0
+    const fn new() -> Self {
+        SpiCounters {
+            sent:     AtomicU32::new(0),
+            failures: AtomicU32::new(0),
+            retries:  AtomicU32::new(0),
+            aborted:  AtomicU32::new(0),
+        }
+    }
 
-/// Semaphore that throttles the number of queued SPI requests
-static mut SPI_THROTTLE_SEM: os::os_sem = fill_zero!(os::os_sem);
+    /// Increment the counter
+    fn increment(counter: &AtomicU32) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
 
-/// Mbuf Queue that contains the SPI data packets to be sent. Why use Mbuf Queue? 
-/// Because it's a Mynewt OS low-level buffer that allows packets of various sizes to be copied efficiently.
-static mut SPI_DATA_QUEUE: os::os_mqueue = fill_zero!(os::os_mqueue);
+/// SPI device that is written without blocking. Requests are copied into an Mbuf Queue and sent by the SPI task.
+/// Each request has an optional Command Byte, sent with the DC pin low, followed by Data Bytes, sent with the DC pin high.
+pub struct NonBlockingSpi {
+    /// Mynewt SPI port
+    spi_num:     i32,
+    /// Chip select pin, active low
+    cs_pin:      i32,
+    /// Data/command pin: low for Command Byte, high for Data Bytes. `None` if the device has no DC pin.
+    dc_pin:      Option<i32>,
+    /// SPI settings for the device
+    config:      SpiConfig,
+    /// Max number of queued requests
+    queue_depth: u16,
+    /// Max number of Data Bytes per request
+    buffer_size: usize,
+    /// Semaphore that throttles the number of queued requests. Starts with `queue_depth` tokens.
+    throttle:    Semaphore,
+    /// Mbuf Queue that contains the requests to be sent. Why use Mbuf Queue?
+    /// Because it's a Mynewt OS low-level buffer that allows packets of various sizes to be copied efficiently.
+    queue:       MbufQueue<SpiRequest>,
+    /// Request composed by `write_command()` and `write_data()`, until it's enqueued by `flush()`.
+    /// Locked while composing or enqueueing, so that tasks sharing the device don't corrupt the request.
+    pending:     Mutex<Option<Mbuf>>,
+    /// Waker of the Future returned by `wait_idle()`
+    idle_waker:  WakerCell,
+    /// Retry and abort policy for failed requests
+    policy:      Cell<SpiErrorPolicy>,
+    /// Error code of the first failed request since the last `flush()`, or 0 if none
+    error:       AtomicI32,
+    /// Generation stamped into each enqueued request. Advanced by `flush()` after a request has failed,
+    /// so that the requests enqueued after the failure has been reported are not discarded.
+    generation:  AtomicU32,
+    /// Queued requests with an older generation are discarded, because a request of their generation has failed
+    abort_before: AtomicU32,
+    /// Counters for sent and failed requests
+    counters:    SpiCounters,
+    /// True if the device has been initialised. Locked while initialising the device.
+    initialised: Mutex<bool>,
+}
 
-/// Event Queue that contains the pending non-blocking SPI requests
-static mut SPI_EVENT_QUEUE: os::os_eventq = fill_zero!(os::os_eventq);
+impl NonBlockingSpi {
+    /// Create a device on SPI port `spi_num` with chip select pin `cs_pin` and optional data/command pin `dc_pin`.
+    /// Up to `queue_depth` requests may be queued, each with up to `buffer_size` Data Bytes.
+    /// `init()` must be called before use.
+    pub const fn new(spi_num: i32, cs_pin: i32, dc_pin: Option<i32>, config: SpiConfig, queue_depth: u16, buffer_size: usize) -> Self {
+        NonBlockingSpi {
+            spi_num,
+            cs_pin,
+            dc_pin,
+            config,
+            queue_depth,
+            buffer_size,
+            throttle:   Semaphore::new(queue_depth),
+            queue:      MbufQueue::new(),
+            pending:    Mutex::new(None),
+            idle_waker: WakerCell::new(),
+            policy:     Cell::new(DEFAULT_ERROR_POLICY),
+            error:      AtomicI32::new(0),
+            generation: AtomicU32::new(0),
+            abort_before: AtomicU32::new(0),
+            counters:   SpiCounters::new(),
+            initialised: Mutex::new(false),
+        }
+    }
 
-/// SPI Task that will send each SPI request sequentially
-static mut SPI_TASK: os::os_task = fill_zero!(os::os_task);
+    /// Init non-blocking SPI transfer for the device. Starts the SPI task if it's not running. Does nothing if the
+    /// device has been initialised, so that drivers sharing the device, like druid and `ST7789`, may both call `init()`.
+    pub fn init(&'static self) -> MynewtResult<()> {
+        let mut initialised = self.initialised.lock() ? ;
+        if *initialised { return Ok(()); }
+        if self.spi_num < 0 || self.spi_num as usize >= SPI_PORT_COUNT { return Err(MynewtError::SYS_EINVAL); }
+        if self.queue_depth == 0 || self.buffer_size > MAX_REQUEST_SIZE { return Err(MynewtError::SYS_EINVAL); }
+        start_spi_task() ? ;
+        {
+            //  Configure SPI port for non-blocking SPI. Lock the port, which may be shared with other SPI devices.
+            let mut bus = lock_spi_bus(self.spi_num, &self.config) ? ;
+
+            //  Callback may only be set while the SPI port is disabled
+            unsafe { hal::hal_spi_disable(self.spi_num) };
+            let done = &SPI_DONE[self.spi_num as usize] as *const Semaphore as Ptr;
+            let rc = unsafe { hal::hal_spi_set_txrx_cb(
+                self.spi_num,
+                Some(spi_noblock_handler),
+                done
+            ) };
+            let rc = if rc == 0 { unsafe { hal::hal_spi_enable(self.spi_num) } }
+                     else { rc };
+            if rc != 0 {
+                *bus = None;  //  Port is disabled, so reconfigure the port when it's next locked
+                return Err(MynewtError::SYS_EIO);
+            }
+        }
 
-/// Stack space for SPI Task, initialised to 0.
-static mut SPI_TASK_STACK: [os::os_stack_t; SPI_TASK_STACK_SIZE] = 
-    [0; SPI_TASK_STACK_SIZE];
+        //  Set SS and DC to high to disable SPI device
+        let rc = unsafe { hal::hal_gpio_init_out(self.cs_pin, 1) };
+        if rc != 0 { return Err(MynewtError::SYS_EINVAL); }
+        if let Some(dc_pin) = self.dc_pin {
+            let rc = unsafe { hal::hal_gpio_init_out(dc_pin, 1) };
+            if rc != 0 { return Err(MynewtError::SYS_EINVAL); }
+        }
 
-/// Size of the stack (in 4-byte units). Previously `OS_STACK_ALIGN(256)`  
-const SPI_TASK_STACK_SIZE: usize = 256;
-//  TODO: Get this constant from Mynewt
-const OS_TICKS_PER_SEC: u32 = 1000;
+        //  Create the Mbuf Queue that will store the SPI requests for the device
+        self.queue.init(
+            Some(spi_event_callback),
+            self as *const NonBlockingSpi as Ptr
+        ) ? ;
+        *initialised = true;
+        Ok(())
+    }
 
-/// Non-blocking SPI transfer callback parameter (not used)
-struct SpiCallback {}
+    /// Set pending request for non-blocking SPI write for Command Byte. Any pending request is enqueued first.
+    /// Returns without waiting for write to complete.
+    pub fn write_command(&self, cmd: u8) -> MynewtResult<()> {
+        self.write_command_with_delay(cmd, 0)
+    }
 
-/// Non-blocking SPI transfer callback values (not used)
-static mut SPI_CALLBACK: SpiCallback = SpiCallback {};
+    /// Same as `write_command()`, but the SPI task will sleep for `delay_ms` milliseconds after writing the Command Byte,
+    /// before writing the Data Bytes. For commands like display reset that need time to complete.
+    pub fn write_command_with_delay(&self, cmd: u8, delay_ms: u16) -> MynewtResult<()> {
+        let mut pending = self.pending.lock() ? ;
+        //  If there is a pending request, enqueue it. If an earlier request has failed, start the new request anyway,
+        //  so that the Data Bytes that follow won't be sent without the Command Byte.
+        let res = self.flush_pending(&mut pending, None);
+        //  Start a new pending request with the Command Byte.
+        let header = RequestHeader { command: Some(cmd), delay_ms, ..RequestHeader::new() };
+        let next = new_request(&header).map(|mbuf| { *pending = Some(mbuf); });
+        self.keep_earlier_error(res, next)
+    }
 
-/// Init non-blocking SPI transfer
-pub fn spi_noblock_init() -> MynewtResult<()> {
-    //  Disable SPI port
-    unsafe { hal::hal_spi_disable(SPI_NUM) };
-
-    //  Configure SPI port for non-blocking SPI
-    let rc = unsafe { hal::hal_spi_config(SPI_NUM, &mut SPI_SETTINGS) }; assert_eq!(rc, 0, "spi config fail");  //  TODO: Map to MynewtResult
-    let arg = unsafe { core::mem::transmute(&mut SPI_CALLBACK) };
-    let rc = unsafe { hal::hal_spi_set_txrx_cb(
-        SPI_NUM, 
-        Some(spi_noblock_handler), 
-        arg
-    ) };
-    assert_eq!(rc, 0, "spi cb fail");  //  TODO: Map to MynewtResult
-
-    //  Enable SPI port and set SS to high to disable SPI device
-    let rc = unsafe { hal::hal_spi_enable(SPI_NUM) }; assert_eq!(rc, 0, "spi enable fail");  //  TODO: Map to MynewtResult
-    let rc = unsafe { hal::hal_gpio_init_out(SPI_SS_PIN, 1) }; assert_eq!(rc, 0, "gpio fail");  //  TODO: Map to MynewtResult
-    let rc = unsafe { hal::hal_gpio_init_out(SPI_DC_PIN, 1) }; assert_eq!(rc, 0, "gpio fail");  //  TODO: Map to MynewtResult
-
-    //  Create Event Queue and Mbuf (Data) Queue that will store the SPI requests
+    /// Append Data Bytes to the pending request. If there is no pending request, start a request without Command Byte.
+    /// Returns without waiting for write to complete. Returns `SYS_ERANGE` if the request would exceed the buffer size,
+    /// keeping the pending request. Returns `SYS_ENOMEM` if out of mbufs, discarding the pending request.
+    pub fn write_data(&self, data: &[u8]) -> MynewtResult<()> {
+        let mut pending = self.pending.lock() ? ;
+        if pending.is_none() {
+            *pending = Some(new_request(&RequestHeader::new()) ?);
+        }
+        if let Some(mbuf) = pending.as_mut() {
+            if mbuf.len() - REQUEST_HEADER_SIZE + data.len() > self.buffer_size {
+                return Err(MynewtError::SYS_ERANGE);
+            }
+            //  Append Data Bytes to the pending request. In case of error, discard the partial request.
+            if let Err(err) = mbuf.append(data) {
+                *pending = None;
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Enqueue any pending request for non-blocking SPI write. Returns without waiting for write to complete.
+    /// Returns the error of the first request that has failed since the last `flush()`, if the failed request had no callback.
+    pub fn flush(&self) -> MynewtResult<()> {
+        self.flush_with_callback(None)
+    }
+
+    /// Enqueue any pending request for non-blocking SPI write. `callback` will be called by the SPI task
+    /// after the request has been sent. Returns without waiting for write to complete.
+    pub fn flush_with_callback(&self, callback: Option<SpiCompletion>) -> MynewtResult<()> {
+        let mut pending = self.pending.lock() ? ;
+        self.flush_pending(&mut pending, callback)
+    }
+
+    /// Enqueue request for non-blocking SPI write: optional Command Byte, followed by Data Bytes. Any pending request
+    /// is enqueued first. `callback` will be called by the SPI task after the request has been sent.
+    /// Returns without waiting for write to complete. Returns `SYS_ERANGE` if the data exceeds the buffer size.
+    pub fn write(&self, cmd: Option<u8>, data: &[u8], callback: Option<SpiCompletion>) -> MynewtResult<()> {
+        //  Enqueue the request even if an earlier request has failed, then return the earlier error.
+        let mut pending = self.pending.lock() ? ;
+        let res = self.flush_pending(&mut pending, None);
+        let next = self.enqueue_write(cmd, data, callback);
+        self.keep_earlier_error(res, next)
+    }
+
+    /// Return a Future that completes when all enqueued requests have been sent. Like `flush()`, the Future returns
+    /// the error of the first failed request. Only one Future should wait on the device at a time.
+    pub fn wait_idle(&self) -> SpiIdle {
+        SpiIdle { spi: self }
+    }
+
+    /// Return the max number of Data Bytes per request
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    /// Set the retry and abort policy for failed requests. The default is `DEFAULT_ERROR_POLICY`.
+    pub fn set_error_policy(&self, policy: SpiErrorPolicy) {
+        critical_section(|| self.policy.set(policy));
+    }
+
+    /// Return the counters for sent and failed requests
+    pub fn stats(&self) -> SpiStats {
+        SpiStats {
+            sent:     self.counters.sent.load(Ordering::Relaxed),
+            failures: self.counters.failures.load(Ordering::Relaxed),
+            retries:  self.counters.retries.load(Ordering::Relaxed),
+            aborted:  self.counters.aborted.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Enqueue the pending request, if any, with the callback. The caller must hold the `pending` lock.
+    fn flush_pending(&self, pending: &mut Option<Mbuf>, callback: Option<SpiCompletion>) -> MynewtResult<()> {
+        //  Fetch the error from the SPI task before enqueueing, so that the pending request won't be aborted.
+        let res = self.take_error();
+        //  If there is a pending request, enqueue it.
+        let next = match pending.take() {
+            Some(mbuf) => self.enqueue(mbuf, callback),
+            None       => Ok(()),
+        };
+        self.keep_earlier_error(res, next)
+    }
+
+    /// Return the error `earlier` that was fetched by `take_error()`, unless the next step `next` has failed.
+    /// If both have failed, return the error of `next` and keep the earlier error for the next `flush()`.
+    fn keep_earlier_error(&self, earlier: MynewtResult<()>, next: MynewtResult<()>) -> MynewtResult<()> {
+        if next.is_err() {
+            if let Err(err) = earlier { self.set_error(err); }
+            return next;
+        }
+        earlier
+    }
+
+    /// Enqueue a new request with the optional Command Byte and Data Bytes. Returns `SYS_ERANGE` if the data
+    /// exceeds the buffer size.
+    fn enqueue_write(&self, cmd: Option<u8>, data: &[u8], callback: Option<SpiCompletion>) -> MynewtResult<()> {
+        if data.len() > self.buffer_size { return Err(MynewtError::SYS_ERANGE); }
+        let header = RequestHeader { command: cmd, ..RequestHeader::new() };
+        let mut mbuf = new_request(&header) ? ;
+        mbuf.append(data) ? ;
+        self.enqueue(mbuf, callback)
+    }
+
+    /// Return and clear the error of the first failed request since the last call. Requests enqueued after this
+    /// call belong to a new generation, which is not discarded because of the earlier failure.
+    fn take_error(&self) -> MynewtResult<()> {
+        let abort_before = self.abort_before.load(Ordering::SeqCst);
+        if is_older(self.generation.load(Ordering::SeqCst), abort_before) {
+            self.generation.store(abort_before, Ordering::SeqCst);
+        }
+        match self.error.swap(0, Ordering::SeqCst) {
+            0  => Ok(()),
+            rc => Err(MynewtError::from(rc)),
+        }
+    }
+
+    /// Remember the error of a failed request, unless an earlier error has not been returned yet
+    fn set_error(&self, err: MynewtError) {
+        self.error.compare_exchange(0, err as i32, Ordering::SeqCst, Ordering::SeqCst).ok();
+    }
+
+    /// Send the request, retrying according to the policy. Called by the SPI task.
+    fn process_request(&self, header: &RequestHeader, mbuf: &Mbuf) -> MynewtResult<()> {
+        let policy = critical_section(|| self.policy.get());
+
+        //  Discard the request if an earlier request of the same generation has failed, i.e. the request was
+        //  enqueued before the failure was returned by `flush()`.
+        if is_older(header.generation, self.abort_before.load(Ordering::SeqCst)) {
+            SpiCounters::increment(&self.counters.aborted);
+            return Err(MynewtError::SYS_EAGAIN);
+        }
+
+        //  Send the request. If it fails, resend the request.
+        let mut res = self.send_request(header, mbuf);
+        let mut retries = 0;
+        while res.is_err() && retries < policy.retries {
+            retries += 1;
+            SpiCounters::increment(&self.counters.retries);
+            SPI_WATCH.checkin().ok();  //  Restart the deadline for the retry
+            res = self.send_request(header, mbuf);
+        }
+        match res {
+            Ok(())   => SpiCounters::increment(&self.counters.sent),
+            Err(_)   => {
+                SpiCounters::increment(&self.counters.failures);
+                if policy.on_failure == SpiFailureAction::Abort {
+                    //  Discard the remaining requests of this generation. Never move the abort point backwards.
+                    let next = header.generation.wrapping_add(1);
+                    let abort_before = self.abort_before.load(Ordering::SeqCst);
+                    if is_older(abort_before, next) { self.abort_before.store(next, Ordering::SeqCst); }
+                }
+            }
+        }
+        res
+    }
+
+    /// Add the request to the Mbuf Queue and trigger an event in the SPI Event Queue. The request is freed upon error.
+    fn enqueue(&self, mut mbuf: Mbuf, callback: Option<SpiCompletion>) -> MynewtResult<()> {
+        //  Stamp the generation and callback into the request header.
+        let mut header = RequestHeader::read(&mbuf);
+        header.generation = self.generation.load(Ordering::SeqCst);
+        header.callback   = callback;
+        header.write(&mut mbuf) ? ;
+
+        //  Throttle the number of queued SPI requests. Don't wait longer than the deadline of the calling task.
+        self.throttle.pend(kernel::ms_to_ticks(SPI_ENQUEUE_TIMEOUT)) ? ;
+
+        let res = self.queue.put(
+            unsafe { &mut SPI_EVENT_QUEUE },
+            SpiRequest { mbuf }
+        );
+        if res.is_err() {  //  If out of memory, quit.
+            self.throttle.release() ? ;  //  Release the throttle
+        }
+        res
+    }
+
+    /// Send the request. Called by the SPI task.
+    fn send_request(&self, header: &RequestHeader, mbuf: &Mbuf) -> MynewtResult<()> {
+        //  Lock the SPI port, which may be shared with other SPI devices like the SPI flash. Unlocked when returning.
+        let _bus = lock_spi_bus(self.spi_num, &self.config) ? ;
+
+        //  Write the Command Byte, followed by the delay if any.
+        if let Some(cmd) = header.command {
+            self.internal_spi_noblock_write(&[cmd], true) ? ;
+            if header.delay_ms > 0 { delay_ms(header.delay_ms); }
+        }
+
+        //  Write the Data Bytes in each mbuf of the chain, skipping the request header.
+        let mut skip = REQUEST_HEADER_SIZE;
+        for data in mbuf.segments() {
+            let start = core::cmp::min(skip, data.len());
+            skip -= start;
+            self.internal_spi_noblock_write(&data[start..], false) ? ;
+        }
+        Ok(())
+    }
+
+    /// Perform non-blocking SPI write in Mynewt OS.  Blocks until SPI write completes.
+    fn internal_spi_noblock_write(&self, buf: &[u8], is_command: bool) -> MynewtResult<()> {
+        let len = buf.len() as i32;
+        if len == 0 { return Ok(()); }
+
+        //  If this is a Command Byte, set DC Pin to low, else set DC Pin to high.
+        if let Some(dc_pin) = self.dc_pin {
+            unsafe { hal::hal_gpio_write(
+                dc_pin,
+                if is_command { 0 }
+                else { 1 }
+            ) };
+        }
+
+        //  Set the SS Pin to low to start the transfer.
+        unsafe { hal::hal_gpio_write(self.cs_pin, 0) };
+
+        if len == 1 {  //  If writing only 1 byte...
+            //  From https://github.com/apache/mynewt-core/blob/master/hw/mcu/nordic/nrf52xxx/src/hal_spi.c#L1106-L1118
+            //  There is a known issue in nRF52832 with sending 1 byte in SPIM mode that
+            //  it clocks out additional byte. For this reason, let us use SPI mode for such a write.
+            //  Write the SPI byte the blocking way.
+            let rc = unsafe { hal::hal_spi_txrx(
+                self.spi_num,
+                buf.as_ptr() as Ptr,  //  TX Buffer
+                NULL,     //  RX Buffer (don't receive)
+                len) };
+
+            //  Set SS Pin to high to stop the transfer, even if the transfer has failed.
+            unsafe { hal::hal_gpio_write(self.cs_pin, 1) };
+            if rc != 0 { return Err(MynewtError::SYS_EIO); }
+
+        } else {  //  If writing more than 1 byte...
+            //  Write the SPI data the non-blocking way.  Will call spi_noblock_handler() after writing.
+            let rc = unsafe { hal::hal_spi_txrx_noblock(
+                self.spi_num,
+                buf.as_ptr() as Ptr,  //  TX Buffer
+                NULL,     //  RX Buffer (don't receive)
+                len) };
+            if rc != 0 {
+                unsafe { hal::hal_gpio_write(self.cs_pin, 1) };
+                return Err(MynewtError::SYS_EIO);
+            }
+
+            //  Wait for spi_noblock_handler() to signal that SPI request has been completed. Check in first, so that
+            //  the transfer times out before the SPI task deadline.
+            SPI_WATCH.checkin().ok();  //  Never fails after `register()`
+            let done = &SPI_DONE[self.spi_num as usize];
+            let res = done.pend(kernel::ms_to_ticks(SPI_TRANSFER_TIMEOUT));
+            if res.is_err() {
+                //  Stop the transfer that has timed out. If spi_noblock_handler() was called after the timeout,
+                //  consume its signal so that the retry won't complete before its transfer.
+                unsafe { hal::hal_spi_abort(self.spi_num) };
+                while done.pend(0).is_ok() {}
+            }
+
+            //  Set SS Pin to high to stop the transfer, even if the wait has timed out.
+            unsafe { hal::hal_gpio_write(self.cs_pin, 1) };
+            return res;
+        }
+        Ok(())
+    }
+}
+
+///  Allow tasks to share the device. The pending request is protected by a Mutex, the queue and throttle are thread-safe.
+unsafe impl Sync for NonBlockingSpi {}
+
+/// Future returned by `NonBlockingSpi::wait_idle()`
+pub struct SpiIdle<'a> {
+    /// Device to be awaited
+    spi: &'a NonBlockingSpi,
+}
+
+impl<'a> Future for SpiIdle<'a> {
+    type Output = MynewtResult<()>;
+
+    /// Complete when the throttle has all its tokens, i.e. no requests are queued or being sent
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<MynewtResult<()>> {
+        //  Register the Waker before checking, so that we won't miss a completed request
+        self.spi.idle_waker.register(cx.waker());
+        if self.spi.throttle.tokens() >= self.spi.queue_depth { Poll::Ready(self.spi.take_error()) }
+        else { Poll::Pending }
+    }
+}
+
+/// Size of the header at the start of each request: flags, Command Byte, delay, generation and completion callback
+const REQUEST_HEADER_SIZE: usize = 8 + core::mem::size_of::<usize>();
+
+/// Flag in the request header that is set if the request has a Command Byte
+const REQUEST_HAS_COMMAND: u8 = 1;
+
+/// Header at the start of each request, stored in the mbuf chain before the Data Bytes
+#[derive(Clone, Copy)]
+struct RequestHeader {
+    /// Command Byte to be sent before the Data Bytes
+    command:  Option<u8>,
+    /// Milliseconds to sleep after sending the Command Byte
+    delay_ms: u16,
+    /// Generation of the device when the request was enqueued
+    generation: u32,
+    /// Function to be called after the request has been sent
+    callback: Option<SpiCompletion>,
+}
+
+impl RequestHeader {
+    /// Create a header for a request without Command Byte
+    const fn new() -> Self {
+        RequestHeader { command: None, delay_ms: 0, generation: 0, callback: None }
+    }
+
+    /// Read the header at the start of the mbuf chain
+    fn read(mbuf: &Mbuf) -> Self {
+        let mut bytes = [0u8; REQUEST_HEADER_SIZE];
+        mbuf.copy_to(0, &mut bytes).ok();  //  Chain always starts with the header
+        let mut callback = [0u8; core::mem::size_of::<usize>()];
+        callback.copy_from_slice(&bytes[8..]);
+        let callback = usize::from_le_bytes(callback);
+        RequestHeader {
+            command:  if bytes[0] & REQUEST_HAS_COMMAND != 0 { Some(bytes[1]) } else { None },
+            delay_ms: u16::from_le_bytes([bytes[2], bytes[3]]),
+            generation: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            callback: if callback == 0 { None }
+                      else { Some(unsafe { core::mem::transmute::<usize, SpiCompletion>(callback) }) },
+        }
+    }
+
+    /// Overwrite the header at the start of the mbuf chain
+    fn write(&self, mbuf: &mut Mbuf) -> MynewtResult<()> {
+        mbuf.copy_from(0, &self.to_bytes())
+    }
+
+    /// Convert the header to bytes
+    fn to_bytes(&self) -> [u8; REQUEST_HEADER_SIZE] {
+        let mut bytes = [0u8; REQUEST_HEADER_SIZE];
+        if let Some(cmd) = self.command {
+            bytes[0] = REQUEST_HAS_COMMAND;
+            bytes[1] = cmd;
+        }
+        bytes[2..4].copy_from_slice(&self.delay_ms.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.generation.to_le_bytes());
+        let callback = self.callback.map_or(0, |callback| callback as usize);
+        bytes[8..].copy_from_slice(&callback.to_le_bytes());
+        bytes
+    }
+}
+
+/// Return true if generation `a` is older than generation `b`, allowing the generations to wrap around
+fn is_older(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// Allocate a new mbuf chain for a request, starting with the request header
+fn new_request(header: &RequestHeader) -> MynewtResult<Mbuf> {
+    let mut mbuf = Mbuf::new_pkthdr(REQUEST_HEADER_SIZE as u16) ? ;
+    mbuf.append(&header.to_bytes()) ? ;
+    Ok(mbuf)
+}
+
+/// SPI request passed through the Mbuf Queue: the request header, followed by the Data Bytes
+struct SpiRequest {
+    /// Mbuf chain that contains the request
+    mbuf: Mbuf,
+}
+
+impl MbufPacket for SpiRequest {
+    fn into_mbuf(self) -> Mbuf { self.mbuf }
+    fn from_mbuf(mbuf: Mbuf) -> Self { SpiRequest { mbuf } }
+}
+
+/// Start the SPI task and its Event Queue, if the task is not running
+fn start_spi_task() -> MynewtResult<()> {
+    //  Lock until the task is started, so that other devices won't use the Event Queue before it's initialised.
+    let mut started = SPI_TASK_STARTED.lock() ? ;
+    if *started { return Ok(()); }
+
+    //  Create Event Queue that will store the SPI requests
     unsafe { os::os_eventq_init(&mut SPI_EVENT_QUEUE) };
-    let rc = unsafe { os::os_mqueue_init(
-        &mut SPI_DATA_QUEUE, 
-        Some(spi_event_callback), 
-        NULL
-    ) };
-    assert_eq!(rc, 0, "mqueue fail");  //  TODO: Map to MynewtResult
-
-    //  Create the Semaphore that will signal whether the SPI request has completed
-    let rc = unsafe { os::os_sem_init(&mut SPI_SEM, 0) };  //  Init to 0 tokens, so caller will block until SPI request is completed.
-    assert_eq!(rc, 0, "sem fail");  //  TODO: Map to MynewtResult
-
-    //  Create the Semaphore that will throttle the number of queued SPI requests
-    let rc = unsafe { os::os_sem_init(&mut SPI_THROTTLE_SEM, 2) };  //  Only max 2 requests queued, the next request will block
-    assert_eq!(rc, 0, "sem fail");  //  TODO: Map to MynewtResult
-    
-    //  Create a task to send SPI requests sequentially from the SPI Event Queue and Mbuf Queue
-    os::task_init(                //  Create a new task and start it...
-        unsafe { &mut SPI_TASK }, //  Task object will be saved here
-        &init_strn!( "spi" ),     //  Name of task
-        Some( spi_task_func ),    //  Function to execute when task starts
-        NULL,  //  Argument to be passed to above function
-        10,    //  Task priority: highest is 0, lowest is 255 (main task is 127)
-        os::OS_WAIT_FOREVER as u32,     //  Don't do sanity / watchdog checking
-        unsafe { &mut SPI_TASK_STACK }, //  Stack space for the task
-        SPI_TASK_STACK_SIZE as u16      //  Size of the stack (in 4-byte units)
-    ) ? ;                               //  `?` means check for error
+
+    //  Create a task to send SPI requests sequentially from the SPI Event Queue and Mbuf Queues
+    task!(                      //  Create a new task and start it...
+        name:     "spi",        //  Name of task
+        func:     spi_task_func,  //  Function to execute when task starts
+        priority: 10,           //  Task priority: highest is 0, lowest is 255 (main task is 127)
+        stack:    256,          //  Size of the stack (in 4-byte units). Previously `OS_STACK_ALIGN(256)`
+    ) ? ;                       //  `?` means check for error. If the task wasn't created, retry at the next `init()`
+    *started = true;
+
+    //  Supervise the SPI task, so that we know which task hung when the watchdog resets
+    SPI_WATCH.register(SPI_TASK_DEADLINE) ? ;
     Ok(())
 }
 
 /// SPI Task Function.  Execute sequentially each SPI request posted to our Event Queue.  When there are no requests to process, block until one arrives.
 extern "C" fn spi_task_func(_arg: Ptr) {
     loop {
-        //  Forever read SPI requests and execute them. Will call spi_event_callback().
-        os::eventq_run(
-            unsafe { &mut SPI_EVENT_QUEUE }
-        ).expect("eventq fail");
+        //  Forever read SPI requests and execute them. Don't supervise the task while waiting for requests.
+        SPI_WATCH.sleep();
+        let event = unsafe { os::os_eventq_get(&mut SPI_EVENT_QUEUE) };
+
+        //  Supervise the task while executing the request. Will call spi_event_callback().
+        SPI_WATCH.checkin().ok();  //  Never fails after `register()`
+        unsafe {
+            if let Some(callback) = (*event).ev_cb { callback(event); }
+        }
 
         //  Tickle the watchdog so that the Watchdog Timer doesn't expire. Mynewt assumes the process is hung if we don't tickle the watchdog.
-        unsafe { hal_watchdog_tickle() };
+        watchdog::tickle();
     }
 }
 
-/// Set pending request for non-blocking SPI write for Command Byte. Returns without waiting for write to complete.
-pub fn spi_noblock_write_command(cmd: u8) -> MynewtResult<()> {
-    //  If there is a pending Command Byte, enqueue it.
-    spi_noblock_write_flush() ? ;
-    //  Set the pending Command Byte.
-    if unsafe { PENDING_CMD.push(cmd).is_err() } {
-        return Err(MynewtError::SYS_EINVAL);
+/// Callback for the event that is triggered when an SPI request is added to the queue of a device.
+extern "C" fn spi_event_callback(event: *mut os::os_event) {
+    let spi = unsafe { &*((*event).ev_arg as *const NonBlockingSpi) };
+    //  For each SPI request found, stored as an mbuf chain...
+    while let Some(request) = spi.queue.get() {
+        //  Send the request and free the entire mbuf chain.
+        let header = RequestHeader::read(&request.mbuf);
+        let res = spi.process_request(&header, &request.mbuf);
+        drop(request);
+
+        //  Report the result to the callback. If the request has no callback, return the error in the next `flush()`.
+        match header.callback {
+            Some(callback) => callback(res),
+            None           => if let Err(err) = res { spi.set_error(err); },
+        }
+
+        //  Release the throttle semaphore to allow next request to be queued. Only fails if the semaphore is invalid.
+        if let Err(err) = spi.throttle.release() { spi.set_error(err); }
+
+        //  Wake the Future that is waiting for all requests to be sent
+        spi.idle_waker.wake();
     }
-    Ok(())
 }
 
-/// Set pending request for non-blocking SPI write for Data Bytes. Returns without waiting for write to complete.
-pub fn spi_noblock_write_data(data: &[u8]) -> MynewtResult<()> {
-    assert!(unsafe { PENDING_CMD.len() } > 0, "no cmd byte");  //  Must have Command Byte before Data Bytes
-    //  if unsafe { PENDING_DATA.len() + data.len() > PENDING_DATA.capacity() } { cortex_m::asm::bkpt(); } ////
-    assert!(unsafe { PENDING_DATA.len() + data.len() <= PENDING_DATA.capacity() }, "spi overflow");
-    //  Append Data Bytes to Pending Data Bytes.
-    unsafe { PENDING_DATA.extend_from_slice(data) } ? ;
-    Ok(())
+/// Called by interrupt handler after Non-blocking SPI transfer has completed
+extern "C" fn spi_noblock_handler(arg: Ptr, _len: i32) {
+    //  Signal to internal_spi_noblock_write() that SPI request has been completed.
+    //  Can't fail for a valid semaphore. If it does, the waiting task will time out and report the error.
+    let done = unsafe { &*(arg as *const Semaphore) };
+    done.release().ok();
 }
 
-/// Enqueue any pending request for non-blocking SPI write for Command Byte and Data Bytes. Returns without waiting for write to complete.
-pub fn spi_noblock_write_flush() -> MynewtResult<()> {
-    //  If no pending request, quit.
-    if unsafe { PENDING_CMD.len() } == 0 &&
-        unsafe { PENDING_DATA.len() } == 0 {
-        return Ok(());
-    }
-    //  Enqueue the pending SPI request into the Mbuf Queue
-    if let Err(e) = spi_noblock_write(
-        unsafe { PENDING_CMD[0] },  //  Command Byte
-        unsafe { &PENDING_DATA }    //  Data Bytes
-    ) {  //  In case of error, clear the pending request and return error.        
-        unsafe { PENDING_CMD.clear() };
-        unsafe { PENDING_DATA.clear() };    
-        return Err(e);
-    }
-    //  Else clear the pending request and return Ok.
-    unsafe { PENDING_CMD.clear() };
-    unsafe { PENDING_DATA.clear() };
-    Ok(())
+/// Init non-blocking SPI transfer for the display
+pub fn spi_noblock_init() -> MynewtResult<()> {
+    DISPLAY.init()
 }
 
-/// Enqueue request for non-blocking SPI write. Returns without waiting for write to complete.
-/// Request must have a Command Byte, followed by optional Data Bytes.
-fn spi_noblock_write(cmd: u8, data: &[u8]) -> MynewtResult<()> {
-    /* Dump the SPI request
-    console::print("spi cmd "); ////
-    console::dump(&cmd, 1 as u32); console::print("\n"); ////
-    console::print("spi data "); ////
-    console::dump(data.as_ptr(), data.len() as u32); console::print("\n"); ////
-    console::flush(); */
-
-    //  Throttle the number of queued SPI requests.
-    let timeout = 30_000;
-    unsafe { os::os_sem_pend(&mut SPI_THROTTLE_SEM, timeout * OS_TICKS_PER_SEC / 1000) };
-
-    //  Allocate a new mbuf chain to copy the data to be sent.
-    let len = data.len() as u16 + 1;  //  1 Command Byte + Multiple Data Bytes
-    let mbuf = unsafe { os::os_msys_get_pkthdr(len, 0) };
-    if mbuf.is_null() {  //  If out of memory, quit.
-        unsafe { os::os_sem_release(&mut SPI_THROTTLE_SEM) };  //  Release the throttle
-        return Err(MynewtError::SYS_ENOMEM); 
-    }
-
-    //  Append the Command Byte to the mbuf chain.
-    let rc = unsafe { os::os_mbuf_append(
-        mbuf, 
-        core::mem::transmute(&cmd), 
-        1
-    ) };
-    if rc != 0 {  //  If out of memory, quit.
-        unsafe { os::os_mbuf_free_chain(mbuf) };               //  Deallocate the mbuf chain
-        unsafe { os::os_sem_release(&mut SPI_THROTTLE_SEM) };  //  Release the throttle
-        return Err(MynewtError::SYS_ENOMEM); 
-    }
-
-    //  Append the Data Bytes to the mbuf chain.  This may increase the number of mbufs in the chain.
-    let rc = unsafe { os::os_mbuf_append(
-        mbuf, 
-        core::mem::transmute(data.as_ptr()), 
-        data.len() as u16
-    ) };
-    if rc != 0 {  //  If out of memory, quit.
-        unsafe { os::os_mbuf_free_chain(mbuf) };               //  Deallocate the mbuf chain
-        unsafe { os::os_sem_release(&mut SPI_THROTTLE_SEM) };  //  Release the throttle
-        return Err(MynewtError::SYS_ENOMEM); 
-    }
-
-    //  Add the mbuf to the SPI Mbuf Queue and trigger an event in the SPI Event Queue.
-    let rc = unsafe { os::os_mqueue_put(
-        &mut SPI_DATA_QUEUE, 
-        &mut SPI_EVENT_QUEUE, 
-        mbuf
-    ) };
-    if rc != 0 {  //  If out of memory, quit.
-        unsafe { os::os_mbuf_free_chain(mbuf) };               //  Deallocate the mbuf chain
-        unsafe { os::os_sem_release(&mut SPI_THROTTLE_SEM) };  //  Release the throttle
-        return Err(MynewtError::SYS_EUNKNOWN); 
-    }
-    Ok(())
+/// Set pending request for non-blocking SPI write to the display for Command Byte. Returns without waiting for write to complete.
+pub fn spi_noblock_write_command(cmd: u8) -> MynewtResult<()> {
+    DISPLAY.write_command_with_delay(cmd, display_command_delay(cmd))
 }
 
-/// Callback for the event that is triggered when an SPI request is added to the queue.
-extern "C" fn spi_event_callback(_event: *mut os::os_event) {    
-    loop {  //  For each mbuf chain found...
-        //  Get the next SPI request, stored as an mbuf chain.
-        let om = unsafe { os::os_mqueue_get(&mut SPI_DATA_QUEUE) };
-        if om.is_null() { break; }
-
-        //  Send the mbuf chain.
-        let mut m = om;
-        let mut first_byte = true;
-        while !m.is_null() {  //  For each mbuf in the chain...
-            let data = unsafe { (*m).om_data };  //  Fetch the data
-            let len = unsafe { (*m).om_len };    //  Fetch the length
-            if first_byte {  //  First byte of the mbuf chain is always Command Byte
-                first_byte = false;
-                //  Write the Command Byte.
-                internal_spi_noblock_write(
-                    unsafe { core::mem::transmute(data) }, 
-                    1 as i32,          //  Write 1 Command Byte
-                    true
-                ).expect("int spi fail");
-
-                //  These commands require a delay. TODO: Move to caller
-                if  unsafe { *data } == 0x01 || //  SWRESET
-                    unsafe { *data } == 0x11 || //  SLPOUT
-                    unsafe { *data } == 0x29 {  //  DISPON
-                    delay_ms(200);
-                }
+/// Set pending request for non-blocking SPI write to the display for Data Bytes. Returns without waiting for write to complete.
+pub fn spi_noblock_write_data(data: &[u8]) -> MynewtResult<()> {
+    DISPLAY.write_data(data)
+}
 
-                //  Then write the Data Bytes.
-                internal_spi_noblock_write(
-                    unsafe { core::mem::transmute(data.add(1)) }, 
-                    (len - 1) as i32,  //  Then write 0 or more Data Bytes
-                    false
-                ).expect("int spi fail");
-
-            } else {  //  Second and subsequently mbufs in the chain are all Data Bytes
-                //  Write the Data Bytes.
-                internal_spi_noblock_write(
-                    unsafe { core::mem::transmute(data) }, 
-                    len as i32,  //  Write all Data Bytes
-                    false
-                ).expect("int spi fail");
-            }
-            m = unsafe { (*m).om_next.sle_next };  //  Fetch next mbuf in the chain.
-        }
-        //  Free the entire mbuf chain.
-        unsafe { os::os_mbuf_free_chain(om) };
+/// Enqueue any pending request for non-blocking SPI write to the display. Returns without waiting for write to complete.
+/// Returns the error of the first request that has failed since the last flush.
+pub fn spi_noblock_write_flush() -> MynewtResult<()> {
+    DISPLAY.flush()
+}
 
-        //  Release the throttle semaphore to allow next request to be queued.
-        let rc = unsafe { os::os_sem_release(&mut SPI_THROTTLE_SEM) };
-        assert_eq!(rc, 0, "sem fail");    
-    }
+/// Set the retry and abort policy for failed requests to the display
+pub fn spi_noblock_set_error_policy(policy: SpiErrorPolicy) {
+    DISPLAY.set_error_policy(policy);
 }
 
-/// Perform non-blocking SPI write in Mynewt OS.  Blocks until SPI write completes.
-fn internal_spi_noblock_write(buf: &'static u8, len: i32, is_command: bool) -> MynewtResult<()> {
-    if len == 0 { return Ok(()); }
-    assert!(len > 0, "bad spi len");
-
-    //  If this is a Command Byte, set DC Pin to low, else set DC Pin to high.
-    unsafe { hal::hal_gpio_write(
-        SPI_DC_PIN,
-        if is_command { 0 }
-        else { 1 }
-    ) };
-
-    //  Set the SS Pin to low to start the transfer.
-    unsafe { hal::hal_gpio_write(SPI_SS_PIN, 0) };
-
-    if len == 1 {  //  If writing only 1 byte...
-        //  From https://github.com/apache/mynewt-core/blob/master/hw/mcu/nordic/nrf52xxx/src/hal_spi.c#L1106-L1118
-        //  There is a known issue in nRF52832 with sending 1 byte in SPIM mode that
-        //  it clocks out additional byte. For this reason, let us use SPI mode for such a write.
-        //  Write the SPI byte the blocking way.
-        let rc = unsafe { hal::hal_spi_txrx(
-            SPI_NUM, 
-            core::mem::transmute(buf), //  TX Buffer
-            NULL,     //  RX Buffer (don't receive)        
-            len) };
-        assert_eq!(rc, 0, "spi fail");  //  TODO: Map to MynewtResult
-
-    } else {  //  If writing more than 1 byte...
-        //  Write the SPI data the non-blocking way.  Will call spi_noblock_handler() after writing.
-        let rc = unsafe { hal::hal_spi_txrx_noblock(
-            SPI_NUM, 
-            core::mem::transmute(buf), //  TX Buffer
-            NULL,     //  RX Buffer (don't receive)        
-            len) };
-        assert_eq!(rc, 0, "spi fail");  //  TODO: Map to MynewtResult
-
-        //  Wait for spi_noblock_handler() to signal that SPI request has been completed. Timeout in 30 seconds.
-        let timeout = 30_000;
-        unsafe { os::os_sem_pend(&mut SPI_SEM, timeout * OS_TICKS_PER_SEC / 1000) };
-    }
-
-    //  Set SS Pin to high to stop the transfer.
-    unsafe { hal::hal_gpio_write(SPI_SS_PIN, 1) };
-    Ok(())
+/// Return the counters for sent and failed requests to the display
+pub fn spi_noblock_stats() -> SpiStats {
+    DISPLAY.stats()
 }
 
-/// Called by interrupt handler after Non-blocking SPI transfer has completed
-extern "C" fn spi_noblock_handler(_arg: Ptr, _len: i32) {
-    //  Signal to internal_spi_noblock_write() that SPI request has been completed.
-    let rc = unsafe { os::os_sem_release(&mut SPI_SEM) };
-    assert_eq!(rc, 0, "sem fail");
+/// Return the number of milliseconds to sleep after sending the display command
+fn display_command_delay(cmd: u8) -> u16 {
+    match cmd {
+        0x01 |  //  SWRESET
+        0x11 |  //  SLPOUT
+        0x29    //  DISPON
+            => 200,
+        _   => 0,
+    }
 }
 
 /// Sleep for the specified number of milliseconds
-fn delay_ms(ms: u8) {
-    let delay_ticks = (ms as u32) * OS_TICKS_PER_SEC / 1000;
+fn delay_ms(ms: u16) {
+    let delay_ticks = kernel::ms_to_ticks(ms as u32);
     unsafe { os::os_time_delay(delay_ticks) };
 }
 
-//  TODO: Move this to Mynewt library
-extern "C" { 
-    /// Tickles the watchdog so that the Watchdog Timer doesn't expire. This needs to be done periodically, before the value configured in hal_watchdog_init() expires.
-    fn hal_watchdog_tickle(); 
-}
-
 /* Original mbuf code in C
     static struct os_mbuf *mbuf = NULL;
 
@@ -1,3 +1,10 @@
 //! Mynewt System API for Rust
 
 pub mod console;  // Export `sys/console.rs` as Rust module `mynewt::sys::console`
+
+pub mod ram_log;  // Export `sys/ram_log.rs` as Rust module `mynewt::sys::ram_log`
+
+pub mod crash;  // Export `sys/crash.rs` as Rust module `mynewt::sys::crash`
+
+#[cfg(all(feature = "dispatch", not(feature = "simulator")))]  //  With dispatch, except in the simulator...
+pub mod dispatch;  // Export `sys/dispatch.rs` as Rust module `mynewt::sys::dispatch`
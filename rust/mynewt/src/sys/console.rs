@@ -1,71 +1,143 @@
-//! Display messages on Arm Semihosting Console (via OpenOCD)
+//! Display messages on Arm Semihosting Console (via OpenOCD). Formatted output is supported by `Console`,
+//! which implements `core::fmt::Write`, and by the macros `println!()` and `log!()`:
+//! ```
+//! println!("x: {}, y: {}", x, y);
+//! debug!("touch {}", count);  //  Removed at compile time if `LOG_LEVEL` is lower than `Debug`
+//! ```
+//! Each module that calls `error!()`, `warn!()`, `info!()`, `debug!()` or `log!()` must have `LOG_LEVEL` in scope.
+//! Use the default level with `use mynewt::sys::console::LOG_LEVEL;` or set the level for the module with
+//! `const LOG_LEVEL: Level = Level::Debug;`
 
-///  Display message `msg` on the Arm Semihosting console (via OpenOCD).
-#[cfg(feature = "dispatch")]  //  With dispatch...
-pub fn print(msg: &str) {
-    //  Call the Semihosting Console API, which is unsafe.
-    let buf = msg.as_ptr();
-    let len = msg.len() as u32;
+use core::fmt;
+use crate::sys::ram_log;
+
+#[cfg(all(feature = "dispatch", not(feature = "simulator")))]  //  With dispatch, except in the simulator...
+use crate::sys::dispatch::get_dispatch_address;
+#[cfg(all(feature = "dispatch", not(feature = "simulator")))]  //  With dispatch, except in the simulator...
+use mynewt_macros::dispatch_hash;
 
-    //  TODO: Dispatch the Mynewt C function `console_buffer`.
-    //  TODO: Use a procedural macro to compute the hash of function name `console_buffer`.
-    let hash = 0;  //  TODO
-    //  TODO: Lookup the dispatch address of `console_buffer`.
-    let _addr = get_dispatch_address(hash);
-    //  TODO: Call the `console_buffer` function using the dispatch address.
-    unsafe { console_buffer(buf, len); }
+/// Log level for messages, from most severe to least severe
+#[derive(Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum Level {
+    /// Log nothing. Only used for `LOG_LEVEL`
+    Off   = 0,
+    /// Error that needs attention
+    Error = 1,
+    /// Unexpected condition that may be recovered
+    Warn  = 2,
+    /// Normal progress messages
+    Info  = 3,
+    /// Diagnostic messages
+    Debug = 4,
 }
 
-/// Return the Dispatch Address for the OS function whose function name hashed is `hash`
-#[cfg(feature = "dispatch")]  //  With dispatch...
-pub fn get_dispatch_address(_hash: u32) -> u32 {
-    //  TODO: Lookup the Dispatch Table for the hash of function name
-    0
+impl Level {
+    /// Return the prefix for messages at this level
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Level::Off   => "",
+            Level::Error => "ERR ",
+            Level::Warn  => "WRN ",
+            Level::Info  => "",
+            Level::Debug => "DBG ",
+        }
+    }
+}
+
+/// Highest level that will be logged by any module. Debug messages are removed from release builds.
+#[cfg(debug_assertions)]
+pub const MAX_LOG_LEVEL: Level = Level::Debug;
+
+/// Highest level that will be logged by any module. Debug messages are removed from release builds.
+#[cfg(not(debug_assertions))]
+pub const MAX_LOG_LEVEL: Level = Level::Info;
+
+/// Default log level for modules that don't declare their own `LOG_LEVEL`
+pub const LOG_LEVEL: Level = Level::Info;
+
+/// Return true if messages at `level` should be logged by a module whose log level is `module_level`.
+/// This is a `const fn` so that disabled messages are removed at compile time.
+pub const fn log_enabled(level: Level, module_level: Level) -> bool {
+    (level as u8) <= (module_level as u8) &&
+    (level as u8) <= (MAX_LOG_LEVEL as u8)
+}
+
+/// Console writer that implements `core::fmt::Write`. Output is buffered until `flush()` is called.
+/// ```
+/// use core::fmt::Write;
+/// write!(Console, "x: {}", x).ok();
+/// ```
+pub struct Console;
+
+impl fmt::Write for Console {
+    /// Add the string to the output buffer
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        buffer(s);
+        Ok(())
+    }
+}
+
+/// Write the formatted arguments to the output buffer. Called by `print!()` and `println!()`.
+pub fn print_fmt(args: fmt::Arguments) {
+    fmt::write(&mut Console, args).ok();  //  Console never fails
 }
 
 ///  Display message `msg` on the Arm Semihosting console (via OpenOCD).
-#[cfg(not(feature = "dispatch"))]  //  Previously without dispatch...
 pub fn print(msg: &str) {
-    //  Call the Semihosting Console API, which is unsafe.
-    unsafe { console_buffer(msg.as_ptr(), msg.len() as u32); }
+    buffer(msg);
 }
 
 ///  Display message `msg` on the Arm Semihosting console (via OpenOCD).
 pub fn print_strn(msg: &crate::Strn) {
+    let bytes = unsafe { core::slice::from_raw_parts(msg.as_ptr(), msg.len()) };
+    ram_log::write(bytes);
     //  Call the Semihosting Console API, which is unsafe.
     unsafe { console_buffer(msg.as_ptr(), msg.len() as u32); }
 }
 
 ///  Add the string to the output buffer.
 pub fn buffer(msg: &str) {
+    ram_log::write(msg.as_bytes());
     //  Call the Semihosting Console API, which is unsafe.
     unsafe { console_buffer(msg.as_ptr(), msg.len() as u32); }
 }
 
+///  Add the bytes to the output buffer without writing to the RAM Log. Used for dumping the RAM Log.
+pub(crate) fn buffer_raw(msg: &[u8]) {
+    unsafe { console_buffer(msg.as_ptr(), msg.len() as u32); }
+}
+
 ///  Write a byte in hexadecimal to the output buffer.
 ///  C API: `void console_printhex(uint8_t v)`
 pub fn printhex(v: u8) {
+    ram_log::write_fmt(format_args!("{:02x}", v));
     unsafe { console_printhex(v); }
 }
 
 ///  Write an int to the output buffer.
 ///  C API: `void console_printint(int i)`
 pub fn printint(v: i32) {
+    ram_log::write_fmt(format_args!("{}", v));
     unsafe { console_printint(v); }
 }
 
 ///  Write a float to the output buffer, with 2 decimal places.
 pub fn printfloat(v: f32) {
+    ram_log::write_fmt(format_args!("{:.2}", v));
     unsafe { console_printfloat(v); }
 }
 
 ///  Write a double to the output buffer, with 6 decimal places.
 pub fn printdouble(v: f64) {
+    ram_log::write_fmt(format_args!("{:.6}", v));
     unsafe { console_printdouble(v); }
 }
 
 ///  Write "length" number of bytes from "buffer" to the output buffer in hex format.
 pub fn dump(buffer: *const u8, len: u32) {
+    let bytes = unsafe { core::slice::from_raw_parts(buffer, len as usize) };
+    for b in bytes { ram_log::write_fmt(format_args!("{:02x} ", b)); }
     unsafe { console_dump(buffer, len); }
 }
 
@@ -74,9 +146,78 @@ pub fn flush() {
     unsafe { console_flush(); }
 }
 
+/// Write formatted output to the console, like `print!()` in `std`
+/// ```
+/// print!("x: {}", x);
+/// ```
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::sys::console::print_fmt(format_args!($($arg)*))
+    };
+}
+
+/// Write formatted output to the console followed by a newline, then flush the console. Like `println!()` in `std`
+/// ```
+/// println!("x: {}", x);
+/// ```
+#[macro_export]
+macro_rules! println {
+    () => {{
+        $crate::sys::console::print("\n");
+        $crate::sys::console::flush();
+    }};
+    ($($arg:tt)*) => {{
+        $crate::sys::console::print_fmt(format_args!($($arg)*));
+        $crate::sys::console::print("\n");
+        $crate::sys::console::flush();
+    }};
+}
+
+/// Write formatted output to the console if `level` is enabled for the calling module. `LOG_LEVEL` must be in scope.
+/// ```
+/// log!(Level::Warn, "i2c fail {}", rc);
+/// ```
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {{
+        let level: $crate::sys::console::Level = $level;
+        //  `LOG_LEVEL` is resolved at the caller, so each module may set its own level
+        if $crate::sys::console::log_enabled(level, LOG_LEVEL) {
+            $crate::sys::console::print(level.prefix());
+            $crate::println!($($arg)*);
+        }
+    }};
+}
+
+/// Log an error. `LOG_LEVEL` must be in scope.
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => { $crate::log!($crate::sys::console::Level::Error, $($arg)*) };
+}
+
+/// Log a warning. `LOG_LEVEL` must be in scope.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => { $crate::log!($crate::sys::console::Level::Warn, $($arg)*) };
+}
+
+/// Log a progress message. `LOG_LEVEL` must be in scope.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => { $crate::log!($crate::sys::console::Level::Info, $($arg)*) };
+}
+
+/// Log a diagnostic message. Removed from release builds. `LOG_LEVEL` must be in scope.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => { $crate::log!($crate::sys::console::Level::Debug, $($arg)*) };
+}
+
 ///  Import the custom Mynewt library for displaying messages on the Arm Semihosting Console (via OpenOCD).
 ///  The library is located at `libs/semihosting_console`
-#[link(name = "libs_semihosting_console")]  //  Functions below are located in the Mynewt build output `libs_semihosting_console.a`
+#[cfg(any(not(feature = "dispatch"), feature = "simulator"))]  //  Without dispatch or in the simulator, link directly with the OS functions
+#[cfg_attr(not(feature = "simulator"), link(name = "libs_semihosting_console"))]  //  Functions below are located in the Mynewt build output `libs_semihosting_console.a`
 extern {
     ///  Add the string to the output buffer.
     ///  C API: `void console_buffer(const char *buffer, unsigned int length)`
@@ -106,3 +247,56 @@ extern {
     ///  C API: `void console_flush(void)`
     fn console_flush();  //  Flush the output buffer to the console.
 }
+
+///  Declare a function that calls the OS function with the same name via the Dispatch Table.
+///  If the OS function is not found, the call is skipped because console output is optional.
+#[cfg(all(feature = "dispatch", not(feature = "simulator")))]  //  With dispatch, except in the simulator...
+macro_rules! dispatch_fn {
+    ( $( $(#[$attr:meta])* fn $name:ident ( $( $arg:ident : $ty:ty ),* ) ; )* ) => {
+        $(
+            $(#[$attr])*
+            unsafe fn $name( $( $arg: $ty ),* ) {
+                //  Lookup the Dispatch Table for the hash of the function name, computed at compile time
+                let addr = match get_dispatch_address(dispatch_hash!($name)) {
+                    Ok(addr) => addr,
+                    Err(_)   => return,  //  OS function not found
+                };
+                //  Call the OS function using the dispatch address
+                let func: unsafe extern "C" fn( $( $ty ),* ) = core::mem::transmute(addr as usize);
+                func( $( $arg ),* );
+            }
+        )*
+    };
+}
+
+//  With dispatch, call the OS functions in the OS firmware via the Dispatch Table
+#[cfg(all(feature = "dispatch", not(feature = "simulator")))]  //  With dispatch, except in the simulator...
+dispatch_fn! {
+    ///  Add the string to the output buffer.
+    ///  C API: `void console_buffer(const char *buffer, unsigned int length)`
+    fn console_buffer(buffer: *const u8, length: u32);
+
+    ///  Write a byte in hexadecimal to the output buffer.
+    ///  C API: `void console_printhex(uint8_t v)`
+    fn console_printhex(v: u8);
+
+    ///  Write an int i to the output buffer.
+    ///  C API: `void console_printint(int i)`
+    fn console_printint(i: i32);
+
+    ///  Write a float to the output buffer, with 2 decimal places.
+    ///  C API: `void console_printfloat(float f)`
+    fn console_printfloat(f: f32);
+
+    ///  Write a double to the output buffer, with 6 decimal places.
+    ///  C API: `void console_printdouble(double d)`
+    fn console_printdouble(d: f64);
+
+    ///  Write "length" number of bytes from "buffer" to the output buffer in hex format.
+    ///  C API: `void console_dump(const uint8_t *buffer, unsigned int len)`
+    fn console_dump(buffer: *const u8, len: u32);
+
+    ///  Flush the output buffer to the console.
+    ///  C API: `void console_flush(void)`
+    fn console_flush();
+}
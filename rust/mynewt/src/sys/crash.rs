@@ -0,0 +1,236 @@
+//! Crash Record that keeps the details of the last panic: file, line, message and a snapshot of the stack.
+//! The record is located in the Non-Zeroed BSS section (`.bss.core.nz`), so it survives the reboot after the
+//! panic and may be reported on the next boot:
+//! ```
+//! //  In the panic handler
+//! crash::record(file, line, column, info.message());
+//! ...
+//! //  After reboot
+//! if let Some(crash) = crash::last_crash() {
+//!     println!("last crash at {} line {}: {}", crash.file(), crash.line, crash.message());
+//! }
+//! ```
+//! A task that is hung is recorded by `record_hung_task()` without formatting, because the Watchdog supervisor
+//! runs on the idle task, whose stack is tiny. Call `format_hung_task()` on the next boot to set the message.
+use core::fmt;
+use crate::{
+    kernel::os,
+    sys::ram_log,
+    Strn,
+};
+
+/// Max size of the file name, including the terminating null
+const CRASH_FILE_SIZE: usize = 48;
+
+/// Max size of the panic message, including the terminating null
+const CRASH_MESSAGE_SIZE: usize = 64;
+
+/// Number of words in the stack snapshot
+pub const CRASH_STACK_WORDS: usize = 16;
+
+/// Magic number that marks the Crash Record as valid: "CRSH"
+const CRASH_MAGIC: u32 = 0x4853_5243;
+
+/// Details of the last panic
+#[repr(C)]
+pub struct CrashInfo {
+    /// `CRASH_MAGIC` if a crash has been recorded
+    magic:    u32,
+    /// True if the crash has been reported to the server
+    reported: u32,
+    /// Source line of the panic
+    pub line:   u32,
+    /// Source column of the panic
+    pub column: u32,
+    /// Uptime in ticks when the panic happened
+    pub uptime: os::os_time_t,
+    /// Boot number (from the RAM Log) when the panic happened
+    pub boot_count: u32,
+    /// Stack pointer when the panic was recorded
+    pub stack_pointer: u32,
+    /// Address of the name of the hung task, or 0 if a panic was recorded
+    hung_task: u32,
+    /// Length of the name of the hung task
+    hung_task_len: u32,
+    /// Words at the top of the stack when the panic was recorded. Unused words are 0.
+    pub stack:  [u32; CRASH_STACK_WORDS],
+    /// Source file of the panic, null-terminated and truncated if too long
+    file:     [u8; CRASH_FILE_SIZE],
+    /// Panic message, null-terminated and truncated if too long
+    message:  [u8; CRASH_MESSAGE_SIZE],
+}
+
+impl CrashInfo {
+    /// Return the source file of the panic
+    pub fn file(&self) -> &str {
+        bytes_to_str(&self.file)
+    }
+
+    /// Return the panic message
+    pub fn message(&self) -> &str {
+        bytes_to_str(&self.message)
+    }
+
+    /// Return the source file of the panic as a null-terminated string, for passing to Mynewt APIs
+    pub fn file_strn(&self) -> Strn {
+        Strn::from_cstr(self.file.as_ptr())
+    }
+
+    /// Return the panic message as a null-terminated string, for passing to Mynewt APIs
+    pub fn message_strn(&self) -> Strn {
+        Strn::from_cstr(self.message.as_ptr())
+    }
+
+    /// Return the name of the task that was recorded as hung by `record_hung_task()`, or `None` for a panic.
+    /// The name is a `&'static str` in flash, so it's still valid after the reboot.
+    pub fn hung_task(&self) -> Option<&'static str> {
+        if self.hung_task == 0 { return None; }
+        let name = unsafe { core::slice::from_raw_parts(self.hung_task as *const u8, self.hung_task_len as usize) };
+        core::str::from_utf8(name).ok()
+    }
+
+    /// Return true if the crash has been reported to the server
+    pub fn is_reported(&self) -> bool {
+        self.reported != 0
+    }
+}
+
+/// The Crash Record. Not zeroed at startup, so the record survives the reboot.
+#[cfg_attr(not(feature = "simulator"), link_section = ".bss.core.nz.crash")]
+static mut CRASH_INFO: CrashInfo = CrashInfo {
+    magic:      0,
+    reported:   0,
+    line:       0,
+    column:     0,
+    uptime:     0,
+    boot_count: 0,
+    stack_pointer: 0,
+    hung_task:  0,
+    hung_task_len: 0,
+    stack:      [0; CRASH_STACK_WORDS],
+    file:       [0; CRASH_FILE_SIZE],
+    message:    [0; CRASH_MESSAGE_SIZE],
+};
+
+/// Record the panic in the Crash Record. Called by the panic handler, so this function must not panic.
+pub fn record(file: &str, line: u32, column: u32, message: Option<&fmt::Arguments>) {
+    let crash = unsafe { &mut CRASH_INFO };
+    crash.magic      = 0;  //  Record is invalid until completely written
+    crash.reported   = 0;
+    crash.line       = line;
+    crash.column     = column;
+    crash.uptime     = unsafe { os::os_time_get() };
+    crash.boot_count = ram_log::boot_count();
+    crash.hung_task  = 0;
+    //  Copy the file and message, truncated if too long
+    copy_str(&mut crash.file, file.as_bytes());
+    let mut writer = TruncatingWriter { buf: &mut crash.message, len: 0 };
+    if let Some(message) = message {
+        fmt::write(&mut writer, *message).ok();  //  Never fails, message is truncated
+    }
+    let len = writer.len;
+    crash.message[len] = 0;
+    //  Snapshot the stack from the current stack pointer upwards, without reading beyond the top of RAM
+    let marker = 0u32;
+    let sp = &marker as *const u32;
+    let stack_top = unsafe { &__StackTop as *const u32 };
+    crash.stack_pointer = sp as u32;
+    for i in 0..CRASH_STACK_WORDS {
+        let ptr = sp.wrapping_add(i);
+        crash.stack[i] =
+            if ptr < stack_top { unsafe { core::ptr::read_volatile(ptr) } }
+            else { 0 };
+    }
+    crash.magic = CRASH_MAGIC;
+}
+
+/// Record that the supervised task named `task` has hung. Called by the Watchdog supervisor on the idle task,
+/// so this function only stores the values and doesn't format the message or snapshot the stack.
+pub fn record_hung_task(task: &'static str, file: &str, line: u32) {
+    let crash = unsafe { &mut CRASH_INFO };
+    crash.magic      = 0;  //  Record is invalid until completely written
+    crash.reported   = 0;
+    crash.line       = line;
+    crash.column     = 0;
+    crash.uptime     = unsafe { os::os_time_get() };
+    crash.boot_count = ram_log::boot_count();
+    crash.stack_pointer = 0;
+    crash.hung_task     = task.as_ptr() as u32;
+    crash.hung_task_len = task.len() as u32;
+    copy_str(&mut crash.file, file.as_bytes());
+    crash.message[0] = 0;
+    crash.magic = CRASH_MAGIC;
+}
+
+/// If the last crash is a hung task, set the message to the name of the task. Called at startup, because the
+/// Watchdog supervisor doesn't have enough stack to format the message.
+pub fn format_hung_task() {
+    let crash = unsafe { &mut CRASH_INFO };
+    if crash.magic != CRASH_MAGIC { return; }
+    let task = match crash.hung_task() {
+        Some(task) => task,
+        None       => return,
+    };
+    let mut writer = TruncatingWriter { buf: &mut crash.message, len: 0 };
+    fmt::write(&mut writer, format_args!("task {} hung", task)).ok();  //  Never fails, message is truncated
+    let len = writer.len;
+    crash.message[len] = 0;
+}
+
+/// Return the last recorded crash, or `None` if no crash has been recorded since power on
+pub fn last_crash() -> Option<&'static CrashInfo> {
+    let crash = unsafe { &CRASH_INFO };
+    if crash.magic != CRASH_MAGIC { return None; }
+    Some(crash)
+}
+
+/// Mark the last crash as reported to the server, so that it won't be reported again
+pub fn mark_reported() {
+    unsafe { CRASH_INFO.reported = 1 };
+}
+
+/// Forget the last crash
+pub fn clear() {
+    unsafe { CRASH_INFO.magic = 0 };
+}
+
+/// Copy the bytes into `buf`, truncated if too long, and terminate with null
+fn copy_str(buf: &mut [u8], bytes: &[u8]) {
+    let len = core::cmp::min(bytes.len(), buf.len() - 1);
+    buf[..len].copy_from_slice(&bytes[..len]);
+    buf[len] = 0;
+}
+
+/// Return the null-terminated bytes as a string. If the bytes were truncated in the middle of a character, drop the character.
+fn bytes_to_str(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    match core::str::from_utf8(&bytes[..len]) {
+        Ok(s)    => s,
+        Err(err) => unsafe { core::str::from_utf8_unchecked(&bytes[..err.valid_up_to()]) },
+    }
+}
+
+/// Writer that formats into a fixed buffer, truncating the output and leaving space for the terminating null
+struct TruncatingWriter<'a> {
+    /// Buffer for the output
+    buf: &'a mut [u8],
+    /// Number of bytes written
+    len: usize,
+}
+
+impl<'a> fmt::Write for TruncatingWriter<'a> {
+    /// Append the string, truncated if the buffer is full
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let space = self.buf.len() - 1 - self.len;
+        let len = core::cmp::min(space, s.len());
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+///  Import the top of the stack from the Mynewt linker script
+extern {
+    ///  Top of the main stack, which is also the top of RAM
+    static __StackTop: u32;
+}
@@ -0,0 +1,51 @@
+//! Dispatch Table for calling OS functions in the Mynewt OS firmware from the Rust application firmware.
+//! The OS firmware exports `mynewt_dispatch_table` (see `libs/mynewt_rust/src/dispatch.c`), which maps the hash
+//! of each OS function name to the function address. The application firmware looks up the address by hash,
+//! so that the application firmware may be flashed and updated independently of the OS firmware:
+//! ```
+//! let addr = get_dispatch_address(dispatch_hash!("console_flush")) ? ;
+//! ```
+//! The table is not linked into the application firmware. The OS linker script `hw/bsp/nrf52/nrf52xxaa.ld` places
+//! the table at `DISPATCH_TABLE_ADDRESS`, where the application firmware reads it.
+use crate::result::*;
+
+/// Address of the Dispatch Table in the OS firmware. Must match the `DISPATCH` region in `hw/bsp/nrf52/nrf52xxaa.ld`
+const DISPATCH_TABLE_ADDRESS: usize = 0x0007_b400;
+
+/// Magic number at the start of the Dispatch Table: "DSPT". Must match `DISPATCH_TABLE_MAGIC` in `dispatch.h`
+const DISPATCH_TABLE_MAGIC: u32 = 0x5450_5344;
+
+/// Entry in the Dispatch Table. Must match `struct dispatch_entry` in `dispatch.h`
+#[repr(C)]
+struct DispatchEntry {
+    /// FNV-1a hash of the function name, computed by `dispatch_hash!()`
+    hash:    u32,
+    /// Address of the function
+    address: u32,
+}
+
+/// Dispatch Table exported by the OS firmware. Must match `struct dispatch_table` in `dispatch.h`
+#[repr(C)]
+struct DispatchTable {
+    /// Must be `DISPATCH_TABLE_MAGIC`
+    magic:   u32,
+    /// Number of entries
+    count:   u32,
+    /// Entries, sorted by hash
+    entries: *const DispatchEntry,
+}
+
+/// Return the Dispatch Address for the OS function whose function name hashed is `hash`.
+/// Returns `SYS_ENODEV` if the OS firmware has no Dispatch Table, `SYS_ENOENT` if the function is not found.
+pub fn get_dispatch_address(hash: u32) -> MynewtResult<u32> {
+    let table = unsafe { &*(DISPATCH_TABLE_ADDRESS as *const DispatchTable) };
+    if table.magic != DISPATCH_TABLE_MAGIC || table.entries.is_null() {
+        return Err(MynewtError::SYS_ENODEV);  //  OS firmware not flashed
+    }
+    let entries = unsafe { core::slice::from_raw_parts(table.entries, table.count as usize) };
+    //  Entries are sorted by hash, so we may do a binary search
+    match entries.binary_search_by_key(&hash, |entry| entry.hash) {
+        Ok(index) => Ok(entries[index].address),
+        Err(_)    => Err(MynewtError::SYS_ENOENT),
+    }
+}
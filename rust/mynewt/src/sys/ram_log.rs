@@ -0,0 +1,163 @@
+//! RAM Log that keeps the most recent console output in a ring buffer. The buffer is located in the Non-Zeroed BSS
+//! section (`.bss.core.nz`, Mynewt's `.noinit` section), so the log survives a soft reset like a watchdog reset or
+//! a panic reboot. This is useful when the PineTime is not connected to a debugger for Semihosting output:
+//! ```
+//! //  After reboot, display the log of the previous boot
+//! ram_log::dump_previous();
+//! //  Or copy the log of the previous boot into a buffer
+//! let len = ram_log::read_previous(0, &mut buf);
+//! ```
+//! All console output from `sys::console` is written to the RAM Log. The log is lost on power off.
+use core::fmt;
+use crate::{
+    kernel::sync::critical_section,
+    sys::console,
+};
+
+/// Size of the RAM Log ring buffer in bytes
+const RAM_LOG_SIZE: usize = 2048;
+
+/// Magic number that marks the RAM Log as valid: "RLOG"
+const RAM_LOG_MAGIC: u32 = 0x474f_4c52;
+
+/// RAM Log header and ring buffer
+#[repr(C)]
+struct RamLog {
+    /// `RAM_LOG_MAGIC` if the RAM Log is valid, else the RAM contains garbage after power on
+    magic:      u32,
+    /// Inverted `magic`, to detect corruption
+    magic_inv:  u32,
+    /// Total number of bytes written since power on. Next byte will be written at `written % RAM_LOG_SIZE`
+    written:    u32,
+    /// Value of `written` when the current boot started
+    boot_mark:  u32,
+    /// Value of `boot_mark` for the previous boot
+    prev_mark:  u32,
+    /// Number of boots since power on
+    boot_count: u32,
+    /// Ring buffer
+    buf:        [u8; RAM_LOG_SIZE],
+}
+
+/// The RAM Log. Not zeroed at startup, so the log survives a soft reset.
+#[cfg_attr(not(feature = "simulator"), link_section = ".bss.core.nz.ram_log")]
+static mut RAM_LOG: RamLog = RamLog {
+    magic:      0,
+    magic_inv:  0,
+    written:    0,
+    boot_mark:  0,
+    prev_mark:  0,
+    boot_count: 0,
+    buf:        [0; RAM_LOG_SIZE],
+};
+
+/// Initialise the RAM Log at startup. Keeps the log of the previous boot if the RAM Log is valid. Called by `sysinit()`.
+pub fn init() {
+    critical_section(|| unsafe {
+        if RAM_LOG.magic != RAM_LOG_MAGIC || RAM_LOG.magic_inv != !RAM_LOG_MAGIC ||
+            RAM_LOG.boot_mark > RAM_LOG.written {
+            //  RAM contains garbage after power on. Clear the log.
+            RAM_LOG.written    = 0;
+            RAM_LOG.boot_mark  = 0;
+            RAM_LOG.boot_count = 0;
+            RAM_LOG.magic      = RAM_LOG_MAGIC;
+            RAM_LOG.magic_inv  = !RAM_LOG_MAGIC;
+        }
+        //  Everything written before this point belongs to the previous boot
+        RAM_LOG.prev_mark  = RAM_LOG.boot_mark;
+        RAM_LOG.boot_mark  = RAM_LOG.written;
+        RAM_LOG.boot_count = RAM_LOG.boot_count.wrapping_add(1);
+    });
+}
+
+/// Append the bytes to the RAM Log, overwriting the oldest bytes if the log is full. May be called from interrupt handlers.
+pub fn write(data: &[u8]) {
+    critical_section(|| unsafe {
+        if RAM_LOG.magic != RAM_LOG_MAGIC { return; }  //  `init()` not called
+        for byte in data {
+            RAM_LOG.buf[RAM_LOG.written as usize % RAM_LOG_SIZE] = *byte;
+            RAM_LOG.written = RAM_LOG.written.wrapping_add(1);
+        }
+    });
+}
+
+/// Append the formatted arguments to the RAM Log
+pub fn write_fmt(args: fmt::Arguments) {
+    fmt::write(&mut RamLogWriter, args).ok();  //  RAM Log never fails
+}
+
+/// Writer that appends to the RAM Log
+struct RamLogWriter;
+
+impl fmt::Write for RamLogWriter {
+    /// Append the string to the RAM Log
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Number of boots since power on, including the current boot
+pub fn boot_count() -> u32 {
+    unsafe { RAM_LOG.boot_count }
+}
+
+/// Return the range of the log for the previous boot `(start, end)`, as values of `written`.
+/// The start is clipped if the bytes have been overwritten.
+fn previous_range() -> (u32, u32) {
+    critical_section(|| unsafe {
+        let oldest = RAM_LOG.written.saturating_sub(RAM_LOG_SIZE as u32);
+        let start = core::cmp::max(RAM_LOG.prev_mark, oldest);
+        let end   = core::cmp::max(RAM_LOG.boot_mark, start);
+        (start, end)
+    })
+}
+
+/// Return the number of bytes still available in the log of the previous boot
+pub fn previous_len() -> usize {
+    let (start, end) = previous_range();
+    (end - start) as usize
+}
+
+/// Copy the log of the previous boot, starting at `offset`, into `buf`. Returns the number of bytes copied.
+pub fn read_previous(offset: usize, buf: &mut [u8]) -> usize {
+    let (start, end) = previous_range();
+    let mut pos = start.saturating_add(offset as u32);
+    let mut len = 0;
+    while pos < end && len < buf.len() {
+        buf[len] = unsafe { RAM_LOG.buf[pos as usize % RAM_LOG_SIZE] };
+        pos += 1;
+        len += 1;
+    }
+    len
+}
+
+/// Display the log of the previous boot on the console. The output is not written to the RAM Log.
+pub fn dump_previous() {
+    let (start, end) = previous_range();
+    dump_range(start, end, b"previous boot log:\n");
+}
+
+/// Display the entire RAM Log on the console. The output is not written to the RAM Log.
+pub fn dump() {
+    let (start, end) = critical_section(|| unsafe {
+        (RAM_LOG.written.saturating_sub(RAM_LOG_SIZE as u32), RAM_LOG.written)
+    });
+    dump_range(start, end, b"ram log:\n");
+}
+
+/// Display the bytes of the RAM Log from `start` to `end` on the console
+fn dump_range(start: u32, end: u32, title: &[u8]) {
+    if start >= end { return; }
+    console::buffer_raw(title);
+    //  Display the two parts of the ring buffer: From `start` to the end of the buffer, then from the start of the buffer
+    let start_pos = start as usize % RAM_LOG_SIZE;
+    let len = (end - start) as usize;
+    let first_len = core::cmp::min(len, RAM_LOG_SIZE - start_pos);
+    unsafe {
+        console::buffer_raw(&RAM_LOG.buf[start_pos..start_pos + first_len]);
+        console::buffer_raw(&RAM_LOG.buf[..len - first_len]);
+    }
+    console::buffer_raw(b"\n");
+    console::flush();
+}
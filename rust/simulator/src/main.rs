@@ -0,0 +1,31 @@
+//! PineTime Simulator: Runs the PineTime app on the host and drives it with a script, e.g. in CI on Linux:
+//! ```text
+//! cargo run --target x86_64-unknown-linux-gnu --manifest-path rust/simulator/Cargo.toml -- rust/simulator/scripts/touch.txt
+//! ```
+//! The script injects touches and saves the display to PNG files, see `mynewt/src/sim/script.rs`.
+//! Exits with code 1 if the script is invalid or fails.
+use std::{ env, fs, process };
+
+fn main() {
+    //  Read the script named on the command line
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: simulator <script>");
+            process::exit(1);
+        }
+    };
+    let script = match fs::read_to_string(&path) {
+        Ok(script) => script,
+        Err(err) => {
+            eprintln!("can't read {}: {}", path, err);
+            process::exit(1);
+        }
+    };
+    //  Run the app's main() and execute the script. Exit when the script ends, even though the app keeps running.
+    if let Err(err) = mynewt::sim::run(app::main, &script) {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    }
+    process::exit(0);
+}